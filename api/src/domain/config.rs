@@ -80,6 +80,39 @@ pub struct ConnectionsConfig {
     pub cache_config: CacheConfig,
     #[envconfig(from = "RATE_LIMIT_ENABLED", default = "true")]
     pub rate_limit_enabled: bool,
+    #[envconfig(from = "SHARED_OUTBOUND_RATE_LIMIT", default = "false")]
+    pub shared_outbound_rate_limit: bool,
+    #[envconfig(from = "SHARED_RESPONSE_CACHE", default = "false")]
+    pub shared_response_cache: bool,
+    #[envconfig(from = "IDEMPOTENCY_ENABLED", default = "true")]
+    pub idempotency_enabled: bool,
+    #[envconfig(from = "IDEMPOTENCY_TTL_SECS", default = "86400")]
+    pub idempotency_ttl_secs: u64,
+    #[envconfig(from = "IDEMPOTENCY_PENDING_TTL_SECS", default = "120")]
+    pub idempotency_pending_ttl_secs: u64,
+    #[envconfig(from = "UNIFIED_BATCH_CONCURRENCY", default = "10")]
+    pub unified_batch_concurrency: usize,
+    #[envconfig(from = "UNIFIED_BATCH_MAX_OPERATIONS", default = "100")]
+    pub unified_batch_max_operations: usize,
+    #[envconfig(from = "OAUTH_REFRESH_ENABLED", default = "false")]
+    pub oauth_refresh_enabled: bool,
+    #[envconfig(from = "OAUTH_REFRESH_INTERVAL_SECS", default = "60")]
+    pub oauth_refresh_interval_secs: u64,
+    #[envconfig(from = "OAUTH_REFRESH_WINDOW_SECS", default = "600")]
+    /// Connections whose access token expires within this window are refreshed
+    pub oauth_refresh_window_secs: i64,
+    #[envconfig(from = "OAUTH_REFRESH_BATCH_SIZE", default = "100")]
+    pub oauth_refresh_batch_size: u64,
+    #[envconfig(from = "JS_SANDBOX_WORKERS", default = "4")]
+    /// Threads running mapping and oauth scripts, each with its own isolate
+    pub js_sandbox_workers: usize,
+    #[envconfig(from = "JS_SANDBOX_TIMEOUT_MS", default = "5000")]
+    pub js_sandbox_timeout_ms: u64,
+    #[envconfig(from = "JS_SANDBOX_MAX_HEAP_MB", default = "64")]
+    pub js_sandbox_max_heap_mb: usize,
+    #[envconfig(from = "JS_SANDBOX_QUEUE_SIZE", default = "1024")]
+    /// Script invocations waiting for a worker before new ones are rejected
+    pub js_sandbox_queue_size: usize,
     #[envconfig(from = "ENVIRONMENT", default = "development")]
     pub environment: Environment,
     #[envconfig(from = "DATABASE_CONNECTION_DOCKER_IMAGE", default = "pica-database")]
@@ -152,6 +185,49 @@ impl Display for ConnectionsConfig {
         writeln!(f, "{}", self.db_config)?;
         writeln!(f, "{}", self.cache_config)?;
         writeln!(f, "RATE_LIMIT_ENABLED: {}", self.rate_limit_enabled)?;
+        writeln!(
+            f,
+            "SHARED_OUTBOUND_RATE_LIMIT: {}",
+            self.shared_outbound_rate_limit
+        )?;
+        writeln!(f, "SHARED_RESPONSE_CACHE: {}", self.shared_response_cache)?;
+        writeln!(f, "IDEMPOTENCY_ENABLED: {}", self.idempotency_enabled)?;
+        writeln!(f, "IDEMPOTENCY_TTL_SECS: {}", self.idempotency_ttl_secs)?;
+        writeln!(
+            f,
+            "IDEMPOTENCY_PENDING_TTL_SECS: {}",
+            self.idempotency_pending_ttl_secs
+        )?;
+        writeln!(
+            f,
+            "UNIFIED_BATCH_CONCURRENCY: {}",
+            self.unified_batch_concurrency
+        )?;
+        writeln!(
+            f,
+            "UNIFIED_BATCH_MAX_OPERATIONS: {}",
+            self.unified_batch_max_operations
+        )?;
+        writeln!(f, "OAUTH_REFRESH_ENABLED: {}", self.oauth_refresh_enabled)?;
+        writeln!(
+            f,
+            "OAUTH_REFRESH_INTERVAL_SECS: {}",
+            self.oauth_refresh_interval_secs
+        )?;
+        writeln!(
+            f,
+            "OAUTH_REFRESH_WINDOW_SECS: {}",
+            self.oauth_refresh_window_secs
+        )?;
+        writeln!(
+            f,
+            "OAUTH_REFRESH_BATCH_SIZE: {}",
+            self.oauth_refresh_batch_size
+        )?;
+        writeln!(f, "JS_SANDBOX_WORKERS: {}", self.js_sandbox_workers)?;
+        writeln!(f, "JS_SANDBOX_TIMEOUT_MS: {}", self.js_sandbox_timeout_ms)?;
+        writeln!(f, "JS_SANDBOX_MAX_HEAP_MB: {}", self.js_sandbox_max_heap_mb)?;
+        writeln!(f, "JS_SANDBOX_QUEUE_SIZE: {}", self.js_sandbox_queue_size)?;
         writeln!(f, "ENVIRONMENT: {}", self.environment)?;
         writeln!(
             f,
@@ -182,6 +258,8 @@ pub struct Headers {
     pub rate_limit_remaining: String,
     #[envconfig(from = "HEADER_RATE_LIMIT_REST", default = "x-pica-rate-limit-reset")]
     pub rate_limit_reset: String,
+    #[envconfig(from = "HEADER_IDEMPOTENCY_KEY", default = "idempotency-key")]
+    pub idempotency_key: String,
 }
 
 impl Headers {
@@ -205,7 +283,8 @@ impl Display for Headers {
             "HEADER_RATE_LIMIT_REMAINING: {}",
             self.rate_limit_remaining
         )?;
-        writeln!(f, "HEADER_RATE_LIMIT_RESET: {}", self.rate_limit_reset)
+        writeln!(f, "HEADER_RATE_LIMIT_RESET: {}", self.rate_limit_reset)?;
+        writeln!(f, "HEADER_IDEMPOTENCY_KEY: {}", self.idempotency_key)
     }
 }
 
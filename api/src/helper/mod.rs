@@ -1,7 +1,9 @@
 pub mod k8s_driver;
+pub mod sdk;
 pub mod shape_mongo_filter;
 
 pub use k8s_driver::*;
+pub use sdk::*;
 pub use shape_mongo_filter::*;
 
 use axum::{extract::Path, Json};
@@ -0,0 +1,604 @@
+use crate::domain::config::Headers;
+use flate2::{write::GzEncoder, Compression};
+use osentities::{
+    type_generation::TypeTarget, ApplicationError, InternalError, PicaError, StringExt,
+};
+
+const PACKAGE_NAME: &str = "pica-unified";
+const PACKAGE_VERSION: &str = "0.1.0";
+
+/// A model exposed by the unified API, with the name of its generated type
+#[derive(Debug, Clone)]
+pub struct SdkModel {
+    pub name: String,
+    pub type_name: String,
+}
+
+impl SdkModel {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            type_name: name.replace("::", "").pascal_case(),
+        }
+    }
+
+    /// Segment of the unified API path, converted back to the model name by the router
+    fn path(&self) -> String {
+        self.type_name.kebab_case()
+    }
+}
+
+/// Files of a package ready to be compiled, relative to its root
+#[derive(Debug, Clone)]
+pub struct SdkPackage {
+    pub name: String,
+    pub files: Vec<(String, String)>,
+}
+
+impl SdkPackage {
+    /// Builds the package for a language. `types` are the generated models, and `models` the
+    /// ones a client is generated for.
+    pub fn new(
+        lang: &TypeTarget,
+        types: String,
+        models: &[SdkModel],
+        headers: &Headers,
+    ) -> Result<Self, PicaError> {
+        let name = format!("{PACKAGE_NAME}-{}", lang_name(lang));
+
+        let files = match lang {
+            TypeTarget::TypeScript => vec![
+                ("package.json".into(), typescript_package_json()),
+                ("tsconfig.json".into(), TYPESCRIPT_CONFIG.into()),
+                ("src/models.ts".into(), types),
+                ("src/client.ts".into(), typescript_client(models, headers)),
+                ("src/index.ts".into(), TYPESCRIPT_INDEX.into()),
+            ],
+            TypeTarget::Rust => vec![
+                ("Cargo.toml".into(), rust_manifest()),
+                ("src/lib.rs".into(), RUST_LIB.into()),
+                (
+                    "src/models.rs".into(),
+                    format!("use serde::{{Deserialize, Serialize}};\n\n{types}"),
+                ),
+                ("src/client.rs".into(), rust_client(models, headers)),
+            ],
+            _ => {
+                return Err(ApplicationError::bad_request(
+                    &format!("SDK generation is not supported for {lang:?}"),
+                    None,
+                ))
+            }
+        };
+
+        Ok(Self { name, files })
+    }
+
+    pub fn file_name(&self) -> String {
+        format!("{}.tar.gz", self.name)
+    }
+
+    /// Writes the files under a directory named after the package into a gzipped tarball
+    pub fn archive(&self) -> Result<Vec<u8>, PicaError> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+
+        for (path, contents) in &self.files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+
+            builder
+                .append_data(
+                    &mut header,
+                    format!("{}/{path}", self.name),
+                    contents.as_bytes(),
+                )
+                .map_err(|e| {
+                    InternalError::io_err(&format!("Could not archive {path}: {e}"), None)
+                })?;
+        }
+
+        builder
+            .into_inner()
+            .and_then(|encoder| encoder.finish())
+            .map_err(|e| InternalError::io_err(&format!("Could not archive the SDK: {e}"), None))
+    }
+}
+
+fn lang_name(lang: &TypeTarget) -> &'static str {
+    match lang {
+        TypeTarget::Rust => "rust",
+        TypeTarget::TypeScript => "typescript",
+        TypeTarget::Python => "python",
+        TypeTarget::Go => "go",
+        TypeTarget::JsonSchema => "jsonschema",
+    }
+}
+
+fn typescript_package_json() -> String {
+    format!(
+        r#"{{
+  "name": "{PACKAGE_NAME}",
+  "version": "{PACKAGE_VERSION}",
+  "main": "dist/index.js",
+  "types": "dist/index.d.ts",
+  "files": ["dist"],
+  "scripts": {{
+    "build": "tsc"
+  }},
+  "devDependencies": {{
+    "typescript": "^5.0.0"
+  }}
+}}
+"#
+    )
+}
+
+const TYPESCRIPT_CONFIG: &str = r#"{
+  "compilerOptions": {
+    "target": "ES2020",
+    "module": "CommonJS",
+    "lib": ["ES2020", "DOM"],
+    "declaration": true,
+    "outDir": "dist",
+    "rootDir": "src",
+    "strict": true,
+    "skipLibCheck": true
+  },
+  "include": ["src"]
+}
+"#;
+
+const TYPESCRIPT_INDEX: &str = r#"export * from "./models";
+export * from "./client";
+"#;
+
+const TYPESCRIPT_CLIENT: &str = r#"import type * as models from "./models";
+
+export interface UnifiedClientOptions {
+  /** Base URL of the API, e.g. `https://api.picaos.com/v1` */
+  baseUrl: string;
+  secret: string;
+  connectionKey: string;
+}
+
+export interface UnifiedResponse<T> {
+  unified: T;
+  meta: Record<string, unknown>;
+  passthrough?: unknown;
+}
+
+export type Query = Record<string, string | number | boolean>;
+
+export class UnifiedError extends Error {
+  constructor(readonly status: number, readonly body: unknown) {
+    super(`Unified API request failed with status ${status}`);
+  }
+}
+
+export class ModelClient<T> {
+  constructor(private readonly client: UnifiedClient, private readonly path: string) {}
+
+  list(query?: Query): Promise<UnifiedResponse<T[]>> {
+    return this.client.request("GET", this.path, undefined, query);
+  }
+
+  count(query?: Query): Promise<UnifiedResponse<{ count: number }>> {
+    return this.client.request("GET", `${this.path}/count`, undefined, query);
+  }
+
+  get(id: string, query?: Query): Promise<UnifiedResponse<T>> {
+    return this.client.request("GET", this.itemPath(id), undefined, query);
+  }
+
+  create(body: T, query?: Query): Promise<UnifiedResponse<T>> {
+    return this.client.request("POST", this.path, body, query);
+  }
+
+  update(id: string, body: Partial<T>, query?: Query): Promise<UnifiedResponse<unknown>> {
+    return this.client.request("PATCH", this.itemPath(id), body, query);
+  }
+
+  upsert(body: T, query?: Query): Promise<UnifiedResponse<T>> {
+    return this.client.request("PUT", this.path, body, query);
+  }
+
+  delete(id: string, query?: Query): Promise<UnifiedResponse<unknown>> {
+    return this.client.request("DELETE", this.itemPath(id), undefined, query);
+  }
+
+  private itemPath(id: string): string {
+    return `${this.path}/${encodeURIComponent(id)}`;
+  }
+}
+
+export class UnifiedClient {
+  constructor(private readonly options: UnifiedClientOptions) {}
+
+{{models}}
+
+  async request<R>(method: string, path: string, body?: unknown, query?: Query): Promise<R> {
+    const url = new URL(`${this.options.baseUrl.replace(/\/$/, "")}/unified/${path}`);
+    Object.entries(query ?? {}).forEach(([key, value]) => url.searchParams.set(key, String(value)));
+
+    const response = await fetch(url, {
+      method,
+      headers: {
+        "content-type": "application/json",
+        "{{auth_header}}": this.options.secret,
+        "{{connection_header}}": this.options.connectionKey,
+      },
+      body: body === undefined ? undefined : JSON.stringify(body),
+    });
+    const payload = await response.json().catch(() => undefined);
+
+    if (!response.ok) {
+      throw new UnifiedError(response.status, payload);
+    }
+
+    return payload as R;
+  }
+}
+"#;
+
+fn typescript_client(models: &[SdkModel], headers: &Headers) -> String {
+    let models = models
+        .iter()
+        .map(|model| {
+            format!(
+                "  readonly {} = new ModelClient<models.{}>(this, \"{}\");",
+                model.type_name.camel_case(),
+                model.type_name,
+                model.path()
+            )
+        })
+        .collect::<Vec<_>>();
+
+    TYPESCRIPT_CLIENT
+        .replace("{{models}}", &models.join("\n"))
+        .replace("{{auth_header}}", &headers.auth_header)
+        .replace("{{connection_header}}", &headers.connection_header)
+}
+
+fn rust_manifest() -> String {
+    format!(
+        r#"[package]
+name = "{PACKAGE_NAME}"
+version = "{PACKAGE_VERSION}"
+edition = "2021"
+
+[dependencies]
+reqwest = {{ version = "0.12", features = ["json"] }}
+serde = {{ version = "1", features = ["derive"] }}
+serde_json = "1"
+"#
+    )
+}
+
+const RUST_LIB: &str = r#"pub mod client;
+pub mod models;
+
+pub use client::*;
+pub use models::*;
+"#;
+
+const RUST_CLIENT: &str = r#"use crate::models;
+use reqwest::Method;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use std::marker::PhantomData;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UnifiedResponse<T> {
+    pub unified: T,
+    pub meta: Value,
+    #[serde(default)]
+    pub passthrough: Option<Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Count {
+    pub count: u64,
+}
+
+#[derive(Debug)]
+pub enum UnifiedError {
+    Request(reqwest::Error),
+    Api { status: u16, body: Value },
+}
+
+impl std::fmt::Display for UnifiedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnifiedError::Request(e) => write!(f, "Unified API request failed: {e}"),
+            UnifiedError::Api { status, body } => {
+                write!(f, "Unified API request failed with status {status}: {body}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for UnifiedError {}
+
+impl From<reqwest::Error> for UnifiedError {
+    fn from(e: reqwest::Error) -> Self {
+        UnifiedError::Request(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, UnifiedError>;
+
+#[derive(Debug, Clone)]
+pub struct UnifiedClient {
+    http: reqwest::Client,
+    base_url: String,
+    secret: String,
+    connection_key: String,
+}
+
+impl UnifiedClient {
+    /// `base_url` is the base URL of the API, e.g. `https://api.picaos.com/v1`
+    pub fn new(base_url: &str, secret: &str, connection_key: &str) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            secret: secret.to_string(),
+            connection_key: connection_key.to_string(),
+        }
+    }
+
+{{models}}
+
+    async fn request<B: Serialize, R: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&B>,
+        query: &[(&str, &str)],
+    ) -> Result<R> {
+        let mut request = self
+            .http
+            .request(method, format!("{}/unified/{path}", self.base_url))
+            .header("{{auth_header}}", &self.secret)
+            .header("{{connection_header}}", &self.connection_key)
+            .query(query);
+
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+
+        if !status.is_success() {
+            let body = response.json().await.unwrap_or(Value::Null);
+            return Err(UnifiedError::Api {
+                status: status.as_u16(),
+                body,
+            });
+        }
+
+        Ok(response.json().await?)
+    }
+}
+
+pub struct ModelClient<'a, T> {
+    client: &'a UnifiedClient,
+    path: &'static str,
+    model: PhantomData<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> ModelClient<'_, T> {
+    pub async fn list(&self, query: &[(&str, &str)]) -> Result<UnifiedResponse<Vec<T>>> {
+        self.client
+            .request::<(), _>(Method::GET, self.path, None, query)
+            .await
+    }
+
+    pub async fn count(&self, query: &[(&str, &str)]) -> Result<UnifiedResponse<Count>> {
+        self.client
+            .request::<(), _>(Method::GET, &format!("{}/count", self.path), None, query)
+            .await
+    }
+
+    pub async fn get(&self, id: &str, query: &[(&str, &str)]) -> Result<UnifiedResponse<T>> {
+        self.client
+            .request::<(), _>(Method::GET, &self.item_path(id), None, query)
+            .await
+    }
+
+    pub async fn create(&self, body: &T, query: &[(&str, &str)]) -> Result<UnifiedResponse<T>> {
+        self.client
+            .request(Method::POST, self.path, Some(body), query)
+            .await
+    }
+
+    pub async fn update(
+        &self,
+        id: &str,
+        body: &T,
+        query: &[(&str, &str)],
+    ) -> Result<UnifiedResponse<Value>> {
+        self.client
+            .request(Method::PATCH, &self.item_path(id), Some(body), query)
+            .await
+    }
+
+    pub async fn upsert(&self, body: &T, query: &[(&str, &str)]) -> Result<UnifiedResponse<T>> {
+        self.client
+            .request(Method::PUT, self.path, Some(body), query)
+            .await
+    }
+
+    pub async fn delete(&self, id: &str, query: &[(&str, &str)]) -> Result<UnifiedResponse<Value>> {
+        self.client
+            .request::<(), _>(Method::DELETE, &self.item_path(id), None, query)
+            .await
+    }
+
+    fn item_path(&self, id: &str) -> String {
+        let id = id
+            .bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                    (b as char).to_string()
+                }
+                b => format!("%{b:02X}"),
+            })
+            .collect::<String>();
+
+        format!("{}/{id}", self.path)
+    }
+}
+"#;
+
+/// Keywords that can't be used as a method name without a raw identifier
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "static", "struct", "trait", "true", "type", "unsafe", "use", "where",
+    "while",
+];
+
+fn rust_client(models: &[SdkModel], headers: &Headers) -> String {
+    let models = models
+        .iter()
+        .map(|model| {
+            let method = model.type_name.snake_case();
+            let method = if RUST_KEYWORDS.contains(&method.as_str()) {
+                format!("r#{method}")
+            } else {
+                method
+            };
+
+            format!(
+                "    pub fn {method}(&self) -> ModelClient<'_, models::{}> {{\n        ModelClient {{\n            client: self,\n            path: \"{}\",\n            model: PhantomData,\n        }}\n    }}\n",
+                model.type_name,
+                model.path()
+            )
+        })
+        .collect::<Vec<_>>();
+
+    RUST_CLIENT
+        .replace("{{models}}", models.join("\n").trim_end())
+        .replace("{{auth_header}}", &headers.auth_header)
+        .replace("{{connection_header}}", &headers.connection_header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use envconfig::Envconfig;
+    use flate2::read::GzDecoder;
+    use std::{collections::HashMap, io::Read};
+
+    #[test]
+    fn test_archive_typescript_package() {
+        let headers = Headers::init_from_hashmap(&HashMap::new()).expect("Failed to init headers");
+        let package = SdkPackage::new(
+            &TypeTarget::TypeScript,
+            "export interface Ticket { id?: string }\n".to_string(),
+            &[SdkModel::new("TicketComment")],
+            &headers,
+        )
+        .expect("Failed to build package");
+
+        let client = &package
+            .files
+            .iter()
+            .find(|(path, _)| path == "src/client.ts")
+            .expect("Missing client")
+            .1;
+        assert!(client.contains(
+            "readonly ticketComment = new ModelClient<models.TicketComment>(this, \"ticket-comment\");"
+        ));
+        assert!(client.contains("\"x-pica-connection-key\": this.options.connectionKey"));
+
+        let archive = package.archive().expect("Failed to archive package");
+        let mut entries = tar::Archive::new(GzDecoder::new(archive.as_slice()));
+        let mut paths = vec![];
+
+        for entry in entries.entries().expect("Failed to read archive") {
+            let mut entry = entry.expect("Failed to read entry");
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).unwrap();
+
+            if entry.path().unwrap().ends_with("models.ts") {
+                assert_eq!(contents, "export interface Ticket { id?: string }\n");
+            }
+            paths.push(entry.path().unwrap().to_string_lossy().to_string());
+        }
+
+        assert_eq!(
+            paths,
+            vec![
+                "pica-unified-typescript/package.json",
+                "pica-unified-typescript/tsconfig.json",
+                "pica-unified-typescript/src/models.ts",
+                "pica-unified-typescript/src/client.ts",
+                "pica-unified-typescript/src/index.ts",
+            ]
+        );
+        assert!(SdkPackage::new(&TypeTarget::Go, String::new(), &[], &headers).is_err());
+    }
+
+    #[test]
+    fn test_archive_rust_package() {
+        let headers = Headers::init_from_hashmap(&HashMap::new()).expect("Failed to init headers");
+        let package = SdkPackage::new(
+            &TypeTarget::Rust,
+            "pub struct Ticket {\n    pub id: Option<String>,\n}\n".to_string(),
+            &[SdkModel::new("TicketComment"), SdkModel::new("Type")],
+            &headers,
+        )
+        .expect("Failed to build package");
+
+        let client = &package
+            .files
+            .iter()
+            .find(|(path, _)| path == "src/client.rs")
+            .expect("Missing client")
+            .1;
+        assert!(client.contains(
+            "    pub fn ticket_comment(&self) -> ModelClient<'_, models::TicketComment> {\n        ModelClient {\n            client: self,\n            path: \"ticket-comment\",\n            model: PhantomData,\n        }\n    }\n"
+        ));
+        assert!(client.contains("pub fn r#type(&self) -> ModelClient<'_, models::Type>"));
+        assert!(client.contains(".header(\"x-pica-connection-key\", &self.connection_key)"));
+        assert!(!client.contains("{{"));
+
+        let archive = package.archive().expect("Failed to archive package");
+        let mut entries = tar::Archive::new(GzDecoder::new(archive.as_slice()));
+        let mut paths = vec![];
+
+        for entry in entries.entries().expect("Failed to read archive") {
+            let mut entry = entry.expect("Failed to read entry");
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).unwrap();
+
+            let path = entry.path().unwrap().to_string_lossy().to_string();
+            match path.as_str() {
+                "pica-unified-rust/Cargo.toml" => {
+                    assert!(contents.contains("name = \"pica-unified\""));
+                }
+                "pica-unified-rust/src/lib.rs" => assert_eq!(contents, RUST_LIB),
+                "pica-unified-rust/src/models.rs" => assert_eq!(
+                    contents,
+                    "use serde::{Deserialize, Serialize};\n\npub struct Ticket {\n    pub id: Option<String>,\n}\n"
+                ),
+                _ => {}
+            }
+            paths.push(path);
+        }
+
+        assert_eq!(
+            paths,
+            vec![
+                "pica-unified-rust/Cargo.toml",
+                "pica-unified-rust/src/lib.rs",
+                "pica-unified-rust/src/models.rs",
+                "pica-unified-rust/src/client.rs",
+            ]
+        );
+    }
+}
@@ -1,7 +1,10 @@
-use super::{create, delete, read, update, HookExt, PublicExt, RequestExt};
-use crate::server::{AppState, AppStores};
+use super::{create, delete, read, HookExt, PublicExt, RequestExt};
+use crate::{
+    router::ServerResponse,
+    server::{AppState, AppStores},
+};
 use axum::{
-    extract::{Json, Path, State},
+    extract::{Json, Path, Query, State},
     routing::{get, patch, post},
     Router,
 };
@@ -11,13 +14,15 @@ use osentities::{
     algebra::MongoStore,
     api_model_config::Lang,
     common_model::{CommonModel, Field},
+    common_model_revision::{CommonModelChanges, CommonModelRevision},
     id::{prefix::IdPrefix, Id},
     json_schema::JsonSchema,
-    ApplicationError, PicaError, Unit,
+    type_generation::TypeTarget,
+    ApplicationError, InternalError, PicaError, Unit,
 };
 use semver::Version;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::{collections::HashMap, sync::Arc};
 use tracing::error;
 
@@ -29,9 +34,9 @@ pub fn get_router() -> Router<Arc<AppState>> {
         )
         .route(
             "/:id",
-            patch(update::<CreateRequest, CommonModel>)
-                .delete(delete::<CreateRequest, CommonModel>),
+            patch(update_common_model).delete(delete::<CreateRequest, CommonModel>),
         )
+        .route("/:id/changes", post(preview_changes))
         .route("/:id/schema", get(as_json_schema))
         .route("/:id/expand", get(expand))
 }
@@ -56,17 +61,26 @@ impl HookExt<CommonModel> for CreateRequest {
         record: &CommonModel,
         stores: &AppStores,
     ) -> Result<Unit, PicaError> {
-        let rust = record.generate_as(&Lang::Rust);
-        let typescript = record.generate_as(&Lang::TypeScript);
+        let rust = record.generate_as(&TypeTarget::Rust)?;
+        let typescript = record.generate_as(&TypeTarget::TypeScript)?;
         let interface =
             HashMap::from_iter(vec![(Lang::Rust, rust), (Lang::TypeScript, typescript)]);
 
+        stores
+            .common_model_revision
+            .create_one(&CommonModelRevision::new(record, None))
+            .await
+            .map_err(|e| {
+                error!("Could not record the first revision of the common model: {e}");
+            })
+            .ok();
+
         update_interface(interface, record, &stores.common_model).await
     }
 
     async fn after_update_hook(record: &CommonModel, stores: &AppStores) -> Result<(), PicaError> {
-        let typescript = record.generate_as(&Lang::TypeScript);
-        let rust = record.generate_as(&Lang::Rust);
+        let typescript = record.generate_as(&TypeTarget::TypeScript)?;
+        let rust = record.generate_as(&TypeTarget::Rust)?;
         let interface =
             HashMap::from_iter(vec![(Lang::Rust, rust), (Lang::TypeScript, typescript)]);
 
@@ -92,9 +106,9 @@ impl RequestExt for CreateRequest {
         Some(record)
     }
 
+    /// The version is not taken from the request, it's bumped according to the changes
     fn update(&self, mut record: Self::Output) -> Self::Output {
         record.name.clone_from(&self.name);
-        record.record_metadata.version = self.version.clone();
         record.fields.clone_from(&self.fields);
         record.category.clone_from(&self.category);
         record.sample = self.sample.clone();
@@ -106,6 +120,226 @@ impl RequestExt for CreateRequest {
     }
 }
 
+/// A connection model schema or definition whose mapping uses fields removed from the model
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MappingDependent {
+    pub id: Id,
+    pub connection_platform: String,
+    pub fields: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommonModelUpdate {
+    pub version: Version,
+    #[serde(flatten)]
+    pub changes: CommonModelChanges,
+    pub connection_model_schemas: Vec<MappingDependent>,
+    pub connection_model_definitions: Vec<MappingDependent>,
+}
+
+/// Response of an update, `success` is kept for the clients of the generic update endpoints
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CommonModelUpdated {
+    pub success: bool,
+    #[serde(flatten)]
+    pub update: CommonModelUpdate,
+}
+
+impl CommonModelUpdate {
+    fn has_dependents(&self) -> bool {
+        !self.connection_model_schemas.is_empty() || !self.connection_model_definitions.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateQuery {
+    /// Accepts a breaking change even though mappings use the removed fields
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// Applies the request to the stored model, classifying the changes and listing the mappings
+/// that use removed fields
+async fn plan_update(
+    id: &Id,
+    payload: &CreateRequest,
+    stores: &AppStores,
+) -> Result<(CommonModel, CommonModel, CommonModelUpdate), PicaError> {
+    let record = stores
+        .common_model
+        .get_one(doc! { "_id": id.to_string(), "deleted": false })
+        .await?
+        .ok_or_else(|| {
+            ApplicationError::not_found(&format!("CommonModel with id {id} not found"), None)
+        })?;
+
+    let mut updated = payload.update(record.clone());
+    let changes = record.changes(&updated);
+
+    updated.record_metadata.mark_updated("system");
+    updated.record_metadata.version = changes.level.bump(&record.record_metadata.version);
+
+    let removed = changes.removed_fields();
+    let (connection_model_schemas, connection_model_definitions) = if removed.is_empty() {
+        (vec![], vec![])
+    } else {
+        let schemas = stores
+            .model_schema
+            .get_many(
+                Some(doc! { "mapping.commonModelId": record.id.to_string(), "deleted": false }),
+                None,
+                None,
+                None,
+                None,
+            )
+            .await?
+            .into_iter()
+            .filter_map(|schema| {
+                let fields = schema.mapping.as_ref()?.referenced_fields(&removed);
+                (!fields.is_empty()).then_some(MappingDependent {
+                    id: schema.id,
+                    connection_platform: schema.connection_platform,
+                    fields,
+                })
+            })
+            .collect();
+
+        let definitions = stores
+            .model_config
+            .get_many(
+                Some(doc! { "mapping.commonModelName": &record.name, "deleted": false }),
+                None,
+                None,
+                None,
+                None,
+            )
+            .await?
+            .into_iter()
+            .filter_map(|definition| {
+                let fields = definition.mapping.as_ref()?.referenced_fields(&removed);
+                (!fields.is_empty()).then_some(MappingDependent {
+                    id: definition.id,
+                    connection_platform: definition.connection_platform,
+                    fields,
+                })
+            })
+            .collect();
+
+        (schemas, definitions)
+    };
+
+    let update = CommonModelUpdate {
+        version: updated.record_metadata.version.clone(),
+        changes,
+        connection_model_schemas,
+        connection_model_definitions,
+    };
+
+    Ok((record, updated, update))
+}
+
+/// Lists the changes an update would make without saving it
+async fn preview_changes(
+    Path(id): Path<Id>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<CreateRequest>,
+) -> Result<Json<ServerResponse<CommonModelUpdate>>, PicaError> {
+    let (_, _, update) = plan_update(&id, &payload, &state.app_stores).await?;
+
+    Ok(Json(ServerResponse::new("changes", update)))
+}
+
+/// Updates the model and records the new revision. Breaking changes are rejected while
+/// mappings use the removed fields, unless `force` is set. The update only applies to the
+/// version it was planned from, a concurrent update is reported as a conflict.
+async fn update_common_model(
+    Path(id): Path<Id>,
+    State(state): State<Arc<AppState>>,
+    query: Option<Query<UpdateQuery>>,
+    Json(payload): Json<CreateRequest>,
+) -> Result<Json<ServerResponse<CommonModelUpdated>>, PicaError> {
+    let force = query.map(|Query(query)| query.force).unwrap_or_default();
+    let stores = &state.app_stores;
+
+    let (record, updated, update) = plan_update(&id, &payload, stores).await?;
+
+    if update.has_dependents() && !force {
+        return Err(ApplicationError::conflict(
+            "Mappings use fields removed from the common model",
+            None,
+        )
+        .set_meta(&json!(update)));
+    }
+
+    // Models saved before revisions were recorded have no history yet
+    let previous = stores
+        .common_model_revision
+        .get_one(doc! {
+            "commonModelId": record.id.to_string(),
+            "version": record.record_metadata.version.to_string(),
+        })
+        .await?;
+
+    if previous.is_none() {
+        stores
+            .common_model_revision
+            .create_one(&CommonModelRevision::new(&record, None))
+            .await?;
+    }
+
+    let bson = bson::to_bson_with_options(&updated, Default::default()).map_err(|e| {
+        error!("Could not serialize record into document: {e}");
+        InternalError::serialize_error(e.to_string().as_str(), None)
+    })?;
+
+    let result = stores
+        .common_model
+        .collection
+        .update_one(
+            doc! {
+                "_id": id.to_string(),
+                "version": record.record_metadata.version.to_string(),
+                "deleted": false,
+            },
+            doc! { "$set": bson },
+        )
+        .await?;
+
+    if result.matched_count == 0 {
+        return Err(ApplicationError::conflict(
+            "The common model was updated concurrently, review the changes and retry",
+            None,
+        ));
+    }
+
+    stores
+        .common_model_revision
+        .create_one(&CommonModelRevision::new(
+            &updated,
+            Some(update.changes.clone()),
+        ))
+        .await?;
+
+    CreateRequest::after_update_hook(&updated, stores)
+        .await
+        .map_err(|e| {
+            error!("Error running after update hook: {:?}", e);
+        })
+        .ok();
+
+    Ok(Json(ServerResponse::new(
+        "update",
+        CommonModelUpdated {
+            success: true,
+            update,
+        },
+    )))
+}
+
 async fn expand(
     Path(id): Path<Id>,
     State(state): State<Arc<AppState>>,
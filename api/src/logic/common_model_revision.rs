@@ -0,0 +1,28 @@
+use super::{read, PublicExt, RequestExt};
+use crate::server::{AppState, AppStores};
+use axum::{routing::get, Router};
+use osentities::{algebra::MongoStore, common_model_revision::CommonModelRevision};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Revisions are recorded when a common model is created or updated and are immutable, they
+/// can only be listed here, e.g. filtered by `commonModelId`
+pub fn get_router() -> Router<Arc<AppState>> {
+    Router::new().route(
+        "/",
+        get(read::<CommonModelRevisionRequest, CommonModelRevision>),
+    )
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CommonModelRevisionRequest;
+
+impl PublicExt<CommonModelRevision> for CommonModelRevisionRequest {}
+
+impl RequestExt for CommonModelRevisionRequest {
+    type Output = CommonModelRevision;
+
+    fn get_store(stores: AppStores) -> MongoStore<Self::Output> {
+        stores.common_model_revision
+    }
+}
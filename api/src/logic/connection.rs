@@ -1,4 +1,4 @@
-use super::{delete, read, PublicExt, ReadResponse, RequestExt};
+use super::{delete, read, HookExt, PublicExt, ReadResponse, RequestExt};
 use crate::{
     helper::{shape_mongo_filter, DeploymentSpecParams, ServiceName, ServiceSpecParams},
     logic::event_access::get_client_throughput,
@@ -147,6 +147,8 @@ async fn test_connection(
     Ok(())
 }
 
+impl HookExt<Connection> for CreateConnectionPayload {}
+
 impl PublicExt<Connection> for CreateConnectionPayload {
     fn public(conn: Connection) -> Value {
         Into::<SanitizedConnection>::into(conn).to_value()
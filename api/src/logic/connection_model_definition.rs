@@ -8,7 +8,7 @@ use axum::{
     extract::Query,
     extract::{Path, State},
     http::{HeaderMap, StatusCode},
-    routing::{patch, post},
+    routing::{get, patch, post},
     Extension, Json, Router,
 };
 use chrono::Utc;
@@ -17,15 +17,17 @@ use mongodb::bson::doc;
 use osentities::{
     algebra::MongoStore,
     api_model_config::{
-        ApiModelConfig, AuthMethod, ModelPaths, ResponseBody, SamplesInput, SchemasInput,
+        ApiModelConfig, AuthMethod, ModelPaths, RateLimitPolicy, ResponseBody, ResponseCachePolicy,
+        RetryPolicy, SamplesInput, SchemasInput,
     },
+    common_model::SchemaType,
     connection_model_definition::{
         ConnectionModelDefinition, CrudAction, CrudMapping, ExtractorConfig, PlatformInfo,
         TestConnection, TestConnectionState,
     },
     event_access::EventAccess,
     id::{prefix::IdPrefix, Id},
-    ApplicationError, InternalError, PicaError,
+    ApplicationError, InternalError, PicaError, Unit,
 };
 use semver::Version;
 use serde::{Deserialize, Serialize};
@@ -36,6 +38,11 @@ use std::{
 };
 use tokio::try_join;
 use tracing::error;
+use unified::{
+    algebra::jsruntime::{JSRuntimeImpl, ScriptCacheStats},
+    domain::{RequestCrud, RequestCrudBuilder, ResponseCrud},
+    unified::{extract_pagination, map_crud_request, map_crud_response},
+};
 
 pub fn get_router() -> Router<Arc<AppState>> {
     Router::new()
@@ -49,6 +56,154 @@ pub fn get_router() -> Router<Arc<AppState>> {
             patch(update::<CreateRequest, ConnectionModelDefinition>)
                 .delete(delete::<CreateRequest, ConnectionModelDefinition>),
         )
+        .route("/:id/mapping/test", post(test_mapping))
+        .route("/scripts/stats", get(get_script_cache_stats))
+}
+
+/// Hit and miss counters of the mapping scripts, in the registry and in the sandbox workers
+pub async fn get_script_cache_stats() -> Json<ServerResponse<ScriptCacheStats>> {
+    Json(ServerResponse::new(
+        "script_cache_stats",
+        JSRuntimeImpl::stats(),
+    ))
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestMappingRequest {
+    /// Request to map, the samples of the definition if not set
+    pub request: Option<RequestCrud>,
+    /// Id of the record the request acts on, for actions such as `getOne`
+    pub id: Option<String>,
+    /// Raw response body of the platform, the first successful response of the definition if
+    /// not set
+    pub response: Option<Value>,
+    #[serde(
+        with = "http_serde_ext_ios::header_map::option",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub response_headers: Option<HeaderMap>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestMappingResponse {
+    pub request: RequestCrud,
+    pub pagination: Option<Value>,
+    pub response: Option<ResponseCrud>,
+}
+
+/// Runs the crud mapping of a definition on a request and a response without calling the
+/// platform, returning the intermediate values of the unified pipeline
+pub async fn test_mapping(
+    Path(id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<TestMappingRequest>,
+) -> Result<Json<ServerResponse<TestMappingResponse>>, PicaError> {
+    let config = state
+        .app_stores
+        .model_config
+        .get_one(doc! {
+            "_id": &id,
+            "deleted": false
+        })
+        .await
+        .inspect_err(|e| {
+            error!("Error fetching connection model definition in mapping test: {e}");
+        })?
+        .ok_or_else(|| {
+            ApplicationError::not_found(
+                &format!("Connection Model Definition with id {id} not found"),
+                None,
+            )
+        })?;
+
+    let fixtures = config.platform_info.config();
+    let request = match payload.request {
+        Some(request) => request,
+        None => sample_request(&fixtures.samples)?,
+    };
+    let (response, response_headers) = match payload.response {
+        Some(response) => (Some(response), payload.response_headers.unwrap_or_default()),
+        None => fixtures
+            .responses
+            .iter()
+            .find(|response| (200..300).contains(&response.status_code))
+            .map(|response| {
+                (
+                    response.body.clone(),
+                    payload
+                        .response_headers
+                        .or_else(|| response.headers.clone())
+                        .unwrap_or_default(),
+                )
+            })
+            .unwrap_or_default(),
+    };
+
+    let namespace = JSRuntimeImpl::namespace(&config.id.to_string());
+    let action_id: Option<Arc<str>> = payload.id.map(Arc::from);
+    let body = request.get_body().cloned();
+
+    let request = map_crud_request(&config, request, action_id.as_ref(), body, &namespace).await?;
+
+    let pagination = match config.action_name {
+        CrudAction::GetMany => extract_pagination(&config, &response)?,
+        _ => None,
+    };
+    let response = map_crud_response(
+        &config,
+        &response_headers,
+        pagination.clone(),
+        &request,
+        &namespace,
+    )
+    .await?;
+
+    Ok(Json(ServerResponse::new(
+        "mapping_test",
+        TestMappingResponse {
+            request,
+            pagination,
+            response,
+        },
+    )))
+}
+
+fn sample_request(samples: &SamplesInput) -> Result<RequestCrud, PicaError> {
+    let params = |value: &Option<Value>| -> HashMap<String, String> {
+        value
+            .as_ref()
+            .and_then(Value::as_object)
+            .map(|params| {
+                params
+                    .iter()
+                    .map(|(key, value)| {
+                        let value = match value {
+                            Value::String(value) => value.clone(),
+                            value => value.to_string(),
+                        };
+                        (key.clone(), value)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    RequestCrudBuilder::default()
+        .headers(samples.headers.clone().unwrap_or_default())
+        .query_params(params(&samples.query_params))
+        .path_params(Some(params(&samples.path_params)).filter(|p| !p.is_empty()))
+        .body(samples.body.clone())
+        .build()
+        .map_err(|e| {
+            error!("Error building request crud from samples: {e}");
+            InternalError::invalid_argument(
+                &format!("Error building request crud from samples: {e}"),
+                None,
+            )
+        })
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -307,13 +462,33 @@ pub struct CreateRequest {
     pub test_connection_status: Option<TestConnection>,
     pub mapping: Option<CrudMapping>,
     pub paths: Option<ModelPaths>,
+    pub retry_policy: Option<RetryPolicy>,
+    pub rate_limit: Option<RateLimitPolicy>,
+    pub response_cache: Option<ResponseCachePolicy>,
+    pub validation: Option<SchemaType>,
     pub supported: Option<bool>,
     pub active: Option<bool>,
     pub knowledge: Option<String>,
     pub tags: Option<Vec<String>>,
 }
 
-impl HookExt<ConnectionModelDefinition> for CreateRequest {}
+impl HookExt<ConnectionModelDefinition> for CreateRequest {
+    async fn after_update_hook(
+        record: &ConnectionModelDefinition,
+        _stores: &AppStores,
+    ) -> Result<Unit, PicaError> {
+        JSRuntimeImpl::invalidate(&JSRuntimeImpl::namespace(&record.id.to_string()));
+        Ok(())
+    }
+
+    async fn after_delete_hook(
+        record: &ConnectionModelDefinition,
+        _stores: &AppStores,
+    ) -> Result<Unit, PicaError> {
+        JSRuntimeImpl::invalidate(&JSRuntimeImpl::namespace(&record.id.to_string()));
+        Ok(())
+    }
+}
 impl PublicExt<ConnectionModelDefinition> for CreateRequest {}
 
 impl RequestExt for CreateRequest {
@@ -353,6 +528,10 @@ impl RequestExt for CreateRequest {
                 samples: self.samples.clone(),
                 responses: self.responses.clone(),
                 paths: self.paths.clone(),
+                retry_policy: self.retry_policy.clone(),
+                rate_limit: self.rate_limit.clone(),
+                response_cache: self.response_cache,
+                validation: self.validation,
             }),
             action: self.http_method.clone(),
             action_name: self.action_name.clone(),
@@ -407,6 +586,10 @@ impl RequestExt for CreateRequest {
             samples: self.samples.clone(),
             responses: self.responses.clone(),
             paths: self.paths.clone(),
+            retry_policy: self.retry_policy.clone(),
+            rate_limit: self.rate_limit.clone(),
+            response_cache: self.response_cache,
+            validation: self.validation,
         });
         record.mapping.clone_from(&self.mapping);
         record.extractor_config.clone_from(&self.extractor_config);
@@ -436,6 +619,12 @@ impl RequestExt for CreateRequest {
         record
     }
 
+    fn prepare(mut output: Self::Output) -> Result<Self::Output, PicaError> {
+        output.mapping = output.mapping.map(CrudMapping::compile).transpose()?;
+
+        Ok(output)
+    }
+
     fn get_store(stores: AppStores) -> MongoStore<Self::Output> {
         stores.model_config.clone()
     }
@@ -226,6 +226,14 @@ impl RequestExt for CreateRequest {
         record
     }
 
+    fn prepare(output: Self::Output) -> Result<Self::Output, PicaError> {
+        if let Some(mapping) = &output.mapping {
+            mapping.validate()?;
+        }
+
+        Ok(output)
+    }
+
     fn get_store(stores: AppStores) -> MongoStore<Self::Output> {
         stores.model_schema.clone()
     }
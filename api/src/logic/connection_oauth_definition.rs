@@ -14,6 +14,7 @@ use osentities::{
     },
     id::{prefix::IdPrefix, Id},
     record_metadata::RecordMetadata,
+    PicaError,
 };
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
@@ -60,6 +61,37 @@ pub struct RequestParams {
     #[serde(skip_serializing_if = "Option::is_none", default = "default_separator")]
     pub compute: Option<String>,
     pub response_compute: String,
+    /// Language of `compute` and `response_compute`, JavaScript if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<Lang>,
+}
+
+impl RequestParams {
+    fn compute_request(&self) -> ComputeRequest {
+        let function = |function: &String| {
+            Function(Compute {
+                entry: "compute".to_string(),
+                function: function.clone(),
+                language: self.language.clone().unwrap_or_default(),
+                compiled: None,
+            })
+        };
+
+        ComputeRequest {
+            computation: self.compute.as_ref().map(function),
+            response: function(&self.response_compute),
+        }
+    }
+}
+
+fn compile(request: ComputeRequest) -> Result<ComputeRequest, PicaError> {
+    Ok(ComputeRequest {
+        computation: request
+            .computation
+            .map(|Function(compute)| compute.compile().map(Function))
+            .transpose()?,
+        response: Function(request.response.0.compile()?),
+    })
 }
 
 fn default_separator() -> Option<String> {
@@ -81,44 +113,8 @@ impl RequestExt for CreateRequest {
             },
             is_full_template_enabled: self.is_full_template_enabled,
             compute: OAuthCompute {
-                init: ComputeRequest {
-                    response: Function(Compute {
-                        entry: "compute".to_string(),
-                        function: self.init.response_compute.clone(),
-                        language: Lang::JavaScript,
-                    }),
-                    computation: self
-                        .init
-                        .compute
-                        .iter()
-                        .map(|compute| {
-                            Function(Compute {
-                                entry: "compute".to_string(),
-                                function: compute.clone(),
-                                language: Lang::JavaScript,
-                            })
-                        })
-                        .next(),
-                },
-                refresh: ComputeRequest {
-                    computation: self
-                        .refresh
-                        .compute
-                        .iter()
-                        .map(|compute| {
-                            Function(Compute {
-                                entry: "compute".to_string(),
-                                function: compute.clone(),
-                                language: Lang::JavaScript,
-                            })
-                        })
-                        .next(),
-                    response: Function(Compute {
-                        entry: "compute".to_string(),
-                        function: self.refresh.response_compute.clone(),
-                        language: Lang::JavaScript,
-                    }),
-                },
+                init: self.init.compute_request(),
+                refresh: self.refresh.compute_request(),
             },
             frontend: Frontend {
                 platform_redirect_uri: self.platform_redirect_uri.clone(),
@@ -141,44 +137,8 @@ impl RequestExt for CreateRequest {
         };
         record.is_full_template_enabled = self.is_full_template_enabled;
         record.compute = OAuthCompute {
-            init: ComputeRequest {
-                computation: self
-                    .init
-                    .compute
-                    .iter()
-                    .map(|compute| {
-                        Function(Compute {
-                            entry: "compute".to_string(),
-                            function: compute.clone(),
-                            language: Lang::JavaScript,
-                        })
-                    })
-                    .next(),
-                response: Function(Compute {
-                    entry: "compute".to_string(),
-                    function: self.init.response_compute.clone(),
-                    language: Lang::JavaScript,
-                }),
-            },
-            refresh: ComputeRequest {
-                response: Function(Compute {
-                    entry: "compute".to_string(),
-                    function: self.refresh.response_compute.clone(),
-                    language: Lang::JavaScript,
-                }),
-                computation: self
-                    .refresh
-                    .compute
-                    .iter()
-                    .map(|compute| {
-                        Function(Compute {
-                            entry: "compute".to_string(),
-                            function: compute.clone(),
-                            language: Lang::JavaScript,
-                        })
-                    })
-                    .next(),
-            },
+            init: self.init.compute_request(),
+            refresh: self.refresh.compute_request(),
         };
         record.frontend = Frontend {
             platform_redirect_uri: self.platform_redirect_uri.clone(),
@@ -193,6 +153,15 @@ impl RequestExt for CreateRequest {
         record
     }
 
+    fn prepare(mut output: Self::Output) -> Result<Self::Output, PicaError> {
+        output.compute = OAuthCompute {
+            init: compile(output.compute.init)?,
+            refresh: compile(output.compute.refresh)?,
+        };
+
+        Ok(output)
+    }
+
     fn get_store(stores: AppStores) -> MongoStore<Self::Output> {
         stores.oauth_config.clone()
     }
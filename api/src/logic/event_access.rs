@@ -1,4 +1,4 @@
-use super::{delete, read, PublicExt, RequestExt};
+use super::{delete, read, HookExt, PublicExt, RequestExt};
 use crate::{
     domain::config::ConnectionsConfig,
     router::ServerResponse,
@@ -53,6 +53,8 @@ pub struct CreateEventAccessRequest {
     pub paths: Paths,
 }
 
+impl HookExt<EventAccess> for CreateEventAccessRequest {}
+
 impl RequestExt for CreateEventAccessRequest {
     type Output = EventAccess;
 
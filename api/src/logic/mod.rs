@@ -23,6 +23,7 @@ use tracing::error;
 
 pub mod common_enum;
 pub mod common_model;
+pub mod common_model_revision;
 pub mod connection;
 pub mod connection_definition;
 pub mod connection_model_definition;
@@ -38,12 +39,14 @@ pub mod openapi;
 pub mod passthrough;
 pub mod platform;
 pub mod platform_page;
+pub mod schema_drift;
 pub mod schema_generator;
 pub mod secrets;
 pub mod tasks;
 pub mod tracker;
 pub mod unified;
 pub mod vault_connection;
+pub mod workflows;
 
 pub trait RequestExt: Sized {
     type Output: Serialize + DeserializeOwned + Unpin + Sync + Send + 'static;
@@ -68,6 +71,15 @@ pub trait RequestExt: Sized {
         output
     }
 
+    /// Prepares `Self::Output` before it is saved, e.g. compiling its scripts. Errors are
+    /// returned to the caller and nothing is saved.
+    ///
+    /// @param output
+    /// @return Result<Self::Output, PicaError>
+    fn prepare(output: Self::Output) -> Result<Self::Output, PicaError> {
+        Ok(output)
+    }
+
     fn get_store(stores: AppStores) -> MongoStore<Self::Output>;
 }
 
@@ -129,6 +141,7 @@ where
             error!("Could not generate output from payload");
             ApplicationError::bad_request("Could not generate output from payload", None)
         })?;
+    let output = T::prepare(output)?;
 
     match T::get_store(state.app_stores.clone())
         .create_one(&output)
@@ -237,7 +250,7 @@ where
         ));
     };
 
-    let record = payload.update(record);
+    let record = T::prepare(payload.update(record))?;
 
     let bson = bson::to_bson_with_options(&record, Default::default()).map_err(|e| {
         error!("Could not serialize record into document: {e}");
@@ -274,7 +287,7 @@ pub async fn delete<T, U>(
     State(state): State<Arc<AppState>>,
 ) -> Result<Json<ServerResponse<U>>, PicaError>
 where
-    T: RequestExt<Output = U> + 'static,
+    T: RequestExt<Output = U> + HookExt<U> + 'static,
     U: Serialize + DeserializeOwned + Unpin + Sync + Send + 'static,
 {
     let store = T::get_store(state.app_stores.clone());
@@ -313,7 +326,16 @@ where
         )
         .await
     {
-        Ok(_) => Ok(Json(ServerResponse::new("delete", res))),
+        Ok(_) => {
+            T::after_delete_hook(&res, &state.app_stores)
+                .await
+                .map_err(|e| {
+                    error!("Error running after delete hook: {:?}", e);
+                })
+                .ok();
+
+            Ok(Json(ServerResponse::new("delete", res)))
+        }
         Err(e) => {
             error!("Could not update record in store: {e}");
             Err(e)
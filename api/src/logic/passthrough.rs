@@ -26,7 +26,7 @@ use rand::distributions::{Alphanumeric, DistString};
 use serde_json::json;
 use std::{collections::HashMap, sync::Arc};
 use tracing::{error, info};
-use unified::domain::UnifiedMetadataBuilder;
+use unified::{client::RequestAttempts, domain::UnifiedMetadataBuilder};
 
 pub fn get_router() -> Router<Arc<AppState>> {
     Router::new().route(
@@ -138,6 +138,11 @@ pub async fn passthrough_request(
     let connection_key = connection.key.to_string();
     let request_headers = headers.clone();
     let request_status_code = model_execution_result.status();
+    let attempts = model_execution_result
+        .extensions()
+        .get::<RequestAttempts>()
+        .map(|RequestAttempts(attempts)| *attempts)
+        .unwrap_or(1);
 
     let database_c = state.app_stores.db.clone();
     let event_access_pass_c = state.config.event_access_password.clone();
@@ -219,6 +224,7 @@ pub async fn passthrough_request(
                     .host(host)
                     .path(path.to_string())
                     .status_code(request_status_code)
+                    .attempts(attempts)
                     .build()
                     .ok()
                     .map(|m| m.as_value());
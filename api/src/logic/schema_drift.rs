@@ -0,0 +1,34 @@
+use super::{delete, read, HookExt, PublicExt, RequestExt};
+use crate::server::{AppState, AppStores};
+use axum::{
+    routing::{delete as axum_delete, get},
+    Router,
+};
+use osentities::{algebra::MongoStore, schema_drift::SchemaDrift};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Drift is recorded by the unified destination when a platform response no longer matches its
+/// `ConnectionModelSchema`, it can only be listed and dismissed here
+pub fn get_router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/", get(read::<SchemaDriftRequest, SchemaDrift>))
+        .route(
+            "/:id",
+            axum_delete(delete::<SchemaDriftRequest, SchemaDrift>),
+        )
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SchemaDriftRequest;
+
+impl HookExt<SchemaDrift> for SchemaDriftRequest {}
+impl PublicExt<SchemaDrift> for SchemaDriftRequest {}
+
+impl RequestExt for SchemaDriftRequest {
+    type Output = SchemaDrift;
+
+    fn get_store(stores: AppStores) -> MongoStore<Self::Output> {
+        stores.schema_drift
+    }
+}
@@ -1,17 +1,22 @@
 use super::ReadResponse;
-use crate::server::AppState;
+use crate::{
+    helper::{SdkModel, SdkPackage},
+    server::AppState,
+};
 use axum::{
     extract::{Path, State},
+    response::IntoResponse,
     routing::get,
     Json, Router,
 };
 use bson::{doc, Document};
 use futures::StreamExt;
+use http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
 use mongodb::options::FindOptions;
 use osentities::{
-    api_model_config::Lang,
-    common_model::{CommonEnum, DataType, SchemaType, TypeGenerationStrategy},
+    common_model::{CommonEnum, CommonModel, DataType, SchemaType, TypeGenerationStrategy},
     prefix::IdPrefix,
+    type_generation::{bundle_types, TypeTarget},
     ApplicationError, Id, InternalError, PicaError, Store, StringExt,
 };
 use serde::{Deserialize, Serialize};
@@ -25,11 +30,12 @@ pub fn get_router() -> Router<Arc<AppState>> {
         .route("/types/:id/:lang", get(generate_types))
         .route("/types/:lang", get(generate_all_types))
         .route("/types/:lang/only/:models", get(generate_specific_types))
+        .route("/sdk/:lang", get(generate_sdk))
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 struct GenerateSpecificTypesRequest {
-    lang: Lang,
+    lang: TypeTarget,
     models: String,
 }
 
@@ -61,7 +67,7 @@ async fn generate_specific_types(
         )
         .await?;
 
-    let mut output_types = String::new();
+    let mut output_types = Vec::new();
 
     for common_model in common_models {
         let expanded = common_model
@@ -74,18 +80,19 @@ async fn generate_specific_types(
                     visited_common_models,
                 },
             )
-            .await;
+            .await?;
 
-        output_types.push_str(&expanded);
+        output_types.push(expanded);
     }
 
-    Ok(output_types)
+    bundle_types(&lang, output_types)
 }
 
-async fn generate_all_types(
-    state: State<Arc<AppState>>,
-    Path(lang): Path<Lang>,
-) -> Result<String, PicaError> {
+/// Active models, except `Collections`, with the enums they use, whether stored or declared
+/// inline in a field
+async fn get_models_and_enums(
+    state: &AppState,
+) -> Result<(Vec<CommonModel>, Vec<CommonEnum>), PicaError> {
     let cm_store = state.app_stores.common_model.clone();
     let ce_store = state.app_stores.common_enum.clone();
 
@@ -116,7 +123,7 @@ async fn generate_all_types(
         )
         .await?;
 
-    let mut output_types = String::new();
+    let mut models = Vec::new();
 
     for cm in common_models {
         enums.extend(
@@ -137,30 +144,98 @@ async fn generate_all_types(
             continue;
         }
 
-        let Some(lang) = cm.interface.get(&lang) else {
-            continue;
+        models.push(cm);
+    }
+
+    Ok((models, enums.into_iter().chain(common_enums).collect()))
+}
+
+async fn generate_all_types(
+    state: State<Arc<AppState>>,
+    Path(lang): Path<TypeTarget>,
+) -> Result<String, PicaError> {
+    let (common_models, common_enums) = get_models_and_enums(&state).await?;
+
+    let mut output_types = Vec::new();
+
+    for cm in common_models {
+        // Only Rust and TypeScript interfaces are stored with the model
+        let interface = match lang.interface_lang().and_then(|key| cm.interface.get(&key)) {
+            Some(interface) => interface.clone(),
+            None => cm.generate_as(&lang)?,
         };
 
-        output_types.push_str(lang);
+        output_types.push(interface);
     }
 
-    for ce in enums.iter().chain(common_enums.iter()) {
-        match lang {
-            Lang::TypeScript => {
-                let ts = ce.as_typescript_type();
-                output_types.push_str(&ts);
-            }
-            Lang::Rust => {
-                let rust = ce.as_rust_type();
-                output_types.push_str(&rust);
-            }
-            Lang::JavaScript => {
-                unimplemented!();
-            }
+    for ce in common_enums {
+        output_types.push(ce.generate_as(&lang)?);
+    }
+
+    bundle_types(&lang, output_types)
+}
+
+/// Generates a package with the models and a client of the unified API for each primary
+/// model, as a gzipped tarball
+async fn generate_sdk(
+    state: State<Arc<AppState>>,
+    Path(lang): Path<TypeTarget>,
+) -> Result<impl IntoResponse, PicaError> {
+    if !matches!(lang, TypeTarget::TypeScript | TypeTarget::Rust) {
+        return Err(ApplicationError::bad_request(
+            &format!("SDK generation is not supported for {lang:?}"),
+            None,
+        ));
+    }
+
+    let (common_models, common_enums) = get_models_and_enums(&state).await?;
+
+    // A package can't declare the same type twice
+    let mut names = HashSet::new();
+    let mut output_types = Vec::new();
+
+    for cm in common_models.iter() {
+        if names.insert(cm.name.replace("::", "").pascal_case()) {
+            output_types.push(match lang {
+                TypeTarget::Rust => cm.as_rust_serde_type(),
+                _ => cm.generate_as(&lang)?,
+            });
         }
     }
 
-    Ok(output_types)
+    for ce in common_enums.iter() {
+        if names.insert(ce.name.replace("::", "").pascal_case()) {
+            output_types.push(match lang {
+                TypeTarget::Rust => ce.as_rust_serde_type(),
+                _ => ce.generate_as(&lang)?,
+            });
+        }
+    }
+
+    let models = common_models
+        .iter()
+        .filter(|cm| cm.primary)
+        .map(|cm| SdkModel::new(&cm.name))
+        .collect::<Vec<_>>();
+
+    let package = SdkPackage::new(
+        &lang,
+        bundle_types(&lang, output_types)?,
+        &models,
+        &state.config.headers,
+    )?;
+    let archive = package.archive()?;
+
+    Ok((
+        [
+            (CONTENT_TYPE, "application/gzip".to_string()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", package.file_name()),
+            ),
+        ],
+        archive,
+    ))
 }
 
 #[tracing::instrument(skip(state))]
@@ -211,7 +286,7 @@ pub async fn get_common_models_projections(
 #[derive(Debug, Deserialize)]
 struct TypeParams {
     id: Id,
-    lang: Lang,
+    lang: TypeTarget,
 }
 
 async fn generate_types(
@@ -230,11 +305,9 @@ async fn generate_types(
                 None,
             ))?;
 
-    let schema = common_model
+    common_model
         .generate_as_expanded(&lang, &cm_store, &ce_store, TypeGenerationStrategy::Unique)
-        .await;
-
-    Ok(schema)
+        .await
 }
 
 #[derive(Debug, Deserialize)]
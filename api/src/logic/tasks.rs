@@ -1,17 +1,30 @@
 use super::{create, delete, read, update, HookExt, PublicExt, RequestExt};
-use crate::server::{AppState, AppStores};
+use crate::{
+    helper::shape_mongo_filter,
+    router::ServerResponse,
+    server::{AppState, AppStores},
+};
 use axum::{
-    routing::{patch, post},
-    Router,
+    extract::{Path, Query, State},
+    routing::{get, patch, post},
+    Extension, Json, Router,
 };
+use bson::doc;
 use chrono::Utc;
 use fake::Dummy;
+use http::{HeaderMap, Method};
 use osentities::{
-    event_access::EventAccess, prefix::IdPrefix, record_metadata::RecordMetadata, task::Task, Id,
+    event_access::EventAccess,
+    prefix::IdPrefix,
+    record_metadata::RecordMetadata,
+    task::{check_reserved_headers, Task, TaskBackoff, TaskState, DEFAULT_MAX_ATTEMPTS},
+    task_schedule::TaskSchedule,
+    ApplicationError, Id, InternalError, PicaError,
 };
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::sync::Arc;
+use tracing::error;
 
 pub fn get_router() -> Router<Arc<AppState>> {
     Router::new()
@@ -23,43 +36,309 @@ pub fn get_router() -> Router<Arc<AppState>> {
             "/:id",
             patch(update::<CreateRequest, Task>).delete(delete::<CreateRequest, Task>),
         )
+        .route("/:id/replay", post(replay))
+        .route("/:id/pause", post(pause))
+        .route("/:id/resume", post(resume))
+        .route("/:id/upcoming", get(upcoming))
 }
 
+const DEFAULT_UPCOMING_COUNT: usize = 5;
+const MAX_UPCOMING_COUNT: usize = 100;
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Dummy)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateRequest {
     pub start_time: i64,
     pub endpoint: String,
     pub payload: Value,
+    #[serde(
+        with = "http_serde_ext_ios::method::option",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[dummy(default)]
+    pub method: Option<Method>,
+    #[serde(
+        with = "http_serde_ext_ios::header_map",
+        default,
+        skip_serializing_if = "HeaderMap::is_empty"
+    )]
+    #[dummy(default)]
+    pub headers: HeaderMap,
+    /// Id of a secret created through `/v1/secrets` holding the signing key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_secret_id: Option<String>,
     #[serde(rename = "await")]
     pub r#await: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backoff: Option<TaskBackoff>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<TaskSchedule>,
 }
 
 impl RequestExt for CreateRequest {
     type Output = Task;
 
     fn from(&self) -> Option<Task> {
+        let id = Id::now(IdPrefix::Task);
+
         Some(Task {
-            id: Id::now(IdPrefix::Task),
-            start_time: Utc::now().timestamp_millis(),
+            id,
+            start_time: self.start_time,
             worker_id: 0,
             end_time: None,
             payload: self.payload.clone(),
             endpoint: self.endpoint.clone(),
+            method: self.method.clone().unwrap_or(Method::POST),
+            headers: self.headers.clone(),
+            signing_secret_id: self.signing_secret_id.clone(),
             status: None,
             r#await: self.r#await,
             log_trail: vec![],
+            state: TaskState::Pending,
+            max_attempts: self.max_attempts.unwrap_or(DEFAULT_MAX_ATTEMPTS).max(1),
+            attempt: 0,
+            backoff: self.backoff.clone().unwrap_or_default(),
+            next_run_at: None,
+            last_error: None,
+            lease_expires_at: None,
+            schedule: self.schedule.clone().map(|schedule| TaskSchedule {
+                run: 1,
+                paused: false,
+                ..schedule
+            }),
+            series_id: self.schedule.as_ref().map(|_| id),
+            workflow_id: None,
+            stage_id: None,
+            ownership: None,
+            environment: None,
             metadata: RecordMetadata::default(),
         })
     }
 
+    fn prepare(output: Self::Output) -> Result<Self::Output, PicaError> {
+        check_reserved_headers(&output.headers)?;
+
+        if let Some(schedule) = &output.schedule {
+            schedule.validate(output.start_time)?;
+        }
+
+        Ok(output)
+    }
+
     fn get_store(stores: AppStores) -> osentities::MongoStore<Self::Output> {
         stores.tasks
     }
 
-    fn access(&self, _: Arc<EventAccess>) -> Option<Self::Output> {
-        self.from()
+    fn access(&self, event_access: Arc<EventAccess>) -> Option<Self::Output> {
+        self.from().map(|task| Task {
+            ownership: Some(event_access.ownership.clone()),
+            environment: Some(event_access.environment),
+            ..task
+        })
     }
 }
 impl HookExt<Task> for CreateRequest {}
 impl PublicExt<Task> for CreateRequest {}
+
+/// Runs a dead-lettered task again, with all its attempts
+async fn replay(
+    Extension(access): Extension<Arc<EventAccess>>,
+    Path(id): Path<Id>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ServerResponse<Value>>, PicaError> {
+    let mut query = shape_mongo_filter(None, Some(access), None);
+    query.filter.insert("_id", id.to_string());
+    query
+        .filter
+        .insert("state", to_bson(&TaskState::DeadLettered)?);
+
+    let store = state.app_stores.tasks.clone();
+
+    let Some(mut task) = store.get_one(query.filter).await? else {
+        return Err(ApplicationError::not_found(
+            &format!("Dead-lettered task with id {id} not found"),
+            None,
+        ));
+    };
+
+    task.state = TaskState::Pending;
+    task.attempt = 0;
+    task.worker_id = 0;
+    task.next_run_at = None;
+    task.end_time = None;
+    task.last_error = None;
+    task.lease_expires_at = None;
+    task.metadata.active = true;
+    task.metadata.mark_updated("system");
+
+    store
+        .update_one(
+            &id.to_string(),
+            doc! {
+                "$set": to_bson(&task)?,
+                "$unset": { "nextRunAt": "", "lastError": "", "leaseExpiresAt": "" },
+            },
+        )
+        .await?;
+
+    Ok(Json(ServerResponse::new(
+        "replay",
+        CreateRequest::public(task),
+    )))
+}
+
+fn to_bson<T: Serialize>(value: &T) -> Result<bson::Bson, PicaError> {
+    bson::to_bson(value).map_err(|e| {
+        error!("Could not serialize task into document: {e}");
+        InternalError::serialize_error(&e.to_string(), None)
+    })
+}
+
+/// Stops creating and running the occurrences of a recurring task
+async fn pause(
+    Extension(access): Extension<Arc<EventAccess>>,
+    Path(id): Path<Id>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ServerResponse<Value>>, PicaError> {
+    let series_id = set_paused(access, id, true, &state).await?;
+
+    Ok(Json(ServerResponse::new(
+        "pause",
+        json!({ "seriesId": series_id, "paused": true }),
+    )))
+}
+
+/// Runs a paused recurring task again. An occurrence missed while paused runs right away.
+async fn resume(
+    Extension(access): Extension<Arc<EventAccess>>,
+    Path(id): Path<Id>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<ServerResponse<Value>>, PicaError> {
+    let series_id = set_paused(access, id, false, &state).await?;
+
+    Ok(Json(ServerResponse::new(
+        "resume",
+        json!({ "seriesId": series_id, "paused": false }),
+    )))
+}
+
+async fn set_paused(
+    access: Arc<EventAccess>,
+    id: Id,
+    paused: bool,
+    state: &Arc<AppState>,
+) -> Result<Id, PicaError> {
+    let store = state.app_stores.tasks.clone();
+    let (_, series_id) = find_recurring(access.clone(), id, state).await?;
+
+    let mut query = shape_mongo_filter(None, Some(access), None);
+    query.filter.insert("seriesId", series_id.to_string());
+    query.filter.insert(
+        "state",
+        doc! { "$in": [
+            to_bson(&TaskState::Pending)?,
+            to_bson(&TaskState::Running)?,
+            to_bson(&TaskState::Retrying)?,
+        ] },
+    );
+
+    store
+        .update_many(
+            query.filter,
+            doc! { "$set": { "schedule.paused": paused, "updatedAt": Utc::now().timestamp_millis() } },
+        )
+        .await?;
+
+    Ok(series_id)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpcomingQuery {
+    pub count: Option<usize>,
+}
+
+/// Start times of the next occurrences of a recurring task, beginning with the pending one
+async fn upcoming(
+    Extension(access): Extension<Arc<EventAccess>>,
+    Path(id): Path<Id>,
+    State(state): State<Arc<AppState>>,
+    query: Option<Query<UpcomingQuery>>,
+) -> Result<Json<ServerResponse<Value>>, PicaError> {
+    let count = query
+        .and_then(|Query(query)| query.count)
+        .unwrap_or(DEFAULT_UPCOMING_COUNT)
+        .clamp(1, MAX_UPCOMING_COUNT);
+
+    let (_, series_id) = find_recurring(access.clone(), id, &state).await?;
+
+    let mut query = shape_mongo_filter(None, Some(access), None);
+    query.filter.insert("seriesId", series_id.to_string());
+    query.filter.insert(
+        "state",
+        doc! { "$in": [to_bson(&TaskState::Pending)?, to_bson(&TaskState::Retrying)?] },
+    );
+
+    let current = state
+        .app_stores
+        .tasks
+        .get_many(
+            Some(query.filter),
+            None,
+            Some(doc! { "startTime": -1 }),
+            Some(1),
+            None,
+        )
+        .await?
+        .pop();
+
+    let (runs, paused) = match current {
+        Some(Task {
+            start_time,
+            schedule: Some(schedule),
+            ..
+        }) => {
+            let mut runs = vec![start_time];
+            runs.extend(schedule.upcoming(start_time, Utc::now().timestamp_millis(), count - 1)?);
+
+            (runs, schedule.paused)
+        }
+        _ => (vec![], false),
+    };
+
+    Ok(Json(ServerResponse::new(
+        "upcoming",
+        json!({ "seriesId": series_id, "paused": paused, "runs": runs }),
+    )))
+}
+
+/// Finds a task with a schedule and the series it belongs to
+async fn find_recurring(
+    access: Arc<EventAccess>,
+    id: Id,
+    state: &Arc<AppState>,
+) -> Result<(Task, Id), PicaError> {
+    let mut query = shape_mongo_filter(None, Some(access), None);
+    query.filter.insert("_id", id.to_string());
+
+    let Some(task) = state.app_stores.tasks.get_one(query.filter).await? else {
+        return Err(ApplicationError::not_found(
+            &format!("Task with id {id} not found"),
+            None,
+        ));
+    };
+
+    if task.schedule.is_none() {
+        return Err(ApplicationError::bad_request(
+            &format!("Task with id {id} is not recurring"),
+            None,
+        ));
+    }
+
+    let series_id = task.series_id.unwrap_or(task.id);
+
+    Ok((task, series_id))
+}
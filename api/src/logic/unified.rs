@@ -1,27 +1,38 @@
 use super::get_connection;
 use crate::{domain::config::Headers, domain::metrics::Metric, server::AppState};
 use axum::{
+    body::Body,
     extract::{Path, Query, State},
     response::{IntoResponse, Response},
     routing::{delete, get, patch, post, put},
     Extension, Json, Router,
 };
+use base64::{engine::general_purpose::STANDARD, Engine};
 use bson::doc;
+use chrono::Utc;
 use convert_case::{Case, Casing};
-use http::{HeaderMap, HeaderName};
+use futures::{stream, StreamExt, TryStreamExt};
+use http::{
+    header::{CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_NONE_MATCH},
+    response::Parts,
+    HeaderMap, HeaderName, HeaderValue, StatusCode,
+};
 use osentities::{
     connection_model_definition::CrudAction,
-    constant::{PASSWORD_LENGTH, PICA_PASSTHROUGH_HEADER},
+    constant::{
+        AUTO_PAGINATE_KEY, MAX_PAGES_KEY, MAX_RECORDS_KEY, PASSWORD_LENGTH, PICA_PASSTHROUGH_HEADER,
+    },
     destination::Action,
     encrypted_access_key::EncryptedAccessKey,
     event_access::EventAccess,
-    AccessKey, ApplicationError, Event, InternalError, META,
+    prefix::IdPrefix,
+    AccessKey, ApplicationError, Connection, ErrorMeta, Event, Id, InternalError, PicaError, META,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, convert::Infallible, str::FromStr, sync::Arc};
 use tracing::error;
-use unified::domain::RequestCrudBuilder;
+use unified::domain::{PaginationLimits, RequestCrudBuilder, UnifiedMetadataBuilder};
 
 pub fn get_router() -> Router<Arc<AppState>> {
     Router::new()
@@ -32,6 +43,7 @@ pub fn get_router() -> Router<Arc<AppState>> {
         .route("/:model/count", get(count_request))
         .route("/:model", post(create_request))
         .route("/:model/:id", delete(delete_request))
+        .route("/batch", post(batch_request))
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -121,21 +133,28 @@ pub async fn list_request(
     Path(model): Path<String>,
     headers: HeaderMap,
     query_params: Option<Query<HashMap<String, String>>>,
-) -> impl IntoResponse {
-    process_request(
-        access,
-        state,
-        headers,
-        query_params,
-        Action::Unified {
-            name: model.to_case(Case::Pascal).into(),
-            action: CrudAction::GetMany,
-            id: None,
-            passthrough: *passthrough,
-        },
-        None,
-    )
-    .await
+) -> Response {
+    let action = Action::Unified {
+        name: model.to_case(Case::Pascal).into(),
+        action: CrudAction::GetMany,
+        id: None,
+        passthrough: *passthrough,
+    };
+
+    let auto_paginate = query_params
+        .as_ref()
+        .and_then(|Query(params)| params.get(AUTO_PAGINATE_KEY))
+        .is_some_and(|value| value == "true");
+
+    if auto_paginate {
+        stream_request(access, state, headers, query_params, action)
+            .await
+            .into_response()
+    } else {
+        process_request(access, state, headers, query_params, action, None)
+            .await
+            .into_response()
+    }
 }
 
 pub async fn count_request(
@@ -211,6 +230,197 @@ pub async fn delete_request(
     .await
 }
 
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchOperation {
+    pub model: String,
+    pub action: CrudAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub query_params: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchOperationResult {
+    pub status: u16,
+    pub body: Value,
+    pub meta: Value,
+}
+
+/// Runs several unified operations against the connection of the request. Operations are
+/// dispatched concurrently and their results are returned in the order they were sent.
+pub async fn batch_request(
+    Extension(access): Extension<Arc<EventAccess>>,
+    Extension(passthrough): Extension<Arc<bool>>,
+    State(state): State<Arc<AppState>>,
+    mut headers: HeaderMap,
+    Json(operations): Json<Vec<BatchOperation>>,
+) -> Result<Json<Vec<BatchOperationResult>>, PicaError> {
+    if operations.is_empty() {
+        return Err(ApplicationError::bad_request(
+            "Batch must contain at least one operation",
+            None,
+        ));
+    }
+
+    if operations.len() > state.config.unified_batch_max_operations {
+        return Err(ApplicationError::bad_request(
+            &format!(
+                "Batch can contain at most {} operations",
+                state.config.unified_batch_max_operations
+            ),
+            None,
+        ));
+    }
+
+    let Some(connection_key_header) = headers.get(&state.config.headers.connection_header) else {
+        return Err(ApplicationError::bad_request(
+            "Missing connection key header",
+            None,
+        ));
+    };
+    let connection = get_connection(
+        access.as_ref(),
+        connection_key_header,
+        &state.app_stores,
+        &state.app_caches.connections_cache,
+    )
+    .await
+    .map_err(|e| {
+        error!("Error getting connection: {:?}", e);
+        e
+    })?;
+
+    let access_key_header_value = headers.get(&state.config.headers.auth_header).cloned();
+
+    remove_event_headers(&mut headers, &state.config.headers);
+
+    let results = stream::iter(operations)
+        .map(|operation| {
+            let state = &state;
+            let connection = connection.clone();
+            let access_key_header_value = access_key_header_value.clone();
+            let headers = headers.clone();
+
+            async move {
+                let started_at = Utc::now().timestamp_millis();
+                let model = operation.model.to_case(Case::Pascal);
+                let requires_id = matches!(
+                    operation.action,
+                    CrudAction::GetOne | CrudAction::Update | CrudAction::Delete
+                );
+
+                let result = if requires_id && operation.id.is_none() {
+                    Err(ApplicationError::bad_request(
+                        &format!("Operation {} requires an id", operation.action),
+                        None,
+                    ))
+                } else {
+                    execute_unified_request(
+                        state,
+                        connection.clone(),
+                        access_key_header_value,
+                        headers,
+                        operation.query_params.unwrap_or_default(),
+                        Action::Unified {
+                            name: model.clone().into(),
+                            action: operation.action.clone(),
+                            id: operation.id.map(Into::into),
+                            passthrough: *passthrough,
+                        },
+                        operation.body,
+                    )
+                    .await
+                };
+
+                match result {
+                    Ok((parts, _, meta, Some(binary))) => {
+                        let content_type = binary
+                            .headers()
+                            .get(CONTENT_TYPE)
+                            .and_then(|value| value.to_str().ok())
+                            .map(ToString::to_string);
+
+                        match binary.bytes().await {
+                            Ok(bytes) => BatchOperationResult {
+                                status: parts.status.as_u16(),
+                                body: json!({
+                                    "data": STANDARD.encode(bytes),
+                                    "contentType": content_type,
+                                }),
+                                meta,
+                            },
+                            Err(e) => BatchOperationResult {
+                                status: StatusCode::BAD_GATEWAY.as_u16(),
+                                body: json!({
+                                    "error": InternalError::io_err(&e.to_string(), None)
+                                }),
+                                meta,
+                            },
+                        }
+                    }
+                    Ok((parts, body, meta, None)) => BatchOperationResult {
+                        status: parts.status.as_u16(),
+                        body,
+                        meta,
+                    },
+                    Err(e) => {
+                        let status: StatusCode = (&e).into();
+
+                        let meta = e.meta().map(|meta| *meta).unwrap_or_else(|| {
+                            failed_operation_metadata(
+                                &connection,
+                                &model,
+                                &operation.action,
+                                started_at,
+                            )
+                        });
+
+                        BatchOperationResult {
+                            status: status.as_u16(),
+                            body: json!({ "error": e }),
+                            meta,
+                        }
+                    }
+                }
+            }
+        })
+        .buffered(state.config.unified_batch_concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await;
+
+    Ok(Json(results))
+}
+
+/// Metadata of a batch operation that failed before reaching the platform, so that every result
+/// of the batch can be attributed to its platform and action
+fn failed_operation_metadata(
+    connection: &Connection,
+    model: &str,
+    action: &CrudAction,
+    started_at: i64,
+) -> Value {
+    UnifiedMetadataBuilder::default()
+        .timestamp(started_at)
+        .platform_rate_limit_remaining(0)
+        .rate_limit_remaining(0)
+        .transaction_key(Id::now(IdPrefix::Transaction))
+        .platform(connection.platform.to_string())
+        .platform_version(connection.platform_version.clone())
+        .action(action.to_string())
+        .common_model(model.to_string())
+        .common_model_version("v1")
+        .connection_key(connection.key.to_string())
+        .latency((Utc::now().timestamp_millis() - started_at) as i32)
+        .build()
+        .map(|metadata| metadata.as_value())
+        .unwrap_or_default()
+}
+
 pub async fn process_request(
     Extension(access): Extension<Arc<EventAccess>>,
     State(state): State<Arc<AppState>>,
@@ -218,7 +428,7 @@ pub async fn process_request(
     query_params: Option<Query<HashMap<String, String>>>,
     action: Action,
     payload: Option<Value>,
-) -> impl IntoResponse {
+) -> Result<Response, PicaError> {
     let Some(connection_key_header) = headers.get(&state.config.headers.connection_header) else {
         return Err(ApplicationError::bad_request(
             "Missing connection key header",
@@ -243,6 +453,97 @@ pub async fn process_request(
 
     remove_event_headers(&mut headers, &state.config.headers);
 
+    // Conditional reads are answered here from the response hash, not forwarded to the platform
+    let if_none_match = headers.remove(IF_NONE_MATCH);
+    let is_read = matches!(
+        action,
+        Action::Unified {
+            action: CrudAction::GetOne | CrudAction::GetMany | CrudAction::GetCount,
+            ..
+        }
+    );
+
+    let (parts, body, metadata, binary) = execute_unified_request(
+        &state,
+        connection,
+        access_key_header_value,
+        headers,
+        query_params,
+        action,
+        payload,
+    )
+    .await?;
+
+    // Files and other non JSON content are streamed back as returned by the platform
+    if let Some(binary) = binary {
+        let mut response = Response::from_parts(parts, ());
+
+        for name in [CONTENT_TYPE, CONTENT_DISPOSITION, CONTENT_LENGTH] {
+            if let Some(value) = binary.headers().get(&name) {
+                response.headers_mut().insert(name, value.clone());
+            }
+        }
+
+        return Ok(response.map(|_| Body::from_stream(binary.bytes_stream())));
+    }
+
+    let mut response = Response::from_parts(parts, ());
+
+    if response.status().is_client_error() || response.status().is_server_error() {
+        let body = json!({
+            META: metadata,
+            "error": body,
+        });
+
+        return Ok((response, Json(body)).into_response());
+    }
+
+    if let Some(etag) = metadata
+        .get("hash")
+        .and_then(Value::as_str)
+        .and_then(|hash| HeaderValue::from_str(&format!("\"{hash}\"")).ok())
+    {
+        let not_modified = is_read
+            && if_none_match
+                .as_ref()
+                .is_some_and(|if_none_match| etag_matches(if_none_match, &etag));
+
+        response.headers_mut().insert(ETAG, etag);
+
+        if not_modified {
+            *response.status_mut() = StatusCode::NOT_MODIFIED;
+            return Ok(response.map(|_| Body::empty()));
+        }
+    }
+
+    Ok((response, Json(body)).into_response())
+}
+
+/// Whether any of the entity tags of an `If-None-Match` header matches the current one, using the
+/// weak comparison required for conditional reads
+fn etag_matches(if_none_match: &HeaderValue, etag: &HeaderValue) -> bool {
+    let (Ok(if_none_match), Ok(etag)) = (if_none_match.to_str(), etag.to_str()) else {
+        return false;
+    };
+
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag.trim_start_matches("W/"))
+}
+
+/// Dispatches a unified action for the connection and emits the corresponding event and
+/// metric. Returns the response parts, the body, the unified metadata and the raw platform
+/// response when it is binary.
+async fn execute_unified_request(
+    state: &AppState,
+    connection: Arc<Connection>,
+    access_key_header_value: Option<HeaderValue>,
+    headers: HeaderMap,
+    query_params: HashMap<String, String>,
+    action: Action,
+    payload: Option<Value>,
+) -> Result<(Parts, Value, Value, Option<reqwest::Response>), PicaError> {
     let Action::Unified {
         name: model_name,
         action: action_name,
@@ -364,18 +665,114 @@ pub async fn process_request(
         error!("Could not send metric to receiver: {e}");
     }
 
-    let response = Response::from_parts(parts, ());
+    Ok((parts, body, metadata, response.binary))
+}
 
-    if response.status().is_client_error() || response.status().is_server_error() {
-        let body = json!({
-            META: metadata,
-            "error": body,
-        });
+/// Streams every record of a `GetMany` action as newline delimited JSON, following the unified
+/// pagination cursor until it is exhausted or the `maxPages`/`maxRecords` caps are reached.
+pub async fn stream_request(
+    Extension(access): Extension<Arc<EventAccess>>,
+    State(state): State<Arc<AppState>>,
+    mut headers: HeaderMap,
+    query_params: Option<Query<HashMap<String, String>>>,
+    action: Action,
+) -> Result<Response, PicaError> {
+    let Some(connection_key_header) = headers.get(&state.config.headers.connection_header) else {
+        return Err(ApplicationError::bad_request(
+            "Missing connection key header",
+            None,
+        ));
+    };
+    let connection = get_connection(
+        access.as_ref(),
+        connection_key_header,
+        &state.app_stores,
+        &state.app_caches.connections_cache,
+    )
+    .await
+    .map_err(|e| {
+        error!("Error getting connection: {:?}", e);
+        e
+    })?;
 
-        Ok((response, Json(body)))
-    } else {
-        Ok((response, Json(body)))
+    let Query(mut query_params) = query_params.unwrap_or_default();
+    query_params.remove(AUTO_PAGINATE_KEY);
+
+    let limits = PaginationLimits {
+        max_pages: parse_limit(query_params.remove(MAX_PAGES_KEY), MAX_PAGES_KEY)?,
+        max_records: parse_limit(query_params.remove(MAX_RECORDS_KEY), MAX_RECORDS_KEY)?,
+    };
+
+    remove_event_headers(&mut headers, &state.config.headers);
+
+    let params = RequestCrudBuilder::default()
+        .headers(headers)
+        .query_params(query_params)
+        .body(None)
+        .build()
+        .map_err(|e| {
+            error!("Error building request crud: {e}");
+            InternalError::invalid_argument(&format!("Error building request crud: {e}"), None)
+        })?;
+
+    let mut records = Box::pin(state.extractor_caller.stream_unified_request(
+        connection.clone(),
+        action.clone(),
+        state.config.environment,
+        params,
+        state.app_caches.connection_model_definition.clone(),
+        limits,
+    ));
+
+    // Failures on the first page are returned as a regular error response, later ones can
+    // only be reported in-band since the status code has already been sent
+    let first = records.try_next().await.inspect_err(|e| {
+        error!("Error executing connection model definition in unified stream: {e}");
+    })?;
+
+    let metric = Metric::unified(connection, action);
+    if let Err(e) = state.metric_tx.send(metric).await {
+        error!("Could not send metric to receiver: {e}");
     }
+
+    let body = stream::iter(first.map(Ok)).chain(records).map(|record| {
+        let line = match record {
+            Ok(record) => record.to_string(),
+            Err(e) => {
+                error!("Error executing connection model definition in unified stream: {e}");
+
+                let status: StatusCode = (&e).into();
+                json!({
+                    "error": {
+                        "code": status.as_u16(),
+                        "message": e.to_string(),
+                    }
+                })
+                .to_string()
+            }
+        };
+
+        Ok::<_, Infallible>(line + "\n")
+    });
+
+    Ok((
+        [(CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+fn parse_limit<T: FromStr>(value: Option<String>, key: &str) -> Result<Option<T>, PicaError> {
+    value
+        .map(|value| {
+            value.parse::<T>().map_err(|_| {
+                ApplicationError::bad_request(
+                    &format!("Invalid value for {key}, expected a positive integer"),
+                    None,
+                )
+            })
+        })
+        .transpose()
 }
 
 fn remove_event_headers(headers: &mut HeaderMap, headers_config: &Headers) {
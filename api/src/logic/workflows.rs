@@ -0,0 +1,116 @@
+use super::{read, PublicExt, RequestExt};
+use crate::{
+    router::ServerResponse,
+    server::{AppState, AppStores},
+};
+use axum::{
+    extract::State,
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use bson::{doc, Document};
+use osentities::{
+    event_access::EventAccess,
+    workflow::{StageDefinition, Workflow},
+    MongoStore, PicaError,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use tracing::error;
+
+pub fn get_router() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/", post(create_workflow))
+        .route("/", get(read::<CreateRequest, Workflow>))
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateRequest {
+    pub name: String,
+    pub stages: Vec<StageDefinition>,
+}
+
+impl RequestExt for CreateRequest {
+    type Output = Workflow;
+
+    fn get_store(stores: AppStores) -> MongoStore<Self::Output> {
+        stores.workflows
+    }
+}
+
+impl PublicExt<Workflow> for CreateRequest {}
+
+/// Creates the workflow along with the tasks of its stages that depend on no other stage. The
+/// tasks are created inactive and only activated once the workflow is saved, so that the
+/// watchdog never runs a task whose workflow doesn't exist. The records created are removed
+/// if a later step fails.
+async fn create_workflow(
+    Extension(access): Extension<Arc<EventAccess>>,
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<CreateRequest>,
+) -> Result<Json<ServerResponse<Value>>, PicaError> {
+    let mut workflow = Workflow::new(payload.name, payload.stages)?;
+    workflow.ownership = Some(access.ownership.clone());
+    workflow.environment = Some(access.environment);
+
+    let mut tasks = workflow.release();
+    for task in tasks.iter_mut() {
+        task.metadata.active = false;
+    }
+
+    let stores = &state.app_stores;
+    let filter = doc! { "workflowId": workflow.id.to_string() };
+
+    if !tasks.is_empty() {
+        stores
+            .tasks
+            .create_many(&tasks)
+            .await
+            .inspect_err(|e| error!("Error creating the tasks of workflow {}: {e}", workflow.id))?;
+    }
+
+    if let Err(e) = stores.workflows.create_one(&workflow).await {
+        error!("Error creating workflow {}: {e}", workflow.id);
+        remove_tasks(stores, filter).await;
+
+        return Err(e);
+    }
+
+    if let Err(e) = stores
+        .tasks
+        .update_many(filter.clone(), doc! { "$set": { "active": true } })
+        .await
+    {
+        error!(
+            "Error activating the tasks of workflow {}: {e}",
+            workflow.id
+        );
+        remove_tasks(stores, filter).await;
+        stores
+            .workflows
+            .collection
+            .delete_one(doc! { "_id": workflow.id.to_string() })
+            .await
+            .inspect_err(|e| error!("Error removing workflow {}: {e}", workflow.id))
+            .ok();
+
+        return Err(e);
+    }
+
+    Ok(Json(ServerResponse::new(
+        "create",
+        CreateRequest::public(workflow),
+    )))
+}
+
+async fn remove_tasks(stores: &AppStores, filter: Document) {
+    stores
+        .tasks
+        .collection
+        .delete_many(filter)
+        .await
+        .inspect_err(|e| error!("Error removing the tasks of a workflow: {e}"))
+        .ok();
+}
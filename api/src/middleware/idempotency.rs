@@ -0,0 +1,349 @@
+use crate::server::AppState;
+use anyhow::{Context, Result};
+use axum::{
+    body::{to_bytes, Body},
+    extract::State,
+    middleware::Next,
+    response::Response,
+    Extension,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use cache::remote::RedisCache;
+use http::{
+    header::CONTENT_LENGTH, HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode,
+};
+use osentities::{
+    api_model_config::RetryPolicy, event_access::EventAccess, ApplicationError, InternalError,
+    PicaError,
+};
+use redis::{aio::ConnectionManager, AsyncCommands};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tracing::{error, warn};
+use unified::unified::is_binary_response;
+
+const KEY_PREFIX: &str = "idempotency";
+const MAX_KEY_LENGTH: usize = 255;
+/// Largest request or response body that is buffered to be fingerprinted or stored
+const MAX_BODY_BYTES: usize = 10 * 1024 * 1024;
+/// Set on responses that were replayed from a previous request with the same key
+const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// Response stored for an idempotency key. The status is empty while the first request with the
+/// key is still being processed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IdempotentResponse {
+    fingerprint: String,
+    #[serde(with = "http_serde_ext_ios::status_code::option", default)]
+    status: Option<StatusCode>,
+    #[serde(with = "http_serde_ext_ios::header_map", default)]
+    headers: HeaderMap,
+    #[serde(default)]
+    body: String,
+    /// Files and responses over `MAX_BODY_BYTES` are streamed back instead of stored, so later
+    /// requests with the key are rejected rather than replayed
+    #[serde(default)]
+    streamed: bool,
+}
+
+#[derive(Clone)]
+pub struct Idempotency {
+    redis: ConnectionManager,
+    key_header_name: HeaderName,
+    connection_header_name: HeaderName,
+    ttl_secs: u64,
+    /// Lifetime of the marker of a request still being processed, so that a key is freed soon
+    /// if the replica handling it dies before storing the response
+    pending_ttl_secs: u64,
+}
+
+impl Idempotency {
+    pub async fn from_state(state: Arc<AppState>) -> Result<Self> {
+        if !state.config.idempotency_enabled {
+            return Err(anyhow::anyhow!("Idempotency keys are disabled"));
+        };
+
+        let redis = RedisCache::new(&state.config.cache_config)
+            .await
+            .context(format!(
+                "Could not connect to redis at {}",
+                state.config.cache_config.url
+            ))?;
+
+        Ok(Idempotency {
+            redis: redis.inner,
+            key_header_name: HeaderName::from_lowercase(
+                state.config.headers.idempotency_key.as_bytes(),
+            )?,
+            connection_header_name: HeaderName::from_lowercase(
+                state.config.headers.connection_header.as_bytes(),
+            )?,
+            ttl_secs: state.config.idempotency_ttl_secs,
+            pending_ttl_secs: state.config.idempotency_pending_ttl_secs,
+        })
+    }
+
+    /// Stores the pending `value` under `key` unless the key is already taken. Returns whether it
+    /// was stored. The marker only lives for `pending_ttl_secs`, `store` keeps the response for
+    /// the full TTL.
+    async fn claim(&self, key: &str, value: &IdempotentResponse) -> Result<bool, PicaError> {
+        let value = serde_json::to_string(value)
+            .map_err(|e| InternalError::serialize_error(&e.to_string(), None))?;
+
+        let claimed: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(value)
+            .arg("NX")
+            .arg("EX")
+            .arg(self.pending_ttl_secs)
+            .query_async(&mut self.redis.clone())
+            .await
+            .map_err(redis_error)?;
+
+        Ok(claimed.is_some())
+    }
+
+    async fn store(&self, key: &str, value: &IdempotentResponse) -> Result<(), PicaError> {
+        let value = serde_json::to_string(value)
+            .map_err(|e| InternalError::serialize_error(&e.to_string(), None))?;
+
+        self.redis
+            .clone()
+            .set_ex::<_, _, ()>(key, value, self.ttl_secs)
+            .await
+            .map_err(redis_error)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<IdempotentResponse>, PicaError> {
+        let value: Option<String> = self.redis.clone().get(key).await.map_err(redis_error)?;
+
+        value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()
+            .map_err(|e| InternalError::deserialize_error(&e.to_string(), None))
+    }
+
+    async fn release(&self, key: &str) {
+        if let Err(e) = self.redis.clone().del::<_, ()>(key).await {
+            error!("Could not release idempotency key {key}: {e}");
+        }
+    }
+}
+
+/// Makes write requests carrying an idempotency key safe to retry. The first response for a key
+/// is stored and replayed for later requests with the same key and payload, without calling the
+/// platform again. Reusing a key with a different payload is rejected, as is reusing the key of a
+/// request whose response was a file streamed back to the caller.
+pub async fn idempotency_middleware(
+    Extension(event_access): Extension<Arc<EventAccess>>,
+    State(state): State<Arc<Idempotency>>,
+    req: Request<Body>,
+    next: Next,
+) -> Result<Response, PicaError> {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return Ok(next.run(req).await);
+    }
+
+    let Some(idempotency_key) = req.headers().get(&state.key_header_name) else {
+        return Ok(next.run(req).await);
+    };
+
+    let idempotency_key = idempotency_key
+        .to_str()
+        .ok()
+        .filter(|key| !key.is_empty() && key.len() <= MAX_KEY_LENGTH)
+        .ok_or_else(|| {
+            ApplicationError::bad_request(
+                &format!(
+                    "Idempotency key must be a non empty string of at most {MAX_KEY_LENGTH} characters"
+                ),
+                None,
+            )
+        })?;
+
+    let connection_key = req
+        .headers()
+        .get(&state.connection_header_name)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    let key = format!(
+        "{KEY_PREFIX}::{}::{connection_key}::{idempotency_key}",
+        event_access.ownership.id
+    );
+
+    let (parts, body) = req.into_parts();
+    let body = to_bytes(body, MAX_BODY_BYTES)
+        .await
+        .map_err(|_| ApplicationError::bad_request("Request body is too large", None))?;
+
+    let fingerprint = STANDARD.encode(
+        Sha256::new()
+            .chain_update(parts.method.as_str())
+            .chain_update(parts.uri.to_string())
+            .chain_update(&body)
+            .finalize(),
+    );
+
+    let pending = IdempotentResponse {
+        fingerprint: fingerprint.clone(),
+        status: None,
+        headers: HeaderMap::new(),
+        body: String::new(),
+        streamed: false,
+    };
+
+    if !state.claim(&key, &pending).await? {
+        return match state.get(&key).await? {
+            Some(stored) if stored.fingerprint != fingerprint => {
+                Err(ApplicationError::unprocessable_entity(
+                    "Idempotency key was already used with a different request",
+                    None,
+                ))
+            }
+            Some(IdempotentResponse { streamed: true, .. }) => Err(ApplicationError::conflict(
+                "Response for this idempotency key was streamed and cannot be replayed",
+                None,
+            )),
+            Some(IdempotentResponse {
+                status: Some(status),
+                headers,
+                body,
+                ..
+            }) => replay(status, headers, &body),
+            Some(_) => Err(ApplicationError::conflict(
+                "A request with this idempotency key is still being processed",
+                None,
+            )),
+            // The key expired between both calls, the caller can safely retry
+            None => Err(ApplicationError::conflict(
+                "Idempotency key expired while processing the request, please retry",
+                None,
+            )),
+        };
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+
+    if is_retryable(response.status()) {
+        state.release(&key).await;
+        return Ok(response);
+    }
+
+    // The request already reached the platform, so the key stays taken even when the response
+    // cannot be kept
+    let streamed = IdempotentResponse {
+        fingerprint: fingerprint.clone(),
+        status: Some(response.status()),
+        headers: HeaderMap::new(),
+        body: String::new(),
+        streamed: true,
+    };
+
+    let too_large = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok())
+        .is_some_and(|length| length > MAX_BODY_BYTES);
+
+    if too_large || is_binary_response(response.headers()) {
+        if let Err(e) = state.store(&key, &streamed).await {
+            error!("Could not store response for idempotency key {key}: {e}");
+        }
+
+        return Ok(response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Could not buffer response for idempotency key {key}: {e}");
+
+            if let Err(e) = state.store(&key, &streamed).await {
+                error!("Could not store response for idempotency key {key}: {e}");
+            }
+
+            return Err(InternalError::io_err(
+                "Could not read the response of the request",
+                None,
+            ));
+        }
+    };
+
+    let stored = IdempotentResponse {
+        fingerprint,
+        status: Some(parts.status),
+        headers: parts.headers.clone(),
+        body: STANDARD.encode(&body),
+        streamed: false,
+    };
+
+    if let Err(e) = state.store(&key, &stored).await {
+        error!("Could not store response for idempotency key {key}: {e}");
+        state.release(&key).await;
+    }
+
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+/// Failures on our side or the platform's, and statuses the caller is expected to retry (timeouts,
+/// rate limits), are not stored so the request can be retried with the same key
+fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error()
+        || RetryPolicy::default()
+            .retryable_status_codes
+            .contains(&status.as_u16())
+}
+
+fn replay(status: StatusCode, headers: HeaderMap, body: &str) -> Result<Response, PicaError> {
+    let body = STANDARD
+        .decode(body)
+        .map_err(|e| InternalError::deserialize_error(&e.to_string(), None))?;
+
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    *response.headers_mut() = headers;
+    response
+        .headers_mut()
+        .insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+
+    Ok(response)
+}
+
+fn redis_error(e: redis::RedisError) -> PicaError {
+    error!("Could not access idempotency keys: {e}");
+    InternalError::io_err(&e.to_string(), Some("redis"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retryable_statuses_release_the_key() {
+        for status in [
+            StatusCode::REQUEST_TIMEOUT,
+            StatusCode::TOO_EARLY,
+            StatusCode::TOO_MANY_REQUESTS,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            StatusCode::NOT_IMPLEMENTED,
+            StatusCode::GATEWAY_TIMEOUT,
+        ] {
+            assert!(is_retryable(status), "{status}");
+        }
+
+        for status in [
+            StatusCode::OK,
+            StatusCode::BAD_REQUEST,
+            StatusCode::NOT_FOUND,
+            StatusCode::CONFLICT,
+            StatusCode::UNPROCESSABLE_ENTITY,
+        ] {
+            assert!(!is_retryable(status), "{status}");
+        }
+    }
+}
@@ -1,6 +1,7 @@
 pub mod header_auth;
 pub mod header_blocker;
 pub mod header_passthrough;
+pub mod idempotency;
 pub mod jwt_auth;
 pub mod rate_limiter;
 
@@ -1,9 +1,9 @@
 use crate::{
     logic::{
-        common_enum, common_model, connection_definition,
+        common_enum, common_model, common_model_revision, connection_definition,
         connection_model_definition::{self},
         connection_model_schema, connection_oauth_definition, event_callback, openapi, platform,
-        platform_page, secrets,
+        platform_page, schema_drift, secrets,
     },
     middleware::jwt_auth::{self, JwtState},
     server::AppState,
@@ -37,9 +37,14 @@ pub async fn get_router(state: &Arc<AppState>) -> Router<Arc<AppState>> {
         )
         .nest("/common-enums", common_enum::get_router())
         .nest("/common-models", common_model::get_router())
+        .nest(
+            "/common-model-revisions",
+            common_model_revision::get_router(),
+        )
         .nest("/event-callbacks", event_callback::get_router())
         .nest("/platform-pages", platform_page::get_router())
         .nest("/platforms", platform::get_router())
+        .nest("/schema-drifts", schema_drift::get_router())
         .route("/admin/connection/:id", get(secrets::get_admin_secret))
         .route("/openapi", post(openapi::refresh_openapi));
 
@@ -6,12 +6,13 @@ use crate::{
             public_get_connection_model_schema, PublicGetConnectionModelSchema,
         },
         event_access, events, knowledge, metrics, oauth, passthrough, secrets, tasks, unified,
-        vault_connection,
+        vault_connection, workflows,
     },
     middleware::{
         header_auth,
         header_blocker::{handle_blocked_error, BlockInvalidHeaders},
         header_passthrough,
+        idempotency::{idempotency_middleware, Idempotency},
         rate_limiter::{rate_limit_middleware, RateLimiter},
     },
     server::AppState,
@@ -32,6 +33,24 @@ use tower_http::{sensitive_headers::SetSensitiveRequestHeadersLayer, trace::Trac
 use tracing::warn;
 
 pub async fn get_router(state: &Arc<AppState>) -> Router<Arc<AppState>> {
+    let (passthrough_routes, unified_routes) = match Idempotency::from_state(state.clone()).await {
+        Ok(idempotency) => {
+            let idempotency = Arc::new(idempotency);
+            (
+                passthrough::get_router().layer(from_fn_with_state(
+                    idempotency.clone(),
+                    idempotency_middleware,
+                )),
+                unified::get_router()
+                    .layer(from_fn_with_state(idempotency, idempotency_middleware)),
+            )
+        }
+        Err(e) => {
+            warn!("Could not enable idempotency keys: {e}");
+            (passthrough::get_router(), unified::get_router())
+        }
+    };
+
     let routes = Router::new()
         .layer(TraceLayer::new_for_http())
         .nest("/connections", connection::get_router())
@@ -41,10 +60,11 @@ pub async fn get_router(state: &Arc<AppState>) -> Router<Arc<AppState>> {
         .nest("/tasks", tasks::get_router())
         .nest("/metrics", metrics::get_router())
         .nest("/oauth", oauth::get_router())
-        .nest("/passthrough", passthrough::get_router())
+        .nest("/passthrough", passthrough_routes)
         .nest("/secrets", secrets::get_router())
-        .nest("/unified", unified::get_router())
+        .nest("/unified", unified_routes)
         .nest("/vault/connections", vault_connection::get_router())
+        .nest("/workflows", workflows::get_router())
         .route(
             "/connection-model-definitions/test/:id",
             post(test_connection_model_definition),
@@ -12,24 +12,32 @@ use crate::{
 };
 use anyhow::{anyhow, Context, Result};
 use axum::Router;
-use cache::local::{
-    ConnectionDefinitionCache, ConnectionHeaderCache, ConnectionModelDefinitionCacheIdKey,
-    ConnectionModelDefinitionCacheStringKey, ConnectionOAuthDefinitionCache, EventAccessCache,
+use cache::{
+    local::{
+        ConnectionDefinitionCache, ConnectionHeaderCache, ConnectionModelDefinitionCacheIdKey,
+        ConnectionModelDefinitionCacheStringKey, ConnectionOAuthDefinitionCache, EventAccessCache,
+    },
+    rate_limit::OutboundRateLimiter,
+    remote::RedisCache,
+    response::ResponseCache,
 };
 use mongodb::{options::UpdateOptions, Client, Database};
 use osentities::{
-    algebra::{DefaultTemplate, MongoStore},
+    algebra::{DefaultTemplate, JsSandbox, MongoStore, SandboxConfig},
     common_model::{CommonEnum, CommonModel},
+    common_model_revision::CommonModelRevision,
     connection_definition::{ConnectionDefinition, PublicConnectionDetails},
     connection_model_definition::ConnectionModelDefinition,
     connection_model_schema::{ConnectionModelSchema, PublicConnectionModelSchema},
     connection_oauth_definition::{ConnectionOAuthDefinition, Settings},
     event_access::EventAccess,
     page::PlatformPage,
+    schema_drift::SchemaDrift,
     secret::Secret,
     secrets::SecretServiceProvider,
     task::Task,
     user::UserClient,
+    workflow::Workflow,
     Connection, Event, GoogleKms, IOSKms, PlatformData, PublicConnection, SecretExt, Store,
 };
 use std::{sync::Arc, time::Duration};
@@ -42,6 +50,7 @@ pub struct AppStores {
     pub clients: MongoStore<UserClient>,
     pub common_enum: MongoStore<CommonEnum>,
     pub common_model: MongoStore<CommonModel>,
+    pub common_model_revision: MongoStore<CommonModelRevision>,
     pub connection: MongoStore<Connection>,
     pub connection_config: MongoStore<ConnectionDefinition>,
     pub db: Database,
@@ -56,10 +65,12 @@ pub struct AppStores {
     pub public_connection: MongoStore<PublicConnection>,
     pub public_connection_details: MongoStore<PublicConnectionDetails>,
     pub public_model_schema: MongoStore<PublicConnectionModelSchema>,
+    pub schema_drift: MongoStore<SchemaDrift>,
     pub knowledge: MongoStore<Knowledge>,
     pub secrets: MongoStore<Secret>,
     pub settings: MongoStore<Settings>,
     pub tasks: MongoStore<Task>,
+    pub workflows: MongoStore<Workflow>,
 }
 
 #[derive(Clone)]
@@ -95,6 +106,16 @@ pub struct Server {
 
 impl Server {
     pub async fn init(config: ConnectionsConfig) -> Result<Self> {
+        // Mapping and oauth scripts run in a bounded worker pool shared by the whole process
+        if let Err(e) = JsSandbox::init(SandboxConfig {
+            workers: config.js_sandbox_workers,
+            timeout: Duration::from_millis(config.js_sandbox_timeout_ms),
+            max_heap_bytes: config.js_sandbox_max_heap_mb * 1024 * 1024,
+            queue_size: config.js_sandbox_queue_size,
+        }) {
+            warn!("Using the running script sandbox: {e}");
+        }
+
         let client = Client::with_uri_str(&config.db_config.event_db_url).await?;
         let db = client.database(&config.db_config.event_db_name);
 
@@ -111,6 +132,7 @@ impl Server {
         let public_model_schema =
             MongoStore::new(&db, &Store::PublicConnectionModelSchemas).await?;
         let common_model = MongoStore::new(&db, &Store::CommonModels).await?;
+        let common_model_revision = MongoStore::new(&db, &Store::CommonModelRevisions).await?;
         let common_enum = MongoStore::new(&db, &Store::CommonEnums).await?;
         let secrets = MongoStore::new(&db, &Store::Secrets).await?;
         let connection = MongoStore::new(&db, &Store::Connections).await?;
@@ -127,6 +149,8 @@ impl Server {
         let clients = MongoStore::new(&db, &Store::Clients).await?;
         let secrets_store = MongoStore::<Secret>::new(&db, &Store::Secrets).await?;
         let tasks = MongoStore::new(&db, &Store::Tasks).await?;
+        let workflows = MongoStore::new(&db, &Store::Workflows).await?;
+        let schema_drift = MongoStore::new(&db, &Store::SchemaDrifts).await?;
 
         let secrets_client: Arc<dyn SecretExt + Sync + Send> = match config.secrets_config.provider
         {
@@ -164,6 +188,48 @@ impl Server {
         .await
         .with_context(|| "Could not initialize extractor caller")?;
 
+        let extractor_caller = if config.shared_outbound_rate_limit {
+            let redis = RedisCache::new(&config.cache_config)
+                .await
+                .with_context(|| "Could not connect to redis for outbound rate limiting")?;
+
+            extractor_caller.with_rate_limiter(OutboundRateLimiter::remote(redis))
+        } else {
+            extractor_caller
+        };
+
+        let extractor_caller = if config.shared_response_cache {
+            let redis = RedisCache::new(&config.cache_config)
+                .await
+                .with_context(|| "Could not connect to redis for unified response caching")?;
+
+            extractor_caller.with_response_cache(ResponseCache::remote(redis))
+        } else {
+            extractor_caller
+        };
+
+        // Refresh oauth connections about to expire in separate thread
+        if config.oauth_refresh_enabled {
+            let refresher = extractor_caller.clone();
+            let interval_secs = config.oauth_refresh_interval_secs;
+            let window_secs = config.oauth_refresh_window_secs;
+            let batch_size = config.oauth_refresh_batch_size;
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+                loop {
+                    interval.tick().await;
+                    match refresher
+                        .refresh_expiring_oauth_connections(window_secs, batch_size)
+                        .await
+                    {
+                        Ok(0) => trace!("No oauth connections to refresh"),
+                        Ok(count) => info!("Refreshed {count} oauth connections"),
+                        Err(e) => error!("Could not refresh oauth connections: {e}"),
+                    }
+                }
+            });
+        }
+
         let app_stores = AppStores {
             db: db.clone(),
             model_config,
@@ -173,9 +239,11 @@ impl Server {
             secrets,
             model_schema,
             public_model_schema,
+            schema_drift,
             platform,
             settings,
             common_model,
+            common_model_revision,
             common_enum,
             connection,
             public_connection,
@@ -186,6 +254,7 @@ impl Server {
             event,
             clients,
             tasks,
+            workflows,
         };
 
         let event_access_cache =
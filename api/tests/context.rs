@@ -516,6 +516,10 @@ impl TestServer {
             },
             responses: vec![],
             paths: None,
+            retry_policy: None,
+            rate_limit: None,
+            response_cache: None,
+            validation: None,
             is_default_crud_mapping: None,
             test_connection_payload: None,
             test_connection_status: None,
@@ -526,6 +530,11 @@ impl TestServer {
                     "function mapCrudRequest(data) { return data; }".to_string(),
                 ),
                 to_common_model: Some("function mapCrudRequest(data) { return data; }".to_string()),
+                from_common_model_schema: None,
+                to_common_model_schema: None,
+                language: None,
+                compiled_from_common_model: None,
+                compiled_to_common_model: None,
             }),
             supported: Some(true),
             active: Some(true),
@@ -2,16 +2,21 @@ use crate::context::TestServer;
 use api::logic::{common_model, ReadResponse};
 use api::logic::{connection_definition, connection_model_definition, connection_model_schema};
 use fake::{Fake, Faker};
-use http::{Method, StatusCode};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, StatusCode};
 use osentities::{
-    common_model::CommonModel, connection_definition::ConnectionDefinition,
-    connection_model_definition::ConnectionModelDefinition,
-    connection_model_schema::ConnectionModelSchema,
+    api_model_config::{Lang, ResponseBody, SamplesInput},
+    common_model::CommonModel,
+    connection_definition::ConnectionDefinition,
+    connection_model_definition::{ConnectionModelDefinition, CrudAction, CrudMapping},
+    connection_model_schema::{ConnectionModelSchema, Mappings},
+    id::{prefix::IdPrefix, Id},
 };
 use osentities::{
     common_model::{DataType, Expandable, Field},
+    common_model_revision::CommonModelRevision,
     json_schema::JsonSchema,
 };
+use semver::Version;
 use serde_json::{json, Value};
 use std::{collections::HashMap, ops::Deref};
 
@@ -327,3 +332,452 @@ async fn test_common_model_crud() {
 
     assert!(get_models.rows.is_empty());
 }
+
+#[tokio::test]
+async fn test_connection_model_definition_transpiles_typescript_mapping() {
+    let server = TestServer::new(None).await;
+
+    let mapping = |from_common_model: &str| CrudMapping {
+        action: CrudAction::GetMany,
+        common_model_name: Faker.fake(),
+        from_common_model: Some(from_common_model.to_string()),
+        to_common_model: None,
+        from_common_model_schema: None,
+        to_common_model_schema: None,
+        language: Some(Lang::TypeScript),
+        compiled_from_common_model: None,
+        compiled_to_common_model: None,
+    };
+
+    let mut payload: connection_model_definition::CreateRequest = Faker.fake();
+    payload.mapping = Some(mapping("function mapCrudRequest(data: { return data; }"));
+
+    let res = server
+        .send_request::<Value, Value>(
+            "v1/connection-model-definitions",
+            Method::POST,
+            Some(&server.live_key),
+            Some(&serde_json::to_value(&payload).unwrap()),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.code, StatusCode::BAD_REQUEST);
+
+    payload.mapping = Some(mapping(
+        "function mapCrudRequest(data: Record<string, unknown>): unknown { return data; }",
+    ));
+
+    let res = server
+        .send_request::<Value, Value>(
+            "v1/connection-model-definitions",
+            Method::POST,
+            Some(&server.live_key),
+            Some(&serde_json::to_value(&payload).unwrap()),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.code, StatusCode::OK);
+
+    let model: ConnectionModelDefinition = serde_json::from_value(res.data).unwrap();
+    let compiled = model
+        .mapping
+        .and_then(|m| m.compiled_from_common_model)
+        .expect("Failed to compile mapping");
+
+    assert!(compiled.contains("function mapCrudRequest(data)"));
+}
+
+#[tokio::test]
+async fn test_connection_model_definition_mapping_test() {
+    let server = TestServer::new(None).await;
+
+    let mut payload: connection_model_definition::CreateRequest = Faker.fake();
+    payload.action_name = CrudAction::GetMany;
+    payload.paths = None;
+    payload.mapping = Some(CrudMapping {
+        action: CrudAction::GetMany,
+        common_model_name: Faker.fake(),
+        from_common_model: Some(
+            "function mapCrudRequest(data) {
+                data.queryParams = { per_page: data.queryParams.limit };
+                return data;
+            }"
+            .to_string(),
+        ),
+        to_common_model: Some(
+            "function mapCrudRequest(data) {
+                return { pagination: { nextCursor: data.request.queryParams.per_page } };
+            }"
+            .to_string(),
+        ),
+        from_common_model_schema: None,
+        to_common_model_schema: None,
+        language: None,
+        compiled_from_common_model: None,
+        compiled_to_common_model: None,
+    });
+
+    let res = server
+        .send_request::<Value, Value>(
+            "v1/connection-model-definitions",
+            Method::POST,
+            Some(&server.live_key),
+            Some(&serde_json::to_value(&payload).unwrap()),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.code, StatusCode::OK);
+
+    let model: ConnectionModelDefinition = serde_json::from_value(res.data).unwrap();
+
+    let res = server
+        .send_request::<Value, Value>(
+            &format!("v1/connection-model-definitions/{}/mapping/test", model.id),
+            Method::POST,
+            Some(&server.live_key),
+            Some(&json!({
+                "request": { "queryParams": { "limit": "10" } },
+                "response": [{ "id": "1" }]
+            })),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.code, StatusCode::OK);
+    assert_eq!(
+        res.data["request"]["queryParams"],
+        json!({ "per_page": "10" })
+    );
+    assert_eq!(
+        res.data["response"]["pagination"],
+        json!({ "nextCursor": "10" })
+    );
+}
+
+#[tokio::test]
+async fn test_connection_model_definition_mapping_test_uses_fixtures() {
+    let server = TestServer::new(None).await;
+
+    let response = |status_code: u16, total: &'static str| ResponseBody {
+        status_code,
+        headers: Some(HeaderMap::from_iter([(
+            HeaderName::from_static("x-total-count"),
+            HeaderValue::from_static(total),
+        )])),
+        body: None,
+    };
+
+    let mut payload: connection_model_definition::CreateRequest = Faker.fake();
+    payload.action_name = CrudAction::GetOne;
+    payload.paths = None;
+    payload.samples = SamplesInput {
+        headers: None,
+        query_params: Some(json!({ "limit": "10" })),
+        path_params: None,
+        body: None,
+    };
+    payload.responses = vec![response(400, "0"), response(200, "42")];
+    payload.mapping = Some(CrudMapping {
+        action: CrudAction::GetOne,
+        common_model_name: Faker.fake(),
+        from_common_model: Some(
+            "function mapCrudRequest(data) {
+                data.queryParams = { per_page: data.queryParams.limit };
+                return data;
+            }"
+            .to_string(),
+        ),
+        to_common_model: Some(
+            "function mapCrudRequest(data) {
+                return {
+                    pagination: {
+                        limit: data.request.queryParams.per_page,
+                        total: data.headers['x-total-count']
+                    }
+                };
+            }"
+            .to_string(),
+        ),
+        from_common_model_schema: None,
+        to_common_model_schema: None,
+        language: None,
+        compiled_from_common_model: None,
+        compiled_to_common_model: None,
+    });
+
+    let res = server
+        .send_request::<Value, Value>(
+            "v1/connection-model-definitions",
+            Method::POST,
+            Some(&server.live_key),
+            Some(&serde_json::to_value(&payload).unwrap()),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.code, StatusCode::OK);
+
+    let model: ConnectionModelDefinition = serde_json::from_value(res.data).unwrap();
+
+    // Without a request nor a response, the samples and the first successful response are used
+    let res = server
+        .send_request::<Value, Value>(
+            &format!("v1/connection-model-definitions/{}/mapping/test", model.id),
+            Method::POST,
+            Some(&server.live_key),
+            Some(&json!({})),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.code, StatusCode::OK);
+    assert_eq!(
+        res.data["request"]["queryParams"],
+        json!({ "per_page": "10" })
+    );
+    assert_eq!(
+        res.data["response"]["pagination"],
+        json!({ "limit": "10", "total": "42" })
+    );
+}
+
+#[tokio::test]
+async fn test_connection_model_schema_requires_a_mapping_script_or_schema() {
+    let server = TestServer::new(None).await;
+
+    let mut payload: connection_model_schema::CreateRequest = Faker.fake();
+    payload.mapping = Some(Mappings {
+        from_common_model: "function mapFromCommonModel(data) { return data; }".to_string(),
+        to_common_model: String::new(),
+        common_model_name: Faker.fake(),
+        common_model_id: Id::now(IdPrefix::CommonModel),
+        unmapped_fields: Default::default(),
+        from_common_model_schema: None,
+        to_common_model_schema: None,
+    });
+
+    let res = server
+        .send_request::<Value, Value>(
+            "v1/connection-model-schemas",
+            Method::POST,
+            Some(&server.live_key),
+            Some(&serde_json::to_value(&payload).unwrap()),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.code, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_script_cache_stats() {
+    let server = TestServer::new(None).await;
+
+    let mut payload: connection_model_definition::CreateRequest = Faker.fake();
+    payload.action_name = CrudAction::GetMany;
+    payload.paths = None;
+    payload.mapping = Some(CrudMapping {
+        action: CrudAction::GetMany,
+        common_model_name: Faker.fake(),
+        from_common_model: Some("function mapCrudRequest(data) { return data; }".to_string()),
+        to_common_model: None,
+        from_common_model_schema: None,
+        to_common_model_schema: None,
+        language: None,
+        compiled_from_common_model: None,
+        compiled_to_common_model: None,
+    });
+
+    let res = server
+        .send_request::<Value, Value>(
+            "v1/connection-model-definitions",
+            Method::POST,
+            Some(&server.live_key),
+            Some(&serde_json::to_value(&payload).unwrap()),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.code, StatusCode::OK);
+
+    let model: ConnectionModelDefinition = serde_json::from_value(res.data).unwrap();
+
+    let stats = || async {
+        let res = server
+            .send_request::<Value, Value>(
+                "v1/connection-model-definitions/scripts/stats",
+                Method::GET,
+                Some(&server.live_key),
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(res.code, StatusCode::OK);
+
+        let counter = |name: &str| res.data[name].as_u64().unwrap();
+        (counter("hits"), counter("misses"))
+    };
+    let test_mapping = || async {
+        let res = server
+            .send_request::<Value, Value>(
+                &format!("v1/connection-model-definitions/{}/mapping/test", model.id),
+                Method::POST,
+                Some(&server.live_key),
+                Some(&json!({ "request": { "queryParams": { "limit": "10" } } })),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(res.code, StatusCode::OK);
+    };
+
+    let (hits, misses) = stats().await;
+
+    // The first run registers the script, the second one reuses it
+    test_mapping().await;
+    let (_, after_first) = stats().await;
+    assert!(after_first > misses);
+
+    test_mapping().await;
+    let (after_second, _) = stats().await;
+    assert!(after_second > hits);
+}
+
+#[tokio::test]
+async fn test_common_model_breaking_update_lists_dependent_mappings() {
+    let server = TestServer::new(None).await;
+
+    let field = |name: &str| Field {
+        name: name.to_string(),
+        datatype: DataType::String,
+        required: false,
+        description: None,
+    };
+
+    let mut payload = common_model::CreateRequest {
+        id: None,
+        name: "Ticket".to_string(),
+        version: Version::new(1, 0, 0),
+        fields: vec![field("id"), field("title")],
+        category: Faker.fake(),
+        sample: json!({}),
+        primary: true,
+    };
+
+    let res = server
+        .send_request::<Value, Value>(
+            "v1/common-models",
+            Method::POST,
+            Some(&server.live_key),
+            Some(&serde_json::to_value(&payload).unwrap()),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::OK);
+
+    let model: CommonModel = serde_json::from_value(res.data).unwrap();
+    let path = format!("v1/common-models/{}", model.id);
+
+    let mut definition: connection_model_definition::CreateRequest = Faker.fake();
+    definition.mapping = Some(CrudMapping {
+        action: CrudAction::GetOne,
+        common_model_name: "Ticket".to_string(),
+        from_common_model: None,
+        to_common_model: Some(
+            "function mapToCommonModel(obj) { return { id: obj.id, title: obj.subject }; }"
+                .to_string(),
+        ),
+        from_common_model_schema: None,
+        to_common_model_schema: None,
+        language: None,
+        compiled_from_common_model: None,
+        compiled_to_common_model: None,
+    });
+
+    let res = server
+        .send_request::<Value, Value>(
+            "v1/connection-model-definitions",
+            Method::POST,
+            Some(&server.live_key),
+            Some(&serde_json::to_value(&definition).unwrap()),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::OK);
+
+    let definition: ConnectionModelDefinition = serde_json::from_value(res.data).unwrap();
+
+    payload.fields.push(field("status"));
+
+    let res = server
+        .send_request::<Value, Value>(
+            &format!("{path}/changes"),
+            Method::POST,
+            Some(&server.live_key),
+            Some(&serde_json::to_value(&payload).unwrap()),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::OK);
+    assert_eq!(res.data["level"], "minor");
+    assert_eq!(res.data["version"], "1.1.0");
+
+    payload.fields.retain(|field| field.name != "title");
+
+    let res = server
+        .send_request::<Value, Value>(
+            &path,
+            Method::PATCH,
+            Some(&server.live_key),
+            Some(&serde_json::to_value(&payload).unwrap()),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::CONFLICT);
+    assert_eq!(res.data["meta"]["level"], "major");
+    assert_eq!(
+        res.data["meta"]["connectionModelDefinitions"],
+        json!([{
+            "id": definition.id,
+            "connectionPlatform": definition.connection_platform,
+            "fields": ["title"]
+        }])
+    );
+
+    let res = server
+        .send_request::<Value, Value>(
+            &format!("{path}?force=true"),
+            Method::PATCH,
+            Some(&server.live_key),
+            Some(&serde_json::to_value(&payload).unwrap()),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::OK);
+    assert_eq!(res.data["success"], true);
+    assert_eq!(res.data["version"], "2.0.0");
+
+    let res = server
+        .send_request::<Value, Value>(
+            &format!("v1/common-model-revisions?commonModelId={}", model.id),
+            Method::GET,
+            Some(&server.live_key),
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::OK);
+
+    let revisions: ReadResponse<CommonModelRevision> = serde_json::from_value(res.data).unwrap();
+    let mut versions = revisions
+        .rows
+        .iter()
+        .map(|revision| revision.version.to_string())
+        .collect::<Vec<_>>();
+    versions.sort();
+    assert_eq!(versions, vec!["1.0.0", "2.0.0"]);
+}
@@ -2,7 +2,10 @@ pub mod auth;
 pub mod callback;
 pub mod connection;
 pub mod crud;
+pub mod oauth;
 pub mod pagination;
 pub mod passthrough;
 pub mod schema;
+pub mod tasks;
 pub mod unified;
+pub mod workflows;
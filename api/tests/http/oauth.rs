@@ -0,0 +1,409 @@
+use crate::context::TestServer;
+use api::logic::connection_model_definition::CreateRequest as CreateConnectionModelDefinitionRequest;
+use chrono::Utc;
+use fake::{faker::filesystem::raw::DirPath, locales::EN, Fake, Faker};
+use futures::future::join_all;
+use http::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    Method, StatusCode,
+};
+use mockito::Mock;
+use mongodb::{bson::doc, Client};
+use osentities::{
+    algebra::MongoStore,
+    api_model_config::{
+        ApiModelConfig, AuthMethod, Compute, ContentType, Function, Lang, SamplesInput,
+        SchemasInput,
+    },
+    connection_model_definition::{ConnectionModelDefinition, CrudAction},
+    connection_oauth_definition::ConnectionOAuthDefinition,
+    environment::Environment,
+    oauth_secret::OAuthSecret,
+    Connection, IOSKms, OAuth, SanitizedConnection, SecretExt, Store,
+};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+const STALE_TOKEN: &str = "stale-token";
+const FRESH_TOKEN: &str = "fresh-token";
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_unauthorized_request_is_replayed_after_refresh() {
+    let mut server = TestServer::new(None).await;
+    let connection = create_oauth_connection(&mut server).await;
+    let path = create_oauth_model_definition(&mut server, &connection).await;
+
+    let refresh = mock_refresh(&mut server, 200, 1).await;
+    let rejected = mock_platform(&mut server, &path, STALE_TOKEN, 401, 1).await;
+    let accepted = mock_platform(&mut server, &path, FRESH_TOKEN, 200, 1).await;
+
+    let res = send_passthrough(&server, &connection, &path).await;
+
+    assert_eq!(res.0, StatusCode::OK);
+    assert_eq!(res.1, json!({ "token": FRESH_TOKEN }));
+
+    refresh.assert_async().await;
+    rejected.assert_async().await;
+    accepted.assert_async().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_concurrent_unauthorized_requests_share_one_refresh() {
+    let mut server = TestServer::new(None).await;
+    let connection = create_oauth_connection(&mut server).await;
+    let path = create_oauth_model_definition(&mut server, &connection).await;
+
+    let refresh = mock_refresh(&mut server, 200, 1).await;
+    let rejected = server
+        .mock_server
+        .mock("GET", format!("/{path}").as_str())
+        .match_header(
+            AUTHORIZATION.as_str(),
+            format!("Bearer {STALE_TOKEN}").as_str(),
+        )
+        .expect_at_least(1)
+        .with_status(401)
+        .with_body(json!({ "token": STALE_TOKEN }).to_string())
+        .create_async()
+        .await;
+    let accepted = mock_platform(&mut server, &path, FRESH_TOKEN, 200, 5).await;
+
+    let responses = join_all((0..5).map(|_| send_passthrough(&server, &connection, &path))).await;
+
+    for (status, body) in responses {
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "token": FRESH_TOKEN }));
+    }
+
+    refresh.assert_async().await;
+    rejected.assert_async().await;
+    accepted.assert_async().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_failed_refresh_returns_original_unauthorized() {
+    let mut server = TestServer::new(None).await;
+    let connection = create_oauth_connection(&mut server).await;
+    let path = create_oauth_model_definition(&mut server, &connection).await;
+
+    let refresh = mock_refresh(&mut server, 400, 1).await;
+    let rejected = mock_platform(&mut server, &path, STALE_TOKEN, 401, 1).await;
+    let accepted = mock_platform(&mut server, &path, FRESH_TOKEN, 200, 0).await;
+
+    let res = send_passthrough(&server, &connection, &path).await;
+
+    assert_eq!(res.0, StatusCode::UNAUTHORIZED);
+
+    refresh.assert_async().await;
+    rejected.assert_async().await;
+    accepted.assert_async().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_unauthorized_request_waits_on_refresh_claimed_by_another_replica() {
+    let mut server = TestServer::new(None).await;
+    let connection = create_oauth_connection(&mut server).await;
+    let path = create_oauth_model_definition(&mut server, &connection).await;
+
+    let refresh = mock_refresh(&mut server, 200, 0).await;
+    let rejected = mock_platform(&mut server, &path, STALE_TOKEN, 401, 1).await;
+    let accepted = mock_platform(&mut server, &path, FRESH_TOKEN, 200, 1).await;
+
+    let db = Client::with_uri_str(&server.config.db_config.event_db_url)
+        .await
+        .unwrap()
+        .database(&server.config.db_config.event_db_name);
+    let connections = MongoStore::<Connection>::new(&db, &Store::Connections)
+        .await
+        .unwrap();
+
+    connections
+        .update_one(
+            &connection.id.to_string(),
+            doc! { "$set": { "oauthRefresh.claimedUntil": Utc::now().timestamp() + 120 } },
+        )
+        .await
+        .unwrap();
+
+    // The other replica stores the refreshed secret while the request waits on its claim
+    let secrets = IOSKms::new(
+        &server.config.secrets_config,
+        MongoStore::new(&db, &Store::Secrets).await.unwrap(),
+    )
+    .await
+    .unwrap();
+    let ownership_id = connection.ownership.id.clone();
+    let id = connection.id.to_string();
+    let other_replica = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        let secret = OAuthSecret {
+            client_id: Faker.fake(),
+            client_secret: Faker.fake(),
+            access_token: FRESH_TOKEN.to_string(),
+            token_type: Some("Bearer".to_string()),
+            refresh_token: Some("refresh-token".to_string()),
+            expires_in: 3600,
+            metadata: Value::Null,
+            request_payload: None,
+        };
+        let secret = secrets
+            .create(&secret.as_json(), &ownership_id)
+            .await
+            .unwrap();
+
+        connections
+            .update_one(
+                &id,
+                doc! {
+                    "$set": { "secretsServiceId": secret.id() },
+                    "$unset": { "oauthRefresh": "" },
+                },
+            )
+            .await
+            .unwrap();
+    });
+
+    let res = send_passthrough(&server, &connection, &path).await;
+    other_replica.await.unwrap();
+
+    assert_eq!(res.0, StatusCode::OK);
+    assert_eq!(res.1, json!({ "token": FRESH_TOKEN }));
+
+    refresh.assert_async().await;
+    rejected.assert_async().await;
+    accepted.assert_async().await;
+}
+
+/// Turns a freshly created connection into an OAuth one holding `STALE_TOKEN`, refreshed through
+/// the `/oauth/token` endpoint of the mock server
+async fn create_oauth_connection(server: &mut TestServer) -> SanitizedConnection {
+    let (connection, _) = server.create_connection(Environment::Live).await;
+
+    let db = Client::with_uri_str(&server.config.db_config.event_db_url)
+        .await
+        .unwrap()
+        .database(&server.config.db_config.event_db_name);
+
+    let mut definition: ConnectionOAuthDefinition = Faker.fake();
+    definition.connection_platform = connection.platform.to_string();
+    definition.configuration.refresh = ApiModelConfig {
+        base_url: server.mock_server.url(),
+        path: "oauth/token".to_string(),
+        auth_method: AuthMethod::None,
+        headers: None,
+        content: Some(ContentType::Json),
+        query_params: None,
+        schemas: SchemasInput {
+            headers: None,
+            query_params: None,
+            path_params: None,
+            body: None,
+        },
+        samples: SamplesInput {
+            headers: None,
+            query_params: None,
+            path_params: None,
+            body: None,
+        },
+        responses: vec![],
+        paths: None,
+        retry_policy: None,
+        rate_limit: None,
+        response_cache: None,
+        validation: None,
+    };
+    definition.compute.refresh.computation = None;
+    definition.compute.refresh.response = Function(Compute {
+        entry: "compute".to_string(),
+        function: "function compute(payload) {
+            return {
+                accessToken: payload.access_token,
+                expiresIn: payload.expires_in,
+                refreshToken: 'refresh-token',
+                tokenType: 'Bearer'
+            };
+        }"
+        .to_string(),
+        language: Lang::JavaScript,
+        compiled: None,
+    });
+
+    MongoStore::<ConnectionOAuthDefinition>::new(&db, &Store::ConnectionOAuthDefinitions)
+        .await
+        .unwrap()
+        .create_one(&definition)
+        .await
+        .unwrap();
+
+    let secrets = IOSKms::new(
+        &server.config.secrets_config,
+        MongoStore::new(&db, &Store::Secrets).await.unwrap(),
+    )
+    .await
+    .unwrap();
+    let secret = OAuthSecret {
+        client_id: Faker.fake(),
+        client_secret: Faker.fake(),
+        access_token: STALE_TOKEN.to_string(),
+        token_type: Some("Bearer".to_string()),
+        refresh_token: Some("refresh-token".to_string()),
+        expires_in: 3600,
+        metadata: Value::Null,
+        request_payload: None,
+    };
+    let secret = secrets
+        .create(&secret.as_json(), &connection.ownership.id)
+        .await
+        .unwrap();
+
+    let oauth = OAuth::Enabled {
+        connection_oauth_definition_id: definition.id,
+        expires_in: Some(3600),
+        expires_at: None,
+    };
+
+    MongoStore::<Connection>::new(&db, &Store::Connections)
+        .await
+        .unwrap()
+        .update_one(
+            &connection.id.to_string(),
+            doc! {
+                "$set": {
+                    "secretsServiceId": secret.id(),
+                    "oauth": mongodb::bson::to_bson(&oauth).unwrap(),
+                }
+            },
+        )
+        .await
+        .unwrap();
+
+    connection
+}
+
+/// Registers a passthrough definition authenticated with the OAuth secret of the connection and
+/// returns its path
+async fn create_oauth_model_definition(
+    server: &mut TestServer,
+    connection: &SanitizedConnection,
+) -> String {
+    let path: String = DirPath(EN).fake();
+    let path = path.trim_start_matches('/').to_string();
+
+    let payload = CreateConnectionModelDefinitionRequest {
+        id: None,
+        connection_platform: connection.platform.to_string(),
+        connection_definition_id: connection.connection_definition_id,
+        platform_version: connection.platform_version.clone(),
+        title: Faker.fake(),
+        name: Faker.fake(),
+        model_name: Faker.fake(),
+        action_name: CrudAction::GetMany,
+        base_url: server.mock_server.url(),
+        path: path.clone(),
+        auth_method: AuthMethod::OAuth,
+        http_method: http::Method::GET,
+        headers: None,
+        query_params: None,
+        extractor_config: None,
+        version: "1.0.0".parse().unwrap(),
+        schemas: SchemasInput {
+            headers: None,
+            query_params: None,
+            path_params: None,
+            body: None,
+        },
+        samples: SamplesInput {
+            headers: None,
+            query_params: None,
+            path_params: None,
+            body: None,
+        },
+        paths: None,
+        retry_policy: None,
+        rate_limit: None,
+        response_cache: None,
+        validation: None,
+        responses: vec![],
+        is_default_crud_mapping: None,
+        test_connection_payload: None,
+        test_connection_status: None,
+        mapping: None,
+        supported: Some(true),
+        active: Some(true),
+        knowledge: None,
+        tags: None,
+    };
+
+    let res = server
+        .send_request::<CreateConnectionModelDefinitionRequest, ConnectionModelDefinition>(
+            "v1/connection-model-definitions",
+            Method::POST,
+            Some(&server.live_key),
+            Some(&payload),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.code, StatusCode::OK);
+
+    path
+}
+
+async fn mock_refresh(server: &mut TestServer, status: usize, hits: usize) -> Mock {
+    server
+        .mock_server
+        .mock("POST", "/oauth/token")
+        .expect(hits)
+        .with_status(status)
+        .with_body(json!({ "access_token": FRESH_TOKEN, "expires_in": 3600 }).to_string())
+        .create_async()
+        .await
+}
+
+async fn mock_platform(
+    server: &mut TestServer,
+    path: &str,
+    token: &str,
+    status: usize,
+    hits: usize,
+) -> Mock {
+    server
+        .mock_server
+        .mock("GET", format!("/{path}").as_str())
+        .match_header(AUTHORIZATION.as_str(), format!("Bearer {token}").as_str())
+        .expect(hits)
+        .with_status(status)
+        .with_body(json!({ "token": token }).to_string())
+        .create_async()
+        .await
+}
+
+async fn send_passthrough(
+    server: &TestServer,
+    connection: &SanitizedConnection,
+    path: &str,
+) -> (StatusCode, Value) {
+    let res = server
+        .send_request_with_headers::<Value, Value>(
+            &format!("v1/passthrough/{path}"),
+            Method::GET,
+            Some(&server.live_key),
+            None,
+            Some(
+                vec![
+                    (CONTENT_TYPE.to_string(), "application/json".to_string()),
+                    (
+                        "x-pica-connection-key".to_string(),
+                        connection.key.to_string(),
+                    ),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+        )
+        .await
+        .unwrap();
+
+    (res.code, res.data)
+}
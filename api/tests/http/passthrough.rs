@@ -66,6 +66,10 @@ async fn test_passthrough_api() {
             body: None,
         },
         paths: None,
+        retry_policy: None,
+        rate_limit: None,
+        response_cache: None,
+        validation: None,
         responses: vec![],
         is_default_crud_mapping: None,
         test_connection_payload: None,
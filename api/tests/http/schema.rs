@@ -1,5 +1,8 @@
 use crate::context::TestServer;
-use http::{Method, StatusCode};
+use http::{
+    header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    Method, StatusCode,
+};
 use serde_json::Value;
 
 #[tokio::test]
@@ -16,3 +19,63 @@ async fn test_connection_oauth_definition_schema_api() {
         .unwrap();
     assert_eq!(res.code, StatusCode::OK);
 }
+
+#[tokio::test]
+async fn test_generate_types_api() {
+    let server = TestServer::new(None).await;
+
+    let res = server
+        .send_request::<Value, Value>(
+            "v1/public/schemas/types/jsonschema",
+            Method::GET,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::OK);
+    assert_eq!(
+        res.data["$schema"],
+        "https://json-schema.org/draft/2020-12/schema"
+    );
+
+    // Functions are written in JavaScript, but no types are generated for it
+    let res = server
+        .client
+        .get(format!(
+            "http://localhost:{}/v1/public/schemas/types/javascript",
+            server.port
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_generate_sdk_api() {
+    let server = TestServer::new(None).await;
+
+    let res = server
+        .client
+        .get(format!(
+            "http://localhost:{}/v1/public/schemas/sdk/typescript",
+            server.port
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()[CONTENT_TYPE], "application/gzip");
+    assert_eq!(
+        res.headers()[CONTENT_DISPOSITION],
+        "attachment; filename=\"pica-unified-typescript.tar.gz\""
+    );
+    assert!(!res.bytes().await.unwrap().is_empty());
+
+    let res = server
+        .send_request::<Value, Value>("v1/public/schemas/sdk/go", Method::GET, None, None)
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,236 @@
+use crate::context::TestServer;
+use chrono::Utc;
+use http::{Method, StatusCode};
+use mongodb::{bson::doc, Client};
+use osentities::{
+    task::{Task, TaskState},
+    MongoStore, Store,
+};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn test_replay_dead_lettered_task() {
+    let server = TestServer::new(None).await;
+    let store = tasks_store(&server).await;
+
+    let task = create_task(&server, json!({ "maxAttempts": 3 })).await;
+    let id = task["_id"].as_str().unwrap();
+    let path = format!("v1/tasks/{id}/replay");
+
+    // Only dead-lettered tasks can be replayed
+    let res = server
+        .send_request::<Value, Value>(&path, Method::POST, Some(&server.live_key), None)
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::NOT_FOUND);
+
+    store
+        .update_one(
+            id,
+            doc! { "$set": {
+                "state": "deadLettered",
+                "attempt": 3,
+                "lastError": "Endpoint responded with status 500",
+                "endTime": Utc::now().timestamp_millis(),
+            } },
+        )
+        .await
+        .unwrap();
+
+    let res = server
+        .send_request::<Value, Value>(&path, Method::POST, Some(&server.live_key), None)
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::OK);
+    assert_eq!(res.data["state"], "pending");
+    assert_eq!(res.data["attempt"], 0);
+    assert_eq!(res.data["maxAttempts"], 3);
+    assert!(res.data.get("lastError").is_none());
+
+    let stored = store.get_one_by_id(id).await.unwrap().unwrap();
+    assert_eq!(stored.state, TaskState::Pending);
+    assert_eq!(stored.attempt, 0);
+    assert_eq!(stored.end_time, None);
+    assert_eq!(stored.last_error, None);
+}
+
+#[tokio::test]
+async fn test_pause_and_resume_series() {
+    let server = TestServer::new(None).await;
+    let store = tasks_store(&server).await;
+
+    let task = create_task(&server, json!({ "schedule": { "intervalSecs": 60 } })).await;
+    let id = task["_id"].as_str().unwrap();
+
+    for paused in [true, false] {
+        let action = if paused { "pause" } else { "resume" };
+
+        let res = server
+            .send_request::<Value, Value>(
+                &format!("v1/tasks/{id}/{action}"),
+                Method::POST,
+                Some(&server.live_key),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.code, StatusCode::OK);
+        assert_eq!(res.data["seriesId"], id);
+        assert_eq!(res.data["paused"], paused);
+
+        let stored = store.get_one_by_id(id).await.unwrap().unwrap();
+        assert_eq!(stored.schedule.unwrap().paused, paused);
+
+        let res = server
+            .send_request::<Value, Value>(
+                &format!("v1/tasks/{id}/upcoming"),
+                Method::GET,
+                Some(&server.live_key),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.code, StatusCode::OK);
+        assert_eq!(res.data["paused"], paused);
+    }
+
+    let task = create_task(&server, json!({})).await;
+    let res = server
+        .send_request::<Value, Value>(
+            &format!("v1/tasks/{}/pause", task["_id"].as_str().unwrap()),
+            Method::POST,
+            Some(&server.live_key),
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_list_upcoming_runs() {
+    let server = TestServer::new(None).await;
+
+    let task = create_task(
+        &server,
+        json!({ "schedule": { "intervalSecs": 60, "maxRuns": 3 } }),
+    )
+    .await;
+    let start_time = task["startTime"].as_i64().unwrap();
+
+    let res = server
+        .send_request::<Value, Value>(
+            &format!(
+                "v1/tasks/{}/upcoming?count=5",
+                task["_id"].as_str().unwrap()
+            ),
+            Method::GET,
+            Some(&server.live_key),
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::OK);
+    assert_eq!(
+        res.data["runs"],
+        json!([start_time, start_time + 60_000, start_time + 120_000])
+    );
+}
+
+#[tokio::test]
+async fn test_reject_invalid_schedules() {
+    let server = TestServer::new(None).await;
+    let start_time = Utc::now().timestamp_millis() + 3_600_000;
+
+    for schedule in [
+        json!({ "intervalSecs": 0 }),
+        json!({ "intervalSecs": u64::MAX }),
+        json!({ "intervalSecs": 60, "endAt": start_time - 1 }),
+        json!({ "cron": "not a cron expression" }),
+    ] {
+        let res = server
+            .send_request::<Value, Value>(
+                "v1/tasks",
+                Method::POST,
+                Some(&server.live_key),
+                Some(&json!({
+                    "startTime": start_time,
+                    "endpoint": server.mock_server.url(),
+                    "payload": {},
+                    "await": false,
+                    "schedule": schedule,
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.code, StatusCode::BAD_REQUEST, "{schedule}");
+    }
+}
+
+#[tokio::test]
+async fn test_reject_reserved_signature_headers() {
+    let server = TestServer::new(None).await;
+
+    for header in ["x-pica-signature", "x-pica-timestamp"] {
+        let res = server
+            .send_request::<Value, Value>(
+                "v1/tasks",
+                Method::POST,
+                Some(&server.live_key),
+                Some(&json!({
+                    "startTime": Utc::now().timestamp_millis(),
+                    "endpoint": server.mock_server.url(),
+                    "payload": {},
+                    "await": false,
+                    "headers": { header: "sha256=forged" },
+                })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.code, StatusCode::BAD_REQUEST, "{header}");
+    }
+
+    let task = create_task(
+        &server,
+        json!({ "method": "PUT", "headers": { "x-custom": "value" } }),
+    )
+    .await;
+    assert_eq!(task["method"], "PUT");
+    assert_eq!(task["headers"]["x-custom"], "value");
+}
+
+/// Creates a task due in an hour, `fields` are merged into the request
+async fn create_task(server: &TestServer, fields: Value) -> Value {
+    let mut payload = json!({
+        "startTime": Utc::now().timestamp_millis() + 3_600_000,
+        "endpoint": server.mock_server.url(),
+        "payload": { "hello": "world" },
+        "await": false,
+    });
+    payload
+        .as_object_mut()
+        .unwrap()
+        .extend(fields.as_object().unwrap().clone());
+
+    let res = server
+        .send_request::<Value, Value>(
+            "v1/tasks",
+            Method::POST,
+            Some(&server.live_key),
+            Some(&payload),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::OK);
+
+    res.data
+}
+
+async fn tasks_store(server: &TestServer) -> MongoStore<Task> {
+    let db = Client::with_uri_str(&server.config.db_config.event_db_url)
+        .await
+        .unwrap()
+        .database(&server.config.db_config.event_db_name);
+
+    MongoStore::new(&db, &Store::Tasks).await.unwrap()
+}
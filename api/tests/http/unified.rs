@@ -1,25 +1,32 @@
 use crate::context::TestServer;
 use api::logic::{
+    common_model::CreateRequest as CreateCommonModelRequest,
     connection_model_definition::CreateRequest as CreateConnectionModelDefinitionRequest,
     connection_model_schema::CreateRequest as CreateConnectionModelSchemaRequest,
     metrics::MetricResponse,
+    unified::{BatchOperation, BatchOperationResult},
 };
 use chrono::{Datelike, Utc};
 use fake::{faker::filesystem::raw::DirPath, locales::EN, Fake, Faker};
 use http::{
-    header::{AUTHORIZATION, CONTENT_TYPE},
+    header::{AUTHORIZATION, CONTENT_TYPE, ETAG, IF_NONE_MATCH},
     Method, StatusCode,
 };
-use mockito::Mock;
+use mockito::{Matcher, Mock};
 use osentities::{
-    api_model_config::{AuthMethod, SamplesInput, SchemasInput},
+    api_model_config::{
+        AuthMethod, ModelPaths, ResponseCachePolicy, ResponseModelPaths, SamplesInput, SchemasInput,
+    },
+    common_model::{CommonModel, DataType, Field, SchemaType},
     connection_model_definition::{ConnectionModelDefinition, CrudAction, CrudMapping},
     connection_model_schema::{ConnectionModelSchema, Mappings},
     environment::Environment,
     id::{prefix::IdPrefix, Id},
+    json_mapper::SchemaMappingDefinition,
+    json_schema::{generate_schema, JsonSchema},
     SanitizedConnection,
 };
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::time::Duration;
 
 #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
@@ -49,6 +56,11 @@ async fn test_unified_api_get_many() {
             }"
                 .to_string(),
             ),
+            from_common_model_schema: None,
+            to_common_model_schema: None,
+            language: None,
+            compiled_from_common_model: None,
+            compiled_to_common_model: None,
         },
     )
     .await;
@@ -107,6 +119,11 @@ async fn test_unified_api_get_one() {
             }"
                 .to_string(),
             ),
+            from_common_model_schema: None,
+            to_common_model_schema: None,
+            language: None,
+            compiled_from_common_model: None,
+            compiled_to_common_model: None,
         },
     )
     .await;
@@ -163,6 +180,11 @@ async fn test_unified_api_get_count() {
             }"
                 .to_string(),
             ),
+            from_common_model_schema: None,
+            to_common_model_schema: None,
+            language: None,
+            compiled_from_common_model: None,
+            compiled_to_common_model: None,
         },
     )
     .await;
@@ -221,6 +243,11 @@ async fn test_unified_api_update() {
             }"
                 .to_string(),
             ),
+            from_common_model_schema: None,
+            to_common_model_schema: None,
+            language: None,
+            compiled_from_common_model: None,
+            compiled_to_common_model: None,
         },
     )
     .await;
@@ -281,6 +308,11 @@ async fn test_unified_api_delete() {
             }"
                 .to_string(),
             ),
+            from_common_model_schema: None,
+            to_common_model_schema: None,
+            language: None,
+            compiled_from_common_model: None,
+            compiled_to_common_model: None,
         },
     )
     .await;
@@ -337,6 +369,11 @@ async fn test_unified_api_create() {
             }"
                 .to_string(),
             ),
+            from_common_model_schema: None,
+            to_common_model_schema: None,
+            language: None,
+            compiled_from_common_model: None,
+            compiled_to_common_model: None,
         },
     )
     .await;
@@ -369,28 +406,1105 @@ async fn test_unified_api_create() {
     mock.assert_async().await;
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_unified_api_create_with_idempotency_key() {
+    let mut server = TestServer::new(None).await;
+    let (connection, _) = server.create_connection(Environment::Live).await;
+
+    let name = "Model".to_string();
+
+    let mock = create_connection_model_definition(
+        &mut server,
+        &connection,
+        CrudMapping {
+            action: CrudAction::Create,
+            common_model_name: name.clone(),
+            from_common_model: Some(
+                "function mapCrudRequest(data) {
+                return data;
+            }"
+                .to_string(),
+            ),
+            to_common_model: Some(
+                "function mapCrudRequest(data) {
+                data.queryParams = undefined;
+                return data;
+            }"
+                .to_string(),
+            ),
+            from_common_model_schema: None,
+            to_common_model_schema: None,
+            language: None,
+            compiled_from_common_model: None,
+            compiled_to_common_model: None,
+        },
+    )
+    .await;
+
+    let idempotency_key = Faker.fake::<String>();
+    let headers: std::collections::BTreeMap<String, String> = vec![
+        (CONTENT_TYPE.to_string(), "application/json".to_string()),
+        (
+            "x-pica-connection-key".to_string(),
+            connection.key.to_string(),
+        ),
+        ("idempotency-key".to_string(), idempotency_key),
+    ]
+    .into_iter()
+    .collect();
+
+    let payload: Value = serde_json::json!({ "name": Faker.fake::<String>() });
+
+    let first = server
+        .send_request_with_headers::<Value, Value>(
+            &format!("v1/unified/{}", name.to_lowercase()),
+            Method::POST,
+            Some(&server.live_key),
+            Some(&payload),
+            Some(headers.clone()),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(first.code, StatusCode::OK);
+
+    let replayed = server
+        .send_request_with_headers::<Value, Value>(
+            &format!("v1/unified/{}", name.to_lowercase()),
+            Method::POST,
+            Some(&server.live_key),
+            Some(&payload),
+            Some(headers.clone()),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(replayed, first);
+
+    let other_payload: Value = serde_json::json!({ "name": Faker.fake::<String>() });
+
+    let rejected = server
+        .send_request_with_headers::<Value, Value>(
+            &format!("v1/unified/{}", name.to_lowercase()),
+            Method::POST,
+            Some(&server.live_key),
+            Some(&other_payload),
+            Some(headers),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(rejected.code, StatusCode::UNPROCESSABLE_ENTITY);
+
+    mock.assert_async().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_unified_api_batch() {
+    let mut server = TestServer::new(None).await;
+    let (connection, _) = server.create_connection(Environment::Live).await;
+
+    let name = "Model".to_string();
+
+    let mock = create_connection_model_definition(
+        &mut server,
+        &connection,
+        CrudMapping {
+            action: CrudAction::Create,
+            common_model_name: name.clone(),
+            from_common_model: Some(
+                "function mapCrudRequest(data) {
+                return data;
+            }"
+                .to_string(),
+            ),
+            to_common_model: Some(
+                "function mapCrudRequest(data) {
+                data.queryParams = undefined;
+                return data;
+            }"
+                .to_string(),
+            ),
+            from_common_model_schema: None,
+            to_common_model_schema: None,
+            language: None,
+            compiled_from_common_model: None,
+            compiled_to_common_model: None,
+        },
+    )
+    .await;
+
+    let payload = vec![
+        BatchOperation {
+            model: name.to_lowercase(),
+            action: CrudAction::Create,
+            id: None,
+            body: Some(Faker.fake()),
+            query_params: None,
+        },
+        BatchOperation {
+            model: name.to_lowercase(),
+            action: CrudAction::GetOne,
+            id: None,
+            body: None,
+            query_params: None,
+        },
+    ];
+
+    let res = server
+        .send_request_with_headers::<Vec<BatchOperation>, Vec<BatchOperationResult>>(
+            "v1/unified/batch",
+            Method::POST,
+            Some(&server.live_key),
+            Some(&payload),
+            Some(
+                vec![
+                    (CONTENT_TYPE.to_string(), "application/json".to_string()),
+                    (
+                        "x-pica-connection-key".to_string(),
+                        connection.key.to_string(),
+                    ),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.code, StatusCode::OK);
+    assert_eq!(res.data.len(), 2);
+    assert_eq!(res.data[0].status, StatusCode::OK.as_u16());
+    assert_eq!(res.data[1].status, StatusCode::BAD_REQUEST.as_u16());
+
+    let meta = &res.data[1].meta;
+    assert_eq!(meta["platform"], connection.platform.as_ref());
+    assert_eq!(meta["connectionKey"], connection.key.as_ref());
+    assert_eq!(meta["action"], "getOne");
+    assert_eq!(meta["commonModel"], name);
+    assert!(meta["latency"].is_number());
+
+    mock.assert_async().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_unified_api_get_one_with_response_cache() {
+    let mut server = TestServer::new(None).await;
+    let (connection, _) = server.create_connection(Environment::Live).await;
+
+    let name = "Model".to_string();
+
+    let id: String = Faker.fake();
+
+    let mock = create_cached_connection_model_definition(
+        &mut server,
+        &connection,
+        CrudMapping {
+            action: CrudAction::GetOne,
+            common_model_name: name.clone(),
+            from_common_model: Some(
+                "function mapCrudRequest(data) {
+                return data;
+            }"
+                .to_string(),
+            ),
+            to_common_model: None,
+            from_common_model_schema: None,
+            to_common_model_schema: None,
+            language: None,
+            compiled_from_common_model: None,
+            compiled_to_common_model: None,
+        },
+        Some(ResponseCachePolicy { ttl_secs: 60 }),
+        None,
+    )
+    .await;
+
+    let path = format!("v1/unified/{}/{id}", name.to_lowercase());
+    let headers = vec![
+        (CONTENT_TYPE.to_string(), "application/json".to_string()),
+        (
+            "x-pica-connection-key".to_string(),
+            connection.key.to_string(),
+        ),
+    ];
+
+    let first = server
+        .send_request_with_headers::<Value, Value>(
+            &path,
+            Method::GET,
+            Some(&server.live_key),
+            None,
+            Some(headers.clone().into_iter().collect()),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(first.code, StatusCode::OK);
+    assert_eq!(first.data["meta"]["cache"]["hit"], false);
+
+    // The platform mock only expects a single call, the second read is served from the cache
+    let second = server
+        .send_request_with_headers::<Value, Value>(
+            &path,
+            Method::GET,
+            Some(&server.live_key),
+            None,
+            Some(headers.clone().into_iter().collect()),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(second.code, StatusCode::OK);
+    assert_eq!(second.data["meta"]["cache"]["hit"], true);
+    assert_eq!(second.data["unified"], first.data["unified"]);
+
+    let mut req = server
+        .client
+        .get(format!("http://localhost:{}/{path}", server.port))
+        .header(&server.config.headers.auth_header, &server.live_key);
+    for (k, v) in &headers {
+        req = req.header(k, v);
+    }
+
+    let res = req.send().await.unwrap();
+    let etag = res.headers().get(ETAG).cloned().unwrap();
+
+    assert_eq!(
+        etag.to_str().unwrap(),
+        format!("\"{}\"", first.data["meta"]["hash"].as_str().unwrap())
+    );
+
+    let mut req = server
+        .client
+        .get(format!("http://localhost:{}/{path}", server.port))
+        .header(&server.config.headers.auth_header, &server.live_key)
+        .header(IF_NONE_MATCH, etag);
+    for (k, v) in &headers {
+        req = req.header(k, v);
+    }
+
+    let res = req.send().await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+    mock.assert_async().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_unified_api_records_schema_drift() {
+    let mut server = TestServer::new(None).await;
+    let (connection, _) = server.create_connection(Environment::Live).await;
+
+    let name = "Model".to_string();
+
+    let id: String = Faker.fake();
+
+    // The platform responds with a string id and no name
+    let mut stored_schema =
+        JsonSchema::from_value(generate_schema(&json!({ "id": 1, "name": "" }), "$"))
+            .expect("Failed to build schema");
+    stored_schema.required = Some(vec!["name".to_string()]);
+
+    let mock = create_cached_connection_model_definition(
+        &mut server,
+        &connection,
+        CrudMapping {
+            action: CrudAction::GetOne,
+            common_model_name: name.clone(),
+            from_common_model: Some(
+                "function mapCrudRequest(data) {
+                return data;
+            }"
+                .to_string(),
+            ),
+            to_common_model: None,
+            from_common_model_schema: None,
+            to_common_model_schema: None,
+            language: None,
+            compiled_from_common_model: None,
+            compiled_to_common_model: None,
+        },
+        None,
+        Some(stored_schema),
+    )
+    .await;
+
+    let res = server
+        .send_request_with_headers::<Value, Value>(
+            &format!("v1/unified/{}/{id}", name.to_lowercase()),
+            Method::GET,
+            Some(&server.live_key),
+            None,
+            Some(
+                vec![
+                    (CONTENT_TYPE.to_string(), "application/json".to_string()),
+                    (
+                        "x-pica-connection-key".to_string(),
+                        connection.key.to_string(),
+                    ),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.code, StatusCode::OK);
+
+    mock.assert_async().await;
+
+    // Drift is saved in the background
+    let mut changes = Value::Null;
+    for _ in 0..20 {
+        let res = server
+            .send_request::<Value, Value>(
+                "v1/schema-drifts",
+                Method::GET,
+                Some(&server.live_key),
+                None,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(res.code, StatusCode::OK);
+
+        if let Some(drift) = res.data["rows"].as_array().and_then(|rows| rows.first()) {
+            // Responses may hold personal data, only their schema is compared
+            assert!(drift.get("sample").is_none());
+            assert_eq!(drift["occurrences"], 1);
+
+            changes = drift["changes"].clone();
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    assert_eq!(
+        changes,
+        json!([
+            { "path": "$.id", "kind": "typeChanged", "expected": "number", "actual": "string" },
+            { "path": "$.name", "kind": "removed", "expected": "string" }
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_unified_api_streams_every_page() {
+    let mut server = TestServer::new(None).await;
+    let (connection, _) = server.create_connection(Environment::Live).await;
+
+    let name = "Model".to_string();
+    let endpoint =
+        register_paginated_connection_model_definition(&mut server, &connection, &name).await;
+
+    let first_page = mock_page(
+        &mut server,
+        &endpoint,
+        None,
+        json!({ "items": [{ "id": 1 }, { "id": 2 }], "next": "page-2" }),
+        1,
+    )
+    .await;
+    let second_page = mock_page(
+        &mut server,
+        &endpoint,
+        Some("page-2"),
+        json!({ "items": [{ "id": 3 }], "next": null }),
+        1,
+    )
+    .await;
+
+    let (status, lines) = stream_unified(
+        &server,
+        &connection,
+        &format!("v1/unified/{}?autoPaginate=true", name.to_lowercase()),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        lines,
+        vec![json!({ "id": 1 }), json!({ "id": 2 }), json!({ "id": 3 })]
+    );
+
+    first_page.assert_async().await;
+    second_page.assert_async().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_unified_api_stream_stops_at_pagination_limits() {
+    let mut server = TestServer::new(None).await;
+    let (connection, _) = server.create_connection(Environment::Live).await;
+
+    let name = "Model".to_string();
+    let endpoint =
+        register_paginated_connection_model_definition(&mut server, &connection, &name).await;
+
+    let first_page = mock_page(
+        &mut server,
+        &endpoint,
+        None,
+        json!({ "items": [{ "id": 1 }, { "id": 2 }], "next": "page-2" }),
+        2,
+    )
+    .await;
+    let second_page = mock_page(
+        &mut server,
+        &endpoint,
+        Some("page-2"),
+        json!({ "items": [{ "id": 3 }, { "id": 4 }], "next": "page-3" }),
+        1,
+    )
+    .await;
+    let third_page = mock_page(
+        &mut server,
+        &endpoint,
+        Some("page-3"),
+        json!({ "items": [{ "id": 5 }], "next": null }),
+        0,
+    )
+    .await;
+
+    let (status, lines) = stream_unified(
+        &server,
+        &connection,
+        &format!(
+            "v1/unified/{}?autoPaginate=true&maxRecords=3",
+            name.to_lowercase()
+        ),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        lines,
+        vec![json!({ "id": 1 }), json!({ "id": 2 }), json!({ "id": 3 })]
+    );
+
+    let (status, lines) = stream_unified(
+        &server,
+        &connection,
+        &format!(
+            "v1/unified/{}?autoPaginate=true&maxPages=1",
+            name.to_lowercase()
+        ),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(lines, vec![json!({ "id": 1 }), json!({ "id": 2 })]);
+
+    first_page.assert_async().await;
+    second_page.assert_async().await;
+    third_page.assert_async().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_unified_api_stream_reports_errors_in_band() {
+    let mut server = TestServer::new(None).await;
+    let (connection, _) = server.create_connection(Environment::Live).await;
+
+    let name = "Model".to_string();
+    let endpoint =
+        register_paginated_connection_model_definition(&mut server, &connection, &name).await;
+
+    let first_page = mock_page(
+        &mut server,
+        &endpoint,
+        None,
+        json!({ "items": [{ "id": 1 }, { "id": 2 }], "next": "page-2" }),
+        1,
+    )
+    .await;
+    let failing_page = server
+        .mock_server
+        .mock("GET", endpoint.path.as_str())
+        .match_query(Matcher::UrlEncoded(
+            "cursor".to_string(),
+            "page-2".to_string(),
+        ))
+        .expect_at_least(1)
+        .with_status(400)
+        .with_body(json!({ "message": "Invalid cursor" }).to_string())
+        .create_async()
+        .await;
+
+    let (status, lines) = stream_unified(
+        &server,
+        &connection,
+        &format!("v1/unified/{}?autoPaginate=true", name.to_lowercase()),
+    )
+    .await;
+
+    // The status line has already been sent once the first page streams out
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[..2], [json!({ "id": 1 }), json!({ "id": 2 })]);
+    assert_eq!(lines[2]["error"]["code"], StatusCode::BAD_REQUEST.as_u16());
+    assert!(lines[2]["error"]["message"].is_string());
+
+    first_page.assert_async().await;
+    failing_page.assert_async().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_passthrough_write_invalidates_response_cache() {
+    let mut server = TestServer::new(None).await;
+    let (connection, _) = server.create_connection(Environment::Live).await;
+
+    let name = "Model".to_string();
+
+    let id: String = Faker.fake();
+
+    let read = register_connection_model_definition(
+        &mut server,
+        &connection,
+        CrudMapping {
+            action: CrudAction::GetOne,
+            common_model_name: name.clone(),
+            from_common_model: Some(
+                "function mapCrudRequest(data) {
+                return data;
+            }"
+                .to_string(),
+            ),
+            to_common_model: None,
+            from_common_model_schema: None,
+            to_common_model_schema: None,
+            language: None,
+            compiled_from_common_model: None,
+            compiled_to_common_model: None,
+        },
+        DefinitionOptions {
+            response_cache: Some(ResponseCachePolicy { ttl_secs: 60 }),
+            ..Default::default()
+        },
+    )
+    .await;
+    let write = register_connection_model_definition(
+        &mut server,
+        &connection,
+        CrudMapping {
+            action: CrudAction::Create,
+            common_model_name: "Other".to_string(),
+            from_common_model: Some(
+                "function mapCrudRequest(data) {
+                return data;
+            }"
+                .to_string(),
+            ),
+            to_common_model: None,
+            from_common_model_schema: None,
+            to_common_model_schema: None,
+            language: None,
+            compiled_from_common_model: None,
+            compiled_to_common_model: None,
+        },
+        DefinitionOptions {
+            method: Some(Method::POST),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    // The read reaches the platform again once the passthrough write dropped the cached one
+    let read_mock = server
+        .mock_server
+        .mock("GET", read.path.as_str())
+        .expect(2)
+        .with_status(200)
+        .with_body(json!({ "id": id }).to_string())
+        .create_async()
+        .await;
+    let write_mock = server
+        .mock_server
+        .mock("POST", write.path.as_str())
+        .expect(1)
+        .with_status(200)
+        .with_body(json!({ "id": id }).to_string())
+        .create_async()
+        .await;
+
+    let headers: std::collections::BTreeMap<String, String> = vec![
+        (CONTENT_TYPE.to_string(), "application/json".to_string()),
+        (
+            "x-pica-connection-key".to_string(),
+            connection.key.to_string(),
+        ),
+    ]
+    .into_iter()
+    .collect();
+    let path = format!("v1/unified/{}/{id}", name.to_lowercase());
+
+    for hit in [false, true] {
+        let res = server
+            .send_request_with_headers::<Value, Value>(
+                &path,
+                Method::GET,
+                Some(&server.live_key),
+                None,
+                Some(headers.clone()),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(res.code, StatusCode::OK);
+        assert_eq!(res.data["meta"]["cache"]["hit"], hit);
+    }
+
+    let res = server
+        .send_request_with_headers::<Value, Value>(
+            &format!("v1/passthrough/{}", write.route),
+            Method::POST,
+            Some(&server.live_key),
+            Some(&json!({ "name": Faker.fake::<String>() })),
+            Some(headers.clone()),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.code, StatusCode::OK);
+
+    let res = server
+        .send_request_with_headers::<Value, Value>(
+            &path,
+            Method::GET,
+            Some(&server.live_key),
+            None,
+            Some(headers),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.code, StatusCode::OK);
+    assert_eq!(res.data["meta"]["cache"]["hit"], false);
+
+    read_mock.assert_async().await;
+    write_mock.assert_async().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_unified_api_schema_mappings_take_precedence_over_scripts() {
+    let mut server = TestServer::new(None).await;
+    let (connection, _) = server.create_connection(Environment::Live).await;
+
+    let name = "Ticket".to_string();
+    let schema =
+        |value: Value| -> SchemaMappingDefinition { serde_json::from_value(value).unwrap() };
+
+    // The scripts of the stored mapping return their input untouched
+    let endpoint = register_connection_model_definition(
+        &mut server,
+        &connection,
+        CrudMapping {
+            action: CrudAction::Create,
+            common_model_name: name.clone(),
+            from_common_model: Some(
+                "function mapCrudRequest(data) {
+                return data;
+            }"
+                .to_string(),
+            ),
+            to_common_model: None,
+            from_common_model_schema: None,
+            to_common_model_schema: None,
+            language: None,
+            compiled_from_common_model: None,
+            compiled_to_common_model: None,
+        },
+        DefinitionOptions {
+            method: Some(Method::POST),
+            from_common_model_schema: Some(schema(json!({
+                "subject": { "type": "string", "path": "$.title", "transformation": "", "required": true }
+            }))),
+            to_common_model_schema: Some(schema(json!({
+                "id": { "type": "string", "path": "$.ticket_id", "transformation": "", "required": true },
+                "title": { "type": "string", "path": "$.subject", "transformation": "", "required": false }
+            }))),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let mock = server
+        .mock_server
+        .mock("POST", endpoint.path.as_str())
+        .match_body(Matcher::Json(json!({ "subject": "Printer on fire" })))
+        .expect(1)
+        .with_status(200)
+        .with_body(json!({ "ticket_id": "42", "subject": "Printer on fire" }).to_string())
+        .create_async()
+        .await;
+
+    let res = server
+        .send_request_with_headers::<Value, Value>(
+            &format!("v1/unified/{}", name.to_lowercase()),
+            Method::POST,
+            Some(&server.live_key),
+            Some(&json!({ "title": "Printer on fire" })),
+            Some(
+                vec![
+                    (CONTENT_TYPE.to_string(), "application/json".to_string()),
+                    (
+                        "x-pica-connection-key".to_string(),
+                        connection.key.to_string(),
+                    ),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.code, StatusCode::OK);
+    assert_eq!(
+        res.data["unified"],
+        json!({ "id": "42", "title": "Printer on fire" })
+    );
+
+    mock.assert_async().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_unified_api_rejects_bodies_failing_strict_validation() {
+    let mut server = TestServer::new(None).await;
+    let (connection, _) = server.create_connection(Environment::Live).await;
+
+    let name = "Ticket".to_string();
+    let common_model = create_common_model(&server).await;
+
+    let endpoint = register_connection_model_definition(
+        &mut server,
+        &connection,
+        CrudMapping {
+            action: CrudAction::Create,
+            common_model_name: name.clone(),
+            from_common_model: Some(
+                "function mapCrudRequest(data) {
+                return data;
+            }"
+                .to_string(),
+            ),
+            to_common_model: None,
+            from_common_model_schema: None,
+            to_common_model_schema: None,
+            language: None,
+            compiled_from_common_model: None,
+            compiled_to_common_model: None,
+        },
+        DefinitionOptions {
+            method: Some(Method::POST),
+            validation: Some(SchemaType::Strict),
+            common_model_id: Some(common_model.id),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let mock = server
+        .mock_server
+        .mock("POST", endpoint.path.as_str())
+        .expect(0)
+        .create_async()
+        .await;
+
+    let res = server
+        .send_request_with_headers::<Value, Value>(
+            &format!("v1/unified/{}", name.to_lowercase()),
+            Method::POST,
+            Some(&server.live_key),
+            Some(&json!({ "id": 42 })),
+            Some(
+                vec![
+                    (CONTENT_TYPE.to_string(), "application/json".to_string()),
+                    (
+                        "x-pica-connection-key".to_string(),
+                        connection.key.to_string(),
+                    ),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.code, StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(res.data["key"]
+        .as_str()
+        .is_some_and(|key| key.ends_with("::schema_validation")));
+    assert_eq!(
+        res.data["meta"]["errors"],
+        json!([
+            { "path": "id", "message": "expected string" },
+            { "path": "title", "message": "missing required field" }
+        ])
+    );
+
+    mock.assert_async().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_unified_api_strict_validation_requires_mapped_schema() {
+    let mut server = TestServer::new(None).await;
+    let (connection, _) = server.create_connection(Environment::Live).await;
+
+    let name = "Ticket".to_string();
+
+    let endpoint = register_connection_model_definition(
+        &mut server,
+        &connection,
+        CrudMapping {
+            action: CrudAction::Create,
+            common_model_name: name.clone(),
+            from_common_model: Some(
+                "function mapCrudRequest(data) {
+                return data;
+            }"
+                .to_string(),
+            ),
+            to_common_model: None,
+            from_common_model_schema: None,
+            to_common_model_schema: None,
+            language: None,
+            compiled_from_common_model: None,
+            compiled_to_common_model: None,
+        },
+        DefinitionOptions {
+            method: Some(Method::POST),
+            validation: Some(SchemaType::Strict),
+            unmapped_schema: true,
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let mock = server
+        .mock_server
+        .mock("POST", endpoint.path.as_str())
+        .expect(0)
+        .create_async()
+        .await;
+
+    let res = server
+        .send_request_with_headers::<Value, Value>(
+            &format!("v1/unified/{}", name.to_lowercase()),
+            Method::POST,
+            Some(&server.live_key),
+            Some(&json!({ "id": "42", "title": "Printer on fire" })),
+            Some(
+                vec![
+                    (CONTENT_TYPE.to_string(), "application/json".to_string()),
+                    (
+                        "x-pica-connection-key".to_string(),
+                        connection.key.to_string(),
+                    ),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.code, StatusCode::INTERNAL_SERVER_ERROR);
+
+    mock.assert_async().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_unified_api_reports_response_drift() {
+    let mut server = TestServer::new(None).await;
+    let (connection, _) = server.create_connection(Environment::Live).await;
+
+    let name = "Ticket".to_string();
+    let id: String = Faker.fake();
+    let common_model = create_common_model(&server).await;
+
+    let endpoint = register_connection_model_definition(
+        &mut server,
+        &connection,
+        CrudMapping {
+            action: CrudAction::GetOne,
+            common_model_name: name.clone(),
+            from_common_model: Some(
+                "function mapCrudRequest(data) {
+                return data;
+            }"
+                .to_string(),
+            ),
+            to_common_model: None,
+            from_common_model_schema: None,
+            to_common_model_schema: None,
+            language: None,
+            compiled_from_common_model: None,
+            compiled_to_common_model: None,
+        },
+        DefinitionOptions {
+            validation: Some(SchemaType::Lax),
+            common_model_id: Some(common_model.id),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    let mock = server
+        .mock_server
+        .mock("GET", endpoint.path.as_str())
+        .expect(1)
+        .with_status(200)
+        .with_body(json!({ "id": 42, "title": "Printer on fire" }).to_string())
+        .create_async()
+        .await;
+
+    let res = server
+        .send_request_with_headers::<Value, Value>(
+            &format!("v1/unified/{}/{id}", name.to_lowercase()),
+            Method::GET,
+            Some(&server.live_key),
+            None,
+            Some(
+                vec![
+                    (CONTENT_TYPE.to_string(), "application/json".to_string()),
+                    (
+                        "x-pica-connection-key".to_string(),
+                        connection.key.to_string(),
+                    ),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.code, StatusCode::OK);
+    assert_eq!(
+        res.data["meta"]["drift"],
+        json!([{ "path": "id", "message": "expected string" }])
+    );
+
+    mock.assert_async().await;
+}
+
+/// A common model with a required `id` and `title`, both strings
+async fn create_common_model(server: &TestServer) -> CommonModel {
+    let field = |name: &str| Field {
+        name: name.to_string(),
+        datatype: DataType::String,
+        description: None,
+        required: true,
+    };
+
+    let payload = CreateCommonModelRequest {
+        id: None,
+        name: Faker.fake(),
+        version: Faker.fake(),
+        fields: vec![field("id"), field("title")],
+        category: Faker.fake(),
+        sample: json!({}),
+        primary: false,
+    };
+
+    let res = server
+        .send_request::<CreateCommonModelRequest, CommonModel>(
+            "v1/common-models",
+            Method::POST,
+            Some(&server.live_key),
+            Some(&payload),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(res.code, StatusCode::OK);
+
+    res.data
+}
+
 async fn create_connection_model_definition(
     server: &mut TestServer,
     connection: &SanitizedConnection,
     mapping: CrudMapping,
 ) -> Mock {
-    let secret_key = Faker.fake::<String>();
-    let url_path: String = DirPath(EN).fake();
-    let path: String = Faker.fake();
+    create_cached_connection_model_definition(server, connection, mapping, None, None).await
+}
+
+async fn create_cached_connection_model_definition(
+    server: &mut TestServer,
+    connection: &SanitizedConnection,
+    mapping: CrudMapping,
+    response_cache: Option<ResponseCachePolicy>,
+    stored_schema: Option<JsonSchema>,
+) -> Mock {
+    let endpoint = register_connection_model_definition(
+        server,
+        connection,
+        mapping,
+        DefinitionOptions {
+            response_cache,
+            stored_schema,
+            ..Default::default()
+        },
+    )
+    .await;
     let response_body = format!("{{\"id\": \"{}\"}}", Faker.fake::<String>());
 
-    let mock = server
+    server
         .mock_server
-        .mock("GET", format!("{url_path}/{path}").as_str())
+        .mock("GET", endpoint.path.as_str())
         .match_header(
             AUTHORIZATION.as_str(),
-            format!("Bearer {secret_key}").as_str(),
+            format!("Bearer {}", endpoint.secret_key).as_str(),
         )
         .expect(1)
         .with_status(200)
-        .with_body(response_body.clone())
+        .with_body(response_body)
         .create_async()
-        .await;
+        .await
+}
+
+#[derive(Default)]
+struct DefinitionOptions {
+    method: Option<Method>,
+    response_cache: Option<ResponseCachePolicy>,
+    stored_schema: Option<JsonSchema>,
+    paths: Option<ModelPaths>,
+    from_common_model_schema: Option<SchemaMappingDefinition>,
+    to_common_model_schema: Option<SchemaMappingDefinition>,
+    validation: Option<SchemaType>,
+    common_model_id: Option<Id>,
+    /// Registers the schema without a mapping to a common model
+    unmapped_schema: bool,
+}
+
+/// Where the platform is reached for a registered definition, so tests can mock its responses
+struct PlatformEndpoint {
+    /// Path requested on the mock server
+    path: String,
+    /// Path of the definition, relative to its base url
+    route: String,
+    secret_key: String,
+}
+
+async fn register_connection_model_definition(
+    server: &mut TestServer,
+    connection: &SanitizedConnection,
+    mapping: CrudMapping,
+    options: DefinitionOptions,
+) -> PlatformEndpoint {
+    let secret_key = Faker.fake::<String>();
+    let url_path: String = DirPath(EN).fake();
+    let path: String = Faker.fake();
+
+    let endpoint = PlatformEndpoint {
+        path: format!("{url_path}/{path}"),
+        route: path.clone(),
+        secret_key: secret_key.clone(),
+    };
 
     let create_model_definition_payload = CreateConnectionModelDefinitionRequest {
         id: None,
@@ -406,7 +1520,7 @@ async fn create_connection_model_definition(
         auth_method: AuthMethod::BearerToken {
             value: secret_key.to_string(),
         },
-        http_method: http::Method::GET,
+        http_method: options.method.unwrap_or(Method::GET),
         headers: None,
         query_params: None,
         extractor_config: None,
@@ -423,7 +1537,11 @@ async fn create_connection_model_definition(
             path_params: None,
             body: None,
         },
-        paths: None,
+        paths: options.paths,
+        retry_policy: None,
+        rate_limit: None,
+        response_cache: options.response_cache,
+        validation: options.validation,
         responses: vec![],
         is_default_crud_mapping: None,
         test_connection_payload: None,
@@ -448,14 +1566,24 @@ async fn create_connection_model_definition(
     assert_eq!(create_model_definition_response.code, StatusCode::OK);
 
     let mut schema: CreateConnectionModelSchemaRequest = Faker.fake();
+    if let Some(stored_schema) = options.stored_schema {
+        schema.schema = stored_schema;
+    }
     schema.connection_platform = connection.platform.to_string();
     schema.mapping = Some(Mappings {
         from_common_model: "function mapFromCommonModel(data) { return data; }".to_string(),
         to_common_model: "function mapToCommonModel(data) { return data; }".to_string(),
         common_model_name: mapping.common_model_name.clone(),
-        common_model_id: Id::now(IdPrefix::CommonModel),
+        common_model_id: options
+            .common_model_id
+            .unwrap_or_else(|| Id::now(IdPrefix::CommonModel)),
         unmapped_fields: Default::default(),
+        from_common_model_schema: options.from_common_model_schema,
+        to_common_model_schema: options.to_common_model_schema,
     });
+    if options.unmapped_schema {
+        schema.mapping = None;
+    }
 
     let res = server
         .send_request::<CreateConnectionModelSchemaRequest, ConnectionModelSchema>(
@@ -469,5 +1597,104 @@ async fn create_connection_model_definition(
 
     assert_eq!(res.code, StatusCode::OK);
 
-    mock
+    endpoint
+}
+
+/// A `GetMany` definition whose records sit under `items` and whose next cursor is `next`
+async fn register_paginated_connection_model_definition(
+    server: &mut TestServer,
+    connection: &SanitizedConnection,
+    name: &str,
+) -> PlatformEndpoint {
+    register_connection_model_definition(
+        server,
+        connection,
+        CrudMapping {
+            action: CrudAction::GetMany,
+            common_model_name: name.to_string(),
+            from_common_model: Some(
+                "function mapCrudRequest(data) {
+                return data;
+            }"
+                .to_string(),
+            ),
+            to_common_model: Some(
+                "function mapCrudRequest(data) {
+                return { pagination: { nextCursor: data.pagination } };
+            }"
+                .to_string(),
+            ),
+            from_common_model_schema: None,
+            to_common_model_schema: None,
+            language: None,
+            compiled_from_common_model: None,
+            compiled_to_common_model: None,
+        },
+        DefinitionOptions {
+            paths: Some(ModelPaths {
+                request: None,
+                response: Some(ResponseModelPaths {
+                    object: Some("$.body.items".to_string()),
+                    id: None,
+                    cursor: Some("$.body.next".to_string()),
+                }),
+            }),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+async fn mock_page(
+    server: &mut TestServer,
+    endpoint: &PlatformEndpoint,
+    cursor: Option<&str>,
+    body: Value,
+    hits: usize,
+) -> Mock {
+    let query = match cursor {
+        Some(cursor) => Matcher::UrlEncoded("cursor".to_string(), cursor.to_string()),
+        None => Matcher::Missing,
+    };
+
+    server
+        .mock_server
+        .mock("GET", endpoint.path.as_str())
+        .match_query(query)
+        .match_header(
+            AUTHORIZATION.as_str(),
+            format!("Bearer {}", endpoint.secret_key).as_str(),
+        )
+        .expect(hits)
+        .with_status(200)
+        .with_body(body.to_string())
+        .create_async()
+        .await
+}
+
+/// Reads an NDJSON unified response, one value per line
+async fn stream_unified(
+    server: &TestServer,
+    connection: &SanitizedConnection,
+    path: &str,
+) -> (StatusCode, Vec<Value>) {
+    let res = server
+        .client
+        .get(format!("http://localhost:{}/{path}", server.port))
+        .header(&server.config.headers.auth_header, &server.live_key)
+        .header(CONTENT_TYPE, "application/json")
+        .header("x-pica-connection-key", connection.key.to_string())
+        .send()
+        .await
+        .unwrap();
+
+    let status = res.status();
+    let body = res.text().await.unwrap();
+    let lines = body
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    (status, lines)
 }
@@ -0,0 +1,91 @@
+use crate::context::TestServer;
+use http::{Method, StatusCode};
+use mongodb::{bson::doc, Client};
+use osentities::{task::Task, MongoStore, Store};
+use serde_json::{json, Value};
+
+#[tokio::test]
+async fn test_create_workflow() {
+    let server = TestServer::new(None).await;
+    let endpoint = server.mock_server.url();
+
+    let res = server
+        .send_request::<Value, Value>(
+            "v1/workflows",
+            Method::POST,
+            Some(&server.live_key),
+            Some(&json!({
+                "name": "deal",
+                "stages": [
+                    { "key": "contact", "endpoint": format!("{endpoint}/contacts") },
+                    {
+                        "key": "deal",
+                        "dependsOn": ["contact"],
+                        "endpoint": format!("{endpoint}/deals"),
+                        "payload": { "contactId": "{{stages.contact.response.id}}" },
+                    },
+                ],
+            })),
+        )
+        .await
+        .unwrap();
+    assert_eq!(res.code, StatusCode::OK);
+    assert_eq!(res.data["state"], "running");
+    assert_eq!(res.data["stages"][0]["status"], "running");
+    assert_eq!(res.data["stages"][1]["status"], "pending");
+
+    let db = Client::with_uri_str(&server.config.db_config.event_db_url)
+        .await
+        .unwrap()
+        .database(&server.config.db_config.event_db_name);
+    let tasks = MongoStore::<Task>::new(&db, &Store::Tasks)
+        .await
+        .unwrap()
+        .get_many(
+            Some(doc! { "workflowId": res.data["_id"].as_str().unwrap() }),
+            None,
+            None,
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+    // Only the stage without dependencies runs, and its task can be claimed
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0].endpoint, format!("{endpoint}/contacts"));
+    assert_eq!(
+        Some(tasks[0].id.to_string().as_str()),
+        res.data["stages"][0]["taskId"].as_str()
+    );
+    assert!(tasks[0].metadata.active);
+}
+
+#[tokio::test]
+async fn test_reject_invalid_workflows() {
+    let server = TestServer::new(None).await;
+
+    for stages in [
+        json!([
+            { "key": "a", "endpoint": "http://localhost/a" },
+            { "key": "b", "dependsOn": ["a", "c"], "endpoint": "http://localhost/b" },
+            { "key": "c", "dependsOn": ["b"], "endpoint": "http://localhost/c" },
+        ]),
+        json!([{
+            "key": "a",
+            "endpoint": "http://localhost/a",
+            "headers": { "x-pica-timestamp": "0" },
+        }]),
+    ] {
+        let res = server
+            .send_request::<Value, Value>(
+                "v1/workflows",
+                Method::POST,
+                Some(&server.live_key),
+                Some(&json!({ "name": "invalid", "stages": stages })),
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.code, StatusCode::BAD_REQUEST, "{stages}");
+    }
+}
@@ -60,6 +60,10 @@ fn test_json_connection_model_definition() {
             },
             responses: vec![],
             paths: None,
+            retry_policy: None,
+            rate_limit: None,
+            response_cache: None,
+            validation: None,
         }),
         extractor_config: None,
         test_connection_status: TestConnection::default(),
@@ -70,6 +74,11 @@ fn test_json_connection_model_definition() {
             common_model_name: "common-model-name".to_string(),
             from_common_model: Some("from-common-model".to_string()),
             to_common_model: Some("to-common-model".to_string()),
+            from_common_model_schema: None,
+            to_common_model_schema: None,
+            language: None,
+            compiled_from_common_model: None,
+            compiled_to_common_model: None,
         }),
         record_metadata: RecordMetadata::test(),
         supported: false,
@@ -110,6 +119,8 @@ fn test_json_connection_model_schema() {
             common_model_name: "common-model-name".to_string(),
             common_model_id: Id::test(IdPrefix::CommonModel),
             unmapped_fields: JsonSchema::default(),
+            from_common_model_schema: None,
+            to_common_model_schema: None,
         }),
         record_metadata: RecordMetadata::test(),
     };
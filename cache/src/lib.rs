@@ -1,2 +1,4 @@
 pub mod local;
+pub mod rate_limit;
 pub mod remote;
+pub mod response;
@@ -3,6 +3,7 @@ use http::HeaderValue;
 use moka::future::Cache;
 use mongodb::bson::Document;
 use mongodb::options::FindOneOptions;
+use osentities::common_model::CommonModel;
 use osentities::connection_definition::ConnectionDefinition;
 use osentities::connection_model_definition::{ConnectionModelDefinition, SparseCMD};
 use osentities::connection_model_schema::ConnectionModelSchema;
@@ -158,6 +159,8 @@ pub struct ConnectionModelDefinitionCacheIdKeyInner {
 pub type ConnectionModelDefinitionCacheIdKey =
     GenericCache<ConnectionModelDefinitionCacheIdKeyInner, ConnectionModelDefinition>;
 pub type ConnectionDefinitionCache = GenericCache<Id, ConnectionDefinition>;
+pub type CommonModelCache = GenericCache<Id, CommonModel>;
+pub type SchemaDriftSampleCache = GenericCache<Id, Unit>;
 
 #[derive(Clone, Hash, Eq, Debug, PartialEq)]
 pub struct ConnectionHeaderKey {
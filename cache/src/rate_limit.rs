@@ -0,0 +1,212 @@
+use crate::remote::RedisCache;
+use moka::future::Cache;
+use osentities::{
+    api_model_config::RateLimitPolicy, ApplicationError, InternalError, PicaError, Unit,
+};
+use redis::{aio::ConnectionManager, Script};
+use std::{
+    fmt::{self, Debug},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Refills the bucket at `KEYS[1]` using the Redis server clock, so that every replica shares the
+/// same view of time, and takes a token when one is available. Returns the number of milliseconds
+/// to wait for the next token, or 0 when a token was taken.
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local interval = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updatedAt')
+local tokens = tonumber(bucket[1]) or capacity
+local updated_at = tonumber(bucket[2]) or now
+local rate = capacity / interval
+tokens = math.min(capacity, tokens + math.max(0, now - updated_at) * rate)
+local wait = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+else
+    wait = math.ceil((1 - tokens) / rate)
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updatedAt', now)
+redis.call('PEXPIRE', KEYS[1], interval * 2)
+return wait
+"#;
+
+const REMOTE_KEY_PREFIX: &str = "outbound_rate_limit";
+
+/// Token buckets limiting the traffic sent to third party platforms. The local variant only
+/// coordinates requests within one process, the remote one shares the budget across replicas.
+#[derive(Clone)]
+pub enum OutboundRateLimiter {
+    Local(LocalTokenBuckets),
+    Remote(RemoteTokenBuckets),
+}
+
+impl Debug for OutboundRateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Local(_) => f.write_str("OutboundRateLimiter::Local"),
+            Self::Remote(_) => f.write_str("OutboundRateLimiter::Remote"),
+        }
+    }
+}
+
+impl OutboundRateLimiter {
+    pub fn local() -> Self {
+        Self::Local(LocalTokenBuckets::default())
+    }
+
+    pub fn remote(cache: RedisCache) -> Self {
+        Self::Remote(RemoteTokenBuckets {
+            inner: cache.inner,
+            script: Arc::new(Script::new(TOKEN_BUCKET_SCRIPT)),
+        })
+    }
+
+    /// Waits until a token is available in the bucket identified by `key`, failing with a
+    /// `429` if that would take longer than the `max_wait_ms` of the policy
+    pub async fn acquire(&self, key: &str, policy: &RateLimitPolicy) -> Result<Unit, PicaError> {
+        if policy.capacity == 0 || policy.interval_ms == 0 {
+            return Err(InternalError::configuration_error(
+                "Rate limit capacity and interval must be greater than zero",
+                None,
+            ));
+        }
+
+        let deadline = Instant::now() + Duration::from_millis(policy.max_wait_ms);
+
+        loop {
+            let wait = match self {
+                Self::Local(buckets) => buckets.try_acquire(key, policy).await,
+                Self::Remote(buckets) => buckets.try_acquire(key, policy).await?,
+            };
+
+            match wait {
+                None => return Ok(()),
+                Some(wait) if Instant::now() + wait > deadline => {
+                    tracing::warn!("Outbound rate limit exceeded for {key}");
+
+                    return Err(ApplicationError::too_many_requests(
+                        "Outbound rate limit for the platform exceeded",
+                        None,
+                    ));
+                }
+                Some(wait) => tokio::time::sleep(wait).await,
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+#[derive(Clone)]
+pub struct LocalTokenBuckets {
+    inner: Cache<String, Arc<Mutex<Bucket>>>,
+}
+
+impl Default for LocalTokenBuckets {
+    fn default() -> Self {
+        Self {
+            inner: Cache::builder()
+                .time_to_idle(Duration::from_secs(60 * 60))
+                .build(),
+        }
+    }
+}
+
+impl LocalTokenBuckets {
+    async fn try_acquire(&self, key: &str, policy: &RateLimitPolicy) -> Option<Duration> {
+        let capacity = policy.capacity as f64;
+        let bucket = self
+            .inner
+            .get_with(key.to_string(), async {
+                Arc::new(Mutex::new(Bucket {
+                    tokens: capacity,
+                    updated_at: Instant::now(),
+                }))
+            })
+            .await;
+
+        let mut bucket = bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let rate = capacity / policy.interval_ms as f64;
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.updated_at).as_millis() as f64;
+
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_millis(
+                ((1.0 - bucket.tokens) / rate).ceil() as u64
+            ))
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RemoteTokenBuckets {
+    inner: ConnectionManager,
+    script: Arc<Script>,
+}
+
+impl RemoteTokenBuckets {
+    async fn try_acquire(
+        &self,
+        key: &str,
+        policy: &RateLimitPolicy,
+    ) -> Result<Option<Duration>, PicaError> {
+        let mut connection = self.inner.clone();
+        let wait: u64 = self
+            .script
+            .key(format!("{REMOTE_KEY_PREFIX}::{key}"))
+            .arg(policy.capacity)
+            .arg(policy.interval_ms)
+            .invoke_async(&mut connection)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to acquire outbound rate limit token: {e}");
+                InternalError::io_err(&e.to_string(), Some("redis"))
+            })?;
+
+        Ok((wait > 0).then_some(Duration::from_millis(wait)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use osentities::api_model_config::RateLimitScope;
+
+    #[tokio::test]
+    async fn test_local_bucket_waits_and_rejects() {
+        let limiter = OutboundRateLimiter::local();
+        let policy = RateLimitPolicy {
+            capacity: 2,
+            interval_ms: 100,
+            max_wait_ms: 200,
+            scope: RateLimitScope::Connection,
+        };
+
+        let start = Instant::now();
+        for _ in 0..3 {
+            limiter.acquire("platform::key", &policy).await.unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(40));
+
+        let strict = RateLimitPolicy {
+            max_wait_ms: 0,
+            ..policy
+        };
+        assert!(limiter.acquire("platform::key", &strict).await.is_err());
+        assert!(limiter.acquire("platform::other", &strict).await.is_ok());
+    }
+}
@@ -0,0 +1,179 @@
+use crate::remote::RedisCache;
+use moka::future::Cache;
+use osentities::{InternalError, PicaError, Unit};
+use redis::{aio::ConnectionManager, AsyncCommands};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const REMOTE_KEY_PREFIX: &str = "unified_response_cache";
+
+/// Cache of unified responses. Entries are grouped in scopes, usually a model of a connection,
+/// carrying a generation number that is part of the key of every entry. Invalidating a scope
+/// bumps its generation, so stale entries are never read again and expire on their own.
+#[derive(Clone)]
+pub enum ResponseCache {
+    Local(LocalResponseCache),
+    Remote(RemoteResponseCache),
+}
+
+impl ResponseCache {
+    pub fn local(size: u64) -> Self {
+        Self::Local(LocalResponseCache {
+            entries: Cache::builder().max_capacity(size).build(),
+            generations: Cache::builder()
+                .time_to_idle(Duration::from_secs(24 * 60 * 60))
+                .build(),
+        })
+    }
+
+    pub fn remote(cache: RedisCache) -> Self {
+        Self::Remote(RemoteResponseCache { inner: cache.inner })
+    }
+
+    /// Current generation of the scope, to be included in the keys of its entries
+    pub async fn generation(&self, scope: &str) -> Result<u64, PicaError> {
+        match self {
+            Self::Local(cache) => Ok(cache.generations.get(scope).await.unwrap_or_default()),
+            Self::Remote(cache) => {
+                let generation: Option<u64> = cache
+                    .inner
+                    .clone()
+                    .get(format!("{REMOTE_KEY_PREFIX}::generation::{scope}"))
+                    .await
+                    .map_err(redis_error)?;
+
+                Ok(generation.unwrap_or_default())
+            }
+        }
+    }
+
+    /// Makes every entry cached for the scope so far unreachable
+    pub async fn invalidate(&self, scope: &str) -> Result<Unit, PicaError> {
+        match self {
+            Self::Local(cache) => {
+                cache
+                    .generations
+                    .entry(scope.to_string())
+                    .and_upsert_with(|generation| async move {
+                        generation.map(|g| g.into_value() + 1).unwrap_or(1)
+                    })
+                    .await;
+
+                Ok(())
+            }
+            Self::Remote(cache) => cache
+                .inner
+                .clone()
+                .incr::<_, _, ()>(format!("{REMOTE_KEY_PREFIX}::generation::{scope}"), 1)
+                .await
+                .map_err(redis_error),
+        }
+    }
+
+    pub async fn get<V: DeserializeOwned>(&self, key: &str) -> Result<Option<V>, PicaError> {
+        let value = match self {
+            Self::Local(cache) => cache
+                .entries
+                .get(key)
+                .await
+                .filter(|entry| entry.expires_at > Instant::now())
+                .map(|entry| entry.value.to_string()),
+            Self::Remote(cache) => cache
+                .inner
+                .clone()
+                .get(format!("{REMOTE_KEY_PREFIX}::{key}"))
+                .await
+                .map_err(redis_error)?,
+        };
+
+        value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()
+            .map_err(|e| InternalError::deserialize_error(&e.to_string(), None))
+    }
+
+    pub async fn insert<V: Serialize>(
+        &self,
+        key: &str,
+        value: &V,
+        ttl_secs: u64,
+    ) -> Result<Unit, PicaError> {
+        let value = serde_json::to_string(value)
+            .map_err(|e| InternalError::serialize_error(&e.to_string(), None))?;
+
+        match self {
+            Self::Local(cache) => {
+                cache
+                    .entries
+                    .insert(
+                        key.to_string(),
+                        LocalEntry {
+                            value: value.into(),
+                            expires_at: Instant::now() + Duration::from_secs(ttl_secs),
+                        },
+                    )
+                    .await;
+
+                Ok(())
+            }
+            Self::Remote(cache) => cache
+                .inner
+                .clone()
+                .set_ex::<_, _, ()>(format!("{REMOTE_KEY_PREFIX}::{key}"), value, ttl_secs)
+                .await
+                .map_err(redis_error),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct LocalEntry {
+    value: Arc<str>,
+    expires_at: Instant,
+}
+
+#[derive(Clone)]
+pub struct LocalResponseCache {
+    entries: Cache<String, LocalEntry>,
+    generations: Cache<String, u64>,
+}
+
+#[derive(Clone)]
+pub struct RemoteResponseCache {
+    inner: ConnectionManager,
+}
+
+fn redis_error(e: redis::RedisError) -> PicaError {
+    tracing::error!("Failed to access the unified response cache: {e}");
+    InternalError::io_err(&e.to_string(), Some("redis"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_local_response_cache_expires_and_invalidates() {
+        let cache = ResponseCache::local(10);
+
+        assert_eq!(cache.generation("scope").await.unwrap(), 0);
+
+        cache.insert("fresh", &"value", 60).await.unwrap();
+        cache.insert("stale", &"value", 0).await.unwrap();
+
+        assert_eq!(
+            cache.get::<String>("fresh").await.unwrap(),
+            Some("value".to_string())
+        );
+        assert_eq!(cache.get::<String>("stale").await.unwrap(), None);
+
+        cache.invalidate("scope").await.unwrap();
+        cache.invalidate("scope").await.unwrap();
+
+        assert_eq!(cache.generation("scope").await.unwrap(), 2);
+        assert_eq!(cache.generation("other").await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,864 @@
+use crate::config::ExtractorServiceConfig;
+use bson::doc;
+use chrono::{DateTime, Utc};
+use futures::{stream, StreamExt};
+use http::{HeaderMap, HeaderName, HeaderValue};
+use mongodb::{
+    options::{IndexOptions, ReturnDocument},
+    IndexModel,
+};
+use osentities::{
+    connection_model_definition::{ConnectionModelDefinition, ExtractorConfig, ParameterLocation},
+    cursor::Cursor,
+    encrypted_access_key::EncryptedAccessKey,
+    event_access::EventAccess,
+    id::Id,
+    secrets::SecretServiceProvider,
+    AccessKey, ApplicationError, Connection, Event, GoogleKms, IOSKms, InternalError, MongoStore,
+    PicaError, Secret, SecretExt, Store, Unit, BODY_KEY, PASSWORD_LENGTH,
+};
+use serde_json::{json, Map, Value};
+use std::{collections::HashMap, str::FromStr, sync::Arc, time::Duration};
+use tracing::{error, info};
+use unified::{
+    algebra::jsruntime::JSRuntimeImpl,
+    unified::{UnifiedCacheTTLs, UnifiedDestination},
+};
+
+const HEADERS_KEY: &str = "headers";
+const CURSOR_PLACEHOLDER: &str = "{cursor}";
+const JS_EXTRACTOR_FN_NAME: &str = "extract";
+
+pub struct ExtractorClient {
+    config: ExtractorServiceConfig,
+    password: [u8; PASSWORD_LENGTH],
+    destination: UnifiedDestination,
+    cursors: MongoStore<Cursor>,
+    events: MongoStore<Event>,
+    event_access: MongoStore<EventAccess>,
+}
+
+impl ExtractorClient {
+    pub async fn new(config: ExtractorServiceConfig) -> Result<Self, PicaError> {
+        let password: [u8; PASSWORD_LENGTH] = config
+            .event_access_password
+            .as_bytes()
+            .try_into()
+            .map_err(|e| {
+                error!("event_access_password is not 32 bytes in length: {e}");
+                InternalError::configuration_error(
+                    "event_access_password is not 32 bytes in length",
+                    None,
+                )
+            })?;
+
+        let client = mongodb::Client::with_uri_str(&config.db_config.event_db_url).await?;
+        let db = client.database(&config.db_config.event_db_name);
+
+        let cursors = MongoStore::<Cursor>::new(&db, &Store::Cursors).await?;
+        // Replicas polling the same pair race on creating its cursor, the index keeps a single one
+        cursors
+            .collection
+            .create_index(
+                IndexModel::builder()
+                    .keys(doc! { "connectionKey": 1, "connectionModelDefinitionId": 1 })
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+            )
+            .await?;
+        let events = MongoStore::new(&db, &Store::Events).await?;
+        let event_access = MongoStore::new(&db, &Store::EventAccess).await?;
+        let secrets_store = MongoStore::<Secret>::new(&db, &Store::Secrets).await?;
+
+        let secrets_client: Arc<dyn SecretExt + Sync + Send> = match config.secrets_config.provider
+        {
+            SecretServiceProvider::GoogleKms => {
+                Arc::new(GoogleKms::new(&config.secrets_config, secrets_store).await?)
+            }
+            SecretServiceProvider::IosKms => {
+                Arc::new(IOSKms::new(&config.secrets_config, secrets_store).await?)
+            }
+        };
+
+        let destination = UnifiedDestination::new(
+            config.db_config.clone(),
+            config.cache_size,
+            secrets_client,
+            UnifiedCacheTTLs {
+                connection_cache_ttl_secs: config.connection_cache_ttl_secs,
+                connection_model_schema_cache_ttl_secs: config
+                    .connection_model_schema_cache_ttl_secs,
+                connection_model_definition_cache_ttl_secs: config
+                    .connection_model_definition_cache_ttl_secs,
+                secret_cache_ttl_secs: config.secret_cache_ttl_secs,
+            },
+        )
+        .await?;
+
+        Ok(Self {
+            config,
+            password,
+            destination,
+            cursors,
+            events,
+            event_access,
+        })
+    }
+
+    pub async fn start(&self) -> Result<Unit, PicaError> {
+        self.run().await
+    }
+
+    async fn run(&self) -> Result<Unit, PicaError> {
+        info!("Starting extractor");
+
+        loop {
+            let definitions = self
+                .destination
+                .connection_model_definitions_store
+                .get_many(
+                    Some(doc! {
+                        "supported": true,
+                        "deleted": false,
+                        "extractorConfig.enabled": true,
+                    }),
+                    None,
+                    None,
+                    None,
+                    None,
+                )
+                .await?;
+
+            let mut pairs = vec![];
+            for definition in definitions {
+                let connections = self
+                    .destination
+                    .connections_store
+                    .get_many(
+                        Some(doc! {
+                            "connectionDefinitionId": definition.connection_definition_id.to_string(),
+                            "deleted": false,
+                            "active": true,
+                        }),
+                        None,
+                        None,
+                        None,
+                        None,
+                    )
+                    .await?;
+
+                let definition = Arc::new(definition);
+                pairs.extend(
+                    connections
+                        .into_iter()
+                        .map(|connection| (definition.clone(), connection)),
+                );
+            }
+
+            info!("Checking {} extractions", pairs.len());
+
+            stream::iter(pairs)
+                .map(|(definition, connection)| async move {
+                    let result = self.extract(&definition, &connection).await;
+                    (definition, connection, result)
+                })
+                .buffer_unordered(self.config.max_concurrent_extractions)
+                .for_each(|(definition, connection, result)| async move {
+                    match result {
+                        Ok(0) => {}
+                        Ok(count) => info!(
+                            "Extracted {count} records for connection {} with model definition {}",
+                            connection.key, definition.id
+                        ),
+                        Err(e) => error!(
+                            "Error extracting records for connection {} with model definition {}: {e}",
+                            connection.key, definition.id
+                        ),
+                    }
+                })
+                .await;
+
+            tokio::time::sleep(Duration::from_secs(self.config.poll_interval_secs)).await;
+        }
+    }
+
+    /// Pulls a single page of records for the given connection and definition if its cursor
+    /// is due, emits every record as an event and advances the persisted cursor.
+    async fn extract(
+        &self,
+        definition: &ConnectionModelDefinition,
+        connection: &Connection,
+    ) -> Result<usize, PicaError> {
+        let Some(extractor) = definition
+            .extractor_config
+            .as_ref()
+            .filter(|extractor| extractor.enabled)
+        else {
+            return Ok(0);
+        };
+
+        let cursor = self.cursor(&connection.key, definition.id).await?;
+        if !cursor.is_due() {
+            return Ok(0);
+        }
+
+        let event_access_filter = match connection.event_access_id {
+            Some(id) => doc! { "_id": id.to_string(), "deleted": false },
+            None => doc! {
+                "ownership.buildableId": connection.ownership.id.as_ref(),
+                "environment": bson::to_bson(&connection.environment).map_err(|e| {
+                    InternalError::serialize_error(&e.to_string(), None)
+                })?,
+                "deleted": false,
+            },
+        };
+
+        let event_access = self
+            .event_access
+            .get_one(event_access_filter)
+            .await?
+            .ok_or_else(|| ApplicationError::not_found("Event access", None))?;
+
+        let encrypted_access_key = EncryptedAccessKey::parse(&event_access.access_key)?;
+        let access_key = AccessKey::parse(&encrypted_access_key, &self.password)?;
+
+        let secret = self.destination.get_secret(connection).await?.as_value()?;
+        let request = ExtractionRequest::new(extractor, &cursor)?;
+        let started_at = Utc::now().timestamp_millis();
+
+        let response = self
+            .destination
+            .execute_connection_model_definition(
+                connection,
+                definition,
+                request.headers,
+                &request.query_params,
+                &secret,
+                request.body,
+            )
+            .await?;
+
+        let status = response.status();
+        let headers = response.headers().clone();
+
+        if !status.is_success() {
+            self.reschedule(&cursor, extractor.pull_frequency).await?;
+
+            return Err(PicaError::from_err_code(
+                status,
+                &format!("Extraction request failed with status {status}"),
+                None,
+            ));
+        }
+
+        let body: Value = response.json().await.map_err(|e| {
+            InternalError::deserialize_error(&format!("Invalid extraction response: {e}"), None)
+        })?;
+
+        let page = json!({
+            BODY_KEY: body,
+            HEADERS_KEY: headers
+                .iter()
+                .filter_map(|(k, v)| v.to_str().ok().map(|v| (k.to_string(), json!(v))))
+                .collect::<Map<String, Value>>(),
+        });
+
+        let records = select_records(&extractor.cursor.data_path, &page)?;
+        let next_cursor = match extractor.cursor.js_extractor_function.as_ref() {
+            Some(code) => {
+                let namespace = format!(
+                    "extractor_{}",
+                    definition.id.to_string().replace([':', '-'], "_")
+                );
+                JSRuntimeImpl
+                    .create(JS_EXTRACTOR_FN_NAME, &namespace, code)?
+                    .run::<Value, Option<Value>>(&page, &namespace)
+                    .await?
+                    .filter(|v| !v.is_null())
+            }
+            None => select_cursor(&extractor.cursor.cursor_path, &page)?,
+        };
+
+        let name = format!(
+            "{}::{}::{}::record-extracted",
+            connection.platform, connection.platform_version, definition.model_name
+        );
+        let events = records
+            .iter()
+            .map(|record| {
+                Event::new(
+                    &access_key,
+                    &encrypted_access_key,
+                    &name,
+                    HeaderMap::new(),
+                    record.to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        if !events.is_empty() {
+            self.events.create_many(&events).await?;
+        }
+
+        let finished = records.is_empty() || next_cursor.is_none() || next_cursor == cursor.value;
+
+        let (value, next_run_in, last_completed_at) = if finished {
+            let value = if extractor.cursor.reset_on_end {
+                None
+            } else {
+                next_cursor.or(cursor.value.clone())
+            };
+            (value, extractor.sleep_after_finish, Some(started_at))
+        } else {
+            (
+                next_cursor,
+                extractor.pull_frequency,
+                cursor.last_completed_at,
+            )
+        };
+
+        self.cursors
+            .update_one(
+                &cursor.id.to_string(),
+                doc! {
+                    "$set": {
+                        "value": bson::to_bson(&value).map_err(|e| {
+                            InternalError::serialize_error(&e.to_string(), None)
+                        })?,
+                        "lastRunAt": started_at,
+                        "lastCompletedAt": last_completed_at,
+                        "nextRunAt": Utc::now().timestamp_millis() + next_run_in * 1000,
+                        "updatedAt": Utc::now().timestamp_millis(),
+                    },
+                    "$inc": {
+                        "recordsExtracted": records.len() as i64,
+                    }
+                },
+            )
+            .await?;
+
+        Ok(records.len())
+    }
+
+    /// Returns the cursor of the given connection and definition, creating it on first use. The
+    /// upsert is atomic so concurrent pollers always end up sharing the same cursor.
+    async fn cursor(
+        &self,
+        connection_key: &str,
+        connection_model_definition_id: Id,
+    ) -> Result<Cursor, PicaError> {
+        let mut cursor =
+            bson::to_document(&Cursor::new(connection_key, connection_model_definition_id))
+                .map_err(|e| InternalError::serialize_error(&e.to_string(), None))?;
+        // Both are set from the filter on insert
+        cursor.remove("connectionKey");
+        cursor.remove("connectionModelDefinitionId");
+
+        self.cursors
+            .collection
+            .find_one_and_update(
+                doc! {
+                    "connectionKey": connection_key,
+                    "connectionModelDefinitionId": connection_model_definition_id.to_string(),
+                },
+                doc! { "$setOnInsert": cursor },
+            )
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .await?
+            .ok_or_else(|| InternalError::key_not_found("cursor", None))
+    }
+
+    async fn reschedule(&self, cursor: &Cursor, next_run_in: i64) -> Result<Unit, PicaError> {
+        self.cursors
+            .update_one(
+                &cursor.id.to_string(),
+                doc! {
+                    "$set": {
+                        "nextRunAt": Utc::now().timestamp_millis() + next_run_in * 1000,
+                        "updatedAt": Utc::now().timestamp_millis(),
+                    }
+                },
+            )
+            .await
+    }
+}
+
+#[derive(Debug, Default)]
+struct ExtractionRequest {
+    headers: HeaderMap,
+    query_params: HashMap<String, String>,
+    body: Option<Vec<u8>>,
+}
+
+impl ExtractionRequest {
+    fn new(extractor: &ExtractorConfig, cursor: &Cursor) -> Result<Self, PicaError> {
+        let mut headers = HeaderMap::new();
+        let mut query_params = HashMap::new();
+        let mut body = Map::new();
+
+        let mut insert = |location: &ParameterLocation, name: &str, value: String| {
+            match location {
+                ParameterLocation::QueryParameter => {
+                    query_params.insert(name.to_string(), value);
+                }
+                ParameterLocation::RequestBody => {
+                    body.insert(name.to_string(), Value::String(value));
+                }
+                ParameterLocation::Header => {
+                    let name = HeaderName::from_str(name).map_err(|e| {
+                        InternalError::invalid_argument(&e.to_string(), Some("header_name"))
+                    })?;
+                    let value = HeaderValue::from_str(&value).map_err(|e| {
+                        InternalError::invalid_argument(&e.to_string(), Some("header_value"))
+                    })?;
+                    headers.insert(name, value);
+                }
+            };
+
+            Ok::<_, PicaError>(())
+        };
+
+        if let Some(limit) = &extractor.limit {
+            insert(
+                &limit.location,
+                &limit.param_name,
+                extractor.batch_size.to_string(),
+            )?;
+        }
+
+        if let (Some(value), Some(param_name), Some(location)) = (
+            cursor.value.as_ref(),
+            extractor.cursor.param_name.as_ref(),
+            extractor.cursor.location.as_ref(),
+        ) {
+            insert(
+                location,
+                param_name,
+                format_cursor(extractor.cursor.format.as_deref(), value),
+            )?;
+        }
+
+        if let (Some(update), Some(since)) = (&extractor.update_config, cursor.last_completed_at) {
+            insert(
+                &update.location,
+                &update.param_name,
+                format_timestamp(&update.format, since),
+            )?;
+        }
+
+        let body = if body.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_vec(&body).map_err(|e| {
+                InternalError::serialize_error(&e.to_string(), Some("extraction_body"))
+            })?)
+        };
+
+        Ok(Self {
+            headers,
+            query_params,
+            body,
+        })
+    }
+}
+
+/// Paths in `CursorConfig` are rooted at `_` (e.g. `_.body.data`), which maps to the JSONPath root
+fn json_path(path: &str) -> String {
+    match path.strip_prefix('_') {
+        Some(rest) => format!("${rest}"),
+        None => path.to_string(),
+    }
+}
+
+fn select_records(path: &str, page: &Value) -> Result<Vec<Value>, PicaError> {
+    let selected = jsonpath_lib::select(page, &json_path(path))
+        .map_err(|e| InternalError::invalid_argument(&e.to_string(), Some("data_path")))?;
+
+    Ok(match selected.as_slice() {
+        [Value::Array(records)] => records.clone(),
+        [Value::Null] => vec![],
+        selected => selected.iter().map(|v| (*v).clone()).collect(),
+    })
+}
+
+fn select_cursor(path: &str, page: &Value) -> Result<Option<Value>, PicaError> {
+    let selected = jsonpath_lib::select(page, &json_path(path))
+        .map_err(|e| InternalError::invalid_argument(&e.to_string(), Some("cursor_path")))?;
+
+    Ok(selected.into_iter().rev().find(|v| !v.is_null()).cloned())
+}
+
+fn format_cursor(format: Option<&str>, cursor: &Value) -> String {
+    let cursor = match cursor {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+
+    match format {
+        Some(format) if format.contains(CURSOR_PLACEHOLDER) => {
+            format.replace(CURSOR_PLACEHOLDER, &cursor)
+        }
+        _ => cursor,
+    }
+}
+
+fn format_timestamp(format: &str, timestamp_millis: i64) -> String {
+    match format {
+        "timestamp" => (timestamp_millis / 1000).to_string(),
+        "timestamp_ms" => timestamp_millis.to_string(),
+        format => DateTime::<Utc>::from_timestamp_millis(timestamp_millis)
+            .map(|date| date.format(format).to_string())
+            .unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use envconfig::Envconfig;
+    use fake::{Fake, Faker};
+    use mockito::{Matcher, Server};
+    use osentities::{
+        access_key_data::AccessKeyData,
+        access_key_prefix::AccessKeyPrefix,
+        api_model_config::{ApiModelConfig, AuthMethod, SamplesInput, SchemasInput},
+        connection_model_definition::{CursorConfig, LimitConfig, PlatformInfo},
+        environment::Environment,
+        event_type::EventType,
+        id::prefix::IdPrefix,
+        settings::Settings,
+        ConnectionType, Throughput,
+    };
+    use std::sync::OnceLock;
+    use testcontainers_modules::{
+        mongo::Mongo,
+        testcontainers::{clients::Cli as Docker, Container},
+    };
+
+    static DOCKER: OnceLock<Docker> = OnceLock::new();
+    static MONGO: OnceLock<Container<'static, Mongo>> = OnceLock::new();
+
+    async fn client() -> ExtractorClient {
+        let docker = DOCKER.get_or_init(Default::default);
+        let mongo = MONGO.get_or_init(|| docker.run(Mongo));
+        let url = format!(
+            "mongodb://127.0.0.1:{}/?directConnection=true",
+            mongo.get_host_port_ipv4(27017)
+        );
+        let db_name = uuid::Uuid::new_v4().to_string();
+
+        let config = ExtractorServiceConfig::init_from_hashmap(&HashMap::from([
+            ("CONTROL_DATABASE_URL".to_string(), url.clone()),
+            ("CONTROL_DATABASE_NAME".to_string(), db_name.clone()),
+            ("EVENT_DATABASE_URL".to_string(), url.clone()),
+            ("EVENT_DATABASE_NAME".to_string(), db_name.clone()),
+            ("CONTEXT_DATABASE_URL".to_string(), url),
+            ("CONTEXT_DATABASE_NAME".to_string(), db_name),
+            (
+                "SECRETS_SERVICE_PROVIDER".to_string(),
+                "ios-kms".to_string(),
+            ),
+        ]))
+        .expect("Could not create envconfig");
+
+        ExtractorClient::new(config).await.unwrap()
+    }
+
+    /// Stores an event access and a secret holding `token`, and returns a live connection using them
+    async fn connection(client: &ExtractorClient, token: &str) -> Connection {
+        let db = mongodb::Client::with_uri_str(&client.config.db_config.event_db_url)
+            .await
+            .unwrap()
+            .database(&client.config.db_config.event_db_name);
+
+        let data: AccessKeyData = Faker.fake();
+        let access_key = AccessKey {
+            prefix: AccessKeyPrefix {
+                environment: Environment::Live,
+                event_type: EventType::SecretKey,
+                version: 1,
+            },
+            data,
+        };
+
+        let mut event_access: EventAccess = Faker.fake();
+        event_access.ownership.id = access_key.data.id.clone().into();
+        event_access.environment = Environment::Live;
+        event_access.group = access_key.data.group.clone();
+        event_access.record_metadata = Default::default();
+        event_access.access_key = access_key
+            .encode(&client.password, &[0; 16])
+            .unwrap()
+            .to_string();
+        client.event_access.create_one(&event_access).await.unwrap();
+
+        let secret = IOSKms::new(
+            &client.config.secrets_config,
+            MongoStore::new(&db, &Store::Secrets).await.unwrap(),
+        )
+        .await
+        .unwrap()
+        .create(&json!({ "token": token }), &event_access.ownership.id)
+        .await
+        .unwrap();
+
+        Connection {
+            id: Id::now(IdPrefix::Connection),
+            platform_version: "1.0.0".to_string(),
+            connection_definition_id: Id::now(IdPrefix::ConnectionDefinition),
+            r#type: ConnectionType::Api {},
+            key: format!("live::stripe::default::{}", event_access.id).into(),
+            group: event_access.group.clone(),
+            name: None,
+            environment: Environment::Live,
+            platform: "stripe".into(),
+            secrets_service_id: secret.id(),
+            event_access_id: Some(event_access.id),
+            access_key: None,
+            identity: None,
+            identity_type: None,
+            settings: Settings::default(),
+            throughput: Throughput {
+                key: event_access.id.to_string(),
+                limit: 100,
+            },
+            ownership: event_access.ownership.clone(),
+            oauth: None,
+            has_error: false,
+            error: None,
+            record_metadata: Default::default(),
+        }
+    }
+
+    fn definition(base_url: String) -> ConnectionModelDefinition {
+        let mut definition: ConnectionModelDefinition = Faker.fake();
+        definition.action = http::Method::GET;
+        definition.platform_info = PlatformInfo::Api(ApiModelConfig {
+            base_url,
+            path: "customers".to_string(),
+            auth_method: AuthMethod::BearerToken {
+                value: "{{token}}".to_string(),
+            },
+            headers: None,
+            query_params: None,
+            content: None,
+            schemas: SchemasInput {
+                headers: None,
+                query_params: None,
+                path_params: None,
+                body: None,
+            },
+            samples: SamplesInput {
+                headers: None,
+                query_params: None,
+                path_params: None,
+                body: None,
+            },
+            responses: vec![],
+            paths: None,
+            retry_policy: None,
+            rate_limit: None,
+            response_cache: None,
+            validation: None,
+        });
+        definition.extractor_config = Some(ExtractorConfig {
+            pull_frequency: 0,
+            batch_size: 2,
+            cursor: CursorConfig {
+                reset_on_end: false,
+                ..extractor_config().cursor
+            },
+            sleep_after_finish: 3600,
+            ..extractor_config()
+        });
+        definition
+    }
+
+    fn extractor_config() -> ExtractorConfig {
+        ExtractorConfig {
+            pull_frequency: 5,
+            batch_size: 100,
+            cursor: CursorConfig {
+                param_name: Some("starting_after".to_string()),
+                location: Some(ParameterLocation::QueryParameter),
+                format: Some("{cursor}".to_string()),
+                cursor_path: "_.body.data[-1:].id".to_string(),
+                data_path: "_.body.data".to_string(),
+                js_extractor_function: None,
+                reset_on_end: true,
+            },
+            limit: Some(LimitConfig {
+                param_name: "limit".to_string(),
+                location: ParameterLocation::QueryParameter,
+            }),
+            sleep_after_finish: 86400,
+            update_config: None,
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn test_select_records_and_cursor() {
+        let page = json!({
+            "body": {
+                "data": [{ "id": "cus_1" }, { "id": "cus_2" }],
+                "has_more": true
+            },
+            "headers": {}
+        });
+
+        let records = select_records("_.body.data", &page).unwrap();
+        assert_eq!(
+            records,
+            vec![json!({ "id": "cus_1" }), json!({ "id": "cus_2" })]
+        );
+
+        let cursor = select_cursor("_.body.data[-1:].id", &page).unwrap();
+        assert_eq!(cursor, Some(json!("cus_2")));
+
+        let cursor = select_cursor("$.body.next", &page).unwrap();
+        assert_eq!(cursor, None);
+    }
+
+    #[test]
+    fn test_extraction_request() {
+        let extractor = extractor_config();
+        let mut cursor = Cursor::new("stripe::key", Id::now(IdPrefix::ConnectionModelDefinition));
+
+        let request = ExtractionRequest::new(&extractor, &cursor).unwrap();
+        assert_eq!(request.query_params.get("limit"), Some(&"100".to_string()));
+        assert!(!request.query_params.contains_key("starting_after"));
+        assert!(request.body.is_none());
+
+        cursor.value = Some(json!("cus_2"));
+        let request = ExtractionRequest::new(&extractor, &cursor).unwrap();
+        assert_eq!(
+            request.query_params.get("starting_after"),
+            Some(&"cus_2".to_string())
+        );
+    }
+
+    #[test]
+    fn test_format_cursor_and_timestamp() {
+        assert_eq!(
+            format_cursor(Some("after:{cursor}"), &json!(10)),
+            "after:10"
+        );
+        assert_eq!(format_cursor(None, &json!("abc")), "abc");
+        assert_eq!(
+            format_timestamp("timestamp", 1_700_000_000_123),
+            "1700000000"
+        );
+        assert_eq!(
+            format_timestamp("%Y-%m-%d", 1_700_000_000_000),
+            "2023-11-14".to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_extract_emits_records_and_persists_cursor() {
+        let client = client().await;
+        let connection = connection(&client, "secret-token").await;
+        let mut platform = Server::new_async().await;
+        let definition = definition(platform.url());
+
+        let first_page = platform
+            .mock("GET", "/customers")
+            .match_header("authorization", "Bearer secret-token")
+            .match_query(Matcher::Exact("limit=2".to_string()))
+            .with_status(200)
+            .with_body(json!({ "data": [{ "id": "cus_1" }, { "id": "cus_2" }] }).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+
+        let count = client.extract(&definition, &connection).await.unwrap();
+        assert_eq!(count, 2);
+        first_page.assert_async().await;
+
+        let events = client
+            .events
+            .get_many(
+                Some(doc! {
+                    "name": format!(
+                        "stripe::1.0.0::{}::record-extracted",
+                        definition.model_name
+                    ),
+                }),
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        let mut bodies = events
+            .into_iter()
+            .map(|event| serde_json::from_str::<Value>(&event.body).unwrap())
+            .collect::<Vec<_>>();
+        bodies.sort_by_key(|body| body["id"].to_string());
+        assert_eq!(
+            bodies,
+            vec![json!({ "id": "cus_1" }), json!({ "id": "cus_2" })]
+        );
+
+        let cursor = client.cursor(&connection.key, definition.id).await.unwrap();
+        assert_eq!(cursor.value, Some(json!("cus_2")));
+        assert_eq!(cursor.records_extracted, 2);
+        assert_eq!(cursor.last_completed_at, None);
+        assert!(cursor.is_due());
+
+        // The next poll resumes from the persisted cursor and finishes on the empty page
+        let last_page = platform
+            .mock("GET", "/customers")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("limit".to_string(), "2".to_string()),
+                Matcher::UrlEncoded("starting_after".to_string(), "cus_2".to_string()),
+            ]))
+            .with_status(200)
+            .with_body(json!({ "data": [] }).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+
+        let count = client.extract(&definition, &connection).await.unwrap();
+        assert_eq!(count, 0);
+        last_page.assert_async().await;
+
+        let cursor = client.cursor(&connection.key, definition.id).await.unwrap();
+        assert_eq!(cursor.value, Some(json!("cus_2")));
+        assert_eq!(cursor.records_extracted, 2);
+        assert!(cursor.last_completed_at.is_some());
+        assert!(!cursor.is_due());
+
+        // Not due until the sleep after finishing has passed
+        let count = client.extract(&definition, &connection).await.unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_pollers_share_cursor() {
+        let client = client().await;
+        let definition_id = Id::now(IdPrefix::ConnectionModelDefinition);
+
+        let (first, second) = tokio::join!(
+            client.cursor("live::stripe::default::key", definition_id),
+            client.cursor("live::stripe::default::key", definition_id),
+        );
+        assert_eq!(first.unwrap().id, second.unwrap().id);
+
+        let count = client
+            .cursors
+            .count(
+                doc! { "connectionModelDefinitionId": definition_id.to_string() },
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+
+        let duplicate = Cursor::new("live::stripe::default::key", definition_id);
+        assert!(client.cursors.create_one(&duplicate).await.is_err());
+    }
+}
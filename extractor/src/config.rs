@@ -0,0 +1,61 @@
+use envconfig::Envconfig;
+use osentities::{database::DatabaseConfig, secrets::SecretsConfig};
+use std::fmt::{Display, Formatter};
+
+#[derive(Envconfig, Clone)] // Intentionally no Debug so secret is not printed
+pub struct ExtractorServiceConfig {
+    #[envconfig(from = "POLL_INTERVAL_SECS", default = "10")]
+    pub poll_interval_secs: u64,
+    #[envconfig(from = "MAX_CONCURRENT_EXTRACTIONS", default = "10")]
+    pub max_concurrent_extractions: usize,
+    #[envconfig(from = "CACHE_SIZE", default = "100")]
+    pub cache_size: u64,
+    #[envconfig(from = "CONNECTION_CACHE_TTL_SECS", default = "120")]
+    pub connection_cache_ttl_secs: u64,
+    #[envconfig(from = "CONNECTION_MODEL_SCHEMA_TTL_SECS", default = "86400")]
+    pub connection_model_schema_cache_ttl_secs: u64,
+    #[envconfig(from = "CONNECTION_MODEL_DEFINITION_CACHE_TTL_SECS", default = "86400")]
+    pub connection_model_definition_cache_ttl_secs: u64,
+    #[envconfig(from = "SECRET_CACHE_TTL_SECS", default = "300")]
+    pub secret_cache_ttl_secs: u64,
+    #[envconfig(
+        from = "EVENT_ACCESS_PASSWORD",
+        default = "32KFFT_i4UpkJmyPwY2TGzgHpxfXs7zS"
+    )]
+    pub event_access_password: String,
+    #[envconfig(nested = true)]
+    pub secrets_config: SecretsConfig,
+    #[envconfig(nested = true)]
+    pub db_config: DatabaseConfig,
+}
+
+impl Display for ExtractorServiceConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "POLL_INTERVAL_SECS: {}", self.poll_interval_secs)?;
+        writeln!(
+            f,
+            "MAX_CONCURRENT_EXTRACTIONS: {}",
+            self.max_concurrent_extractions
+        )?;
+        writeln!(f, "CACHE_SIZE: {}", self.cache_size)?;
+        writeln!(
+            f,
+            "CONNECTION_CACHE_TTL_SECS: {}",
+            self.connection_cache_ttl_secs
+        )?;
+        writeln!(
+            f,
+            "CONNECTION_MODEL_SCHEMA_TTL_SECS: {}",
+            self.connection_model_schema_cache_ttl_secs
+        )?;
+        writeln!(
+            f,
+            "CONNECTION_MODEL_DEFINITION_CACHE_TTL_SECS: {}",
+            self.connection_model_definition_cache_ttl_secs
+        )?;
+        writeln!(f, "SECRET_CACHE_TTL_SECS: {}", self.secret_cache_ttl_secs)?;
+        writeln!(f, "EVENT_ACCESS_PASSWORD: ***")?;
+        write!(f, "{}", self.secrets_config)?;
+        writeln!(f, "{}", self.db_config)
+    }
+}
@@ -0,0 +1,28 @@
+mod client;
+mod config;
+
+use crate::client::ExtractorClient;
+use anyhow::{Context, Result};
+use config::ExtractorServiceConfig;
+use dotenvy::dotenv;
+use envconfig::Envconfig;
+use osentities::telemetry::{get_subscriber, init_subscriber};
+use tracing::info;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    dotenv().ok();
+
+    let suscriber = get_subscriber("extractor".into(), "info".into(), std::io::stdout, None);
+    init_subscriber(suscriber);
+
+    let config = ExtractorServiceConfig::init_from_env().context("Could not load config")?;
+
+    info!("Starting extractor with config:\n{config}");
+
+    let client = ExtractorClient::new(config).await?;
+
+    client.start().await?;
+
+    Ok(())
+}
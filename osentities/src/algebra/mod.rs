@@ -3,19 +3,23 @@ mod hash;
 mod json;
 mod oauth;
 mod pipeline;
+mod sandbox;
 mod secret;
 mod store;
 mod string;
 mod template;
 mod timed;
+mod transpiler;
 
 pub use crypto::*;
 pub use hash::*;
 pub use json::*;
 pub use oauth::*;
 pub use pipeline::*;
+pub use sandbox::*;
 pub use secret::*;
 pub use store::*;
 pub use string::*;
 pub use template::*;
 pub use timed::*;
+pub use transpiler::*;
@@ -0,0 +1,609 @@
+use crate::{InternalError, PicaError};
+use deno_core::{serde_v8, v8, JsRuntime, PollEventLoopOptions, RuntimeOptions};
+use indexmap::IndexSet;
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    cell::Cell,
+    fmt::{self, Display},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Arc, Mutex, OnceLock,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Subtype of the `script_error` returned when a script exceeds its wall-clock limit
+pub const SCRIPT_TIMEOUT_SUBTYPE: &str = "timeout";
+/// Subtype of the `script_error` returned when a script exceeds its heap limit
+pub const SCRIPT_HEAP_LIMIT_SUBTYPE: &str = "heap_limit";
+/// Subtype of the `script_error` returned when the sandbox has no room for another invocation
+pub const SCRIPT_QUEUE_FULL_SUBTYPE: &str = "queue_full";
+
+const SCRIPT_NAME: &str = "sandbox.js";
+/// Compiled namespaces kept by each worker, the least recently used one is evicted past this
+const MAX_NAMESPACES_PER_ISOLATE: usize = 1000;
+const CONSOLE: &str =
+    "globalThis.console = { log: function(expr) { Deno.core.print(expr + '\\n', false); } };";
+
+static GLOBAL: OnceLock<JsSandbox> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SandboxConfig {
+    /// Number of worker threads, each owning its own isolate
+    pub workers: usize,
+    /// Wall-clock limit of a single invocation
+    pub timeout: Duration,
+    /// Heap limit of the isolate of each worker
+    pub max_heap_bytes: usize,
+    /// Invocations waiting for a worker, further ones are rejected until the queue drains
+    pub queue_size: usize,
+}
+
+/// Compilation counters of the sandbox workers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SandboxStats {
+    /// Invocations of a namespace already compiled by the worker
+    pub compiled_hits: u64,
+    /// Invocations that compiled their namespace first
+    pub compiled_misses: u64,
+    /// Namespaces evicted from a worker
+    pub evictions: u64,
+}
+
+#[derive(Default)]
+struct SandboxCounters {
+    compiled_hits: AtomicU64,
+    compiled_misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            workers: 4,
+            timeout: Duration::from_secs(5),
+            max_heap_bytes: 64 * 1024 * 1024,
+            queue_size: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SandboxError {
+    /// The script did not return within the wall-clock limit
+    Timeout(Duration),
+    /// The script exhausted the heap of its isolate
+    HeapLimit(usize),
+    /// The script could not be compiled or threw
+    Script(String),
+    /// The payload or the result could not be converted
+    Serde(String),
+    /// No worker is available to run the script
+    Unavailable,
+    /// Every worker is busy and the queue of pending invocations is full
+    QueueFull(usize),
+}
+
+impl Display for SandboxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout(timeout) => write!(
+                f,
+                "Script exceeded its execution time limit of {}ms",
+                timeout.as_millis()
+            ),
+            Self::HeapLimit(bytes) => write!(f, "Script exceeded its heap limit of {bytes} bytes"),
+            Self::Script(message) | Self::Serde(message) => write!(f, "{message}"),
+            Self::Unavailable => write!(f, "Script sandbox is not available"),
+            Self::QueueFull(size) => write!(
+                f,
+                "Script sandbox is overloaded, {size} invocations are already queued"
+            ),
+        }
+    }
+}
+
+impl From<SandboxError> for PicaError {
+    fn from(error: SandboxError) -> Self {
+        let subtype = match error {
+            SandboxError::Timeout(_) => Some(SCRIPT_TIMEOUT_SUBTYPE),
+            SandboxError::HeapLimit(_) => Some(SCRIPT_HEAP_LIMIT_SUBTYPE),
+            SandboxError::QueueFull(_) => Some(SCRIPT_QUEUE_FULL_SUBTYPE),
+            _ => None,
+        };
+
+        InternalError::script_error(&error.to_string(), subtype)
+    }
+}
+
+/// A function defined by user code. Namespaced scripts are compiled once per worker and reused
+/// across invocations, anonymous ones are evaluated on every call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SandboxScript {
+    namespace: Option<String>,
+    fn_name: String,
+    code: String,
+}
+
+impl SandboxScript {
+    pub fn new(fn_name: &str, code: &str) -> Self {
+        Self {
+            namespace: None,
+            fn_name: fn_name.to_string(),
+            code: code.to_string(),
+        }
+    }
+
+    /// The namespace must be a valid JavaScript identifier
+    pub fn namespaced(mut self, namespace: &str) -> Self {
+        self.namespace = Some(namespace.to_string());
+        self
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    /// Expression evaluating to the function, without leaking the script into the global scope
+    fn factory(&self) -> String {
+        format!(
+            "(function() {{\n{}\n;return {};\n}})()",
+            self.code, self.fn_name
+        )
+    }
+}
+
+struct Job {
+    script: Arc<SandboxScript>,
+    payload: Value,
+    reply: Box<dyn FnOnce(Result<Value, SandboxError>) + Send>,
+}
+
+/// Pool of worker threads running user scripts, isolated from the async runtime. Every
+/// invocation is bounded by a wall-clock and a heap limit, the isolate of a worker is rebuilt
+/// after a script exceeds either of them.
+#[derive(Clone)]
+pub struct JsSandbox {
+    jobs: mpsc::SyncSender<Job>,
+    queue_size: usize,
+    counters: Arc<SandboxCounters>,
+}
+
+impl JsSandbox {
+    pub fn new(config: SandboxConfig) -> Result<Self, PicaError> {
+        let queue_size = config.queue_size.max(1);
+        let (jobs, receiver) = mpsc::sync_channel::<Job>(queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let counters = Arc::new(SandboxCounters::default());
+
+        for index in 0..config.workers.max(1) {
+            let receiver = receiver.clone();
+            let counters = counters.clone();
+
+            thread::Builder::new()
+                .name(format!("js-sandbox-{index}"))
+                .spawn(move || run_worker(receiver, config, counters))
+                .map_err(|e| {
+                    InternalError::configuration_error(
+                        &format!("Failed to spawn script sandbox worker: {e}"),
+                        None,
+                    )
+                })?;
+        }
+
+        Ok(Self {
+            jobs,
+            queue_size,
+            counters,
+        })
+    }
+
+    pub fn stats(&self) -> SandboxStats {
+        SandboxStats {
+            compiled_hits: self.counters.compiled_hits.load(Ordering::Relaxed),
+            compiled_misses: self.counters.compiled_misses.load(Ordering::Relaxed),
+            evictions: self.counters.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Initializes the process wide sandbox. Fails if it was already initialized, either by a
+    /// previous call or by a script running with the default configuration.
+    pub fn init(config: SandboxConfig) -> Result<&'static Self, PicaError> {
+        let sandbox = Self::new(config)?;
+
+        GLOBAL.set(sandbox).map_err(|_| {
+            InternalError::configuration_error("Script sandbox is already initialized", None)
+        })?;
+
+        Self::global()
+    }
+
+    /// Process wide sandbox, started with the default configuration unless initialized before
+    pub fn global() -> Result<&'static Self, PicaError> {
+        if let Some(sandbox) = GLOBAL.get() {
+            return Ok(sandbox);
+        }
+
+        // Losing a race against another initialization only drops the extra workers
+        let sandbox = Self::new(SandboxConfig::default())?;
+
+        Ok(GLOBAL.get_or_init(|| sandbox))
+    }
+
+    pub async fn call(
+        &self,
+        script: Arc<SandboxScript>,
+        payload: Value,
+    ) -> Result<Value, SandboxError> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+
+        self.submit(script, payload, move |result| {
+            let _ = tx.send(result);
+        })?;
+
+        rx.await.map_err(|_| SandboxError::Unavailable)?
+    }
+
+    fn submit(
+        &self,
+        script: Arc<SandboxScript>,
+        payload: Value,
+        reply: impl FnOnce(Result<Value, SandboxError>) + Send + 'static,
+    ) -> Result<(), SandboxError> {
+        self.jobs
+            .try_send(Job {
+                script,
+                payload,
+                reply: Box::new(reply),
+            })
+            .map_err(|e| match e {
+                mpsc::TrySendError::Full(_) => SandboxError::QueueFull(self.queue_size),
+                mpsc::TrySendError::Disconnected(_) => SandboxError::Unavailable,
+            })
+    }
+}
+
+/// Terminates the script running in an isolate once its deadline passes
+struct Watchdog {
+    deadlines: mpsc::Sender<Option<(Instant, v8::IsolateHandle)>>,
+    fired: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    fn spawn() -> Self {
+        let (deadlines, receiver) = mpsc::channel::<Option<(Instant, v8::IsolateHandle)>>();
+        let fired = Arc::new(AtomicBool::new(false));
+        let flag = fired.clone();
+
+        thread::spawn(move || {
+            while let Ok(message) = receiver.recv() {
+                let Some((deadline, handle)) = message else {
+                    continue;
+                };
+
+                match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(_) => {}
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        flag.store(true, Ordering::SeqCst);
+                        handle.terminate_execution();
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        });
+
+        Self { deadlines, fired }
+    }
+
+    fn arm(&self, timeout: Duration, handle: v8::IsolateHandle) {
+        self.fired.store(false, Ordering::SeqCst);
+        let _ = self
+            .deadlines
+            .send(Some((Instant::now() + timeout, handle)));
+    }
+
+    /// Returns whether the deadline was reached
+    fn disarm(&self) -> bool {
+        let _ = self.deadlines.send(None);
+        self.fired.load(Ordering::SeqCst)
+    }
+}
+
+struct Isolate {
+    runtime: JsRuntime,
+    /// Compiled namespaces, from the least to the most recently used
+    namespaces: IndexSet<String>,
+    heap_exceeded: Rc<Cell<bool>>,
+}
+
+impl Isolate {
+    fn new(max_heap_bytes: usize) -> Result<Self, SandboxError> {
+        let mut runtime = JsRuntime::new(RuntimeOptions {
+            create_params: Some(v8::CreateParams::default().heap_limits(0, max_heap_bytes)),
+            ..Default::default()
+        });
+
+        let heap_exceeded = Rc::new(Cell::new(false));
+        let flag = heap_exceeded.clone();
+        let handle = runtime.v8_isolate().thread_safe_handle();
+
+        // Terminating the script and raising the limit lets the isolate unwind instead of
+        // aborting the whole process. The isolate is dropped right after.
+        runtime.add_near_heap_limit_callback(move |current, _| {
+            flag.set(true);
+            handle.terminate_execution();
+            current * 2
+        });
+
+        runtime
+            .execute_script(SCRIPT_NAME, CONSOLE)
+            .map_err(|e| SandboxError::Script(e.to_string()))?;
+
+        Ok(Self {
+            runtime,
+            namespaces: IndexSet::new(),
+            heap_exceeded,
+        })
+    }
+
+    /// Compiles the namespace unless it already was, marking it as the most recently used
+    fn compile(
+        &mut self,
+        namespace: &str,
+        script: &SandboxScript,
+        counters: &SandboxCounters,
+    ) -> Result<(), SandboxError> {
+        if self.namespaces.shift_remove(namespace) {
+            counters.compiled_hits.fetch_add(1, Ordering::Relaxed);
+            self.namespaces.insert(namespace.to_string());
+
+            return Ok(());
+        }
+
+        counters.compiled_misses.fetch_add(1, Ordering::Relaxed);
+
+        while self.namespaces.len() >= MAX_NAMESPACES_PER_ISOLATE {
+            let Some(evicted) = self.namespaces.shift_remove_index(0) else {
+                break;
+            };
+
+            counters.evictions.fetch_add(1, Ordering::Relaxed);
+            self.runtime
+                .execute_script(SCRIPT_NAME, format!("delete globalThis.{evicted};"))
+                .map_err(|e| SandboxError::Script(e.to_string()))?;
+        }
+
+        self.runtime
+            .execute_script(
+                SCRIPT_NAME,
+                format!("globalThis.{namespace} = {};", script.factory()),
+            )
+            .map_err(|e| SandboxError::Script(e.to_string()))?;
+        self.namespaces.insert(namespace.to_string());
+
+        Ok(())
+    }
+
+    async fn execute(
+        &mut self,
+        script: &SandboxScript,
+        payload: &Value,
+        counters: &SandboxCounters,
+    ) -> Result<Value, SandboxError> {
+        let input =
+            serde_json::to_string(payload).map_err(|e| SandboxError::Serde(e.to_string()))?;
+
+        let source = match script.namespace() {
+            Some(namespace) => {
+                self.compile(namespace, script, counters)?;
+
+                format!("{namespace}({input})")
+            }
+            None => format!("{}({input})", script.factory()),
+        };
+
+        let value = self
+            .runtime
+            .execute_script(SCRIPT_NAME, source)
+            .map_err(|e| SandboxError::Script(e.to_string()))?;
+
+        let resolve = self.runtime.resolve(value);
+        let value = self
+            .runtime
+            .with_event_loop_promise(resolve, PollEventLoopOptions::default())
+            .await
+            .map_err(|e| SandboxError::Script(e.to_string()))?;
+
+        let scope = &mut self.runtime.handle_scope();
+        let value = v8::Local::new(scope, value);
+
+        if value.is_null_or_undefined() {
+            return Ok(Value::Null);
+        }
+
+        serde_v8::from_v8(scope, value).map_err(|e| SandboxError::Serde(e.to_string()))
+    }
+}
+
+fn run_worker(
+    jobs: Arc<Mutex<mpsc::Receiver<Job>>>,
+    config: SandboxConfig,
+    counters: Arc<SandboxCounters>,
+) {
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            tracing::error!("Failed to start script sandbox worker: {e}");
+            return;
+        }
+    };
+
+    let watchdog = Watchdog::spawn();
+    let mut isolate: Option<Isolate> = None;
+
+    loop {
+        let job = match jobs.lock().map(|jobs| jobs.recv()) {
+            Ok(Ok(job)) => job,
+            // The sandbox was dropped
+            _ => return,
+        };
+
+        let current = match isolate.take() {
+            Some(current) => Ok(current),
+            None => Isolate::new(config.max_heap_bytes),
+        };
+
+        let mut current = match current {
+            Ok(current) => current,
+            Err(e) => {
+                (job.reply)(Err(e));
+                continue;
+            }
+        };
+
+        watchdog.arm(
+            config.timeout,
+            current.runtime.v8_isolate().thread_safe_handle(),
+        );
+        let result = runtime.block_on(current.execute(&job.script, &job.payload, &counters));
+        let timed_out = watchdog.disarm();
+
+        // A terminated isolate is not reused
+        let result = if current.heap_exceeded.get() {
+            tracing::warn!("Script exceeded the sandbox heap limit");
+            Err(SandboxError::HeapLimit(config.max_heap_bytes))
+        } else if timed_out {
+            tracing::warn!("Script exceeded the sandbox execution time limit");
+            Err(SandboxError::Timeout(config.timeout))
+        } else {
+            isolate = Some(current);
+            result
+        };
+
+        (job.reply)(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn sandbox() -> JsSandbox {
+        JsSandbox::new(SandboxConfig {
+            workers: 1,
+            timeout: Duration::from_millis(200),
+            max_heap_bytes: 16 * 1024 * 1024,
+            queue_size: 8,
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_runs_scripts() {
+        let sandbox = sandbox();
+
+        let script = Arc::new(
+            SandboxScript::new("double", "function double(data) { return data.value * 2; }")
+                .namespaced("test_double"),
+        );
+
+        let result = sandbox
+            .call(script.clone(), json!({ "value": 21 }))
+            .await
+            .unwrap();
+        assert_eq!(result, json!(42));
+
+        let result = sandbox.call(script, json!({ "value": 1 })).await.unwrap();
+        assert_eq!(result, json!(2));
+        assert_eq!(
+            sandbox.stats(),
+            SandboxStats {
+                compiled_hits: 1,
+                compiled_misses: 1,
+                evictions: 0,
+            }
+        );
+
+        let script = Arc::new(SandboxScript::new(
+            "double",
+            "async function double(data) { return { value: data.value * 2 }; }",
+        ));
+
+        let result = sandbox.call(script, json!({ "value": 2 })).await.unwrap();
+        assert_eq!(result, json!({ "value": 4 }));
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_limits_execution_time() {
+        let sandbox = sandbox();
+
+        let script = Arc::new(SandboxScript::new(
+            "spin",
+            "function spin() { while (true) {} }",
+        ));
+
+        let error = sandbox.call(script, Value::Null).await.unwrap_err();
+        assert_eq!(error, SandboxError::Timeout(Duration::from_millis(200)));
+
+        // The worker recovers with a fresh isolate
+        let script = Arc::new(SandboxScript::new("one", "function one() { return 1; }"));
+        assert_eq!(sandbox.call(script, Value::Null).await.unwrap(), json!(1));
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_limits_heap() {
+        let sandbox = sandbox();
+
+        let script = Arc::new(SandboxScript::new(
+            "grow",
+            "function grow() { const data = []; while (true) { data.push(new Array(10000).fill('x')); } }",
+        ));
+
+        let error = sandbox.call(script, Value::Null).await.unwrap_err();
+        assert_eq!(error, SandboxError::HeapLimit(16 * 1024 * 1024));
+    }
+
+    #[tokio::test]
+    async fn test_sandbox_rejects_invocations_past_its_queue() {
+        let sandbox = JsSandbox::new(SandboxConfig {
+            workers: 1,
+            timeout: Duration::from_millis(500),
+            max_heap_bytes: 16 * 1024 * 1024,
+            queue_size: 1,
+        })
+        .unwrap();
+
+        let spin = Arc::new(SandboxScript::new(
+            "spin",
+            "function spin() { while (true) {} }",
+        ));
+
+        // One invocation keeps the worker busy while the second one fills the queue
+        let running = tokio::spawn({
+            let sandbox = sandbox.clone();
+            let spin = spin.clone();
+            async move { sandbox.call(spin, Value::Null).await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let queued = tokio::spawn({
+            let sandbox = sandbox.clone();
+            let spin = spin.clone();
+            async move { sandbox.call(spin, Value::Null).await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let error = sandbox.call(spin, Value::Null).await.unwrap_err();
+        assert_eq!(error, SandboxError::QueueFull(1));
+
+        assert!(running.await.unwrap().is_err());
+        assert!(queued.await.unwrap().is_err());
+    }
+}
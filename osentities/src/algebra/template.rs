@@ -24,6 +24,17 @@ impl Default for DefaultTemplate {
     }
 }
 
+impl DefaultTemplate {
+    /// Renders values as they are instead of escaping them for HTML, for templates producing
+    /// urls, headers or JSON strings
+    pub fn no_escape() -> Self {
+        let mut template = Handlebars::new();
+        template.register_escape_fn(handlebars::no_escape);
+
+        Self { template }
+    }
+}
+
 impl TemplateExt for DefaultTemplate {
     fn render(&self, template: &str, data: Option<&Value>) -> Result<String, PicaError> {
         self.template
@@ -136,4 +147,15 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(result.unwrap().hello, "{{hello}}");
     }
+
+    #[test]
+    fn test_render_without_escape() {
+        let data = Some(serde_json::json!({ "value": "it's a=b & \"c\"" }));
+
+        let result = DefaultTemplate::default().render("{{value}}", data.as_ref());
+        assert_eq!(result.unwrap(), "it&#x27;s a&#x3D;b &amp; &quot;c&quot;");
+
+        let result = DefaultTemplate::no_escape().render("{{value}}", data.as_ref());
+        assert_eq!(result.unwrap(), "it's a=b & \"c\"");
+    }
 }
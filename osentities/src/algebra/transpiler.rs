@@ -0,0 +1,68 @@
+use crate::{ApplicationError, PicaError};
+use deno_ast::{
+    parse_module, EmitOptions, MediaType, ModuleSpecifier, ParseParams, SourceMapOption,
+    TranspileModuleOptions, TranspileOptions,
+};
+
+const TYPESCRIPT_SPECIFIER: &str = "file:///script.ts";
+
+/// Strips the types of a TypeScript source, returning JavaScript that can be run by the sandbox
+///
+/// # Errors
+///
+/// Returns a `bad_request` application error with the diagnostic if the source doesn't parse
+/// or can't be emitted as JavaScript.
+pub fn transpile_typescript(source: &str) -> Result<String, PicaError> {
+    let specifier = ModuleSpecifier::parse(TYPESCRIPT_SPECIFIER).map_err(|e| {
+        ApplicationError::bad_request(&format!("Invalid TypeScript specifier: {e}"), None)
+    })?;
+
+    let parsed = parse_module(ParseParams {
+        specifier,
+        text: source.into(),
+        media_type: MediaType::TypeScript,
+        capture_tokens: false,
+        scope_analysis: false,
+        maybe_syntax: None,
+    })
+    .map_err(|e| {
+        tracing::error!("Failed to parse TypeScript source: {e}");
+        ApplicationError::bad_request(&format!("Failed to parse TypeScript source: {e}"), None)
+    })?;
+
+    let transpiled = parsed
+        .transpile(
+            &TranspileOptions::default(),
+            &TranspileModuleOptions::default(),
+            &EmitOptions {
+                source_map: SourceMapOption::None,
+                ..Default::default()
+            },
+        )
+        .map_err(|e| {
+            tracing::error!("Failed to transpile TypeScript source: {e}");
+            ApplicationError::bad_request(
+                &format!("Failed to transpile TypeScript source: {e}"),
+                None,
+            )
+        })?;
+
+    Ok(transpiled.into_source().text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transpile_typescript_strips_types() {
+        let code = transpile_typescript(
+            "interface Data { id: string }\nfunction compute(data: Data): string { return data.id as string; }",
+        )
+        .expect("Failed to transpile");
+
+        assert!(code.contains("function compute(data)"));
+        assert!(!code.contains("interface"));
+        assert!(transpile_typescript("function compute(data: { return 1; }").is_err());
+    }
+}
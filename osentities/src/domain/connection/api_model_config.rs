@@ -1,10 +1,15 @@
-use http::HeaderMap;
-use js_sandbox_ios::Script;
+use chrono::{DateTime, Utc};
+use http::{header::RETRY_AFTER, HeaderMap, Method, StatusCode};
+use rand::Rng;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::BTreeMap;
+use std::{borrow::Cow, collections::BTreeMap, sync::Arc, time::Duration};
 
-use crate::{prelude::schema::json_schema::JsonSchema, InternalError, PicaError};
+use crate::{
+    prelude::schema::{common_model::SchemaType, json_schema::JsonSchema},
+    transpile_typescript, ApplicationError, InternalError, JsSandbox, PicaError, SandboxError,
+    SandboxScript,
+};
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[cfg_attr(feature = "dummy", derive(fake::Dummy))]
@@ -28,6 +33,16 @@ pub struct ApiModelConfig {
     pub responses: Vec<ResponseBody>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub paths: Option<ModelPaths>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_policy: Option<RetryPolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimitPolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_cache: Option<ResponseCachePolicy>,
+    /// Validates unified bodies against the common model, requests failing validation are
+    /// rejected and responses failing it are reported as drift in the metadata
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub validation: Option<SchemaType>,
 }
 
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize, Default)]
@@ -37,10 +52,32 @@ pub struct ApiModelConfig {
 pub enum ContentType {
     Json,
     Form,
+    /// `multipart/form-data`, the body is a list of `MultipartPart`
+    Multipart,
+    /// Raw bytes, the body is a base64 encoded string or a `MultipartPart` carrying `data`
+    Binary,
     #[default]
     Other,
 }
 
+/// A part of a `multipart/form-data` request, as mapped from the common model. Text fields set
+/// `value`, files set `data` to their base64 encoded content.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub struct MultipartPart {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+}
+
 impl ApiModelConfig {
     /// Returns the full path of the API endpoint
     /// e.g. https://api.example.com/v1/users
@@ -54,6 +91,144 @@ impl ApiModelConfig {
     }
 }
 
+/// Retry policy for transient platform failures. Without a policy requests are sent exactly once.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase", default)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first request
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub retryable_status_codes: Vec<u16>,
+    pub respect_retry_after: bool,
+    pub jitter: bool,
+    /// Also retry methods that are not idempotent, such as `POST` and `PATCH`. Only enable this
+    /// for platforms that deduplicate writes, otherwise a retried request can apply twice.
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff_ms: 200,
+            max_backoff_ms: 10_000,
+            retryable_status_codes: vec![408, 425, 429, 500, 502, 503, 504],
+            respect_retry_after: true,
+            jitter: true,
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether requests with the given method may be sent more than once
+    pub fn applies_to(&self, method: &Method) -> bool {
+        self.retry_non_idempotent || method.is_idempotent()
+    }
+
+    pub fn is_retryable(&self, status: StatusCode) -> bool {
+        self.retryable_status_codes.contains(&status.as_u16())
+    }
+
+    /// Exponential backoff before retrying after the given (1-based) attempt, capped at
+    /// `max_backoff_ms`. With jitter enabled the delay is drawn from the upper half of the window.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let window = self
+            .initial_backoff_ms
+            .saturating_mul(2u64.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff_ms);
+
+        let delay = if self.jitter && window > 1 {
+            window / 2 + rand::thread_rng().gen_range(0..=window / 2)
+        } else {
+            window
+        };
+
+        Duration::from_millis(delay)
+    }
+
+    /// Delay requested through the `Retry-After` header, either in seconds or as an HTTP date
+    pub fn retry_after(&self, headers: &HeaderMap) -> Option<Duration> {
+        if !self.respect_retry_after {
+            return None;
+        }
+
+        let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+        match value.parse::<u64>() {
+            Ok(seconds) => Some(Duration::from_secs(seconds)),
+            Err(_) => DateTime::parse_from_rfc2822(value).ok().map(|date| {
+                (date.with_timezone(&Utc) - Utc::now())
+                    .to_std()
+                    .unwrap_or_default()
+            }),
+        }
+    }
+
+    /// Delay before the next attempt for a retryable response, or `None` if the platform asked
+    /// to wait longer than `max_backoff_ms`
+    pub fn delay_for(&self, attempt: u32, headers: &HeaderMap) -> Option<Duration> {
+        match self.retry_after(headers) {
+            Some(delay) if delay > Duration::from_millis(self.max_backoff_ms) => None,
+            Some(delay) => Some(delay),
+            None => Some(self.backoff(attempt)),
+        }
+    }
+}
+
+/// Outbound token bucket protecting a platform's own rate limits. Up to `capacity` requests are
+/// sent per `interval_ms`, and a request waits at most `max_wait_ms` for a token before failing.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimitPolicy {
+    pub capacity: u32,
+    pub interval_ms: u64,
+    #[serde(default = "default_max_wait_ms")]
+    pub max_wait_ms: u64,
+    #[serde(default)]
+    pub scope: RateLimitScope,
+}
+
+fn default_max_wait_ms() -> u64 {
+    5_000
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Deserialize, Serialize, Default)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub enum RateLimitScope {
+    /// One bucket per connection, for platforms that limit by access token
+    #[default]
+    Connection,
+    /// One bucket shared by every connection of the platform
+    Platform,
+}
+
+impl RateLimitPolicy {
+    /// Definitions sharing a scope only share a bucket when they also agree on its size, so a
+    /// stricter policy is never drained by a looser one
+    pub fn bucket_key(&self, platform: &str, connection_key: &str) -> String {
+        let size = format!("{}/{}", self.capacity, self.interval_ms);
+
+        match self.scope {
+            RateLimitScope::Connection => format!("{platform}::{connection_key}::{size}"),
+            RateLimitScope::Platform => format!("{platform}::{size}"),
+        }
+    }
+}
+
+/// Opts the read actions of a model definition into the unified response cache. Cached responses
+/// are served for `ttl_secs` or until a write on the same model and connection.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseCachePolicy {
+    pub ttl_secs: u64,
+}
+
 #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 #[cfg_attr(feature = "dummy", derive(fake::Dummy))]
 pub struct ModelPaths {
@@ -171,23 +346,29 @@ impl<T> fake::Dummy<T> for Function {
             entry: Faker.fake(),
             function: Faker.fake(),
             language: Lang::JavaScript,
+            compiled: None,
         })
     }
 }
 
 impl Function {
-    pub fn compute<T: DeserializeOwned>(&self, payload: &Value) -> Result<T, PicaError> {
-        let mut js_code = Script::from_string(&self.0.function).map_err(|e| {
-            InternalError::script_error(
-                &e.to_string(),
-                Some("Failed to create script from function"),
-            )
-        })?;
-        let response: T = js_code.call(&self.0.entry, (payload,)).map_err(|e| {
-            InternalError::script_error(&e.to_string(), Some("Failed to call function"))
-        })?;
+    /// Runs the function in the script sandbox, bounded by its execution time and heap limits
+    pub async fn compute<T: DeserializeOwned>(&self, payload: &Value) -> Result<T, PicaError> {
+        let script = SandboxScript::new(&self.0.entry, &self.0.script()?);
+
+        let response = JsSandbox::global()?
+            .call(Arc::new(script), payload.clone())
+            .await
+            .map_err(|e| match e {
+                SandboxError::Script(message) => {
+                    InternalError::script_error(&message, Some("Failed to call function"))
+                }
+                e => e.into(),
+            })?;
 
-        Ok(response)
+        serde_json::from_value(response).map_err(|e| {
+            InternalError::script_error(&e.to_string(), Some("Failed to call function"))
+        })
     }
 }
 
@@ -198,6 +379,30 @@ pub struct Compute {
     pub entry: String,
     pub function: String,
     pub language: Lang,
+    /// JavaScript emitted from the function when it isn't written in JavaScript
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compiled: Option<String>,
+}
+
+impl Compute {
+    /// Emits the JavaScript of the function, so that errors are reported when it's saved
+    pub fn compile(mut self) -> Result<Self, PicaError> {
+        self.compiled = self.language.compile(&self.function)?;
+
+        Ok(self)
+    }
+
+    /// JavaScript to run, transpiled on the fly if the function wasn't compiled when saved
+    pub fn script(&self) -> Result<Cow<'_, str>, PicaError> {
+        match &self.compiled {
+            Some(compiled) => Ok(Cow::Borrowed(compiled)),
+            None => Ok(self
+                .language
+                .compile(&self.function)?
+                .map(Cow::Owned)
+                .unwrap_or(Cow::Borrowed(&self.function))),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Default)]
@@ -209,3 +414,17 @@ pub enum Lang {
     TypeScript,
     Rust,
 }
+
+impl Lang {
+    /// JavaScript emitted from the source, `None` if the source is already JavaScript
+    pub fn compile(&self, source: &str) -> Result<Option<String>, PicaError> {
+        match self {
+            Lang::JavaScript => Ok(None),
+            Lang::TypeScript => transpile_typescript(source).map(Some),
+            Lang::Rust => Err(ApplicationError::bad_request(
+                "Rust functions are not supported, use JavaScript or TypeScript",
+                None,
+            )),
+        }
+    }
+}
@@ -1,7 +1,11 @@
-use super::api_model_config::ApiModelConfig;
+use super::api_model_config::{ApiModelConfig, Lang};
 use crate::{
     id::Id,
-    prelude::{schema::common_model::CommonModel, shared::record_metadata::RecordMetadata},
+    prelude::{
+        schema::{common_model::CommonModel, json_mapper::SchemaMappingDefinition},
+        shared::record_metadata::RecordMetadata,
+    },
+    PicaError,
 };
 use http::Method;
 use serde::{Deserialize, Serialize};
@@ -164,6 +168,55 @@ pub struct CrudMapping {
     pub common_model_name: String,
     pub from_common_model: Option<String>,
     pub to_common_model: Option<String>,
+    /// Declarative alternative to `from_common_model`, takes precedence over the script
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_common_model_schema: Option<SchemaMappingDefinition>,
+    /// Declarative alternative to `to_common_model`, takes precedence over the script
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_common_model_schema: Option<SchemaMappingDefinition>,
+    /// Language of the scripts, JavaScript if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "dummy", dummy(default))]
+    pub language: Option<Lang>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "dummy", dummy(default))]
+    pub compiled_from_common_model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "dummy", dummy(default))]
+    pub compiled_to_common_model: Option<String>,
+}
+
+impl CrudMapping {
+    /// Emits the JavaScript of the scripts, so that errors are reported when they're saved
+    pub fn compile(mut self) -> Result<Self, PicaError> {
+        let language = self.language.clone().unwrap_or_default();
+        let compile = |script: &Option<String>| {
+            script
+                .as_deref()
+                .map(|script| language.compile(script))
+                .transpose()
+                .map(Option::flatten)
+        };
+
+        self.compiled_from_common_model = compile(&self.from_common_model)?;
+        self.compiled_to_common_model = compile(&self.to_common_model)?;
+
+        Ok(self)
+    }
+
+    /// JavaScript of `from_common_model`
+    pub fn from_common_model_script(&self) -> Option<&str> {
+        self.compiled_from_common_model
+            .as_deref()
+            .or(self.from_common_model.as_deref())
+    }
+
+    /// JavaScript of `to_common_model`
+    pub fn to_common_model_script(&self) -> Option<&str> {
+        self.compiled_to_common_model
+            .as_deref()
+            .or(self.to_common_model.as_deref())
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize, Display, EnumIter)]
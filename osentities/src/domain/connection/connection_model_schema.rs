@@ -1,6 +1,10 @@
 use crate::{
     id::{prefix::IdPrefix, Id},
-    prelude::{schema::json_schema::JsonSchema, shared::record_metadata::RecordMetadata},
+    prelude::{
+        schema::{json_mapper::SchemaMappingDefinition, json_schema::JsonSchema},
+        shared::record_metadata::RecordMetadata,
+    },
+    ApplicationError, PicaError,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
@@ -41,11 +45,50 @@ pub struct SchemaPaths {
 #[cfg_attr(feature = "dummy", derive(fake::Dummy))]
 #[serde(rename_all = "camelCase")]
 pub struct Mappings {
+    #[serde(default)]
     pub from_common_model: String,
+    #[serde(default)]
     pub to_common_model: String,
     pub common_model_name: String,
     pub common_model_id: Id,
     pub unmapped_fields: JsonSchema,
+    /// Declarative alternative to `from_common_model`, takes precedence over the script
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_common_model_schema: Option<SchemaMappingDefinition>,
+    /// Declarative alternative to `to_common_model`, takes precedence over the script
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_common_model_schema: Option<SchemaMappingDefinition>,
+}
+
+impl Mappings {
+    /// Rejects mappings missing both the script and the schema of a direction
+    pub fn validate(&self) -> Result<(), PicaError> {
+        let missing = [
+            (
+                "fromCommonModel",
+                self.from_common_model.is_empty() && self.from_common_model_schema.is_none(),
+            ),
+            (
+                "toCommonModel",
+                self.to_common_model.is_empty() && self.to_common_model_schema.is_none(),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(direction, missing)| missing.then_some(direction))
+        .collect::<Vec<_>>();
+
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        Err(ApplicationError::bad_request(
+            &format!(
+                "Mapping requires either a script or a schema for {}",
+                missing.join(" and ")
+            ),
+            None,
+        ))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
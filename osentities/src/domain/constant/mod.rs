@@ -37,6 +37,8 @@ pub const JWT_SECRET_REF_NAME: &str = "event-secrets";
 
 // Header constants
 pub const PICA_PASSTHROUGH_HEADER: &str = "x-pica-passthrough";
+pub const TASK_SIGNATURE_HEADER: &str = "x-pica-signature";
+pub const TASK_TIMESTAMP_HEADER: &str = "x-pica-timestamp";
 
 // Encryption constants
 pub const HASH_LENGTH: usize = 32;
@@ -106,6 +108,10 @@ pub const PAGINATION_KEY: &str = "pagination";
 pub const STATUS_HEADER_KEY: &str = "response-status";
 pub const META_KEY: &str = "meta";
 pub const ACTION_KEY: &str = "action";
+pub const AUTO_PAGINATE_KEY: &str = "autoPaginate";
+pub const MAX_PAGES_KEY: &str = "maxPages";
+pub const MAX_RECORDS_KEY: &str = "maxRecords";
+pub const AUTO_PAGINATE_MAX_PAGES: u32 = 1000;
 
 // Database constants
 pub const MAX_LIMIT: usize = 100;
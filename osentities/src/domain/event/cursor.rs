@@ -0,0 +1,44 @@
+use crate::{
+    id::{prefix::IdPrefix, Id},
+    record_metadata::RecordMetadata,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Progress of an incremental extraction for a single connection and connection model definition
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Cursor {
+    #[serde(rename = "_id")]
+    pub id: Id,
+    pub connection_key: String,
+    pub connection_model_definition_id: Id,
+    pub value: Option<Value>,
+    pub last_run_at: Option<i64>,
+    pub last_completed_at: Option<i64>,
+    pub next_run_at: i64,
+    pub records_extracted: i64,
+    #[serde(flatten)]
+    pub metadata: RecordMetadata,
+}
+
+impl Cursor {
+    pub fn new(connection_key: &str, connection_model_definition_id: Id) -> Self {
+        Self {
+            id: Id::now(IdPrefix::Cursor),
+            connection_key: connection_key.to_string(),
+            connection_model_definition_id,
+            value: None,
+            last_run_at: None,
+            last_completed_at: None,
+            next_run_at: Utc::now().timestamp_millis(),
+            records_extracted: 0,
+            metadata: RecordMetadata::default(),
+        }
+    }
+
+    pub fn is_due(&self) -> bool {
+        self.next_run_at <= Utc::now().timestamp_millis()
+    }
+}
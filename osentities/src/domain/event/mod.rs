@@ -1,8 +1,11 @@
+pub mod cursor;
 pub mod emitted_events;
 pub mod event_access;
 pub mod event_state;
 pub mod hashes;
 pub mod task;
+pub mod task_schedule;
+pub mod workflow;
 
 use self::{
     event_state::EventState,
@@ -1,7 +1,17 @@
-use crate::{record_metadata::RecordMetadata, Id};
+use super::task_schedule::TaskSchedule;
+use crate::{
+    id::prefix::IdPrefix,
+    prelude::{configuration::environment::Environment, shared::ownership::Ownership},
+    record_metadata::RecordMetadata,
+    ApplicationError, Id, InternalError, PicaError, TASK_SIGNATURE_HEADER, TASK_TIMESTAMP_HEADER,
+};
 use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use http::{HeaderMap, Method};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use sha2::Sha256;
+use std::fmt::{Display, Formatter};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
@@ -13,9 +23,315 @@ pub struct Task {
     pub end_time: Option<i64>,
     pub payload: Value,
     pub endpoint: String,
+    #[serde(with = "http_serde_ext_ios::method", default = "default_method")]
+    pub method: Method,
+    #[serde(
+        with = "http_serde_ext_ios::header_map",
+        default,
+        skip_serializing_if = "HeaderMap::is_empty"
+    )]
+    pub headers: HeaderMap,
+    /// Secret of the task's ownership its requests are signed with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_secret_id: Option<String>,
     pub status: Option<String>,
     pub r#await: bool,
     pub log_trail: Vec<Bytes>,
+    #[serde(default)]
+    pub state: TaskState,
+    #[serde(default = "default_max_attempts")]
+    pub max_attempts: u32,
+    /// Attempts made so far, including the running one
+    #[serde(default)]
+    pub attempt: u32,
+    #[serde(default)]
+    pub backoff: TaskBackoff,
+    /// When a retry is due, the task is not run before `start_time` otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_run_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// Until when the watchdog replica running the task holds it, it may be reclaimed after
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lease_expires_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<TaskSchedule>,
+    /// First occurrence of a recurring task, shared by all of its occurrences
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub series_id: Option<Id>,
+    /// Workflow and stage the task runs, see [`super::workflow::Workflow`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workflow_id: Option<Id>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stage_id: Option<Id>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ownership: Option<Ownership>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<Environment>,
     #[serde(flatten)]
     pub metadata: RecordMetadata,
 }
+
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 1;
+
+fn default_max_attempts() -> u32 {
+    DEFAULT_MAX_ATTEMPTS
+}
+
+fn default_method() -> Method {
+    Method::POST
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TaskState {
+    #[default]
+    Pending,
+    Running,
+    /// Failed with a retryable error and scheduled to run again at `next_run_at`
+    Retrying,
+    Succeeded,
+    /// Failed with an error that can't be retried or ran out of attempts, it is only run again
+    /// when replayed
+    DeadLettered,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub enum BackoffStrategy {
+    Fixed,
+    #[default]
+    Exponential,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub struct TaskBackoff {
+    #[serde(default)]
+    pub strategy: BackoffStrategy,
+    pub delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for TaskBackoff {
+    fn default() -> Self {
+        Self {
+            strategy: BackoffStrategy::Exponential,
+            delay_ms: 1_000,
+            max_delay_ms: 300_000,
+        }
+    }
+}
+
+impl TaskBackoff {
+    /// Delay before the retry following the given attempt, starting at 1
+    pub fn delay(&self, attempt: u32) -> u64 {
+        match self.strategy {
+            BackoffStrategy::Fixed => self.delay_ms,
+            BackoffStrategy::Exponential => self
+                .delay_ms
+                .saturating_mul(2u64.saturating_pow(attempt.saturating_sub(1)))
+                .min(self.max_delay_ms),
+        }
+    }
+}
+
+/// Why an attempt to run a task failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TaskFailure {
+    Status(u16),
+    Timeout,
+    Connect,
+    Other(String),
+}
+
+impl TaskFailure {
+    /// Server errors, timeouts and connection errors may succeed on a later attempt
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            TaskFailure::Status(status) => *status >= 500,
+            TaskFailure::Timeout | TaskFailure::Connect => true,
+            TaskFailure::Other(_) => false,
+        }
+    }
+}
+
+impl Display for TaskFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaskFailure::Status(status) => write!(f, "Endpoint responded with status {status}"),
+            TaskFailure::Timeout => write!(f, "Request timed out"),
+            TaskFailure::Connect => write!(f, "Could not connect to the endpoint"),
+            TaskFailure::Other(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl Task {
+    /// When the task should run again after the current attempt failed, if it should
+    pub fn next_retry_at(&self, failure: &TaskFailure, now: i64) -> Option<i64> {
+        (failure.is_retryable() && self.attempt < self.max_attempts)
+            .then(|| now.saturating_add(self.backoff.delay(self.attempt) as i64))
+    }
+
+    /// The occurrence following this one if the task is recurring and its schedule has not
+    /// ended. It starts with all of its attempts.
+    pub fn next_occurrence(&self, now: i64) -> Result<Option<Task>, PicaError> {
+        let Some(schedule) = &self.schedule else {
+            return Ok(None);
+        };
+
+        let Some(start_time) = schedule.next_run(self.start_time, now)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(Task {
+            id: Id::now(IdPrefix::Task),
+            worker_id: 0,
+            start_time,
+            end_time: None,
+            status: None,
+            log_trail: vec![],
+            state: TaskState::Pending,
+            attempt: 0,
+            next_run_at: None,
+            last_error: None,
+            lease_expires_at: None,
+            schedule: Some(TaskSchedule {
+                run: schedule.run + 1,
+                ..schedule.clone()
+            }),
+            series_id: Some(self.series_id.unwrap_or(self.id)),
+            metadata: RecordMetadata::default(),
+            ..self.clone()
+        }))
+    }
+}
+
+/// Response body of a task from its log trail, as JSON when it is valid JSON
+pub fn response_body(log_trail: &[Bytes]) -> Value {
+    let body = log_trail.concat();
+
+    serde_json::from_slice(&body)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned()))
+}
+
+/// Rejects the headers the watchdog sets on signed requests, they can't be set on a task
+pub fn check_reserved_headers(headers: &HeaderMap) -> Result<(), PicaError> {
+    match [TASK_SIGNATURE_HEADER, TASK_TIMESTAMP_HEADER]
+        .into_iter()
+        .find(|header| headers.contains_key(*header))
+    {
+        Some(header) => Err(ApplicationError::bad_request(
+            &format!("The {header} header is set by Pica and can't be overridden"),
+            None,
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Signature of a task request, sent as `sha256=<hex HMAC-SHA256>` of `<timestamp>.<body>`.
+/// Receivers recompute it to check the request came from Pica, and compare the timestamp to
+/// their clock to reject replayed requests.
+pub fn sign_task_request(secret: &str, timestamp: i64, body: &[u8]) -> Result<String, PicaError> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .map_err(|e| InternalError::encryption_error(&e.to_string(), None))?;
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+
+    Ok(format!(
+        "sha256={}",
+        hex::encode(mac.finalize().into_bytes())
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task_schedule::Recurrence;
+
+    #[test]
+    fn test_retries_until_attempts_are_exhausted() {
+        let mut task: Task = serde_json::from_value(serde_json::json!({
+            "_id": Id::now(IdPrefix::Task),
+            "workerId": 0,
+            "startTime": 0,
+            "endTime": null,
+            "payload": {},
+            "endpoint": "http://localhost",
+            "status": null,
+            "await": false,
+            "logTrail": [],
+            "maxAttempts": 3,
+        }))
+        .expect("Failed to deserialize task");
+
+        assert_eq!(task.state, TaskState::Pending);
+        assert_eq!(task.method, Method::POST);
+        assert!(task.headers.is_empty());
+        assert_eq!(task.backoff, TaskBackoff::default());
+
+        task.attempt = 1;
+        assert_eq!(
+            task.next_retry_at(&TaskFailure::Status(503), 0),
+            Some(1_000)
+        );
+        assert_eq!(task.next_retry_at(&TaskFailure::Status(404), 0), None);
+
+        task.attempt = 2;
+        assert_eq!(task.next_retry_at(&TaskFailure::Timeout, 0), Some(2_000));
+
+        task.attempt = 3;
+        assert_eq!(task.next_retry_at(&TaskFailure::Connect, 0), None);
+
+        let backoff = TaskBackoff {
+            strategy: BackoffStrategy::Exponential,
+            delay_ms: 1_000,
+            max_delay_ms: 5_000,
+        };
+        assert_eq!(backoff.delay(10), 5_000);
+        assert_eq!(task.next_occurrence(0).unwrap(), None);
+
+        task.schedule = Some(TaskSchedule {
+            recurrence: Recurrence::IntervalSecs(60),
+            timezone: "UTC".to_string(),
+            end_at: None,
+            max_runs: Some(2),
+            run: 1,
+            paused: false,
+        });
+
+        let next = task
+            .next_occurrence(0)
+            .unwrap()
+            .expect("Missing next occurrence");
+        assert_eq!(next.start_time, 60_000);
+        assert_eq!(next.attempt, 0);
+        assert_eq!(next.series_id, Some(task.id));
+        assert_eq!(next.next_occurrence(60_000).unwrap(), None);
+    }
+
+    #[test]
+    fn test_sign_task_request() {
+        let signature =
+            sign_task_request("secret", 1_700_000_000_000, b"{}").expect("Failed to sign request");
+
+        assert!(signature.starts_with("sha256="));
+        assert_eq!(signature.len(), "sha256=".len() + 64);
+        assert_eq!(
+            signature,
+            sign_task_request("secret", 1_700_000_000_000, b"{}").unwrap()
+        );
+        assert_ne!(
+            signature,
+            sign_task_request("secret", 1_700_000_000_001, b"{}").unwrap()
+        );
+        assert_ne!(
+            signature,
+            sign_task_request("other", 1_700_000_000_000, b"{}").unwrap()
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use crate::{ApplicationError, PicaError};
+use chrono::{DateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use cron::Schedule;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+const DEFAULT_TIMEZONE: &str = "UTC";
+/// A year, longer intervals are better expressed as cron expressions
+pub const MAX_INTERVAL_SECS: u64 = 366 * 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub enum Recurrence {
+    /// Cron expression, with or without the seconds field, e.g. `0 9 * * Mon-Fri`
+    Cron(String),
+    IntervalSecs(u64),
+}
+
+/// Makes a task recurring. Each occurrence is a task of its own, created when the previous one
+/// completes, until `end_at` or `max_runs` is reached.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub struct TaskSchedule {
+    #[serde(flatten)]
+    pub recurrence: Recurrence,
+    /// IANA name of the timezone cron expressions are evaluated in
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_runs: Option<u32>,
+    /// Number of the occurrence, starting at 1
+    #[serde(default = "first_run")]
+    pub run: u32,
+    #[serde(default)]
+    pub paused: bool,
+}
+
+fn default_timezone() -> String {
+    DEFAULT_TIMEZONE.to_string()
+}
+
+fn first_run() -> u32 {
+    1
+}
+
+impl TaskSchedule {
+    /// Checks the schedule of a task first running at `start_time`
+    pub fn validate(&self, start_time: i64) -> Result<(), PicaError> {
+        self.timezone()?;
+
+        if self.end_at.is_some_and(|end_at| end_at < start_time) {
+            return Err(ApplicationError::bad_request(
+                "The end of a schedule can't be before its start time",
+                None,
+            ));
+        }
+
+        match &self.recurrence {
+            Recurrence::Cron(_) => self.cron().map(|_| ()),
+            Recurrence::IntervalSecs(0) => Err(ApplicationError::bad_request(
+                "The interval of a schedule must be at least one second",
+                None,
+            )),
+            Recurrence::IntervalSecs(interval) if *interval > MAX_INTERVAL_SECS => {
+                Err(ApplicationError::bad_request(
+                    &format!(
+                        "The interval of a schedule can't be longer than {MAX_INTERVAL_SECS} seconds"
+                    ),
+                    None,
+                ))
+            }
+            Recurrence::IntervalSecs(_) => Ok(()),
+        }
+    }
+
+    fn timezone(&self) -> Result<Tz, PicaError> {
+        Tz::from_str(&self.timezone).map_err(|e| {
+            ApplicationError::bad_request(&format!("Invalid schedule timezone: {e}"), None)
+        })
+    }
+
+    fn cron(&self) -> Result<Schedule, PicaError> {
+        let Recurrence::Cron(expression) = &self.recurrence else {
+            return Err(ApplicationError::bad_request(
+                "The schedule is not a cron expression",
+                None,
+            ));
+        };
+
+        // The cron crate expects seconds, the usual five fields expression runs on the minute
+        let expression = match expression.split_whitespace().count() {
+            5 => format!("0 {expression}"),
+            _ => expression.to_string(),
+        };
+
+        Schedule::from_str(&expression).map_err(|e| {
+            ApplicationError::bad_request(&format!("Invalid cron expression: {e}"), None)
+        })
+    }
+
+    /// Time of the occurrence following the one at `previous`, skipping the ones already past
+    /// `now`. `None` once the schedule has ended.
+    pub fn next_run(&self, previous: i64, now: i64) -> Result<Option<i64>, PicaError> {
+        if self.max_runs.is_some_and(|max_runs| self.run >= max_runs) {
+            return Ok(None);
+        }
+
+        let next = match &self.recurrence {
+            Recurrence::Cron(_) => {
+                let after = to_datetime(previous.max(now))?.with_timezone(&self.timezone()?);
+
+                self.cron()?
+                    .after(&after)
+                    .next()
+                    .map(|next| next.timestamp_millis())
+            }
+            Recurrence::IntervalSecs(interval) => {
+                let interval = i64::try_from(*interval)
+                    .unwrap_or(i64::MAX)
+                    .saturating_mul(1_000)
+                    .max(1);
+                let next = previous.saturating_add(interval);
+
+                Some(if next > now {
+                    next
+                } else {
+                    next + ((now - next) / interval + 1) * interval
+                })
+            }
+        };
+
+        Ok(next.filter(|next| !self.end_at.is_some_and(|end_at| *next > end_at)))
+    }
+
+    /// Times of up to `count` occurrences following the one at `from`
+    pub fn upcoming(&self, from: i64, now: i64, count: usize) -> Result<Vec<i64>, PicaError> {
+        let mut schedule = self.clone();
+        let mut previous = from;
+        let mut runs = vec![];
+
+        while runs.len() < count {
+            let Some(next) = schedule.next_run(previous, now)? else {
+                break;
+            };
+
+            runs.push(next);
+            previous = next;
+            schedule.run += 1;
+        }
+
+        Ok(runs)
+    }
+}
+
+fn to_datetime(timestamp: i64) -> Result<DateTime<Utc>, PicaError> {
+    Utc.timestamp_millis_opt(timestamp).single().ok_or_else(|| {
+        ApplicationError::bad_request(&format!("Invalid timestamp: {timestamp}"), None)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(recurrence: Recurrence) -> TaskSchedule {
+        TaskSchedule {
+            recurrence,
+            timezone: default_timezone(),
+            end_at: None,
+            max_runs: None,
+            run: 1,
+            paused: false,
+        }
+    }
+
+    fn millis(datetime: &str) -> i64 {
+        DateTime::parse_from_rfc3339(datetime)
+            .expect("Invalid datetime")
+            .timestamp_millis()
+    }
+
+    #[test]
+    fn test_next_cron_run_in_timezone() {
+        let mut schedule = schedule(Recurrence::Cron("0 9 * * *".to_string()));
+        schedule.timezone = "Europe/Paris".to_string();
+        schedule.validate(0).expect("Invalid schedule");
+
+        let previous = millis("2024-03-30T08:00:00Z");
+
+        assert_eq!(
+            schedule.next_run(previous, previous).unwrap(),
+            Some(millis("2024-03-31T07:00:00Z"))
+        );
+
+        schedule.end_at = Some(millis("2024-03-31T00:00:00Z"));
+        assert_eq!(schedule.next_run(previous, previous).unwrap(), None);
+
+        schedule.timezone = "Mars/Olympus".to_string();
+        assert!(schedule.validate(0).is_err());
+    }
+
+    #[test]
+    fn test_interval_runs_skip_missed_occurrences() {
+        let mut schedule = schedule(Recurrence::IntervalSecs(60));
+        schedule.max_runs = Some(3);
+
+        assert_eq!(schedule.next_run(0, 0).unwrap(), Some(60_000));
+        assert_eq!(schedule.next_run(0, 150_000).unwrap(), Some(180_000));
+        assert_eq!(schedule.upcoming(0, 0, 5).unwrap(), vec![60_000, 120_000]);
+
+        schedule.run = 3;
+        assert_eq!(schedule.next_run(0, 0).unwrap(), None);
+
+        assert!(TaskSchedule {
+            recurrence: Recurrence::IntervalSecs(0),
+            ..schedule
+        }
+        .validate(0)
+        .is_err());
+    }
+
+    #[test]
+    fn test_validate_interval_and_end() {
+        let mut schedule = schedule(Recurrence::IntervalSecs(MAX_INTERVAL_SECS));
+        assert!(schedule.validate(0).is_ok());
+
+        schedule.end_at = Some(59_999);
+        assert!(schedule.validate(60_000).is_err());
+        assert!(schedule.validate(59_999).is_ok());
+
+        schedule.recurrence = Recurrence::IntervalSecs(u64::MAX);
+        assert!(schedule.validate(0).is_err());
+        // Stored schedules are not validated again, their runs still can't overflow
+        assert_eq!(schedule.next_run(0, 0).unwrap(), None);
+    }
+}
@@ -0,0 +1,582 @@
+use super::task::{check_reserved_headers, Task, TaskBackoff, TaskState, DEFAULT_MAX_ATTEMPTS};
+use crate::{
+    id::prefix::IdPrefix,
+    prelude::{configuration::environment::Environment, shared::ownership::Ownership},
+    record_metadata::RecordMetadata,
+    ApplicationError, DefaultTemplate, Id, PicaError, TemplateExt,
+};
+use http::{HeaderMap, HeaderValue, Method};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{HashMap, HashSet};
+
+/// Tasks run as a DAG. A stage is released, i.e. its task is created, once all the stages it
+/// depends on succeeded. It is skipped if any of them failed or was skipped.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Workflow {
+    #[serde(rename = "_id")]
+    pub id: Id,
+    pub name: String,
+    pub stages: Vec<WorkflowStage>,
+    #[serde(default)]
+    pub state: WorkflowState,
+    /// Incremented on every save, stages completing at the same time are recorded one by one
+    #[serde(default)]
+    pub revision: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ownership: Option<Ownership>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<Environment>,
+    #[serde(flatten)]
+    pub metadata: RecordMetadata,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum WorkflowState {
+    #[default]
+    Running,
+    Succeeded,
+    /// At least one stage failed, the stages depending on it were skipped
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum StageStatus {
+    /// Waiting for the stages it depends on
+    #[default]
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Skipped,
+}
+
+impl StageStatus {
+    pub fn is_done(&self) -> bool {
+        matches!(
+            self,
+            StageStatus::Succeeded | StageStatus::Failed | StageStatus::Skipped
+        )
+    }
+}
+
+/// What a stage runs. The endpoint, header values and every string of the payload are
+/// Handlebars templates rendered, without escaping, with the responses of the stages it depends
+/// on, e.g. `{{stages.contact.response.id}}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StageDefinition {
+    /// Name of the stage, unique in the workflow
+    pub key: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    pub endpoint: String,
+    #[serde(with = "http_serde_ext_ios::method", default = "default_method")]
+    pub method: Method,
+    #[serde(
+        with = "http_serde_ext_ios::header_map",
+        default,
+        skip_serializing_if = "HeaderMap::is_empty"
+    )]
+    pub headers: HeaderMap,
+    #[serde(default)]
+    pub payload: Value,
+    #[serde(rename = "await", default)]
+    pub r#await: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_attempts: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backoff: Option<TaskBackoff>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_secret_id: Option<String>,
+}
+
+fn default_method() -> Method {
+    Method::POST
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkflowStage {
+    #[serde(rename = "_id")]
+    pub id: Id,
+    #[serde(flatten)]
+    pub definition: StageDefinition,
+    #[serde(default)]
+    pub status: StageStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_id: Option<Id>,
+    /// Response body of the task, parsed as JSON when possible
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Workflow {
+    pub fn new(name: String, stages: Vec<StageDefinition>) -> Result<Self, PicaError> {
+        validate(&stages)?;
+
+        Ok(Self {
+            id: Id::now(IdPrefix::Job),
+            name,
+            stages: stages
+                .into_iter()
+                .map(|definition| WorkflowStage {
+                    id: Id::now(IdPrefix::JobStage),
+                    definition,
+                    status: StageStatus::Pending,
+                    task_id: None,
+                    response: None,
+                    error: None,
+                })
+                .collect(),
+            state: WorkflowState::Running,
+            revision: 0,
+            ownership: None,
+            environment: None,
+            metadata: RecordMetadata::default(),
+        })
+    }
+
+    /// Records the outcome of the task of a running stage, `Ok` with its response when it
+    /// succeeded. Returns whether the stage was running.
+    pub fn complete_stage(&mut self, stage_id: Id, outcome: Result<Value, String>) -> bool {
+        let Some(stage) = self
+            .stages
+            .iter_mut()
+            .find(|stage| stage.id == stage_id && stage.status == StageStatus::Running)
+        else {
+            return false;
+        };
+
+        match outcome {
+            Ok(response) => {
+                stage.status = StageStatus::Succeeded;
+                stage.response = Some(response);
+            }
+            Err(error) => {
+                stage.status = StageStatus::Failed;
+                stage.error = Some(error);
+            }
+        }
+
+        true
+    }
+
+    /// Skips the stages depending on a failed stage and creates the tasks of the stages whose
+    /// dependencies all succeeded. A stage that can't be rendered fails, and the stages
+    /// depending on it are skipped. The workflow ends once no stage is left to run.
+    pub fn release(&mut self) -> Vec<Task> {
+        // Responses are passed on as they are, not escaped for HTML
+        let template = DefaultTemplate::no_escape();
+        let mut tasks = vec![];
+
+        loop {
+            self.skip_dependents_of_failed();
+
+            let statuses = self.statuses();
+            let context = self.context();
+            let mut failed = false;
+
+            for index in 0..self.stages.len() {
+                let stage = &self.stages[index];
+
+                if stage.status != StageStatus::Pending
+                    || !stage
+                        .definition
+                        .depends_on
+                        .iter()
+                        .all(|key| statuses.get(key.as_str()) == Some(&StageStatus::Succeeded))
+                {
+                    continue;
+                }
+
+                let task = self.task(stage, &template, &context);
+
+                let stage = &mut self.stages[index];
+                match task {
+                    Ok(task) => {
+                        stage.status = StageStatus::Running;
+                        stage.task_id = Some(task.id);
+                        tasks.push(task);
+                    }
+                    Err(e) => {
+                        stage.status = StageStatus::Failed;
+                        stage.error = Some(format!("Could not render the stage: {e}"));
+                        failed = true;
+                    }
+                }
+            }
+
+            if !failed {
+                break;
+            }
+        }
+
+        if self.stages.iter().all(|stage| stage.status.is_done()) {
+            self.state = if self
+                .stages
+                .iter()
+                .all(|stage| stage.status == StageStatus::Succeeded)
+            {
+                WorkflowState::Succeeded
+            } else {
+                WorkflowState::Failed
+            };
+        }
+
+        tasks
+    }
+
+    fn skip_dependents_of_failed(&mut self) {
+        loop {
+            let statuses = self.statuses();
+            let mut skipped = false;
+
+            for stage in self.stages.iter_mut() {
+                if stage.status == StageStatus::Pending
+                    && stage.definition.depends_on.iter().any(|key| {
+                        matches!(
+                            statuses.get(key.as_str()),
+                            Some(StageStatus::Failed | StageStatus::Skipped)
+                        )
+                    })
+                {
+                    stage.status = StageStatus::Skipped;
+                    skipped = true;
+                }
+            }
+
+            if !skipped {
+                return;
+            }
+        }
+    }
+
+    fn statuses(&self) -> HashMap<String, StageStatus> {
+        self.stages
+            .iter()
+            .map(|stage| (stage.definition.key.clone(), stage.status))
+            .collect()
+    }
+
+    /// Data the stages are rendered with, `{ "stages": { <key>: { "status", "response" } } }`
+    fn context(&self) -> Value {
+        let stages = self
+            .stages
+            .iter()
+            .map(|stage| {
+                (
+                    stage.definition.key.clone(),
+                    json!({
+                        "status": stage.status,
+                        "response": stage.response,
+                    }),
+                )
+            })
+            .collect::<Map<_, _>>();
+
+        json!({ "stages": stages })
+    }
+
+    fn task(
+        &self,
+        stage: &WorkflowStage,
+        template: &impl TemplateExt,
+        context: &Value,
+    ) -> Result<Task, PicaError> {
+        let definition = &stage.definition;
+
+        let endpoint = template.render(&definition.endpoint, Some(context))?;
+        let payload = render_payload(template, &definition.payload, context)?;
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in definition.headers.iter() {
+            let value = value.to_str().map_err(|e| {
+                ApplicationError::bad_request(&format!("Invalid header {name}: {e}"), None)
+            })?;
+            let value =
+                HeaderValue::from_str(&template.render(value, Some(context))?).map_err(|e| {
+                    ApplicationError::bad_request(&format!("Invalid header {name}: {e}"), None)
+                })?;
+
+            headers.append(name.clone(), value);
+        }
+
+        Ok(Task {
+            id: Id::now(IdPrefix::Task),
+            worker_id: 0,
+            start_time: chrono::Utc::now().timestamp_millis(),
+            end_time: None,
+            payload,
+            endpoint,
+            method: definition.method.clone(),
+            headers,
+            signing_secret_id: definition.signing_secret_id.clone(),
+            status: None,
+            r#await: definition.r#await,
+            log_trail: vec![],
+            state: TaskState::Pending,
+            max_attempts: definition
+                .max_attempts
+                .unwrap_or(DEFAULT_MAX_ATTEMPTS)
+                .max(1),
+            attempt: 0,
+            backoff: definition.backoff.clone().unwrap_or_default(),
+            next_run_at: None,
+            last_error: None,
+            lease_expires_at: None,
+            schedule: None,
+            series_id: None,
+            workflow_id: Some(self.id),
+            stage_id: Some(stage.id),
+            ownership: self.ownership.clone(),
+            environment: self.environment,
+            metadata: RecordMetadata::default(),
+        })
+    }
+}
+
+/// Stages must have unique keys and depend on other stages of the workflow, without cycles.
+/// Like tasks, they can't set the headers of signed requests.
+fn validate(stages: &[StageDefinition]) -> Result<(), PicaError> {
+    if stages.is_empty() {
+        return Err(ApplicationError::bad_request(
+            "A workflow must have at least one stage",
+            None,
+        ));
+    }
+
+    let mut keys = HashSet::new();
+    for stage in stages {
+        check_reserved_headers(&stage.headers)?;
+
+        if !keys.insert(stage.key.as_str()) {
+            return Err(ApplicationError::bad_request(
+                &format!("Duplicate stage key {}", stage.key),
+                None,
+            ));
+        }
+    }
+
+    for stage in stages {
+        if let Some(key) = stage
+            .depends_on
+            .iter()
+            .find(|key| !keys.contains(key.as_str()) || **key == stage.key)
+        {
+            return Err(ApplicationError::bad_request(
+                &format!("Stage {} can't depend on stage {key}", stage.key),
+                None,
+            ));
+        }
+    }
+
+    // Stages are resolved as their dependencies are, whatever is left is part of a cycle
+    let mut resolved = HashSet::new();
+    while resolved.len() < stages.len() {
+        let next = stages
+            .iter()
+            .filter(|stage| !resolved.contains(stage.key.as_str()))
+            .filter(|stage| {
+                stage
+                    .depends_on
+                    .iter()
+                    .all(|key| resolved.contains(key.as_str()))
+            })
+            .map(|stage| stage.key.as_str())
+            .collect::<Vec<_>>();
+
+        if next.is_empty() {
+            return Err(ApplicationError::bad_request(
+                "The stages of a workflow can't depend on each other in a cycle",
+                None,
+            ));
+        }
+
+        resolved.extend(next);
+    }
+
+    Ok(())
+}
+
+/// Renders every string of the payload on its own, so that rendered values can't break the
+/// JSON around them
+fn render_payload(
+    template: &impl TemplateExt,
+    payload: &Value,
+    context: &Value,
+) -> Result<Value, PicaError> {
+    Ok(match payload {
+        Value::String(value) => Value::String(template.render(value, Some(context))?),
+        Value::Array(values) => Value::Array(
+            values
+                .iter()
+                .map(|value| render_payload(template, value, context))
+                .collect::<Result<_, _>>()?,
+        ),
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), render_payload(template, value, context)?)))
+                .collect::<Result<_, PicaError>>()?,
+        ),
+        value => value.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stage(key: &str, depends_on: &[&str], payload: Value) -> StageDefinition {
+        serde_json::from_value(json!({
+            "key": key,
+            "dependsOn": depends_on,
+            "endpoint": format!("http://localhost/{key}"),
+            "payload": payload,
+        }))
+        .expect("Failed to deserialize stage")
+    }
+
+    #[test]
+    fn test_rejects_invalid_dags() {
+        assert!(Workflow::new("empty".to_string(), vec![]).is_err());
+        assert!(Workflow::new(
+            "duplicate".to_string(),
+            vec![stage("a", &[], json!({})), stage("a", &[], json!({}))]
+        )
+        .is_err());
+        assert!(Workflow::new("unknown".to_string(), vec![stage("a", &["b"], json!({}))]).is_err());
+
+        let mut signed = stage("a", &[], json!({}));
+        signed.headers.insert(
+            crate::TASK_SIGNATURE_HEADER,
+            HeaderValue::from_static("sha256=forged"),
+        );
+        assert!(Workflow::new("signed".to_string(), vec![signed]).is_err());
+        assert!(Workflow::new(
+            "cycle".to_string(),
+            vec![
+                stage("a", &[], json!({})),
+                stage("b", &["a", "c"], json!({})),
+                stage("c", &["b"], json!({})),
+            ]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_releases_stages_once_dependencies_succeed() {
+        let mut workflow = Workflow::new(
+            "deal".to_string(),
+            vec![
+                stage("contact", &[], json!({ "name": "Jane" })),
+                stage(
+                    "deal",
+                    &["contact"],
+                    json!({ "contactId": "{{stages.contact.response.id}}" }),
+                ),
+                stage("notify", &["deal"], json!({})),
+            ],
+        )
+        .expect("Failed to create workflow");
+
+        let tasks = workflow.release();
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].endpoint, "http://localhost/contact");
+        assert_eq!(tasks[0].workflow_id, Some(workflow.id));
+        assert_eq!(workflow.stages[0].status, StageStatus::Running);
+        assert_eq!(workflow.stages[1].status, StageStatus::Pending);
+
+        let contact = workflow.stages[0].id;
+        assert!(workflow.complete_stage(contact, Ok(json!({ "id": "ct_1" }))));
+        assert!(!workflow.complete_stage(contact, Ok(json!({}))));
+
+        let tasks = workflow.release();
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].payload, json!({ "contactId": "ct_1" }));
+        assert_eq!(tasks[0].stage_id, Some(workflow.stages[1].id));
+
+        let deal = workflow.stages[1].id;
+        assert!(workflow.complete_stage(deal, Err("Endpoint responded with status 400".into())));
+
+        assert!(workflow.release().is_empty());
+        assert_eq!(workflow.stages[2].status, StageStatus::Skipped);
+        assert_eq!(workflow.state, WorkflowState::Failed);
+    }
+
+    #[test]
+    fn test_fails_stages_that_cannot_be_rendered() {
+        let mut broken = stage("deal", &["contact"], json!({}));
+        broken.endpoint = "http://localhost/{{#if stages.contact.response}}".to_string();
+
+        let mut workflow = Workflow::new(
+            "deal".to_string(),
+            vec![
+                stage("contact", &[], json!({})),
+                broken,
+                stage("notify", &["deal"], json!({})),
+                stage("audit", &["contact"], json!({})),
+            ],
+        )
+        .expect("Failed to create workflow");
+
+        let tasks = workflow.release();
+        assert_eq!(tasks.len(), 1);
+
+        let contact = workflow.stages[0].id;
+        assert!(workflow.complete_stage(contact, Ok(json!({ "id": "ct_1" }))));
+
+        let tasks = workflow.release();
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].stage_id, Some(workflow.stages[3].id));
+        assert_eq!(workflow.stages[1].status, StageStatus::Failed);
+        assert!(workflow.stages[1].error.is_some());
+        assert_eq!(workflow.stages[2].status, StageStatus::Skipped);
+        assert_eq!(workflow.state, WorkflowState::Running);
+
+        let audit = workflow.stages[3].id;
+        assert!(workflow.complete_stage(audit, Ok(json!({}))));
+        assert!(workflow.release().is_empty());
+        assert_eq!(workflow.state, WorkflowState::Failed);
+    }
+
+    #[test]
+    fn test_passes_responses_on_unescaped() {
+        let note = "it's a=b & \"c\"\nd";
+        let mut workflow = Workflow::new(
+            "deal".to_string(),
+            vec![
+                stage("contact", &[], json!({})),
+                stage(
+                    "deal",
+                    &["contact"],
+                    json!({
+                        "note": "{{stages.contact.response.note}}",
+                        "notes": ["{{stages.contact.response.note}}", 1, null],
+                    }),
+                ),
+            ],
+        )
+        .expect("Failed to create workflow");
+        workflow.stages[1].definition.endpoint =
+            "http://localhost/deals?contact={{stages.contact.response.id}}".to_string();
+
+        workflow.release();
+        let contact = workflow.stages[0].id;
+        assert!(workflow.complete_stage(contact, Ok(json!({ "id": "a=b&c", "note": note }))));
+
+        let tasks = workflow.release();
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].endpoint, "http://localhost/deals?contact=a=b&c");
+        assert_eq!(
+            tasks[0].payload,
+            json!({ "note": note, "notes": [note, 1, null] })
+        );
+    }
+}
@@ -7,6 +7,7 @@ pub enum IdPrefix {
     Archive,
     CommonEnum,
     CommonModel,
+    CommonModelRevision,
     Connection,
     ConnectionDefinition,
     ConnectionModelDefinition,
@@ -29,6 +30,7 @@ pub enum IdPrefix {
     PipelineEvent,
     Platform,
     PlatformPage,
+    SchemaDrift,
     SessionId,
     Settings,
     Transaction,
@@ -43,6 +45,7 @@ impl Display for IdPrefix {
             IdPrefix::Archive => write!(f, "arch"),
             IdPrefix::CommonEnum => write!(f, "ce"),
             IdPrefix::CommonModel => write!(f, "cm"),
+            IdPrefix::CommonModelRevision => write!(f, "cm_rev"),
             IdPrefix::Connection => write!(f, "conn"),
             IdPrefix::ConnectionDefinition => write!(f, "conn_def"),
             IdPrefix::ConnectionModelDefinition => write!(f, "conn_mod_def"),
@@ -65,6 +68,7 @@ impl Display for IdPrefix {
             IdPrefix::PipelineEvent => write!(f, "pipe_evt"),
             IdPrefix::Platform => write!(f, "plf"),
             IdPrefix::PlatformPage => write!(f, "plf_pg"),
+            IdPrefix::SchemaDrift => write!(f, "sch_drft"),
             IdPrefix::SessionId => write!(f, "session_id"),
             IdPrefix::Settings => write!(f, "st"),
             IdPrefix::Transaction => write!(f, "tx"),
@@ -83,6 +87,7 @@ impl TryFrom<&str> for IdPrefix {
             "arch" => Ok(IdPrefix::Archive),
             "ce" => Ok(IdPrefix::CommonEnum),
             "cm" => Ok(IdPrefix::CommonModel),
+            "cm_rev" => Ok(IdPrefix::CommonModelRevision),
             "conn" => Ok(IdPrefix::Connection),
             "conn_def" => Ok(IdPrefix::ConnectionDefinition),
             "conn_mod_def" => Ok(IdPrefix::ConnectionModelDefinition),
@@ -105,6 +110,7 @@ impl TryFrom<&str> for IdPrefix {
             "pipe_evt" => Ok(IdPrefix::PipelineEvent),
             "plf" => Ok(IdPrefix::Platform),
             "plf_pg" => Ok(IdPrefix::PlatformPage),
+            "sch_drft" => Ok(IdPrefix::SchemaDrift),
             "session_id" => Ok(IdPrefix::SessionId),
             "st" => Ok(IdPrefix::Settings),
             "tx" => Ok(IdPrefix::Transaction),
@@ -125,6 +131,7 @@ impl From<IdPrefix> for String {
             IdPrefix::Archive => "arch".to_string(),
             IdPrefix::CommonEnum => "ce".to_string(),
             IdPrefix::CommonModel => "cm".to_string(),
+            IdPrefix::CommonModelRevision => "cm_rev".to_string(),
             IdPrefix::Connection => "conn".to_string(),
             IdPrefix::ConnectionDefinition => "conn_def".to_string(),
             IdPrefix::ConnectionModelDefinition => "conn_mod_def".to_string(),
@@ -147,6 +154,7 @@ impl From<IdPrefix> for String {
             IdPrefix::PipelineEvent => "pipe_evt".to_string(),
             IdPrefix::Platform => "plf".to_string(),
             IdPrefix::PlatformPage => "plf_pg".to_string(),
+            IdPrefix::SchemaDrift => "sch_drft".to_string(),
             IdPrefix::SessionId => "session_id".to_string(),
             IdPrefix::Settings => "st".to_string(),
             IdPrefix::Transaction => "tx".to_string(),
@@ -225,6 +233,14 @@ mod test {
         assert_eq!(IdPrefix::try_from("ut").unwrap(), IdPrefix::UnitTest);
         assert_eq!(IdPrefix::try_from("ea").unwrap(), IdPrefix::EarlyAccess);
         assert_eq!(IdPrefix::try_from("task").unwrap(), IdPrefix::Task);
+        assert_eq!(
+            IdPrefix::try_from("sch_drft").unwrap(),
+            IdPrefix::SchemaDrift
+        );
+        assert_eq!(
+            IdPrefix::try_from("cm_rev").unwrap(),
+            IdPrefix::CommonModelRevision
+        );
     }
 
     #[test]
@@ -269,5 +285,7 @@ mod test {
         assert_eq!(format!("{}", IdPrefix::UnitTest), "ut");
         assert_eq!(format!("{}", IdPrefix::EarlyAccess), "ea");
         assert_eq!(format!("{}", IdPrefix::Task), "task");
+        assert_eq!(format!("{}", IdPrefix::SchemaDrift), "sch_drft");
+        assert_eq!(format!("{}", IdPrefix::CommonModelRevision), "cm_rev");
     }
 }
@@ -1,3 +1,4 @@
+use super::type_generation::TypeTarget;
 use crate::{
     api_model_config::Lang,
     id::{prefix::IdPrefix, Id},
@@ -177,7 +178,7 @@ pub struct CommonEnum {
     pub record_metadata: RecordMetadata,
 }
 
-fn replace_reserved_keyword(name: &str, lang: Lang) -> String {
+pub(super) fn replace_reserved_keyword(name: &str, lang: Lang) -> String {
     match lang {
         Lang::Rust => match name.to_lowercase().as_str() {
             "type" => "r#type".to_owned(),
@@ -726,11 +727,16 @@ impl CommonModel {
     ///
     /// # Arguments
     /// * `lang` - The language to generate the model in
-    pub fn generate_as(&self, lang: &Lang) -> String {
+    ///
+    /// # Errors
+    /// Returns a serialization error if the JSON Schema document can't be written
+    pub fn generate_as(&self, lang: &TypeTarget) -> Result<String, PicaError> {
         match lang {
-            Lang::Rust => self.as_rust_ref(),
-            Lang::TypeScript => self.as_typescript_ref(),
-            _ => unimplemented!(),
+            TypeTarget::Rust => Ok(self.as_rust_ref()),
+            TypeTarget::TypeScript => Ok(self.as_typescript_ref()),
+            TypeTarget::Python => Ok(self.as_python_ref()),
+            TypeTarget::Go => Ok(self.as_go_ref()),
+            TypeTarget::JsonSchema => self.as_json_schema_ref(),
         }
     }
 
@@ -744,20 +750,24 @@ impl CommonModel {
     /// * `cm_store` - The store for common models
     /// * `ce_store` - The store for common enums
     /// * `strategy` - The strategy to use for generating the type
+    ///
+    /// # Errors
+    /// Returns a serialization error if the JSON Schema document can't be written
     pub async fn generate_as_expanded(
         &self,
-        lang: &Lang,
+        lang: &TypeTarget,
         cm_store: &MongoStore<CommonModel>,
         ce_store: &MongoStore<CommonEnum>,
         strategy: TypeGenerationStrategy<'_>,
-    ) -> String {
+    ) -> Result<String, PicaError> {
         match lang {
-            Lang::Rust => self.as_rust_expanded(cm_store, ce_store, strategy).await,
-            Lang::TypeScript => {
-                self.as_typescript_expanded(cm_store, ce_store, strategy)
-                    .await
+            TypeTarget::Rust => Ok(self.as_rust_expanded(cm_store, ce_store, strategy).await),
+            TypeTarget::TypeScript => Ok(self
+                .as_typescript_expanded(cm_store, ce_store, strategy)
+                .await),
+            TypeTarget::Python | TypeTarget::Go | TypeTarget::JsonSchema => {
+                self.as_expanded(lang, cm_store, ce_store, strategy).await
             }
-            _ => unimplemented!(),
         }
     }
 
@@ -1253,20 +1263,20 @@ impl CommonModel {
         let mut new_model = self.clone();
         let ts = self
             .generate_as_expanded(
-                &Lang::TypeScript,
+                &TypeTarget::TypeScript,
                 &cm_store,
                 &ce_store,
                 TypeGenerationStrategy::Unique,
             )
-            .await;
+            .await?;
         let rust = self
             .generate_as_expanded(
-                &Lang::Rust,
+                &TypeTarget::Rust,
                 &cm_store,
                 &ce_store,
                 TypeGenerationStrategy::Unique,
             )
-            .await;
+            .await?;
         let interface = HashMap::from_iter(vec![(Lang::Rust, rust), (Lang::TypeScript, ts)]);
         new_model.interface = interface;
         new_model.fields = Vec::new(); // Clear the fields to populate them freshly
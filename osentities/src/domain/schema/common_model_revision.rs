@@ -0,0 +1,433 @@
+use super::{
+    common_model::{CommonModel, DataType, Field},
+    json_mapper::{Field as MappingField, SchemaMappingDefinition},
+};
+use crate::{
+    id::{prefix::IdPrefix, Id},
+    prelude::{
+        connection::{connection_model_definition::CrudMapping, connection_model_schema::Mappings},
+        shared::record_metadata::RecordMetadata,
+    },
+};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub enum FieldChangeKind {
+    Added,
+    Removed,
+    TypeChanged,
+    MadeRequired,
+    MadeOptional,
+    OptionsAdded,
+    OptionsRemoved,
+    DescriptionChanged,
+}
+
+/// A single difference between two revisions of a common model
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub struct FieldChange {
+    pub field: String,
+    pub kind: FieldChangeKind,
+    pub breaking: bool,
+}
+
+/// Semantic version component bumped by a change
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeLevel {
+    Patch,
+    Minor,
+    Major,
+}
+
+impl ChangeLevel {
+    pub fn bump(&self, version: &Version) -> Version {
+        match self {
+            ChangeLevel::Patch => Version::new(version.major, version.minor, version.patch + 1),
+            ChangeLevel::Minor => Version::new(version.major, version.minor + 1, 0),
+            ChangeLevel::Major => Version::new(version.major + 1, 0, 0),
+        }
+    }
+}
+
+/// Classification of an update of a common model. Removing or retyping a field, or requiring
+/// one, breaks the consumers of the model and bumps the major version. Any other change of the
+/// fields is additive and bumps the minor version, everything else the patch version.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub struct CommonModelChanges {
+    pub level: ChangeLevel,
+    pub changes: Vec<FieldChange>,
+}
+
+impl CommonModelChanges {
+    pub fn is_breaking(&self) -> bool {
+        self.level == ChangeLevel::Major
+    }
+
+    pub fn removed_fields(&self) -> Vec<String> {
+        self.changes
+            .iter()
+            .filter(|change| change.kind == FieldChangeKind::Removed)
+            .map(|change| change.field.clone())
+            .collect()
+    }
+}
+
+/// Immutable snapshot of a common model at a given version
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub struct CommonModelRevision {
+    #[serde(rename = "_id")]
+    pub id: Id,
+    pub common_model_id: Id,
+    #[cfg_attr(feature = "dummy", dummy(expr = "Version::new(1,0,0)"))]
+    pub version: Version,
+    /// Changes from the previous revision, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changes: Option<CommonModelChanges>,
+    pub model: CommonModel,
+    #[serde(flatten, default)]
+    pub record_metadata: RecordMetadata,
+}
+
+impl CommonModelRevision {
+    pub fn new(model: &CommonModel, changes: Option<CommonModelChanges>) -> Self {
+        let mut model = model.clone();
+        model.interface = Default::default();
+
+        Self {
+            id: Id::now(IdPrefix::CommonModelRevision),
+            common_model_id: model.id,
+            version: model.record_metadata.version.clone(),
+            changes,
+            model,
+            record_metadata: Default::default(),
+        }
+    }
+}
+
+impl CommonModel {
+    /// Compares the fields of the model with the ones of its updated version
+    pub fn changes(&self, updated: &CommonModel) -> CommonModelChanges {
+        let mut changes = vec![];
+        let mut change = |field: &Field, kind, breaking| {
+            changes.push(FieldChange {
+                field: field.name.clone(),
+                kind,
+                breaking,
+            })
+        };
+
+        for field in &self.fields {
+            let Some(updated) = updated.fields.iter().find(|f| f.name == field.name) else {
+                change(field, FieldChangeKind::Removed, true);
+                continue;
+            };
+
+            match compare_types(&field.datatype, &updated.datatype) {
+                Some(FieldChangeKind::TypeChanged) => {
+                    change(field, FieldChangeKind::TypeChanged, true)
+                }
+                Some(FieldChangeKind::OptionsRemoved) => {
+                    change(field, FieldChangeKind::OptionsRemoved, true)
+                }
+                Some(kind) => change(field, kind, false),
+                None => {}
+            }
+
+            match (field.required, updated.required) {
+                (false, true) => change(field, FieldChangeKind::MadeRequired, true),
+                (true, false) => change(field, FieldChangeKind::MadeOptional, false),
+                _ => {}
+            }
+
+            if field.description != updated.description {
+                change(field, FieldChangeKind::DescriptionChanged, false);
+            }
+        }
+
+        for field in &updated.fields {
+            if !self.fields.iter().any(|f| f.name == field.name) {
+                change(field, FieldChangeKind::Added, field.required);
+            }
+        }
+
+        let level = if changes.iter().any(|change| change.breaking) {
+            ChangeLevel::Major
+        } else if changes
+            .iter()
+            .any(|change| change.kind != FieldChangeKind::DescriptionChanged)
+        {
+            ChangeLevel::Minor
+        } else {
+            ChangeLevel::Patch
+        };
+
+        CommonModelChanges { level, changes }
+    }
+}
+
+/// Returns how a type changed, if it did. Expanded and unexpanded references to a model are
+/// the same type, and an enum accepting more options is an additive change.
+fn compare_types(previous: &DataType, current: &DataType) -> Option<FieldChangeKind> {
+    match (previous, current) {
+        (
+            DataType::Enum {
+                options: previous_options,
+                reference: previous_reference,
+            },
+            DataType::Enum {
+                options: current_options,
+                reference: current_reference,
+            },
+        ) => {
+            if previous_reference != current_reference {
+                return Some(FieldChangeKind::TypeChanged);
+            }
+
+            let previous_options = previous_options.as_deref().unwrap_or_default();
+            let current_options = current_options.as_deref().unwrap_or_default();
+
+            if previous_options
+                .iter()
+                .any(|option| !current_options.contains(option))
+            {
+                Some(FieldChangeKind::OptionsRemoved)
+            } else if current_options.len() > previous_options.len() {
+                Some(FieldChangeKind::OptionsAdded)
+            } else {
+                None
+            }
+        }
+        (DataType::Expandable(previous), DataType::Expandable(current)) => {
+            (previous.reference() != current.reference()).then_some(FieldChangeKind::TypeChanged)
+        }
+        (
+            DataType::Array {
+                element_type: previous,
+            },
+            DataType::Array {
+                element_type: current,
+            },
+        ) => compare_types(previous, current),
+        (previous, current) => (std::mem::discriminant(previous)
+            != std::mem::discriminant(current))
+        .then_some(FieldChangeKind::TypeChanged),
+    }
+}
+
+impl Mappings {
+    /// Fields of a common model used by the mapping, among the given ones
+    pub fn referenced_fields(&self, fields: &[String]) -> Vec<String> {
+        fields
+            .iter()
+            .filter(|field| {
+                references(&self.from_common_model, field)
+                    || references(&self.to_common_model, field)
+                    || self
+                        .from_common_model_schema
+                        .as_ref()
+                        .is_some_and(|schema| reads(schema, field))
+                    || self
+                        .to_common_model_schema
+                        .as_ref()
+                        .is_some_and(|schema| schema.contains_key(*field))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+impl CrudMapping {
+    /// Fields of a common model used by the mapping, among the given ones
+    pub fn referenced_fields(&self, fields: &[String]) -> Vec<String> {
+        fields
+            .iter()
+            .filter(|field| {
+                self.from_common_model
+                    .as_deref()
+                    .is_some_and(|script| references(script, field))
+                    || self
+                        .to_common_model
+                        .as_deref()
+                        .is_some_and(|script| references(script, field))
+                    || self
+                        .from_common_model_schema
+                        .as_ref()
+                        .is_some_and(|schema| reads(schema, field))
+                    || self
+                        .to_common_model_schema
+                        .as_ref()
+                        .is_some_and(|schema| schema.contains_key(*field))
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// Whether the field name appears in the script as a whole identifier
+fn references(script: &str, field: &str) -> bool {
+    let is_identifier = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+
+    script.match_indices(field).any(|(index, _)| {
+        let before = script[..index].chars().next_back();
+        let after = script[index + field.len()..].chars().next();
+
+        !before.is_some_and(is_identifier) && !after.is_some_and(is_identifier)
+    })
+}
+
+/// Whether a path of the declarative mapping reads the field
+fn reads(schema: &SchemaMappingDefinition, field: &str) -> bool {
+    fn paths<'a>(field: &'a MappingField, output: &mut Vec<&'a str>) {
+        match field {
+            MappingField::String { path, .. }
+            | MappingField::Boolean { path, .. }
+            | MappingField::Number { path, .. }
+            | MappingField::Unknown { path, .. } => output.push(path),
+            MappingField::Array { path, items, .. } => {
+                output.push(path);
+                paths(items, output);
+            }
+            MappingField::Object { fields, .. } => {
+                fields.values().for_each(|field| paths(field, output))
+            }
+        }
+    }
+
+    let mut output = vec![];
+    schema.values().for_each(|field| paths(field, &mut output));
+
+    output.into_iter().any(|path| references(path, field))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{common_model::Expandable, connection_model_definition::CrudAction};
+
+    fn field(name: &str, datatype: DataType, required: bool) -> Field {
+        Field {
+            name: name.to_string(),
+            datatype,
+            description: None,
+            required,
+        }
+    }
+
+    fn model(fields: Vec<Field>) -> CommonModel {
+        CommonModel {
+            name: "Ticket".to_string(),
+            fields,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_classify_changes() {
+        let status = |options: &[&str]| DataType::Enum {
+            options: Some(options.iter().map(|o| o.to_string()).collect()),
+            reference: String::new(),
+        };
+        let assignee = DataType::Expandable(Expandable::Unexpanded {
+            reference: "User".to_string(),
+        });
+
+        let previous = model(vec![
+            field("id", DataType::String, true),
+            field("status", status(&["open"]), false),
+            field("assignee", assignee.clone(), false),
+        ]);
+
+        let additive = model(vec![
+            field("id", DataType::String, true),
+            field("status", status(&["open", "closed"]), false),
+            field("assignee", assignee.clone(), false),
+            field("title", DataType::String, false),
+        ]);
+        let changes = previous.changes(&additive);
+        assert_eq!(changes.level, ChangeLevel::Minor);
+        assert_eq!(
+            changes.changes,
+            vec![
+                FieldChange {
+                    field: "status".to_string(),
+                    kind: FieldChangeKind::OptionsAdded,
+                    breaking: false,
+                },
+                FieldChange {
+                    field: "title".to_string(),
+                    kind: FieldChangeKind::Added,
+                    breaking: false,
+                },
+            ]
+        );
+        assert_eq!(
+            changes.level.bump(&Version::new(1, 2, 3)),
+            Version::new(1, 3, 0)
+        );
+
+        let breaking = model(vec![
+            field("id", DataType::Number, true),
+            field("status", status(&["open"]), true),
+        ]);
+        let changes = previous.changes(&breaking);
+        assert!(changes.is_breaking());
+        assert_eq!(changes.removed_fields(), vec!["assignee".to_string()]);
+        assert_eq!(
+            changes
+                .changes
+                .iter()
+                .map(|change| change.kind)
+                .collect::<Vec<_>>(),
+            vec![
+                FieldChangeKind::TypeChanged,
+                FieldChangeKind::MadeRequired,
+                FieldChangeKind::Removed,
+            ]
+        );
+        assert_eq!(
+            changes.level.bump(&Version::new(1, 2, 3)),
+            Version::new(2, 0, 0)
+        );
+
+        let mut described = previous.clone();
+        described.fields[0].description = Some("Identifier".to_string());
+        assert_eq!(previous.changes(&described).level, ChangeLevel::Patch);
+    }
+
+    #[test]
+    fn test_referenced_fields() {
+        let fields = vec!["assignee".to_string(), "status".to_string()];
+        let mapping = CrudMapping {
+            action: CrudAction::Update,
+            common_model_name: "Ticket".to_string(),
+            from_common_model: Some(
+                "function mapFromCommonModel(obj) { return { owner: obj.assigneeId }; }"
+                    .to_string(),
+            ),
+            to_common_model: Some(
+                "function mapToCommonModel(obj) { return { status: obj.state }; }".to_string(),
+            ),
+            from_common_model_schema: None,
+            to_common_model_schema: None,
+            language: None,
+            compiled_from_common_model: None,
+            compiled_to_common_model: None,
+        };
+
+        assert_eq!(
+            mapping.referenced_fields(&fields),
+            vec!["status".to_string()]
+        );
+    }
+}
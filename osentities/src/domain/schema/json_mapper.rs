@@ -6,9 +6,9 @@ use crate::{InternalError, PicaError};
 use jsonpath_lib::select;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
-#[derive(Deserialize, Debug, Clone, Serialize, Eq, PartialEq)]
+#[derive(Deserialize, Debug, Clone, Serialize, Eq, PartialEq, Hash)]
 #[cfg_attr(feature = "dummy", derive(fake::Dummy))]
 #[serde(tag = "type")]
 pub enum Field {
@@ -39,7 +39,7 @@ pub enum Field {
     #[serde(rename = "object")]
     Object {
         required: bool,
-        fields: HashMap<String, Field>,
+        fields: BTreeMap<String, Field>,
     },
     #[serde(rename = "array")]
     Array {
@@ -84,7 +84,7 @@ impl Field {
                         None,
                     ));
                 };
-                let mut fields = HashMap::new();
+                let mut fields = BTreeMap::new();
                 for (name, prop) in properties {
                     let field =
                         Self::from_property(prop, path.clone(), transformation.clone(), required)?;
@@ -107,7 +107,7 @@ impl Field {
             }
             "unknown" => Field::Object {
                 required,
-                fields: HashMap::new(),
+                fields: BTreeMap::new(),
             },
             _ => {
                 return Err(InternalError::configuration_error(
@@ -164,7 +164,7 @@ impl Field {
                     ));
                 };
 
-                let mut fields = HashMap::new();
+                let mut fields = BTreeMap::new();
                 for field in &model.fields {
                     let name = field.name.clone();
                     let field = Self::from_data_type(
@@ -220,7 +220,7 @@ impl Field {
     }
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Clone, Eq, PartialEq, Hash)]
 #[cfg_attr(feature = "dummy", derive(fake::Dummy))]
 pub struct FieldDefault {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -229,7 +229,7 @@ pub struct FieldDefault {
     pub function: Option<String>,
 }
 
-pub type SchemaMappingDefinition = HashMap<String, Field>;
+pub type SchemaMappingDefinition = BTreeMap<String, Field>;
 
 pub fn map_data_by_schema(
     data: &Value,
@@ -381,7 +381,7 @@ pub fn map_data_by_schema(
     fn object(
         data: &Value,
         key: &str,
-        fields: &HashMap<String, Field>,
+        fields: &BTreeMap<String, Field>,
         required: bool,
     ) -> Result<Value, PicaError> {
         let obj = map_data_by_schema(data, fields)?;
@@ -5,7 +5,7 @@ use super::{
 use crate::{InternalError, PicaError};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Map, Value};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Default)]
 #[cfg_attr(feature = "dummy", derive(fake::Dummy))]
@@ -263,7 +263,7 @@ impl Property {
         }
     }
 
-    pub fn retain_recursive(&mut self, name: &str, map: &HashMap<String, Field>) -> bool {
+    pub fn retain_recursive(&mut self, name: &str, map: &BTreeMap<String, Field>) -> bool {
         match self.r#type.as_str() {
             "object" => {
                 let Some(ref mut props) = self.properties else {
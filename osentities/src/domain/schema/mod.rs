@@ -1,3 +1,7 @@
 pub mod common_model;
+pub mod common_model_revision;
 pub mod json_mapper;
 pub mod json_schema;
+pub mod schema_drift;
+pub mod type_generation;
+pub mod validation;
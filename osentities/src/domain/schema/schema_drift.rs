@@ -0,0 +1,235 @@
+use super::json_schema::{JsonSchema, Property};
+use crate::{
+    hashed_secret::HashedSecret,
+    id::{prefix::IdPrefix, Id},
+    prelude::{
+        connection::connection_model_schema::ConnectionModelSchema,
+        shared::record_metadata::RecordMetadata,
+    },
+    InternalError, PicaError,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const UNKNOWN_TYPE: &str = "unknown";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub enum SchemaChangeKind {
+    Added,
+    Removed,
+    TypeChanged,
+}
+
+/// A single difference between the stored schema of a model and the schema of a live sample
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub struct SchemaChange {
+    pub path: String,
+    pub kind: SchemaChangeKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub actual: Option<String>,
+}
+
+/// Drift detected between a `ConnectionModelSchema` and the responses of its platform. The
+/// same set of changes is recorded once per schema, responses showing it again only bump
+/// `occurrences`. Responses themselves are not kept as they may hold personal data.
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[cfg_attr(feature = "dummy", derive(fake::Dummy))]
+#[serde(rename_all = "camelCase")]
+pub struct SchemaDrift {
+    #[serde(rename = "_id")]
+    pub id: Id,
+    pub connection_model_schema_id: Id,
+    pub connection_model_definition_id: Id,
+    pub connection_platform: String,
+    pub platform_version: String,
+    pub model_name: String,
+    pub changes: Vec<SchemaChange>,
+    /// Hash of `changes`, identifying the drift among the ones of the schema
+    pub changes_hash: String,
+    /// Sampled responses showing the drift
+    #[serde(default)]
+    pub occurrences: u64,
+    #[serde(flatten, default)]
+    pub record_metadata: RecordMetadata,
+}
+
+impl SchemaDrift {
+    pub fn new(
+        schema: &ConnectionModelSchema,
+        connection_model_definition_id: Id,
+        changes: Vec<SchemaChange>,
+    ) -> Result<Self, PicaError> {
+        let value = serde_json::to_value(&changes)
+            .map_err(|e| InternalError::serialize_error(&e.to_string(), None))?;
+        let changes_hash = HashedSecret::try_from(value)?.inner().to_string();
+
+        Ok(Self {
+            id: Id::now(IdPrefix::SchemaDrift),
+            connection_model_schema_id: schema.id,
+            connection_model_definition_id,
+            connection_platform: schema.connection_platform.clone(),
+            platform_version: schema.platform_version.clone(),
+            model_name: schema.model_name.clone(),
+            changes,
+            changes_hash,
+            occurrences: 1,
+            record_metadata: Default::default(),
+        })
+    }
+}
+
+impl JsonSchema {
+    /// Compares the schema with one inferred from a live value. Fields of `unknown` type, e.g.
+    /// null in the sample, are compatible with any type. A single response often omits optional
+    /// fields, so a missing field is only reported as removed when the schema marks it as
+    /// `required`. Changes are sorted by path.
+    pub fn diff(&self, actual: &JsonSchema) -> Vec<SchemaChange> {
+        let mut changes = vec![];
+        diff_properties(
+            &self.properties,
+            &actual.properties,
+            self.required.as_deref().unwrap_or_default(),
+            "$",
+            &mut changes,
+        );
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        changes
+    }
+}
+
+fn diff_properties(
+    expected: &HashMap<String, Property>,
+    actual: &HashMap<String, Property>,
+    required: &[String],
+    path: &str,
+    changes: &mut Vec<SchemaChange>,
+) {
+    for (name, property) in expected {
+        let path = format!("{path}.{name}");
+
+        match actual.get(name) {
+            Some(actual) => diff_property(property, actual, &path, changes),
+            None if !required.contains(name) => {}
+            None => changes.push(SchemaChange {
+                path,
+                kind: SchemaChangeKind::Removed,
+                expected: Some(property.r#type.clone()),
+                actual: None,
+            }),
+        }
+    }
+
+    for (name, property) in actual {
+        if !expected.contains_key(name) {
+            changes.push(SchemaChange {
+                path: format!("{path}.{name}"),
+                kind: SchemaChangeKind::Added,
+                expected: None,
+                actual: Some(property.r#type.clone()),
+            });
+        }
+    }
+}
+
+fn diff_property(
+    expected: &Property,
+    actual: &Property,
+    path: &str,
+    changes: &mut Vec<SchemaChange>,
+) {
+    if expected.r#type == UNKNOWN_TYPE || actual.r#type == UNKNOWN_TYPE {
+        return;
+    }
+
+    if expected.r#type != actual.r#type {
+        changes.push(SchemaChange {
+            path: path.to_string(),
+            kind: SchemaChangeKind::TypeChanged,
+            expected: Some(expected.r#type.clone()),
+            actual: Some(actual.r#type.clone()),
+        });
+        return;
+    }
+
+    // Nested properties don't track whether they're required
+    if let (Some(expected), Some(actual)) = (&expected.properties, &actual.properties) {
+        diff_properties(expected, actual, &[], path, changes);
+    }
+
+    if let (Some(expected), Some(actual)) = (&expected.items, &actual.items) {
+        diff_property(expected, actual, &format!("{path}[*]"), changes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json_schema::generate_schema;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff_reports_added_removed_and_changed_fields() {
+        let mut stored = JsonSchema::from_value(generate_schema(
+            &json!({
+                "id": "1",
+                "total": 10,
+                "status": "open",
+                "channel": "email",
+                "note": null,
+                "customer": { "name": "Jane", "email": "jane@example.com" },
+                "lines": [{ "sku": "a" }]
+            }),
+            "$",
+        ))
+        .expect("Failed to build stored schema");
+        stored.required = Some(vec!["id".to_string(), "status".to_string()]);
+
+        let sample = JsonSchema::from_value(generate_schema(
+            &json!({
+                "id": 1,
+                "total": 10,
+                "note": "Leave at the door",
+                "currency": "USD",
+                "customer": { "name": "Jane" },
+                "lines": [{ "sku": 7 }]
+            }),
+            "$",
+        ))
+        .expect("Failed to build sample schema");
+
+        let change =
+            |path: &str, kind, expected: Option<&str>, actual: Option<&str>| SchemaChange {
+                path: path.to_string(),
+                kind,
+                expected: expected.map(str::to_string),
+                actual: actual.map(str::to_string),
+            };
+
+        assert_eq!(
+            stored.diff(&sample),
+            vec![
+                change("$.currency", SchemaChangeKind::Added, None, Some("string")),
+                change(
+                    "$.id",
+                    SchemaChangeKind::TypeChanged,
+                    Some("string"),
+                    Some("number")
+                ),
+                change(
+                    "$.lines[*].sku",
+                    SchemaChangeKind::TypeChanged,
+                    Some("string"),
+                    Some("number")
+                ),
+                change("$.status", SchemaChangeKind::Removed, Some("string"), None),
+            ]
+        );
+        assert!(stored.diff(&stored).is_empty());
+    }
+}
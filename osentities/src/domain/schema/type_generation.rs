@@ -0,0 +1,621 @@
+use super::common_model::{
+    replace_reserved_keyword, CommonEnum, CommonModel, DataType, Field, TypeGenerationStrategy,
+};
+use crate::{
+    api_model_config::Lang,
+    prelude::{MongoStore, StringExt},
+    InternalError, PicaError,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+
+const JSON_SCHEMA_DRAFT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+const PYTHON_PREAMBLE: &str = "from __future__ import annotations
+
+from enum import Enum
+from typing import Any, List, Optional
+
+from pydantic import BaseModel, ConfigDict, Field
+
+";
+
+const GO_PREAMBLE: &str = "package models
+
+";
+
+/// Languages common models and enums can be generated in. Unlike [`Lang`], which is the
+/// language a function is written in, none of these can be executed by the platform.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TypeTarget {
+    Rust,
+    TypeScript,
+    Python,
+    Go,
+    JsonSchema,
+}
+
+impl TypeTarget {
+    /// Key of the interface stored with the model, only Rust and TypeScript are stored
+    pub fn interface_lang(&self) -> Option<Lang> {
+        match self {
+            TypeTarget::Rust => Some(Lang::Rust),
+            TypeTarget::TypeScript => Some(Lang::TypeScript),
+            TypeTarget::Python | TypeTarget::Go | TypeTarget::JsonSchema => None,
+        }
+    }
+}
+
+/// Joins the types generated for several models with `TypeGenerationStrategy::Cumulative` into
+/// a single output. JSON Schema definitions are merged into one document, other languages are
+/// concatenated after the imports they need.
+pub fn bundle_types(lang: &TypeTarget, types: Vec<String>) -> Result<String, PicaError> {
+    match lang {
+        TypeTarget::JsonSchema => {
+            let mut definitions = Map::new();
+
+            for document in types {
+                let document: Value = serde_json::from_str(&document).map_err(|e| {
+                    InternalError::deserialize_error(
+                        &format!("Invalid JSON Schema document: {e}"),
+                        None,
+                    )
+                })?;
+
+                if let Some(Value::Object(defs)) = document.get("$defs") {
+                    definitions.extend(defs.clone());
+                }
+            }
+
+            json_schema_document(json!({ "$schema": JSON_SCHEMA_DRAFT, "$defs": definitions }))
+        }
+        TypeTarget::Python => Ok(format!("{PYTHON_PREAMBLE}{}", types.join("\n\n"))),
+        TypeTarget::Go => Ok(format!("{GO_PREAMBLE}{}", types.join("\n"))),
+        TypeTarget::Rust | TypeTarget::TypeScript => Ok(types.concat()),
+    }
+}
+
+fn json_schema_document(document: Value) -> Result<String, PicaError> {
+    serde_json::to_string_pretty(&document).map_err(|e| {
+        InternalError::serialize_error(&format!("Invalid JSON Schema document: {e}"), None)
+    })
+}
+
+/// Appends an underscore to the names Python reserves
+fn python_keyword(name: &str) -> String {
+    match name {
+        "and" | "as" | "assert" | "async" | "await" | "break" | "class" | "continue" | "def"
+        | "del" | "elif" | "else" | "except" | "finally" | "for" | "from" | "global" | "if"
+        | "import" | "in" | "is" | "lambda" | "nonlocal" | "not" | "or" | "pass" | "raise"
+        | "return" | "try" | "while" | "with" | "yield" | "None" | "True" | "False" => {
+            format!("{name}_")
+        }
+        _ => name.to_owned(),
+    }
+}
+
+fn type_name(name: &str) -> String {
+    identifier(&name.replace("::", "").pascal_case())
+}
+
+/// Replaces the characters that can't be used in an identifier
+fn identifier(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect::<String>();
+
+    match name.chars().next() {
+        Some(c) if c.is_ascii_digit() => format!("_{name}"),
+        Some(_) => name,
+        None => "_".to_string(),
+    }
+}
+
+/// Options with a unique identifier, in their original order. Values are kept as is so that
+/// the generated types accept the payloads of the unified API.
+fn enum_members<'a>(
+    options: &'a [String],
+    member: impl Fn(&str) -> String,
+) -> Vec<(String, &'a str)> {
+    let mut seen = HashSet::new();
+
+    options
+        .iter()
+        .map(|option| (member(option), option.as_str()))
+        .filter(|(name, _)| seen.insert(name.clone()))
+        .collect()
+}
+
+impl CommonEnum {
+    /// Generates the enum as a string in the specified language
+    pub fn generate_as(&self, lang: &TypeTarget) -> Result<String, PicaError> {
+        match lang {
+            TypeTarget::Rust => Ok(self.as_rust_type()),
+            TypeTarget::TypeScript => Ok(self.as_typescript_type()),
+            TypeTarget::Python => Ok(self.as_python_type()),
+            TypeTarget::Go => Ok(self.as_go_type()),
+            TypeTarget::JsonSchema => json_schema_document(json!({
+                "$defs": { type_name(&self.name): self.as_json_schema() }
+            })),
+        }
+    }
+
+    pub fn as_python_type(&self) -> String {
+        let members = enum_members(&self.options, |option| {
+            identifier(&option.to_string().snake_case().to_uppercase())
+        })
+        .into_iter()
+        .map(|(name, value)| format!("    {name} = \"{value}\""))
+        .collect::<Vec<_>>();
+
+        format!(
+            "class {}(str, Enum):\n{}\n",
+            type_name(&self.name),
+            if members.is_empty() {
+                "    pass".to_string()
+            } else {
+                members.join("\n")
+            }
+        )
+    }
+
+    pub fn as_go_type(&self) -> String {
+        let name = type_name(&self.name);
+        let members = enum_members(&self.options, |option| {
+            format!("{name}{}", identifier(&option.pascal_case()))
+        })
+        .into_iter()
+        .map(|(member, value)| format!("\t{member} {name} = \"{value}\""))
+        .collect::<Vec<_>>();
+
+        format!("type {name} string\n\nconst (\n{}\n)\n", members.join("\n"))
+    }
+
+    pub fn as_json_schema(&self) -> Value {
+        json!({
+            "title": self.name,
+            "type": "string",
+            "enum": self.options,
+        })
+    }
+}
+
+impl DataType {
+    fn as_python_ref(&self, enum_name: &str) -> String {
+        match self {
+            DataType::String | DataType::Date => "str".into(),
+            DataType::Number => "float".into(),
+            DataType::Boolean => "bool".into(),
+            DataType::Enum { reference, .. } if reference.is_empty() => type_name(enum_name),
+            DataType::Enum { reference, .. } => type_name(reference),
+            DataType::Expandable(expandable) => type_name(&expandable.reference()),
+            DataType::Array { element_type } => {
+                format!("List[{}]", element_type.as_python_ref(enum_name))
+            }
+            DataType::Unknown => "Any".into(),
+        }
+    }
+
+    fn as_go_ref(&self, enum_name: &str) -> String {
+        match self {
+            DataType::String | DataType::Date => "string".into(),
+            DataType::Number => "float64".into(),
+            DataType::Boolean => "bool".into(),
+            DataType::Enum { reference, .. } if reference.is_empty() => type_name(enum_name),
+            DataType::Enum { reference, .. } => type_name(reference),
+            DataType::Expandable(expandable) => type_name(&expandable.reference()),
+            DataType::Array { element_type } => {
+                format!("[]{}", element_type.as_go_ref(enum_name))
+            }
+            DataType::Unknown => "any".into(),
+        }
+    }
+
+    pub fn as_json_schema(&self) -> Value {
+        match self {
+            DataType::String => json!({ "type": "string" }),
+            DataType::Number => json!({ "type": "number" }),
+            DataType::Boolean => json!({ "type": "boolean" }),
+            DataType::Date => json!({ "type": "string", "format": "date-time" }),
+            DataType::Enum { options, reference } => match options {
+                Some(options) if reference.is_empty() => {
+                    json!({ "type": "string", "enum": options })
+                }
+                _ if reference.is_empty() => json!({ "type": "string" }),
+                _ => json!({ "$ref": format!("#/$defs/{}", type_name(reference)) }),
+            },
+            DataType::Expandable(expandable) => {
+                json!({ "$ref": format!("#/$defs/{}", type_name(&expandable.reference())) })
+            }
+            DataType::Array { element_type } => {
+                json!({ "type": "array", "items": element_type.as_json_schema() })
+            }
+            DataType::Unknown => json!({}),
+        }
+    }
+}
+
+impl Field {
+    fn as_python_ref(&self) -> String {
+        let name = identifier(&python_keyword(&self.name.snake_case()));
+        let r#type = self.datatype.as_python_ref(&self.name);
+
+        match (name == self.name, self.required) {
+            (true, true) => format!("    {name}: {type}"),
+            (true, false) => format!("    {name}: Optional[{type}] = None"),
+            (false, true) => format!("    {name}: {type} = Field(alias=\"{}\")", self.name),
+            (false, false) => format!(
+                "    {name}: Optional[{type}] = Field(default=None, alias=\"{}\")",
+                self.name
+            ),
+        }
+    }
+
+    fn as_go_ref(&self) -> String {
+        let r#type = match &self.datatype {
+            DataType::Array { .. } | DataType::Unknown => self.datatype.as_go_ref(&self.name),
+            datatype => format!("*{}", datatype.as_go_ref(&self.name)),
+        };
+
+        format!(
+            "\t{} {type} `json:\"{},omitempty\"`",
+            identifier(&self.name.pascal_case()),
+            self.name
+        )
+    }
+
+    fn as_json_schema(&self) -> Value {
+        let mut schema = self.datatype.as_json_schema();
+
+        if let (Some(description), Value::Object(schema)) = (&self.description, &mut schema) {
+            schema.insert("description".to_string(), json!(description));
+        }
+
+        schema
+    }
+}
+
+impl CommonModel {
+    pub(super) fn as_python_ref(&self) -> String {
+        let fields = self
+            .fields
+            .iter()
+            .map(Field::as_python_ref)
+            .collect::<Vec<_>>();
+
+        format!(
+            "class {}(BaseModel):\n    model_config = ConfigDict(populate_by_name=True)\n{}\n",
+            type_name(&self.name),
+            if fields.is_empty() {
+                String::new()
+            } else {
+                format!("\n{}", fields.join("\n"))
+            }
+        )
+    }
+
+    pub(super) fn as_go_ref(&self) -> String {
+        format!(
+            "type {} struct {{\n{}\n}}\n",
+            type_name(&self.name),
+            self.fields
+                .iter()
+                .map(Field::as_go_ref)
+                .collect::<Vec<_>>()
+                .join("\n")
+        )
+    }
+
+    pub fn as_json_schema(&self) -> Value {
+        let properties = self
+            .fields
+            .iter()
+            .map(|field| (field.name.clone(), field.as_json_schema()))
+            .collect::<Map<_, _>>();
+        let required = self
+            .fields
+            .iter()
+            .filter(|field| field.required)
+            .map(|field| field.name.clone())
+            .collect::<Vec<_>>();
+
+        let mut schema = json!({
+            "title": self.name,
+            "type": "object",
+            "properties": properties,
+        });
+
+        if !required.is_empty() {
+            schema["required"] = json!(required);
+        }
+
+        schema
+    }
+
+    pub(super) fn as_json_schema_ref(&self) -> Result<String, PicaError> {
+        json_schema_document(json!({ "$defs": { type_name(&self.name): self.as_json_schema() } }))
+    }
+
+    /// Generates the model with its enums and children for the languages without a dedicated
+    /// generator, following the same strategy as the Rust and TypeScript generators
+    pub(super) async fn as_expanded(
+        &self,
+        lang: &TypeTarget,
+        cm_store: &MongoStore<CommonModel>,
+        ce_store: &MongoStore<CommonEnum>,
+        strategy: TypeGenerationStrategy<'_>,
+    ) -> Result<String, PicaError> {
+        let mut long_lived_visited_enums = HashSet::new();
+        let mut long_lived_visited_common_models = HashSet::new();
+
+        let (unique, visited_enums, visited_common_models) = match strategy {
+            TypeGenerationStrategy::Cumulative {
+                visited_enums,
+                visited_common_models,
+            } => (false, visited_enums, visited_common_models),
+            TypeGenerationStrategy::Unique => (
+                true,
+                &mut long_lived_visited_enums,
+                &mut long_lived_visited_common_models,
+            ),
+        };
+
+        let mut enums = self
+            .fetch_all_enum_references(cm_store.clone(), ce_store.clone())
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|enum_model| visited_enums.insert(enum_model.id))
+            .collect::<Vec<_>>();
+        enums.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut children = self
+            .fetch_all_children_common_models(cm_store.clone())
+            .await
+            .map(|(children, _)| children.into_values().collect::<Vec<_>>())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|child| child.id != self.id && visited_common_models.insert(child.id))
+            .collect::<Vec<_>>();
+        children.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let include_self = visited_common_models.insert(self.id);
+
+        match lang {
+            TypeTarget::JsonSchema => {
+                let mut definitions = Map::new();
+                definitions.extend(
+                    enums
+                        .iter()
+                        .map(|e| (type_name(&e.name), e.as_json_schema())),
+                );
+                definitions.extend(
+                    children
+                        .iter()
+                        .map(|child| (type_name(&child.name), child.as_json_schema())),
+                );
+
+                if unique {
+                    let mut document = self.as_json_schema();
+                    document["$schema"] = json!(JSON_SCHEMA_DRAFT);
+                    document["$defs"] = Value::Object(definitions);
+
+                    json_schema_document(document)
+                } else {
+                    if include_self {
+                        definitions.insert(type_name(&self.name), self.as_json_schema());
+                    }
+
+                    json_schema_document(json!({ "$defs": definitions }))
+                }
+            }
+            _ => {
+                let mut types = enums
+                    .iter()
+                    .map(|e| e.generate_as(lang))
+                    .chain(children.iter().map(|child| child.generate_as(lang)))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                if include_self {
+                    types.push(self.generate_as(lang)?);
+                }
+
+                if unique {
+                    bundle_types(lang, types)
+                } else {
+                    Ok(types.join("\n"))
+                }
+            }
+        }
+    }
+}
+
+/// Rust types deriving serde, with the names of the payloads of the unified API, used by the
+/// generated SDKs where the types have to compile on their own
+impl CommonEnum {
+    pub fn as_rust_serde_type(&self) -> String {
+        let variants = enum_members(&self.options, |option| identifier(&option.pascal_case()))
+            .into_iter()
+            .map(|(variant, value)| format!("    #[serde(rename = \"{value}\")]\n    {variant},"))
+            .collect::<Vec<_>>();
+
+        format!(
+            "#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]\npub enum {} {{\n{}\n}}\n",
+            type_name(&self.name),
+            variants.join("\n")
+        )
+    }
+}
+
+impl DataType {
+    fn as_rust_serde_ref(&self, enum_name: &str) -> String {
+        match self {
+            DataType::String | DataType::Date => "String".into(),
+            DataType::Number => "f64".into(),
+            DataType::Boolean => "bool".into(),
+            DataType::Enum { reference, .. } if reference.is_empty() => type_name(enum_name),
+            DataType::Enum { reference, .. } => type_name(reference),
+            // Boxed since models can reference themselves
+            DataType::Expandable(expandable) => {
+                format!("Box<{}>", type_name(&expandable.reference()))
+            }
+            DataType::Array { element_type } => match element_type.as_ref() {
+                DataType::Expandable(expandable) => {
+                    format!("Vec<{}>", type_name(&expandable.reference()))
+                }
+                element_type => format!("Vec<{}>", element_type.as_rust_serde_ref(enum_name)),
+            },
+            DataType::Unknown => "serde_json::Value".into(),
+        }
+    }
+}
+
+impl CommonModel {
+    pub fn as_rust_serde_type(&self) -> String {
+        let fields = self
+            .fields
+            .iter()
+            .map(|field| {
+                format!(
+                    "    #[serde(rename = \"{}\", default, skip_serializing_if = \"Option::is_none\")]\n    pub {}: Option<{}>,",
+                    field.name,
+                    replace_reserved_keyword(&identifier(&field.name.snake_case()), Lang::Rust),
+                    field.datatype.as_rust_serde_ref(&field.name)
+                )
+            })
+            .collect::<Vec<_>>();
+
+        format!(
+            "#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]\npub struct {} {{\n{}\n}}\n",
+            type_name(&self.name),
+            fields.join("\n")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common_model::Expandable;
+
+    fn model() -> CommonModel {
+        let field = |name: &str, datatype, required| Field {
+            name: name.to_string(),
+            datatype,
+            description: None,
+            required,
+        };
+
+        CommonModel {
+            name: "Ticket".to_string(),
+            fields: vec![
+                field("id", DataType::String, true),
+                field(
+                    "status",
+                    DataType::Enum {
+                        options: None,
+                        reference: "TicketStatus".to_string(),
+                    },
+                    false,
+                ),
+                field(
+                    "assignee",
+                    DataType::Expandable(Expandable::Unexpanded {
+                        reference: "User".to_string(),
+                    }),
+                    false,
+                ),
+                field(
+                    "createdAt",
+                    DataType::Array {
+                        element_type: Box::new(DataType::Date),
+                    },
+                    false,
+                ),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_generate_python_go_and_json_schema() {
+        let model = model();
+
+        assert_eq!(
+            model.generate_as(&TypeTarget::Python).unwrap(),
+            "class Ticket(BaseModel):\n    model_config = ConfigDict(populate_by_name=True)\n\n    id: str\n    status: Optional[TicketStatus] = None\n    assignee: Optional[User] = None\n    created_at: Optional[List[str]] = Field(default=None, alias=\"createdAt\")\n"
+        );
+        assert_eq!(
+            model.generate_as(&TypeTarget::Go).unwrap(),
+            "type Ticket struct {\n\tId *string `json:\"id,omitempty\"`\n\tStatus *TicketStatus `json:\"status,omitempty\"`\n\tAssignee *User `json:\"assignee,omitempty\"`\n\tCreatedAt []string `json:\"createdAt,omitempty\"`\n}\n"
+        );
+
+        let schema: Value =
+            serde_json::from_str(&model.generate_as(&TypeTarget::JsonSchema).unwrap()).unwrap();
+        assert_eq!(
+            schema["$defs"]["Ticket"],
+            json!({
+                "title": "Ticket",
+                "type": "object",
+                "properties": {
+                    "id": { "type": "string" },
+                    "status": { "$ref": "#/$defs/TicketStatus" },
+                    "assignee": { "$ref": "#/$defs/User" },
+                    "createdAt": { "type": "array", "items": { "type": "string", "format": "date-time" } }
+                },
+                "required": ["id"]
+            })
+        );
+    }
+
+    #[test]
+    fn test_generate_rust_serde_types() {
+        assert_eq!(
+            model().as_rust_serde_type(),
+            "#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]\npub struct Ticket {\n    #[serde(rename = \"id\", default, skip_serializing_if = \"Option::is_none\")]\n    pub id: Option<String>,\n    #[serde(rename = \"status\", default, skip_serializing_if = \"Option::is_none\")]\n    pub status: Option<TicketStatus>,\n    #[serde(rename = \"assignee\", default, skip_serializing_if = \"Option::is_none\")]\n    pub assignee: Option<Box<User>>,\n    #[serde(rename = \"createdAt\", default, skip_serializing_if = \"Option::is_none\")]\n    pub created_at: Option<Vec<String>>,\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_generate_enums_and_bundle() {
+        let status = CommonEnum {
+            id: crate::Id::now(crate::prefix::IdPrefix::CommonEnum),
+            name: "TicketStatus".to_string(),
+            options: vec!["open".to_string(), "inProgress".to_string()],
+            record_metadata: Default::default(),
+        };
+
+        assert_eq!(
+            status.generate_as(&TypeTarget::Python).unwrap(),
+            "class TicketStatus(str, Enum):\n    OPEN = \"open\"\n    IN_PROGRESS = \"inProgress\"\n"
+        );
+        assert_eq!(
+            status.generate_as(&TypeTarget::Go).unwrap(),
+            "type TicketStatus string\n\nconst (\n\tTicketStatusOpen TicketStatus = \"open\"\n\tTicketStatusInProgress TicketStatus = \"inProgress\"\n)\n"
+        );
+
+        let bundle = bundle_types(
+            &TypeTarget::JsonSchema,
+            vec![
+                status.generate_as(&TypeTarget::JsonSchema).unwrap(),
+                model().generate_as(&TypeTarget::JsonSchema).unwrap(),
+            ],
+        )
+        .unwrap();
+        let bundle: Value = serde_json::from_str(&bundle).unwrap();
+
+        assert_eq!(bundle["$schema"], JSON_SCHEMA_DRAFT);
+        assert_eq!(
+            bundle["$defs"]["TicketStatus"]["enum"],
+            json!(["open", "inProgress"])
+        );
+        assert!(bundle["$defs"]["Ticket"].is_object());
+
+        assert!(bundle_types(&TypeTarget::Python, vec![])
+            .unwrap()
+            .starts_with("from __future__ import annotations"));
+    }
+}
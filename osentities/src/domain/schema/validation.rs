@@ -0,0 +1,184 @@
+use super::common_model::{CommonModel, DataType, Expandable, Field, SchemaType};
+use chrono::{DateTime, NaiveDate};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A field of a value that doesn't match its common model
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldError {
+    pub path: String,
+    pub message: String,
+}
+
+impl FieldError {
+    fn new(path: &str, message: impl Into<String>) -> Self {
+        Self {
+            path: path.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+impl CommonModel {
+    /// Checks a value against the fields of the model. `Lax` only checks the types of the fields
+    /// that are set, `Strict` also rejects missing or null `required` fields. Expandable fields
+    /// are only checked in depth when the model is expanded.
+    pub fn validate(&self, value: &Value, r#type: SchemaType) -> Vec<FieldError> {
+        let mut errors = vec![];
+        validate_fields(&self.fields, value, "", r#type, &mut errors);
+        errors
+    }
+}
+
+fn join(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{path}.{name}")
+    }
+}
+
+fn validate_fields(
+    fields: &[Field],
+    value: &Value,
+    path: &str,
+    r#type: SchemaType,
+    errors: &mut Vec<FieldError>,
+) {
+    let Value::Object(object) = value else {
+        errors.push(FieldError::new(path, "expected object"));
+        return;
+    };
+
+    for field in fields {
+        let path = join(path, &field.name);
+
+        match object.get(&field.name) {
+            None | Some(Value::Null) => {
+                if field.required && r#type == SchemaType::Strict {
+                    errors.push(FieldError::new(&path, "missing required field"));
+                }
+            }
+            Some(value) => validate_datatype(&field.datatype, value, &path, r#type, errors),
+        }
+    }
+}
+
+fn validate_datatype(
+    datatype: &DataType,
+    value: &Value,
+    path: &str,
+    r#type: SchemaType,
+    errors: &mut Vec<FieldError>,
+) {
+    match (datatype, value) {
+        (_, Value::Null) | (DataType::Unknown, _) => {}
+        (DataType::String, Value::String(_))
+        | (DataType::Number, Value::Number(_))
+        | (DataType::Boolean, Value::Bool(_))
+        | (DataType::Date, Value::Number(_)) => {}
+        (DataType::Date, Value::String(date)) => {
+            if DateTime::parse_from_rfc3339(date).is_err()
+                && NaiveDate::parse_from_str(date, "%Y-%m-%d").is_err()
+            {
+                errors.push(FieldError::new(path, "expected date"));
+            }
+        }
+        (DataType::Enum { options, .. }, Value::String(option)) => {
+            if let Some(options) = options.as_ref().filter(|options| !options.is_empty()) {
+                if !options.contains(option) {
+                    errors.push(FieldError::new(
+                        path,
+                        format!("expected one of {}", options.join(", ")),
+                    ));
+                }
+            }
+        }
+        (DataType::Expandable(Expandable::Expanded { model, .. }), value) => {
+            validate_fields(&model.fields, value, path, r#type, errors)
+        }
+        (DataType::Expandable(_), Value::Object(_)) => {}
+        (DataType::Array { element_type }, Value::Array(elements)) => {
+            for (index, element) in elements.iter().enumerate() {
+                let path = format!("{path}[{index}]");
+                validate_datatype(element_type, element, &path, r#type, errors);
+            }
+        }
+        (datatype, _) => errors.push(FieldError::new(
+            path,
+            format!("expected {}", expected(datatype)),
+        )),
+    }
+}
+
+fn expected(datatype: &DataType) -> &'static str {
+    match datatype {
+        DataType::String | DataType::Enum { .. } => "string",
+        DataType::Number => "number",
+        DataType::Boolean => "boolean",
+        DataType::Date => "date",
+        DataType::Expandable(_) => "object",
+        DataType::Array { .. } => "array",
+        DataType::Unknown => "any value",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn field(name: &str, datatype: DataType, required: bool) -> Field {
+        Field {
+            name: name.to_string(),
+            datatype,
+            description: None,
+            required,
+        }
+    }
+
+    #[test]
+    fn test_validate_lax_and_strict() {
+        let model = CommonModel {
+            fields: vec![
+                field("id", DataType::String, true),
+                field("count", DataType::Number, false),
+                field(
+                    "status",
+                    DataType::Enum {
+                        options: Some(vec!["open".to_string(), "closed".to_string()]),
+                        reference: String::new(),
+                    },
+                    false,
+                ),
+                field(
+                    "tags",
+                    DataType::Array {
+                        element_type: Box::new(DataType::String),
+                    },
+                    false,
+                ),
+            ],
+            ..Default::default()
+        };
+
+        let value = json!({ "count": "1", "status": "pending", "tags": ["a", 2] });
+
+        assert_eq!(
+            model.validate(&value, SchemaType::Lax),
+            vec![
+                FieldError::new("count", "expected number"),
+                FieldError::new("status", "expected one of open, closed"),
+                FieldError::new("tags[1]", "expected string"),
+            ]
+        );
+        assert_eq!(
+            model.validate(&value, SchemaType::Strict)[0],
+            FieldError::new("id", "missing required field")
+        );
+        assert!(model
+            .validate(&json!({ "id": "1", "count": 1 }), SchemaType::Strict)
+            .is_empty());
+    }
+}
@@ -63,6 +63,8 @@ generate_stores!(
     "system-stats",
     CommonModels,
     "common-models",
+    CommonModelRevisions,
+    "common-model-revisions",
     CommonEnums,
     "common-enums",
     Platforms,
@@ -79,6 +81,8 @@ generate_stores!(
     "settings",
     Tasks,
     "tasks",
+    Workflows,
+    "workflows",
     EmbedTokens,
     "embed-tokens",
     Sessions,
@@ -97,6 +101,8 @@ generate_stores!(
     "connection-model-schema",
     PublicConnectionModelSchemas,
     "connection-model-schema",
+    SchemaDrifts,
+    "schema-drifts",
     Transactions,
     "event-transactions",
     Clients,
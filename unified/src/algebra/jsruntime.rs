@@ -1,12 +1,45 @@
-use js_sandbox_ios::Script;
-use osentities::{ApplicationError, PicaError};
+use moka::{policy::EvictionPolicy, sync::Cache};
+use osentities::{
+    ApplicationError, JsSandbox, PicaError, SandboxError, SandboxScript, SandboxStats,
+};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
-use std::cell::RefCell;
 use std::fmt::Debug;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, LazyLock,
+};
 
-thread_local! {
-    static JS_RUNTIME: RefCell<Script> = RefCell::new(Script::new());
+/// Maximum number of namespaces remembered by the runtime
+const MAX_SCRIPTS: u64 = 10_000;
+
+/// Latest script added under each namespace, shared by every worker of the sandbox. The least
+/// recently used namespaces are evicted first.
+static SCRIPTS: LazyLock<Cache<String, RegisteredScript>> = LazyLock::new(|| {
+    Cache::builder()
+        .max_capacity(MAX_SCRIPTS)
+        .eviction_policy(EvictionPolicy::lru())
+        .support_invalidation_closures()
+        .build()
+});
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone)]
+struct RegisteredScript {
+    code_hash: u64,
+    script: Arc<SandboxScript>,
+}
+
+/// Hit and miss counters of the script registry and of the compiled scripts of the sandbox
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ScriptCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: u64,
+    pub sandbox: SandboxStats,
 }
 
 #[derive(Default, Clone, Copy)]
@@ -29,27 +62,65 @@ impl JSRuntimeImpl {
     ///
     /// # Errors
     ///
-    /// Returns an error if adding the script to the runtime fails, logging the error
-    /// and returning a `bad_request` application error.
+    /// Never fails, scripts are compiled by the sandbox workers on their first run so syntax
+    /// errors are returned by `run`. Adding a different code under an existing namespace
+    /// replaces it, the workers compile it under a new name derived from the code hash.
     pub fn create(&self, fn_name: &str, namespace: &str, code: &str) -> Result<Self, PicaError> {
-        JS_RUNTIME
-            .with_borrow_mut(|script| script.add_script(namespace, fn_name, code))
-            .map_err(|e| {
-                tracing::error!(
-                    "Failed to create javascript function in namespace {}. Error: {}",
-                    namespace,
-                    e
-                );
+        let mut hasher = DefaultHasher::new();
+        (fn_name, code).hash(&mut hasher);
+        let code_hash = hasher.finish();
 
-                ApplicationError::bad_request(
-                    &format!("Failed while creating request schema mapping script: {e}"),
-                    None,
-                )
-            })?;
+        match SCRIPTS.get(namespace) {
+            Some(registered) if registered.code_hash == code_hash => {
+                HITS.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => {
+                MISSES.fetch_add(1, Ordering::Relaxed);
+
+                let script = SandboxScript::new(fn_name, code)
+                    .namespaced(&format!("{namespace}_{code_hash:x}"));
+
+                SCRIPTS.insert(
+                    namespace.to_string(),
+                    RegisteredScript {
+                        code_hash,
+                        script: Arc::new(script),
+                    },
+                );
+            }
+        }
 
         Ok(*self)
     }
 
+    /// Namespace prefix of the scripts of a definition, e.g. a connection model definition
+    pub fn namespace(id: &str) -> String {
+        id.replace([':', '-'], "_")
+    }
+
+    /// Drops every script whose namespace starts with the prefix, e.g. after the mappings of a
+    /// definition were updated
+    pub fn invalidate(prefix: &str) {
+        let prefix = prefix.to_string();
+
+        if let Err(e) =
+            SCRIPTS.invalidate_entries_if(move |namespace, _| namespace.starts_with(&prefix))
+        {
+            tracing::error!("Failed to invalidate javascript functions. Error: {e}");
+        }
+    }
+
+    pub fn stats() -> ScriptCacheStats {
+        ScriptCacheStats {
+            hits: HITS.load(Ordering::Relaxed),
+            misses: MISSES.load(Ordering::Relaxed),
+            entries: SCRIPTS.entry_count(),
+            sandbox: JsSandbox::global()
+                .map(JsSandbox::stats)
+                .unwrap_or_default(),
+        }
+    }
+
     /// Executes a JavaScript function in the runtime associated with a specific namespace,
     /// passing serialized input data and deserializing the output.
     ///
@@ -72,6 +143,8 @@ impl JSRuntimeImpl {
     ///
     /// Returns an error if serialization of the input data fails or if the JavaScript
     /// function fails to execute. Logs the error and returns a `bad_request` application error.
+    /// Scripts exceeding the limits of the sandbox return a `script_error` with the `timeout`
+    /// or `heap_limit` subtype instead.
     pub async fn run<P, R>(&self, payload: &P, namespace: &str) -> Result<R, PicaError>
     where
         P: Serialize + Debug,
@@ -86,19 +159,119 @@ impl JSRuntimeImpl {
             )
         })?;
 
-        let payload = JS_RUNTIME
-            .with_borrow_mut(|script| script.call_namespace(namespace, payload))
+        let script = SCRIPTS.get(namespace).map(|registered| registered.script).ok_or_else(|| {
+            tracing::error!("No javascript function in namespace {namespace}");
+
+            ApplicationError::bad_request(
+                &format!("Failed while running request schema mapping script: no script in namespace {namespace}"),
+                None,
+            )
+        })?;
+
+        let response = JsSandbox::global()?
+            .call(script, payload)
+            .await
             .map_err(|e| {
                 tracing::error!("Error running javascript function: {}", e);
 
-                ApplicationError::bad_request(
-                    &format!("Failed while running request schema mapping script: {e}"),
-                    None,
-                )
-            });
+                match e {
+                    SandboxError::Script(_) | SandboxError::Serde(_) => {
+                        ApplicationError::bad_request(
+                            &format!("Failed while running request schema mapping script: {e}"),
+                            None,
+                        )
+                    }
+                    e => e.into(),
+                }
+            })?;
+
+        serde_json::from_value(response).map_err(|e| {
+            tracing::error!("Error deserializing javascript function result: {}", e);
+
+            ApplicationError::bad_request(
+                &format!("Failed while running request schema mapping script: {e}"),
+                None,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn registered(namespace: &str) -> Option<Arc<SandboxScript>> {
+        SCRIPTS.get(namespace).map(|registered| registered.script)
+    }
+
+    #[test]
+    fn test_registry_reuses_scripts_with_the_same_code() {
+        let namespace = JSRuntimeImpl::namespace("test::registry-hit");
+        let code = "function double(data) { return data * 2; }";
+
+        let misses = MISSES.load(Ordering::Relaxed);
+        JSRuntimeImpl.create("double", &namespace, code).unwrap();
+        assert!(MISSES.load(Ordering::Relaxed) > misses);
+        let first = registered(&namespace).unwrap();
+
+        let hits = HITS.load(Ordering::Relaxed);
+        JSRuntimeImpl.create("double", &namespace, code).unwrap();
+        assert!(HITS.load(Ordering::Relaxed) > hits);
+
+        assert!(Arc::ptr_eq(&first, &registered(&namespace).unwrap()));
+    }
+
+    #[test]
+    fn test_registry_replaces_scripts_with_a_different_code() {
+        let namespace = JSRuntimeImpl::namespace("test::registry-miss");
+
+        JSRuntimeImpl
+            .create("one", &namespace, "function one() { return 1; }")
+            .unwrap();
+        let first = registered(&namespace).unwrap();
+
+        let misses = MISSES.load(Ordering::Relaxed);
+        JSRuntimeImpl
+            .create("one", &namespace, "function one() { return 2; }")
+            .unwrap();
+        assert!(MISSES.load(Ordering::Relaxed) > misses);
+
+        let second = registered(&namespace).unwrap();
+        assert_ne!(first.namespace(), second.namespace());
+    }
+
+    #[tokio::test]
+    async fn test_registry_invalidates_scripts_by_prefix() {
+        let prefix = JSRuntimeImpl::namespace("test::registry-invalidate");
+        let namespace = format!("{prefix}_mapCrudRequest");
+        let other = JSRuntimeImpl::namespace("test::registry-kept");
+
+        let runtime = JSRuntimeImpl
+            .create(
+                "identity",
+                &namespace,
+                "function identity(data) { return data; }",
+            )
+            .unwrap();
+        JSRuntimeImpl
+            .create(
+                "identity",
+                &other,
+                "function identity(data) { return data; }",
+            )
+            .unwrap();
+
+        let result: Value = runtime.run(&json!({ "id": 1 }), &namespace).await.unwrap();
+        assert_eq!(result, json!({ "id": 1 }));
 
-        tokio::task::yield_now().await;
+        JSRuntimeImpl::invalidate(&prefix);
 
-        payload
+        assert!(registered(&namespace).is_none());
+        assert!(registered(&other).is_some());
+        assert!(runtime
+            .run::<_, Value>(&json!({ "id": 1 }), &namespace)
+            .await
+            .is_err());
     }
 }
@@ -0,0 +1,85 @@
+use osentities::{
+    algebra::JsonExt,
+    json_mapper::{map_data_by_schema, SchemaMappingDefinition},
+    ApplicationError, PicaError,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fmt::Debug;
+
+/// Maps a payload with a declarative schema mapping definition, the native counterpart of
+/// `JSRuntimeImpl::run`.
+///
+/// # Errors
+///
+/// Returns a `bad_request` application error if the payload can't be serialized, a required
+/// field is missing or has an invalid type, or the mapped value doesn't deserialize into `R`.
+pub fn map_by_schema<P, R>(
+    payload: &P,
+    definition: &SchemaMappingDefinition,
+) -> Result<R, PicaError>
+where
+    P: Serialize + Debug,
+    R: DeserializeOwned + Debug,
+{
+    let payload = serde_json::to_value(payload).map_err(|e| {
+        tracing::error!("Error serializing payload: {}", e);
+
+        ApplicationError::bad_request(
+            &format!("Failed while serializing request for declarative schema mapping: {e}"),
+            None,
+        )
+    })?;
+
+    let mapped = map_data_by_schema(&payload, definition).map_err(|e| {
+        tracing::error!("Error running declarative schema mapping: {}", e);
+
+        ApplicationError::bad_request(
+            &format!("Failed while running declarative schema mapping: {e}"),
+            None,
+        )
+    })?;
+
+    serde_json::from_value(mapped.drop_nulls()).map_err(|e| {
+        tracing::error!(
+            "Error deserializing declarative schema mapping result: {}",
+            e
+        );
+
+        ApplicationError::bad_request(
+            &format!("Failed while running declarative schema mapping: {e}"),
+            None,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_map_by_schema_maps_and_drops_nulls() {
+        let definition: SchemaMappingDefinition = serde_json::from_value(json!({
+            "id": { "type": "string", "path": "$.ticket_id", "transformation": "", "required": true },
+            "title": { "type": "string", "path": "$.subject", "transformation": "", "required": false },
+            "closed": {
+                "type": "boolean",
+                "path": "$.is_closed",
+                "transformation": "",
+                "required": false,
+                "default": { "value": "false" }
+            }
+        }))
+        .expect("Failed to deserialize definition");
+
+        let mapped: Value = map_by_schema(&json!({ "ticket_id": "42" }), &definition)
+            .expect("Failed to map payload");
+
+        assert_eq!(mapped, json!({ "id": "42", "closed": false }));
+
+        let missing = map_by_schema::<Value, Value>(&json!({}), &definition);
+
+        assert!(missing.is_err());
+    }
+}
@@ -1 +1,2 @@
 pub mod jsruntime;
+pub mod mapper;
@@ -1,23 +1,46 @@
+use base64::{prelude::BASE64_STANDARD, Engine};
+use cache::rate_limit::OutboundRateLimiter;
 use derive_builder::Builder;
-use http::HeaderMap;
+use http::{header::CONTENT_TYPE, HeaderMap, HeaderValue};
 use indexmap::IndexMap;
 use osentities::{
-    api_model_config::{ApiModelConfig, AuthMethod, OAuthLegacyHashAlgorithm},
+    api_model_config::{
+        ApiModelConfig, AuthMethod, ContentType, MultipartPart, OAuthLegacyHashAlgorithm,
+    },
     oauth_secret::OAuthLegacySecret,
     prelude::oauth_secret::OAuthSecret,
     AuthorizationType, InternalError, Nonce, OAuthData, PicaError, SignableRequest,
     SignatureMethod, SigningKey,
 };
-use reqwest::{Client, Response, Url};
+use reqwest::{
+    multipart::{Form, Part},
+    Client, RequestBuilder, Response, Url,
+};
+use serde::Deserialize;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::error::Error;
 
+/// Number of times a request was sent before its response was returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestAttempts(pub u32);
+
+/// Body of a `ContentType::Binary` request, either the base64 encoded content itself or a part
+/// carrying it along with its content type
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum BinaryBody {
+    Encoded(String),
+    Part(MultipartPart),
+}
+
 #[derive(Debug, Clone, Builder)]
 pub struct CallerClient<'a> {
     config: &'a ApiModelConfig,
     action: http::Method,
     client: &'a Client,
+    #[builder(default)]
+    rate_limiter: Option<(&'a OutboundRateLimiter, String)>,
 }
 
 impl<'a> CallerClient<'a> {
@@ -26,9 +49,20 @@ impl<'a> CallerClient<'a> {
             config,
             action,
             client,
+            rate_limiter: None,
         }
     }
 
+    /// Takes a token from the bucket identified by `key` before every attempt, following the
+    /// `rate_limit` of the model config
+    pub fn with_rate_limiter(mut self, rate_limiter: &'a OutboundRateLimiter, key: String) -> Self {
+        self.rate_limiter = Some((rate_limiter, key));
+        self
+    }
+
+    /// Sends the request, retrying transient failures according to the `retry_policy` of the
+    /// model config. Non idempotent methods are only retried when the policy opts into it. The
+    /// number of attempts is stored as a `RequestAttempts` response extension.
     pub async fn make_request(
         &self,
         payload: Option<Vec<u8>>,
@@ -42,8 +76,6 @@ impl<'a> CallerClient<'a> {
             format!("{}/{}", self.config.base_url, self.config.path)
         };
 
-        let mut request_builder = self.client.request(self.action.clone(), &endpoint);
-
         let mut merged_headers = headers.unwrap_or_default();
 
         if let Some(model_headers) = &self.config.headers {
@@ -54,7 +86,92 @@ impl<'a> CallerClient<'a> {
         merged_headers.remove(http::header::ACCEPT_ENCODING);
         merged_headers.remove(http::header::HOST);
 
-        for (key, value) in merged_headers.iter() {
+        // The content type of encoded bodies is set when building them, e.g. with the boundary
+        // of a multipart form
+        if matches!(
+            self.config.content,
+            Some(ContentType::Multipart | ContentType::Binary)
+        ) && payload.is_some()
+        {
+            merged_headers.remove(CONTENT_TYPE);
+        }
+
+        let policy = self
+            .config
+            .retry_policy
+            .as_ref()
+            .filter(|policy| policy.applies_to(&self.action));
+        let max_attempts = policy.map(|p| p.max_attempts.max(1)).unwrap_or(1);
+        let mut attempt = 1;
+
+        loop {
+            if let (Some(policy), Some((rate_limiter, key))) =
+                (self.config.rate_limit.as_ref(), self.rate_limiter.as_ref())
+            {
+                rate_limiter.acquire(key, policy).await?;
+            }
+
+            let result = self
+                .request_builder(
+                    &endpoint,
+                    payload.as_ref(),
+                    secret,
+                    &merged_headers,
+                    query_params,
+                )?
+                .send()
+                .await;
+
+            let retry_in = match (policy, &result) {
+                (Some(policy), Ok(res))
+                    if attempt < max_attempts && policy.is_retryable(res.status()) =>
+                {
+                    policy.delay_for(attempt, res.headers())
+                }
+                (Some(policy), Err(e))
+                    if attempt < max_attempts && (e.is_timeout() || e.is_connect()) =>
+                {
+                    Some(policy.backoff(attempt))
+                }
+                _ => None,
+            };
+
+            match retry_in {
+                Some(delay) => {
+                    tracing::warn!(
+                        "Retrying request to {endpoint} in {}ms. Attempt {attempt} of {max_attempts}",
+                        delay.as_millis()
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                None => {
+                    let mut res = result.map_err(|e| {
+                        tracing::error!("Failed to send request: {}", e.source().unwrap_or(&e));
+                        InternalError::io_err(
+                            &format!("Failed to send request: {}", e),
+                            Some("reqwest::Error"),
+                        )
+                    })?;
+                    res.extensions_mut().insert(RequestAttempts(attempt));
+
+                    return Ok(res);
+                }
+            }
+        }
+    }
+
+    fn request_builder(
+        &self,
+        endpoint: &str,
+        payload: Option<&Vec<u8>>,
+        secret: Option<&Value>,
+        headers: &HeaderMap,
+        query_params: Option<&HashMap<String, String>>,
+    ) -> Result<RequestBuilder, PicaError> {
+        let mut request_builder = self.client.request(self.action.clone(), endpoint);
+
+        for (key, value) in headers.iter() {
             request_builder = request_builder.header(key, value);
         }
 
@@ -67,7 +184,17 @@ impl<'a> CallerClient<'a> {
         }
 
         if let Some(payload) = payload {
-            request_builder = request_builder.body(payload);
+            request_builder = match self.config.content {
+                Some(ContentType::Multipart) => request_builder.multipart(multipart_form(payload)?),
+                Some(ContentType::Binary) => {
+                    let (content_type, bytes) = binary_body(payload)?;
+
+                    request_builder
+                        .header(CONTENT_TYPE, content_type)
+                        .body(bytes)
+                }
+                _ => request_builder.body(payload.clone()),
+            };
         }
 
         request_builder = match &self.config.auth_method {
@@ -109,7 +236,7 @@ impl<'a> CallerClient<'a> {
                     token_secret: Some(secret.access_token_secret),
                 };
 
-                let uri = Url::parse(&endpoint).map_err(|e| {
+                let uri = Url::parse(endpoint).map_err(|e| {
                     InternalError::invalid_argument(&e.to_string(), Some("endpoint"))
                 })?;
 
@@ -157,25 +284,95 @@ impl<'a> CallerClient<'a> {
             AuthMethod::None => request_builder,
         };
 
-        let res = request_builder.send().await.map_err(|e| {
-            tracing::error!("Failed to send request: {}", e.source().unwrap_or(&e));
-            InternalError::io_err(
-                &format!("Failed to send request: {}", e),
-                Some("reqwest::Error"),
-            )
-        })?;
-
-        Ok(res)
+        Ok(request_builder)
     }
 }
 
+/// Builds a multipart form from a JSON list of `MultipartPart`
+fn multipart_form(payload: &[u8]) -> Result<Form, PicaError> {
+    let parts: Vec<MultipartPart> = serde_json::from_slice(payload).map_err(|e| {
+        InternalError::invalid_argument(
+            &format!("Multipart body must be a list of parts: {e}"),
+            Some("multipart"),
+        )
+    })?;
+
+    parts.into_iter().try_fold(Form::new(), |form, part| {
+        let name = part.name.clone();
+
+        let mut field = match (part.data, part.value) {
+            (Some(data), _) => Part::bytes(decode_base64(&data)?),
+            (None, Some(value)) => Part::text(value),
+            (None, None) => {
+                return Err(InternalError::invalid_argument(
+                    &format!("Multipart part {name} has neither data nor value"),
+                    Some("multipart"),
+                ))
+            }
+        };
+
+        if let Some(file_name) = part.file_name {
+            field = field.file_name(file_name);
+        }
+
+        if let Some(content_type) = part.content_type {
+            field = field.mime_str(&content_type).map_err(|e| {
+                InternalError::invalid_argument(&e.to_string(), Some("content_type"))
+            })?;
+        }
+
+        Ok(form.part(name, field))
+    })
+}
+
+/// Decodes a `ContentType::Binary` body into its content type and raw bytes
+fn binary_body(payload: &[u8]) -> Result<(HeaderValue, Vec<u8>), PicaError> {
+    let body: BinaryBody = serde_json::from_slice(payload).map_err(|e| {
+        InternalError::invalid_argument(
+            &format!("Binary body must be a base64 encoded string: {e}"),
+            Some("binary"),
+        )
+    })?;
+
+    let (content_type, data) = match body {
+        BinaryBody::Encoded(data) => (None, data),
+        BinaryBody::Part(MultipartPart {
+            data: Some(data),
+            content_type,
+            ..
+        }) => (content_type, data),
+        BinaryBody::Part(_) => {
+            return Err(InternalError::invalid_argument(
+                "Binary body has no data",
+                Some("binary"),
+            ))
+        }
+    };
+
+    let content_type = match content_type {
+        Some(content_type) => HeaderValue::from_str(&content_type)
+            .map_err(|e| InternalError::invalid_argument(&e.to_string(), Some("content_type")))?,
+        None => HeaderValue::from_static("application/octet-stream"),
+    };
+
+    Ok((content_type, decode_base64(&data)?))
+}
+
+fn decode_base64(data: &str) -> Result<Vec<u8>, PicaError> {
+    BASE64_STANDARD
+        .decode(data)
+        .map_err(|e| InternalError::invalid_argument(&e.to_string(), Some("base64")))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use http::StatusCode;
-    use mockito::Server;
+    use mockito::{Matcher, Server};
     use osentities::{
-        api_model_config::{SamplesInput, SchemasInput},
+        api_model_config::{
+            RateLimitPolicy, RateLimitScope, RetryPolicy, SamplesInput, SchemasInput,
+        },
         connection_model_definition::{
             ConnectionModelDefinition, CrudAction, PlatformInfo, TestConnection,
         },
@@ -218,6 +415,10 @@ mod tests {
             },
             responses: vec![],
             paths: None,
+            retry_policy: None,
+            rate_limit: None,
+            response_cache: None,
+            validation: None,
         };
 
         let stripe_model_config = ConnectionModelDefinition {
@@ -294,6 +495,10 @@ mod tests {
             },
             responses: vec![],
             paths: None,
+            retry_policy: None,
+            rate_limit: None,
+            response_cache: None,
+            validation: None,
         };
 
         let stripe_model_config = ConnectionModelDefinition {
@@ -332,4 +537,346 @@ mod tests {
         let response = res.bytes().await.unwrap();
         assert_eq!(response, "Not found".as_bytes().to_vec());
     }
+
+    #[tokio::test]
+    async fn test_retried_make_request() {
+        let mut mock_server = Server::new_async().await;
+
+        let mock = mock_server
+            .mock("GET", "/api/customers")
+            .with_status(503)
+            .with_header("retry-after", "0")
+            .with_body("Service unavailable")
+            .expect(3)
+            .create_async()
+            .await;
+
+        let api_model_config = ApiModelConfig {
+            base_url: mock_server.url() + "/api",
+            path: "customers".to_string(),
+            auth_method: AuthMethod::None,
+            headers: None,
+            content: None,
+            query_params: None,
+            schemas: SchemasInput {
+                headers: None,
+                query_params: None,
+                path_params: None,
+                body: None,
+            },
+            samples: SamplesInput {
+                headers: None,
+                query_params: None,
+                path_params: None,
+                body: None,
+            },
+            responses: vec![],
+            paths: None,
+            retry_policy: Some(RetryPolicy {
+                max_attempts: 3,
+                initial_backoff_ms: 1,
+                max_backoff_ms: 10,
+                ..Default::default()
+            }),
+            rate_limit: None,
+            response_cache: None,
+            validation: None,
+        };
+
+        let client = Client::new();
+        let single_api_caller = CallerClient::new(&api_model_config, http::Method::GET, &client);
+
+        let res = single_api_caller
+            .make_request(None, None, None, None)
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            res.extensions().get::<RequestAttempts>(),
+            Some(&RequestAttempts(3))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limited_retries_take_a_token_per_attempt() {
+        let mut mock_server = Server::new_async().await;
+
+        let mock = mock_server
+            .mock("GET", "/api/customers")
+            .with_status(503)
+            .with_header("retry-after", "0")
+            .with_body("Service unavailable")
+            .expect(1)
+            .create_async()
+            .await;
+
+        let api_model_config = ApiModelConfig {
+            base_url: mock_server.url() + "/api",
+            path: "customers".to_string(),
+            auth_method: AuthMethod::None,
+            headers: None,
+            content: None,
+            query_params: None,
+            schemas: SchemasInput {
+                headers: None,
+                query_params: None,
+                path_params: None,
+                body: None,
+            },
+            samples: SamplesInput {
+                headers: None,
+                query_params: None,
+                path_params: None,
+                body: None,
+            },
+            responses: vec![],
+            paths: None,
+            retry_policy: Some(RetryPolicy {
+                max_attempts: 3,
+                initial_backoff_ms: 1,
+                max_backoff_ms: 10,
+                ..Default::default()
+            }),
+            rate_limit: Some(RateLimitPolicy {
+                capacity: 1,
+                interval_ms: 60_000,
+                max_wait_ms: 0,
+                scope: RateLimitScope::Connection,
+            }),
+            response_cache: None,
+            validation: None,
+        };
+
+        let client = Client::new();
+        let rate_limiter = OutboundRateLimiter::local();
+        let single_api_caller = CallerClient::new(&api_model_config, http::Method::GET, &client)
+            .with_rate_limiter(&rate_limiter, "platform::key".to_string());
+
+        // The only token is spent on the first attempt, so the retry is rejected
+        let err = single_api_caller
+            .make_request(None, None, None, None)
+            .await
+            .unwrap_err();
+
+        mock.assert_async().await;
+        assert_eq!(StatusCode::from(&err), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_non_idempotent_make_request_is_not_retried() {
+        let mut mock_server = Server::new_async().await;
+
+        let mock = mock_server
+            .mock("POST", "/api/customers")
+            .with_status(503)
+            .with_header("retry-after", "0")
+            .with_body("Service unavailable")
+            .expect(1)
+            .create_async()
+            .await;
+
+        let mut api_model_config = ApiModelConfig {
+            base_url: mock_server.url() + "/api",
+            path: "customers".to_string(),
+            auth_method: AuthMethod::None,
+            headers: None,
+            content: None,
+            query_params: None,
+            schemas: SchemasInput {
+                headers: None,
+                query_params: None,
+                path_params: None,
+                body: None,
+            },
+            samples: SamplesInput {
+                headers: None,
+                query_params: None,
+                path_params: None,
+                body: None,
+            },
+            responses: vec![],
+            paths: None,
+            retry_policy: Some(RetryPolicy {
+                max_attempts: 3,
+                initial_backoff_ms: 1,
+                max_backoff_ms: 10,
+                ..Default::default()
+            }),
+            rate_limit: None,
+            response_cache: None,
+            validation: None,
+        };
+
+        let client = Client::new();
+        let single_api_caller = CallerClient::new(&api_model_config, http::Method::POST, &client);
+
+        let res = single_api_caller
+            .make_request(None, None, None, None)
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            res.extensions().get::<RequestAttempts>(),
+            Some(&RequestAttempts(1))
+        );
+
+        let mock = mock_server
+            .mock("POST", "/api/customers")
+            .with_status(503)
+            .with_header("retry-after", "0")
+            .with_body("Service unavailable")
+            .expect(3)
+            .create_async()
+            .await;
+
+        if let Some(policy) = api_model_config.retry_policy.as_mut() {
+            policy.retry_non_idempotent = true;
+        }
+        let single_api_caller = CallerClient::new(&api_model_config, http::Method::POST, &client);
+
+        let res = single_api_caller
+            .make_request(None, None, None, None)
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(
+            res.extensions().get::<RequestAttempts>(),
+            Some(&RequestAttempts(3))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_multipart_make_request() {
+        let mut mock_server = Server::new_async().await;
+
+        let mock = mock_server
+            .mock("POST", "/api/files")
+            .match_header(
+                "content-type",
+                Matcher::Regex("^multipart/form-data; boundary=".to_string()),
+            )
+            .match_body(Matcher::AllOf(vec![
+                Matcher::Regex("name=\"purpose\"\r\n\r\nattachment".to_string()),
+                Matcher::Regex("filename=\"hello.txt\"".to_string()),
+                Matcher::Regex("Content-Type: text/plain\r\n\r\nhello world".to_string()),
+            ]))
+            .with_status(200)
+            .with_body("{\"id\": \"file_1\"}")
+            .create_async()
+            .await;
+
+        let api_model_config = ApiModelConfig {
+            base_url: mock_server.url() + "/api",
+            path: "files".to_string(),
+            auth_method: AuthMethod::None,
+            headers: None,
+            content: Some(ContentType::Multipart),
+            query_params: None,
+            schemas: SchemasInput {
+                headers: None,
+                query_params: None,
+                path_params: None,
+                body: None,
+            },
+            samples: SamplesInput {
+                headers: None,
+                query_params: None,
+                path_params: None,
+                body: None,
+            },
+            responses: vec![],
+            paths: None,
+            retry_policy: None,
+            rate_limit: None,
+            response_cache: None,
+            validation: None,
+        };
+
+        let payload = serde_json::to_vec(&serde_json::json!([
+            { "name": "purpose", "value": "attachment" },
+            {
+                "name": "file",
+                "data": BASE64_STANDARD.encode("hello world"),
+                "fileName": "hello.txt",
+                "contentType": "text/plain"
+            }
+        ]))
+        .unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+        let client = Client::new();
+        let single_api_caller = CallerClient::new(&api_model_config, http::Method::POST, &client);
+
+        let res = single_api_caller
+            .make_request(Some(payload), None, Some(headers), None)
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_binary_make_request() {
+        let mut mock_server = Server::new_async().await;
+
+        let mock = mock_server
+            .mock("PUT", "/api/documents")
+            .match_header("content-type", "application/pdf")
+            .match_body(vec![0x25, 0x50, 0x44, 0x46])
+            .with_status(200)
+            .create_async()
+            .await;
+
+        let api_model_config = ApiModelConfig {
+            base_url: mock_server.url() + "/api",
+            path: "documents".to_string(),
+            auth_method: AuthMethod::None,
+            headers: None,
+            content: Some(ContentType::Binary),
+            query_params: None,
+            schemas: SchemasInput {
+                headers: None,
+                query_params: None,
+                path_params: None,
+                body: None,
+            },
+            samples: SamplesInput {
+                headers: None,
+                query_params: None,
+                path_params: None,
+                body: None,
+            },
+            responses: vec![],
+            paths: None,
+            retry_policy: None,
+            rate_limit: None,
+            response_cache: None,
+            validation: None,
+        };
+
+        let payload = serde_json::to_vec(&serde_json::json!({
+            "data": BASE64_STANDARD.encode([0x25, 0x50, 0x44, 0x46]),
+            "contentType": "application/pdf"
+        }))
+        .unwrap();
+
+        let client = Client::new();
+        let single_api_caller = CallerClient::new(&api_model_config, http::Method::PUT, &client);
+
+        let res = single_api_caller
+            .make_request(Some(payload), None, None, None)
+            .await
+            .unwrap();
+
+        mock.assert_async().await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
 }
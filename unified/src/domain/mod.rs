@@ -2,6 +2,7 @@ use bson::doc;
 use derive_builder::Builder;
 use http::StatusCode;
 use http::{HeaderMap, HeaderName, HeaderValue};
+use osentities::validation::FieldError;
 use osentities::Id;
 use osentities::PicaError;
 use serde::{Deserialize, Serialize};
@@ -155,6 +156,15 @@ impl ResponseCrud {
     }
 }
 
+/// Caller supplied caps for following a unified cursor across pages. The number of pages is
+/// always bounded by `AUTO_PAGINATE_MAX_PAGES` regardless of what the caller requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaginationLimits {
+    pub max_pages: Option<u32>,
+    pub max_records: Option<usize>,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Builder)]
 #[builder(setter(into), build_fn(error = "PicaError"))]
 #[serde(rename_all = "camelCase")]
@@ -184,12 +194,21 @@ pub struct UnifiedMetadata {
     latency: Option<i32>,
     #[builder(setter(strip_option), default)]
     hash: Option<String>,
+    #[builder(setter(strip_option), default)]
+    attempts: Option<u32>,
+    /// Fields of the response not matching the common model, when validation is enabled
+    #[builder(setter(strip_option), default)]
+    drift: Option<Vec<FieldError>>,
 }
 
 impl UnifiedMetadata {
     pub fn as_value(&self) -> Value {
         serde_json::to_value(self).unwrap_or_default()
     }
+
+    pub fn hash(&self) -> Option<&str> {
+        self.hash.as_deref()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
@@ -198,3 +217,13 @@ pub struct UnifiedCache {
     ttl: u64,
     key: String,
 }
+
+impl UnifiedCache {
+    pub fn new(hit: bool, ttl: u64, key: impl Into<String>) -> Self {
+        Self {
+            hit,
+            ttl,
+            key: key.into(),
+        }
+    }
+}
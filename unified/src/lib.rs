@@ -2,4 +2,5 @@ pub mod algebra;
 pub mod client;
 pub mod domain;
 pub mod helper;
+pub mod oauth;
 pub mod unified;
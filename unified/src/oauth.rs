@@ -0,0 +1,623 @@
+use crate::{client::CallerClient, unified::UnifiedDestination};
+use bson::{doc, Document};
+use cache::local::LocalCacheExt;
+use chrono::{Duration, Utc};
+use futures::{stream, StreamExt};
+use handlebars::Handlebars;
+use http::{header::CONTENT_TYPE, HeaderMap, HeaderValue, StatusCode};
+use moka::future::Cache;
+use mongodb::options::ReturnDocument;
+use osentities::{
+    api_model_config::{ApiModelConfig, AuthMethod, ContentType},
+    connection_model_definition::ConnectionModelDefinition,
+    connection_oauth_definition::{Computation, ConnectionOAuthDefinition, OAuthResponse},
+    oauth_secret::OAuthSecret,
+    Connection, InternalError, OAuth, PicaError, Secret,
+};
+use serde_json::Value;
+use std::time::Duration as StdDuration;
+use tracing::{error, info, warn};
+
+/// Seconds subtracted from the lifetime of an access token so that it is refreshed before the
+/// platform starts rejecting it
+const EXPIRY_MARGIN_SECS: i64 = 120;
+
+/// Maximum number of connections refreshed at the same time
+const MAX_CONCURRENT_REFRESHES: usize = 10;
+
+/// Seconds a replica holds the refresh claim of a connection before another one may take it over
+const REFRESH_CLAIM_SECS: i64 = 120;
+
+/// Delay before retrying a connection whose refresh failed, doubled with every consecutive failure
+/// up to `MAX_REFRESH_RETRY_DELAY_SECS`
+const REFRESH_RETRY_DELAY_SECS: i64 = 30;
+const MAX_REFRESH_RETRY_DELAY_SECS: i64 = 60 * 60;
+
+/// Longest time a request rejected by the platform waits on a refresh claimed by another replica,
+/// and how often it rereads the connection meanwhile
+const MAX_REFRESH_WAIT_SECS: i64 = 30;
+const REFRESH_WAIT_POLL_MILLIS: u64 = 500;
+
+/// Seconds a refreshed connection is kept so that requests which failed with the previous token
+/// replay with the new one instead of refreshing again
+const REFRESH_RESULT_TTL_SECS: u64 = 60;
+
+/// Deduplicates on-demand token refreshes. Concurrent requests rejected with the same secret of a
+/// connection wait on a single refresh and share its result.
+#[derive(Clone)]
+pub struct OAuthRefreshes {
+    /// Keyed by the connection id and the secret that was rejected by the platform
+    inner: Cache<(String, String), Connection>,
+}
+
+impl Default for OAuthRefreshes {
+    fn default() -> Self {
+        Self {
+            inner: Cache::builder()
+                .time_to_live(StdDuration::from_secs(REFRESH_RESULT_TTL_SECS))
+                .build(),
+        }
+    }
+}
+
+/// Request sent to the platform to exchange a refresh token for a new access token
+struct RefreshRequest {
+    config: ApiModelConfig,
+    headers: HeaderMap,
+    body: Option<Vec<u8>>,
+}
+
+impl UnifiedDestination {
+    /// Refreshes the access token of every OAuth connection that expires within `window_secs`.
+    /// Returns the number of connections that were refreshed successfully.
+    ///
+    /// Each connection is claimed before it is refreshed so that replicas never refresh the same
+    /// one, and connections that failed recently are backed off so that they cannot starve the
+    /// rest of the batch.
+    pub async fn refresh_expiring_oauth_connections(
+        &self,
+        window_secs: i64,
+        limit: u64,
+    ) -> Result<usize, PicaError> {
+        let mut connections = vec![];
+        while (connections.len() as u64) < limit {
+            match self.claim_expiring_oauth_connection(window_secs).await? {
+                Some(claimed) => connections.push(claimed),
+                None => break,
+            }
+        }
+
+        let refreshed = stream::iter(connections)
+            .map(|(connection, failures)| async move {
+                let result = self.refresh_oauth_connection(&connection).await;
+
+                if let Err(e) = &result {
+                    error!("Failed to refresh oauth connection {}: {e}", connection.key);
+
+                    self.record_refresh_failure(&connection, failures).await;
+                }
+
+                result.is_ok()
+            })
+            .buffer_unordered(MAX_CONCURRENT_REFRESHES)
+            .filter(|ok| futures::future::ready(*ok))
+            .count()
+            .await;
+
+        Ok(refreshed)
+    }
+
+    /// Claims the connection expiring the soonest that is neither claimed by another replica nor
+    /// backed off after a failure. Connections that never failed, or failed the longest time ago,
+    /// go first. Returns it along with its number of consecutive refresh failures.
+    ///
+    /// The claim and the failures are kept under `oauthRefresh` in the connection document, which
+    /// is cleared once a refresh succeeds.
+    async fn claim_expiring_oauth_connection(
+        &self,
+        window_secs: i64,
+    ) -> Result<Option<(Connection, i64)>, PicaError> {
+        let now = Utc::now().timestamp();
+
+        let claimed = self
+            .connections_store
+            .collection
+            .clone_with_type::<Document>()
+            .find_one_and_update(
+                doc! {
+                    "oauth.enabled.expires_at": { "$lte": now + window_secs },
+                    "deleted": false,
+                    "oauthRefresh.claimedUntil": { "$not": { "$gt": now } },
+                    "oauthRefresh.nextAttemptAt": { "$not": { "$gt": now } },
+                },
+                doc! {
+                    "$set": { "oauthRefresh.claimedUntil": now + REFRESH_CLAIM_SECS }
+                },
+            )
+            .sort(doc! {
+                "oauthRefresh.nextAttemptAt": 1,
+                "oauth.enabled.expires_at": 1,
+            })
+            .return_document(ReturnDocument::After)
+            .await?;
+
+        let Some(document) = claimed else {
+            return Ok(None);
+        };
+
+        let failures = document
+            .get_document("oauthRefresh")
+            .ok()
+            .and_then(|state| state.get("failures"))
+            .and_then(|failures| failures.as_i64().or(failures.as_i32().map(i64::from)))
+            .unwrap_or_default();
+        let connection: Connection = bson::from_document(document)
+            .map_err(|e| InternalError::deserialize_error(&e.to_string(), None))?;
+
+        Ok(Some((connection, failures)))
+    }
+
+    /// Releases the claim of a connection whose refresh failed and backs it off exponentially.
+    /// Errors are only logged, the claim expires on its own anyway.
+    async fn record_refresh_failure(&self, connection: &Connection, failures: i64) {
+        let delay = REFRESH_RETRY_DELAY_SECS
+            .saturating_mul(2i64.saturating_pow(failures.clamp(0, 32) as u32))
+            .min(MAX_REFRESH_RETRY_DELAY_SECS);
+
+        if let Err(e) = self
+            .connections_store
+            .update_one(
+                &connection.id.to_string(),
+                doc! {
+                    "$set": {
+                        "oauthRefresh.failures": failures + 1,
+                        "oauthRefresh.nextAttemptAt": Utc::now().timestamp() + delay,
+                    },
+                    "$unset": { "oauthRefresh.claimedUntil": "" },
+                },
+            )
+            .await
+        {
+            error!(
+                "Failed to record refresh failure of oauth connection {}: {e}",
+                connection.key
+            );
+        }
+    }
+
+    /// Refreshes the access token of the connection after the platform rejected a request made
+    /// with it. Returns the refreshed connection and its secret when the request should be
+    /// replayed, or `None` when the definition does not use OAuth or the refresh failed.
+    pub(crate) async fn refresh_after_unauthorized(
+        &self,
+        connection: &Connection,
+        config: &ConnectionModelDefinition,
+        status: StatusCode,
+    ) -> Option<(Connection, Secret)> {
+        if status != StatusCode::UNAUTHORIZED
+            || !matches!(config.platform_info.config().auth_method, AuthMethod::OAuth)
+            || !matches!(connection.oauth, Some(OAuth::Enabled { .. }))
+        {
+            return None;
+        }
+
+        warn!(
+            "Platform rejected the access token of connection {}, refreshing it",
+            connection.key
+        );
+
+        let key = (
+            connection.id.to_string(),
+            connection.secrets_service_id.clone(),
+        );
+
+        let refreshed = self
+            .oauth_refreshes
+            .inner
+            .try_get_with(key, self.refresh_or_wait(connection))
+            .await
+            .inspect_err(|e| error!("Failed to refresh oauth connection {}: {e}", connection.key))
+            .ok()?;
+
+        // Requests holding a stale connection may have cached the previous secret again, so the
+        // new one is read from the secrets service
+        let secret = self
+            .secrets_client
+            .get(&refreshed.secrets_service_id, &refreshed.ownership.id)
+            .await
+            .inspect_err(|e| {
+                error!(
+                    "Failed to get refreshed secret of connection {}: {e}",
+                    connection.key
+                )
+            })
+            .ok()?;
+        self.secrets_cache.insert(&refreshed, &secret).await.ok()?;
+
+        Some((refreshed, secret))
+    }
+
+    /// Refreshes the connection under the same claim as the scheduled refreshes. While another
+    /// replica holds it, the connection is reread until that replica stored the new secret or the
+    /// claim expired, so a rejected token is only ever refreshed once across replicas.
+    async fn refresh_or_wait(&self, connection: &Connection) -> Result<Connection, PicaError> {
+        let id = connection.id.to_string();
+        let deadline = Utc::now().timestamp() + MAX_REFRESH_WAIT_SECS;
+
+        loop {
+            let now = Utc::now().timestamp();
+
+            // Matching on the rejected secret leaves connections that were already refreshed
+            // unclaimed
+            let claimed = self
+                .connections_store
+                .collection
+                .find_one_and_update(
+                    doc! {
+                        "_id": &id,
+                        "secretsServiceId": &connection.secrets_service_id,
+                        "oauthRefresh.claimedUntil": { "$not": { "$gt": now } },
+                    },
+                    doc! {
+                        "$set": { "oauthRefresh.claimedUntil": now + REFRESH_CLAIM_SECS }
+                    },
+                )
+                .return_document(ReturnDocument::After)
+                .await?;
+
+            if let Some(claimed) = claimed {
+                let refreshed = self.refresh_oauth_connection(&claimed).await;
+                if refreshed.is_err() {
+                    self.release_refresh_claim(&claimed).await;
+                }
+
+                return refreshed;
+            }
+
+            let current = self
+                .connections_store
+                .get_one_by_id(&id)
+                .await?
+                .ok_or_else(|| InternalError::key_not_found("Connection", None))?;
+
+            if current.secrets_service_id != connection.secrets_service_id {
+                info!(
+                    "Oauth connection {} was refreshed by another replica",
+                    connection.key
+                );
+
+                self.secrets_cache.remove(connection).await?;
+                self.connections_cache.remove(&connection.key).await?;
+
+                return Ok(current);
+            }
+
+            if now >= deadline {
+                return Err(InternalError::timeout(
+                    "Timed out waiting for the oauth refresh of another replica",
+                    Some(connection.key.as_ref()),
+                ));
+            }
+
+            tokio::time::sleep(StdDuration::from_millis(REFRESH_WAIT_POLL_MILLIS)).await;
+        }
+    }
+
+    /// Releases the refresh claim of a connection so that the next rejected request can retry
+    /// right away. Errors are only logged, the claim expires on its own anyway.
+    async fn release_refresh_claim(&self, connection: &Connection) {
+        if let Err(e) = self
+            .connections_store
+            .update_one(
+                &connection.id.to_string(),
+                doc! { "$unset": { "oauthRefresh.claimedUntil": "" } },
+            )
+            .await
+        {
+            error!(
+                "Failed to release refresh claim of oauth connection {}: {e}",
+                connection.key
+            );
+        }
+    }
+
+    /// Exchanges the refresh token of the connection for a new access token, stores the new
+    /// secret and returns the connection pointing to it
+    pub async fn refresh_oauth_connection(
+        &self,
+        connection: &Connection,
+    ) -> Result<Connection, PicaError> {
+        let Some(OAuth::Enabled {
+            connection_oauth_definition_id,
+            ..
+        }) = &connection.oauth
+        else {
+            return Err(InternalError::invalid_argument(
+                "Connection does not use OAuth",
+                Some(connection.key.as_ref()),
+            ));
+        };
+
+        let definition = self
+            .connection_oauth_definitions_store
+            .get_one_by_id(&connection_oauth_definition_id.to_string())
+            .await?
+            .ok_or_else(|| InternalError::key_not_found("Connection OAuth definition", None))?;
+
+        // The cached secret may already have been rotated by another refresh, so it is always
+        // read from the secrets service
+        let secret: OAuthSecret = self
+            .secrets_client
+            .get(&connection.secrets_service_id, &connection.ownership.id)
+            .await?
+            .decode()?;
+
+        let request = build_refresh_request(&definition, &secret).await?;
+
+        let response = CallerClient::new(&request.config, http::Method::POST, &self.http_client)
+            .make_request(
+                request.body,
+                Some(&secret.as_json()),
+                Some(request.headers),
+                None,
+            )
+            .await?;
+
+        let status = response.status();
+        let response = response.json::<Value>().await.map_err(|e| {
+            error!("Failed to decode third party oauth refresh response: {e}");
+            InternalError::deserialize_error(&e.to_string(), None)
+        })?;
+
+        if !status.is_success() {
+            return Err(InternalError::connection_error(
+                &format!("OAuth refresh request failed with status {status}: {response}"),
+                Some(connection.key.as_ref()),
+            ));
+        }
+
+        let decoded: OAuthResponse = definition
+            .compute
+            .refresh
+            .response
+            .compute(&response)
+            .await?;
+        let oauth_secret = secret.from_refresh(decoded, None, None, response);
+
+        let new_secret = self
+            .secrets_client
+            .create(&oauth_secret.as_json(), &connection.ownership.id)
+            .await?;
+
+        let oauth = OAuth::Enabled {
+            connection_oauth_definition_id: connection_oauth_definition_id.clone(),
+            expires_in: Some(oauth_secret.expires_in),
+            expires_at: Some(expires_at(oauth_secret.expires_in)),
+        };
+
+        let oauth_bson = bson::to_bson(&oauth)
+            .map_err(|e| InternalError::serialize_error(&e.to_string(), None))?;
+
+        self.connections_store
+            .update_one(
+                &connection.id.to_string(),
+                doc! {
+                    "$set": {
+                        "secretsServiceId": new_secret.id(),
+                        "oauth": oauth_bson,
+                        "updatedAt": Utc::now().timestamp_millis(),
+                    },
+                    "$unset": { "oauthRefresh": "" },
+                },
+            )
+            .await?;
+
+        self.secrets_cache.remove(connection).await?;
+        self.connections_cache.remove(&connection.key).await?;
+
+        info!("Refreshed oauth connection {}", connection.key);
+
+        Ok(Connection {
+            secrets_service_id: new_secret.id(),
+            oauth: Some(oauth),
+            ..connection.clone()
+        })
+    }
+}
+
+/// Runs the refresh computation of the definition on the current secret and renders the refresh
+/// api config with its output, the same way the init flow does
+async fn build_refresh_request(
+    definition: &ConnectionOAuthDefinition,
+    secret: &OAuthSecret,
+) -> Result<RefreshRequest, PicaError> {
+    let payload = secret.as_json();
+
+    let computation = match &definition.compute.refresh.computation {
+        Some(function) => Some(function.compute::<Computation>(&payload).await?),
+        None => None,
+    };
+
+    let mut context = payload.clone();
+    if let (Some(map), Some(computation)) = (context.as_object_mut(), &computation) {
+        [&computation.headers, &computation.query_params]
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_object)
+            .for_each(|values| map.extend(values.clone()));
+    }
+
+    let renderer = Handlebars::new();
+
+    let config = serde_json::to_string(&definition.configuration.refresh)
+        .map_err(|e| InternalError::serialize_error(&e.to_string(), None))?;
+    let config = renderer
+        .render_template(&config, &context)
+        .map_err(|e| InternalError::invalid_argument(&e.to_string(), None))?;
+    let config: ApiModelConfig = serde_json::from_str(&config)
+        .map_err(|e| InternalError::invalid_argument(&e.to_string(), None))?;
+
+    let body = computation
+        .and_then(|computation| computation.body)
+        .map(|body| -> Result<Value, PicaError> {
+            let body = serde_json::to_string(&body)
+                .map_err(|e| InternalError::serialize_error(&e.to_string(), None))?;
+            let body = renderer
+                .render_template(&body, &payload)
+                .map_err(|e| InternalError::invalid_argument(&e.to_string(), None))?;
+
+            serde_json::from_str(&body)
+                .map_err(|e| InternalError::invalid_argument(&e.to_string(), None))
+        })
+        .transpose()?;
+
+    let mut headers = HeaderMap::new();
+    let body = match (body, &config.content) {
+        (None, _) => None,
+        (Some(body), Some(ContentType::Form)) => {
+            headers.insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("application/x-www-form-urlencoded"),
+            );
+
+            Some(
+                serde_urlencoded::to_string(&body)
+                    .map_err(|e| InternalError::serialize_error(&e.to_string(), None))?
+                    .into_bytes(),
+            )
+        }
+        (Some(body), _) => {
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+            Some(
+                serde_json::to_vec(&body)
+                    .map_err(|e| InternalError::serialize_error(&e.to_string(), None))?,
+            )
+        }
+    };
+
+    Ok(RefreshRequest {
+        config,
+        headers,
+        body,
+    })
+}
+
+/// Timestamp in seconds at which a token living for `expires_in` seconds should be refreshed
+pub fn expires_at(expires_in: i32) -> i64 {
+    (Utc::now() + Duration::seconds(expires_in as i64 - EXPIRY_MARGIN_SECS)).timestamp()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use osentities::{
+        api_model_config::{AuthMethod, Compute, Function, Lang, SamplesInput, SchemasInput},
+        connection_oauth_definition::{ComputeRequest, Frontend, OAuthApiConfig, OAuthCompute},
+        id::{prefix::IdPrefix, Id},
+    };
+    use serde_json::json;
+
+    fn api_config(path: &str, content: Option<ContentType>) -> ApiModelConfig {
+        ApiModelConfig {
+            base_url: "https://api.example.com".to_string(),
+            path: path.to_string(),
+            auth_method: AuthMethod::None,
+            headers: None,
+            query_params: None,
+            content,
+            schemas: SchemasInput {
+                headers: None,
+                query_params: None,
+                path_params: None,
+                body: None,
+            },
+            samples: SamplesInput {
+                headers: None,
+                query_params: None,
+                path_params: None,
+                body: None,
+            },
+            responses: vec![],
+            paths: None,
+            retry_policy: None,
+            rate_limit: None,
+            response_cache: None,
+            validation: None,
+        }
+    }
+
+    fn function(function: &str) -> Function {
+        Function(Compute {
+            entry: "compute".to_string(),
+            function: function.to_string(),
+            language: Lang::JavaScript,
+            compiled: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_build_refresh_request() {
+        let response = function("function compute(payload) { return payload; }");
+        let definition = ConnectionOAuthDefinition {
+            id: Id::now(IdPrefix::ConnectionOAuthDefinition),
+            configuration: OAuthApiConfig {
+                init: api_config("/oauth/token", Some(ContentType::Form)),
+                refresh: api_config("/oauth/{{tenant}}/token", Some(ContentType::Form)),
+            },
+            connection_platform: "example".to_string(),
+            compute: OAuthCompute {
+                init: ComputeRequest {
+                    computation: None,
+                    response: response.clone(),
+                },
+                refresh: ComputeRequest {
+                    computation: Some(function(
+                        r#"function compute(payload) {
+                            return {
+                                queryParams: { tenant: payload.OAUTH_METADATA.tenant },
+                                body: {
+                                    grant_type: "refresh_token",
+                                    refresh_token: "{{OAUTH_REFRESH_TOKEN}}"
+                                }
+                            };
+                        }"#,
+                    )),
+                    response,
+                },
+            },
+            frontend: Frontend {
+                platform_redirect_uri: "https://example.com".to_string(),
+                sandbox_platform_redirect_uri: None,
+                scopes: String::new(),
+                ios_redirect_uri: String::new(),
+                separator: None,
+            },
+            is_full_template_enabled: false,
+            record_metadata: Default::default(),
+        };
+
+        let secret = OAuthSecret {
+            client_id: "client".to_string(),
+            client_secret: "secret".to_string(),
+            access_token: "access".to_string(),
+            token_type: None,
+            refresh_token: Some("refresh".to_string()),
+            expires_in: 3600,
+            metadata: json!({ "tenant": "acme" }),
+            request_payload: None,
+        };
+
+        let request = build_refresh_request(&definition, &secret).await.unwrap();
+
+        assert_eq!(request.config.path, "/oauth/acme/token");
+        assert_eq!(
+            request.headers.get(CONTENT_TYPE).unwrap(),
+            "application/x-www-form-urlencoded"
+        );
+        assert_eq!(
+            String::from_utf8(request.body.unwrap()).unwrap(),
+            "grant_type=refresh_token&refresh_token=refresh"
+        );
+    }
+}
@@ -1,23 +1,32 @@
 use crate::domain::{ResponseCrudToMapBuilder, ResponseCrudToMapRequest};
 use crate::{
-    algebra::jsruntime::JSRuntimeImpl,
-    client::CallerClient,
-    domain::{RequestCrud, ResponseCrud, UnifiedMetadata, UnifiedMetadataBuilder},
+    algebra::{jsruntime::JSRuntimeImpl, mapper::map_by_schema},
+    client::{CallerClient, RequestAttempts},
+    domain::{
+        PaginationLimits, RequestCrud, ResponseCrud, UnifiedCache, UnifiedMetadata,
+        UnifiedMetadataBuilder,
+    },
     helper::{match_route, template_route},
+    oauth::OAuthRefreshes,
 };
 use bson::doc;
-use cache::local::{
-    ConnectionCache, ConnectionModelDefinitionCacheIdKey, ConnectionModelDefinitionCacheIdKeyInner,
-    ConnectionModelDefinitionDestinationCache, ConnectionModelSchemaCache, LocalCacheExt,
-    SecretCache,
+use cache::{
+    local::{
+        CommonModelCache, ConnectionCache, ConnectionModelDefinitionCacheIdKey,
+        ConnectionModelDefinitionCacheIdKeyInner, ConnectionModelDefinitionDestinationCache,
+        ConnectionModelSchemaCache, LocalCacheExt, SchemaDriftSampleCache, SecretCache,
+    },
+    rate_limit::OutboundRateLimiter,
+    response::ResponseCache,
 };
 use chrono::Utc;
 use futures::{
-    future::{join_all, OptionFuture},
+    future::join_all,
+    stream::{self, Stream, TryStreamExt},
     FutureExt,
 };
 use handlebars::Handlebars;
-use http::{HeaderMap, HeaderName, HeaderValue, Response, StatusCode};
+use http::{header::CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue, Response, StatusCode};
 use mongodb::{
     options::{Collation, CollationStrength, FindOneOptions},
     Client,
@@ -25,8 +34,10 @@ use mongodb::{
 use osentities::{
     algebra::JsonExt,
     api_model_config::{ModelPaths, RequestModelPaths},
+    common_model::{CommonEnum, CommonModel, SchemaType},
     connection_model_definition::{ConnectionModelDefinition, CrudAction, PlatformInfo},
-    connection_model_schema::ConnectionModelSchema,
+    connection_model_schema::{ConnectionModelSchema, Mappings},
+    connection_oauth_definition::ConnectionOAuthDefinition,
     constant::*,
     database::DatabaseConfig,
     destination::{Action, Destination},
@@ -34,16 +45,43 @@ use osentities::{
     error::InternalError,
     hashed_secret::HashedSecret,
     id::{prefix::IdPrefix, Id},
+    json_schema::{generate_schema, JsonSchema},
     prelude::{MongoStore, TimedExt},
+    schema_drift::{SchemaChange, SchemaDrift},
+    validation::FieldError,
     ApplicationError, Connection, ErrorMeta, PicaError, Secret, SecretExt, Store,
 };
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Number, Value};
-use std::{collections::HashMap, str::FromStr, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    sync::Arc,
+};
 use tracing::error;
 
 pub struct UnifiedResponse {
     pub response: Response<Value>,
     pub metadata: UnifiedMetadata,
+    /// Platform response with non JSON content, such as a file, to be streamed back untouched.
+    /// The JSON response then only carries the metadata.
+    pub binary: Option<reqwest::Response>,
+}
+
+struct PaginationState {
+    params: Option<RequestCrud>,
+    pages: u32,
+    records: usize,
+}
+
+/// Successful unified read stored in the response cache
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CachedUnifiedResponse {
+    #[serde(with = "http_serde_ext_ios::header_map")]
+    headers: HeaderMap,
+    body: Value,
+    hash: Option<String>,
 }
 
 pub struct SendToDestinationUnified {
@@ -62,9 +100,18 @@ pub struct UnifiedDestination {
     pub connection_model_definitions_store: MongoStore<ConnectionModelDefinition>,
     pub connection_model_schemas_cache: ConnectionModelSchemaCache,
     pub connection_model_schemas_store: MongoStore<ConnectionModelSchema>,
+    pub connection_oauth_definitions_store: MongoStore<ConnectionOAuthDefinition>,
+    pub common_models_cache: CommonModelCache,
+    pub common_models_store: MongoStore<CommonModel>,
+    pub common_enums_store: MongoStore<CommonEnum>,
+    pub schema_drift_samples: SchemaDriftSampleCache,
+    pub schema_drifts_store: MongoStore<SchemaDrift>,
     pub secrets_client: Arc<dyn SecretExt + Sync + Send>,
     pub secrets_cache: SecretCache,
     pub http_client: reqwest::Client,
+    pub rate_limiter: OutboundRateLimiter,
+    pub oauth_refreshes: OAuthRefreshes,
+    pub response_cache: ResponseCache,
 }
 
 pub struct UnifiedCacheTTLs {
@@ -93,6 +140,15 @@ impl UnifiedDestination {
             cache_ttls.connection_model_schema_cache_ttl_secs,
         );
         let secrets_cache = SecretCache::new(cache_size, cache_ttls.secret_cache_ttl_secs);
+        let common_models_cache = CommonModelCache::new(
+            cache_size,
+            cache_ttls.connection_model_schema_cache_ttl_secs,
+        );
+        // Responses are compared to their schema at most once while the schema is cached
+        let schema_drift_samples = SchemaDriftSampleCache::new(
+            cache_size,
+            cache_ttls.connection_model_schema_cache_ttl_secs,
+        );
 
         let client = Client::with_uri_str(&db_config.control_db_url)
             .await
@@ -110,6 +166,11 @@ impl UnifiedDestination {
             MongoStore::new(&db, &Store::ConnectionModelDefinitions).await?;
         let connection_model_schemas_store =
             MongoStore::new(&db, &Store::ConnectionModelSchemas).await?;
+        let connection_oauth_definitions_store =
+            MongoStore::new(&db, &Store::ConnectionOAuthDefinitions).await?;
+        let common_models_store = MongoStore::new(&db, &Store::CommonModels).await?;
+        let common_enums_store = MongoStore::new(&db, &Store::CommonEnums).await?;
+        let schema_drifts_store = MongoStore::new(&db, &Store::SchemaDrifts).await?;
 
         Ok(Self {
             connections_cache,
@@ -118,12 +179,68 @@ impl UnifiedDestination {
             connection_model_definitions_store,
             connection_model_schemas_cache,
             connection_model_schemas_store,
+            connection_oauth_definitions_store,
+            common_models_cache,
+            common_models_store,
+            common_enums_store,
+            schema_drift_samples,
+            schema_drifts_store,
             secrets_client,
             secrets_cache,
             http_client,
+            rate_limiter: OutboundRateLimiter::local(),
+            oauth_refreshes: OAuthRefreshes::default(),
+            response_cache: ResponseCache::local(cache_size),
         })
     }
 
+    /// Replaces the in-process outbound rate limiter, e.g. with one backed by Redis so that
+    /// several replicas share the same budget per platform
+    pub fn with_rate_limiter(mut self, rate_limiter: OutboundRateLimiter) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// Replaces the in-process unified response cache, e.g. with one backed by Redis so that
+    /// several replicas serve and invalidate the same entries
+    pub fn with_response_cache(mut self, response_cache: ResponseCache) -> Self {
+        self.response_cache = response_cache;
+        self
+    }
+
+    /// Key of a cached unified read. It includes the current generations of the connection and of
+    /// the model scope, so writes on the same model and connection, and passthrough writes on the
+    /// connection, make previous entries unreachable.
+    async fn response_cache_key(
+        &self,
+        connection: &Connection,
+        name: &str,
+        action: &CrudAction,
+        id: Option<&Arc<str>>,
+        is_passthrough: bool,
+        params: &RequestCrud,
+    ) -> Result<String, PicaError> {
+        let scope = response_cache_scope(connection, name);
+        let connection_generation = self.response_cache.generation(&connection.key).await?;
+        let generation = self.response_cache.generation(&scope).await?;
+        let query_params = params.get_query_params().iter().collect::<BTreeMap<_, _>>();
+        let query_params = serde_urlencoded::to_string(query_params)
+            .map_err(|e| InternalError::serialize_error(&e.to_string(), None))?;
+
+        Ok(format!(
+            "{scope}::g{connection_generation}.{generation}::{action}::{}::{is_passthrough}::{query_params}",
+            id.map(|id| id.as_ref()).unwrap_or_default()
+        ))
+    }
+
+    /// Drops the cached reads of a scope after a write, either of a model on a connection or of
+    /// the whole connection. Failures are only logged, entries expire after their TTL anyway.
+    async fn invalidate_response_cache(&self, scope: &str) {
+        if let Err(e) = self.response_cache.invalidate(&scope).await {
+            error!("Failed to invalidate unified response cache. Scope: {scope}, Error: {e}");
+        }
+    }
+
     pub async fn get_connection_model_definition(
         &self,
         destination: &Destination,
@@ -217,8 +334,29 @@ impl UnifiedDestination {
         }
     }
 
+    pub async fn get_secret(&self, connection: &Connection) -> Result<Secret, PicaError> {
+        self.secrets_cache
+            .get_or_insert_with_fn(connection, || async {
+                match self
+                    .secrets_client
+                    .get(&connection.secrets_service_id, &connection.ownership.id)
+                    .map(|v| Some(v).transpose())
+                    .await
+                {
+                    Ok(Some(c)) => Ok(c),
+                    Ok(None) => Err(InternalError::key_not_found("secret", None)),
+                    Err(e) => Err(InternalError::connection_error(
+                        format!("Failed to get secret: {}", e.message().as_ref()).as_str(),
+                        None,
+                    )),
+                }
+            })
+            .await
+    }
+
     pub async fn execute_model_definition_from_request(
         &self,
+        connection: &Connection,
         config: &ConnectionModelDefinition,
         params: &RequestCrud,
         secret: &Value,
@@ -235,7 +373,8 @@ impl UnifiedDestination {
             })?),
         };
 
-        self.execute_model_definition(
+        self.execute_connection_model_definition(
+            connection,
             config,
             params.get_headers().to_owned(),
             params.get_query_params(),
@@ -252,6 +391,41 @@ impl UnifiedDestination {
         query_params: &HashMap<String, String>,
         secret: &Value,
         context: Option<Vec<u8>>,
+    ) -> Result<reqwest::Response, PicaError> {
+        self.send_model_definition(None, config, headers, query_params, secret, context)
+            .await
+    }
+
+    /// Executes the model definition on behalf of a connection, taking a token from the outbound
+    /// rate limit of the definition, if it has one, before every attempt
+    pub async fn execute_connection_model_definition(
+        &self,
+        connection: &Connection,
+        config: &ConnectionModelDefinition,
+        headers: HeaderMap,
+        query_params: &HashMap<String, String>,
+        secret: &Value,
+        context: Option<Vec<u8>>,
+    ) -> Result<reqwest::Response, PicaError> {
+        self.send_model_definition(
+            Some(connection),
+            config,
+            headers,
+            query_params,
+            secret,
+            context,
+        )
+        .await
+    }
+
+    async fn send_model_definition(
+        &self,
+        connection: Option<&Connection>,
+        config: &ConnectionModelDefinition,
+        headers: HeaderMap,
+        query_params: &HashMap<String, String>,
+        secret: &Value,
+        context: Option<Vec<u8>>,
     ) -> Result<reqwest::Response, PicaError> {
         let renderer = Handlebars::new();
 
@@ -268,6 +442,13 @@ impl UnifiedDestination {
         match config.platform_info {
             PlatformInfo::Api(ref c) => {
                 let api_caller = CallerClient::new(c, config.action, &self.http_client);
+                let api_caller = match (connection, c.rate_limit.as_ref()) {
+                    (Some(connection), Some(policy)) => api_caller.with_rate_limiter(
+                        &self.rate_limiter,
+                        policy.bucket_key(&connection.platform, &connection.key),
+                    ),
+                    _ => api_caller,
+                };
 
                 let response = api_caller
                     .make_request(context, Some(secret), Some(headers), Some(query_params))
@@ -306,6 +487,117 @@ impl UnifiedDestination {
             })
     }
 
+    /// Follows the unified pagination cursor of a `GetMany` action and yields the records of every
+    /// page, until the platform stops returning a `nextCursor` or one of the `limits` is reached.
+    pub fn stream_unified_request(
+        &self,
+        connection: Arc<Connection>,
+        action: Action,
+        environment: Environment,
+        params: RequestCrud,
+        cache: ConnectionModelDefinitionCacheIdKey,
+        limits: PaginationLimits,
+    ) -> impl Stream<Item = Result<Value, PicaError>> + Send + 'static {
+        let destination = self.clone();
+        let max_pages = limits
+            .max_pages
+            .unwrap_or(AUTO_PAGINATE_MAX_PAGES)
+            .min(AUTO_PAGINATE_MAX_PAGES);
+        let state = PaginationState {
+            params: Some(params),
+            pages: 0,
+            records: 0,
+        };
+
+        stream::try_unfold(state, move |mut state| {
+            let destination = destination.clone();
+            let connection = connection.clone();
+            let action = action.clone();
+            let cache = cache.clone();
+
+            async move {
+                if !matches!(
+                    action,
+                    Action::Unified {
+                        action: CrudAction::GetMany,
+                        ..
+                    }
+                ) {
+                    return Err(ApplicationError::bad_request(
+                        "Automatic pagination is only supported for list requests",
+                        None,
+                    ));
+                }
+
+                let Some(params) = state.params.take() else {
+                    return Ok(None);
+                };
+
+                if state.pages >= max_pages
+                    || limits.max_records.is_some_and(|max| state.records >= max)
+                {
+                    return Ok(None);
+                }
+
+                let response = destination
+                    .dispatch_unified_request(
+                        connection,
+                        action,
+                        environment,
+                        params.clone(),
+                        cache,
+                    )
+                    .await?;
+                let (parts, body) = response.response.into_parts();
+
+                if !parts.status.is_success() {
+                    return Err(
+                        PicaError::from_err_code(parts.status, &body.to_string(), None)
+                            .set_meta(&response.metadata.as_value()),
+                    );
+                }
+
+                let mut records = match body.get(UNIFIED_KEY) {
+                    Some(Value::Array(records)) => records.clone(),
+                    Some(Value::Null) | None => vec![],
+                    Some(record) => vec![record.clone()],
+                };
+
+                if let Some(max) = limits.max_records {
+                    records.truncate(max.saturating_sub(state.records));
+                }
+
+                let next_cursor = body
+                    .get(PAGINATION_KEY)
+                    .and_then(|pagination| pagination.get(NEXT_CURSOR))
+                    .and_then(|cursor| match cursor {
+                        Value::String(cursor) if !cursor.is_empty() => Some(cursor.to_owned()),
+                        Value::Number(cursor) => Some(cursor.to_string()),
+                        _ => None,
+                    });
+                let current_cursor = params.get_query_params().get(CURSOR).cloned();
+
+                state.pages += 1;
+                state.records += records.len();
+                state.params = match next_cursor {
+                    Some(cursor)
+                        if !records.is_empty() && Some(&cursor) != current_cursor.as_ref() =>
+                    {
+                        Some(
+                            params
+                                .extend_query_params(HashMap::from([(CURSOR.to_string(), cursor)])),
+                        )
+                    }
+                    _ => None,
+                };
+
+                Ok(Some((records, state)))
+            }
+        })
+        .map_ok(|records| stream::iter(records.into_iter().map(Ok)))
+        .try_flatten()
+    }
+
     async fn perform_unified_request(
         &self,
         connection: Arc<Connection>,
@@ -338,6 +630,84 @@ impl UnifiedDestination {
                     .action(action.to_string())
                     .common_model(config.mapping.as_ref().map(|m| m.common_model_name.clone()).unwrap_or_default());
 
+                let cache_policy = config
+                    .platform_info
+                    .config()
+                    .response_cache
+                    .filter(|_| matches!(action, CrudAction::GetOne | CrudAction::GetMany));
+
+                let cache_key = match cache_policy {
+                    Some(_) => self
+                        .response_cache_key(&connection, &name, &action, id.as_ref(), is_passthrough, &params)
+                        .await
+                        .inspect_err(|e| error!("Failed to build unified response cache key. ID: {}, Error: {e}", config.id))
+                        .ok(),
+                    None => None,
+                };
+
+                if let (Some(policy), Some(cache_key)) = (cache_policy, &cache_key) {
+                    let cached = self.response_cache.get::<CachedUnifiedResponse>(cache_key).await.unwrap_or_else(|e| {
+                        error!("Failed to read unified response cache. Key: {cache_key}, Error: {e}");
+                        None
+                    });
+
+                    if let Some(cached) = cached {
+                        tracing::debug!("Serving unified response from cache. Key: {cache_key}");
+
+                        metadata.cache(UnifiedCache::new(true, policy.ttl_secs, cache_key.as_str()));
+                        if let Some(hash) = cached.hash {
+                            metadata.hash(hash);
+                        }
+
+                        return build_cached_response(cached.body, cached.headers, metadata);
+                    }
+
+                    metadata.cache(UnifiedCache::new(false, policy.ttl_secs, cache_key.as_str()));
+                }
+
+                let validation = config.platform_info.config().validation;
+                // Lax validation is best effort, strict validation doesn't let unchecked bodies through
+                let common_model = match validation {
+                    Some(schema_type) => match self.get_common_model(&cms).await {
+                        Ok(None) if schema_type == SchemaType::Strict => {
+                            error!("Schema has no common model for strict validation. ID: {}", cms.id);
+                            return Err(InternalError::configuration_error(
+                                &format!("Strict validation requires schema {} to map a common model", cms.id),
+                                None,
+                            ));
+                        }
+                        Ok(common_model) => common_model,
+                        Err(e) if schema_type == SchemaType::Strict => {
+                            error!("Failed to get common model for strict validation. ID: {}, Error: {e}", cms.id);
+                            return Err(e);
+                        }
+                        Err(e) => {
+                            error!("Failed to get common model for validation. ID: {}, Error: {e}", cms.id);
+                            None
+                        }
+                    },
+                    None => None,
+                };
+
+                if let (Some(schema_type), Some(common_model), Some(body)) = (validation, &common_model, params.get_body()) {
+                    // Updates are partial, only the fields that are set are checked
+                    let schema_type = match action {
+                        CrudAction::Update => Some(SchemaType::Lax),
+                        CrudAction::Create | CrudAction::Upsert => Some(schema_type),
+                        _ => None,
+                    };
+
+                    let errors = schema_type.map(|schema_type| common_model.validate(body, schema_type)).unwrap_or_default();
+
+                    if !errors.is_empty() {
+                        return Err(ApplicationError::unprocessable_entity(
+                            &format!("Request body doesn't match the common model {}", common_model.name),
+                            Some("schema_validation"),
+                        )
+                        .set_meta(&json!({ "errors": errors })));
+                    }
+                }
+
                 let secret = insert_action_id(secret.as_value()?, id.as_ref());
 
                 // Namespace for js scripts
@@ -346,41 +716,19 @@ impl UnifiedDestination {
                 let schema_namespace = generate_script_namespace(self.secrets_cache.max_capacity(), &cms.id.to_string());
 
                 let body = params.get_body();
-                let body = match cms.mapping.as_ref().map(|m| m.from_common_model.as_str()) {
-                    Some(code) if body.is_some() => {
+                let body = match cms.mapping.as_ref() {
+                    Some(Mappings { from_common_model_schema: Some(definition), .. }) if body.is_some() => {
+                        map_by_schema::<Option<&Value>, Option<Value>>(&body, definition)?
+                    }
+                    Some(mapping) if body.is_some() => {
                         let namespace = schema_namespace.clone() + "_mapFromCommonModel";
 
-                        jsruntime.create("mapFromCommonModel", &namespace, code)?.run::<Option<&Value>, Option<Value>>(&body, &namespace).await?.map(|v| v.drop_nulls())
+                        jsruntime.create("mapFromCommonModel", &namespace, &mapping.from_common_model)?.run::<Option<&Value>, Option<Value>>(&body, &namespace).await?.map(|v| v.drop_nulls())
                     }
                     _ => body.cloned()
                 };
 
-                let default_params = params.clone();
-                let request_crud: Option<Result<RequestCrud, PicaError>> = OptionFuture::from(config.mapping.as_ref().map(|m| m.from_common_model.to_owned())
-                    .map(|code| async {
-                        match code {
-                            None => Ok(params),
-                            Some(code) => {
-                                let namespace = crud_namespace.clone() + "_mapFromCrudRequest";
-                                let jsruntime = jsruntime.create("mapCrudRequest", &namespace, &code)?;
-
-                                tracing::debug!("Code for mapping crud request ready for unified destination. Code: {code}, Namespace: {namespace}");
-
-                                let payload = prepare_crud_mapping(params, &config, id.as_ref())?;
-
-                                tracing::debug!("Request crud prepared for unified destination. RequestCrud: {:?}", payload);
-
-                                let params: RequestCrud = jsruntime.run(&payload, &namespace).await?;
-                                let params: RequestCrud = params.extend_body(body);
-
-                                Ok(params)
-                            }
-                        }
-                    })).await;
-
-                tracing::debug!("Request crud prepared for unified destination. RequestCrud: {:?}", request_crud);
-
-                let params: RequestCrud = request_crud.unwrap_or(Ok(default_params))?;
+                let params: RequestCrud = map_crud_request(&config, params, id.as_ref(), body, &crud_namespace).await?;
                 let secret: Value = extend_secret(secret, params.get_path_params());
 
                 let body: Option<Value> = insert_body_into_path_object(&config, params.get_body());
@@ -388,13 +736,29 @@ impl UnifiedDestination {
 
                 tracing::debug!("Request crud prepared for unified destination. RequestCrud: {:?}", params);
 
-                let response: reqwest::Response = self.execute_model_definition_from_request(&config, &params, &secret).timed(|_, duration| {
+                let response: reqwest::Response = self.execute_model_definition_from_request(&connection, &config, &params, &secret).timed(|_, duration| {
                     metadata.latency(duration.as_millis() as i32);
                 }).await?;
 
+                // Replay the request once with a fresh token if the platform rejected the current one
+                let response: reqwest::Response = match self.refresh_after_unauthorized(&connection, &config, response.status()).await {
+                    Some((connection, secret)) => {
+                        let secret = extend_secret(insert_action_id(secret.as_value()?, id.as_ref()), params.get_path_params());
+
+                        self.execute_model_definition_from_request(&connection, &config, &params, &secret).timed(|_, duration| {
+                            metadata.latency(duration.as_millis() as i32);
+                        }).await?
+                    }
+                    None => response,
+                };
+
                 let status: StatusCode = response.status();
                 let headers: HeaderMap = response.headers().clone();
 
+                if let Some(RequestAttempts(attempts)) = response.extensions().get::<RequestAttempts>() {
+                    metadata.attempts(*attempts);
+                }
+
                 tracing::info!("Received response for unified destination. Status: {:?}", response.status());
 
                 let error_for_status = if response.status().is_client_error() || response.status().is_server_error() {
@@ -403,6 +767,12 @@ impl UnifiedDestination {
                     Ok(())
                 };
 
+                if error_for_status.is_ok() && is_binary_response(&headers) {
+                    tracing::debug!("Streaming binary response for unified destination. ID: {}", config.id);
+
+                    return build_binary_response(response, metadata);
+                }
+
                 let body: Result<Value, PicaError> = response.json().await.map_err(|e| {
                     error!("Failed to get json body from successful response. ID: {}, Error: {}", config.id, e);
 
@@ -422,7 +792,7 @@ impl UnifiedDestination {
                                 PicaError::from_err_code(status, &e.to_string(), None)
                             })?;
                         *response.headers_mut() = headers;
-                        return Ok(UnifiedResponse { response, metadata: metadata.build()? });
+                        return Ok(UnifiedResponse { response, metadata: metadata.build()?, binary: None });
                     }
                     Ok(_) => body.ok(),
                 };
@@ -432,52 +802,53 @@ impl UnifiedDestination {
                 let passthrough: Option<Value> = if is_passthrough { body.clone() } else { None };
                 let pagination: Option<Value> = match &config.action_name {
                     CrudAction::GetMany => {
-                        match config.mapping.as_ref().and_then(|m| m.to_common_model.as_ref()) {
-                            Some(code) => {
-                                tracing::debug!("Code for mapping crud request ready for unified destination. Code: {code}, Namespace: {crud_namespace}");
-
-                                let namespace = crud_namespace.clone() + "_mapToCrudRequest";
-                                let jsruntime: JSRuntimeImpl = jsruntime.create("mapCrudRequest", &namespace, code).inspect_err(|e| {
-                                    error!("Failed to create request crud mapping script for connection model. ID: {}, Error: {}", config.id, e);
-                                })?;
-
-                                let pagination = extract_pagination(&config, &body)?;
-                                let res_to_map = ResponseCrudToMapBuilder::default()
-                                    .headers(&headers)
-                                    .pagination(pagination)
-                                    .request(ResponseCrudToMapRequest::new(params.get_query_params()))
-                                    .build()?;
-
-                                let response: ResponseCrud = jsruntime.run(&res_to_map, &namespace).await?;
+                        let pagination = extract_pagination(&config, &body)?;
 
-                                response.get_pagination().cloned()
-                            }
-                            _ => None
-                        }
+                        map_crud_response(&config, &headers, pagination, &params, &crud_namespace)
+                            .await?
+                            .and_then(|response| response.get_pagination().cloned())
                     }
                     _ => None
                 };
 
                 let body = transform_response_with_path(&config, body, &environment);
+
+                let is_common_model = matches!(config.action_name, CrudAction::GetMany | CrudAction::GetOne | CrudAction::Create | CrudAction::Upsert);
+                if let (Ok(Some(body)), true) = (&body, is_common_model) {
+                    self.sample_schema_drift(&config, &cms, body).await;
+                }
+
                 let body = match config.action_name {
                     CrudAction::GetMany | CrudAction::GetOne | CrudAction::Create | CrudAction::Upsert => {
-                        match cms.mapping.as_ref().map(|m| m.to_common_model.as_str()) {
-                            Some(code) => {
+                        match cms.mapping.as_ref() {
+                            Some(mapping) => {
                                 let namespace = schema_namespace.clone() + "_mapToCommonModel";
 
-                                let jsruntime = jsruntime.create("mapToCommonModel", &namespace, code).inspect_err(|e| {
-                                    error!("Failed to create request schema mapping script for connection model schema. ID: {}, Error: {}", config.id, e);
-                                })?;
+                                if mapping.to_common_model_schema.is_none() {
+                                    jsruntime.create("mapToCommonModel", &namespace, &mapping.to_common_model).inspect_err(|e| {
+                                        error!("Failed to create request schema mapping script for connection model schema. ID: {}, Error: {}", config.id, e);
+                                    })?;
+                                }
+
+                                let map_to_common_model = |body: Value| {
+                                    let namespace = &namespace;
+                                    let config = &config;
+                                    async move {
+                                        match &mapping.to_common_model_schema {
+                                            Some(definition) => map_by_schema::<Value, Value>(&body, definition),
+                                            None => jsruntime.run::<Value, Value>(&body, namespace).await.map(|v| v.drop_nulls()),
+                                        }.inspect_err(|e| {
+                                            error!("Failed to run request schema mapping for connection model schema. ID: {}, Error: {}", config.id, e);
+                                        })
+                                    }
+                                };
 
                                 let mapped_body = match body {
                                     Ok(Some(Value::Array(arr))) => {
                                         let futures = arr.into_iter().map(|body| {
-                                            let jsruntime = &jsruntime;
-                                            let namespace = &namespace;
+                                            let map_to_common_model = &map_to_common_model;
                                             async move {
-                                                let mut response = jsruntime.run::<Value, Value>(&body, namespace).await.inspect_err(|e| {
-                                                    error!("Failed to run request schema mapping script for connection model schema. ID: {}, Error: {}", config.id, e);
-                                                })?.drop_nulls();
+                                                let mut response = map_to_common_model(body).await?;
 
                                                 if let Value::Object(map) = &mut response {
                                                     if !map.contains_key(MODIFY_TOKEN_KEY) {
@@ -496,11 +867,7 @@ impl UnifiedDestination {
                                             .collect::<Result<Vec<Value>, _>>()?;
                                         Ok(Value::Array(values))
                                     }
-                                    Ok(Some(body)) => {
-                                        Ok(jsruntime.run::<Value, Value>(&body, &namespace).await.inspect_err(|e| {
-                                            error!("Failed to run request schema mapping script for connection model schema. ID: {}, Error: {}", config.id, e);
-                                        })?.drop_nulls())
-                                    }
+                                    Ok(Some(body)) => map_to_common_model(body).await,
                                     Ok(_) if config.action_name == CrudAction::GetMany => Ok(Value::Array(Default::default())),
                                     Err(e) => Err(e),
                                     _ => Ok(Value::Object(Default::default())),
@@ -522,7 +889,51 @@ impl UnifiedDestination {
                     CrudAction::Update | CrudAction::Delete => Ok(None),
                 }?;
 
-                build_unified_response(config, metadata, is_passthrough)(body, pagination, passthrough, params, status, headers)
+                if let (Some(schema_type), Some(common_model), Some(body), true) = (validation, &common_model, &body, is_common_model) {
+                    let drift = match body {
+                        Value::Array(items) => items
+                            .iter()
+                            .enumerate()
+                            .flat_map(|(index, item)| {
+                                common_model.validate(item, schema_type).into_iter().map(move |error| FieldError {
+                                    path: match error.path.as_str() {
+                                        "" => format!("[{index}]"),
+                                        path => format!("[{index}].{path}"),
+                                    },
+                                    ..error
+                                })
+                            })
+                            .collect::<Vec<_>>(),
+                        body => common_model.validate(body, schema_type),
+                    };
+
+                    if !drift.is_empty() {
+                        tracing::warn!("Unified response drifted from the common model. ID: {}, Fields: {}", config.id, drift.len());
+                        metadata.drift(drift);
+                    }
+                }
+
+                let is_write = matches!(config.action_name, CrudAction::Create | CrudAction::Update | CrudAction::Upsert | CrudAction::Delete);
+                let response = build_unified_response(config, metadata, is_passthrough)(body, pagination, passthrough, params, status, headers)?;
+
+                if is_write {
+                    self.invalidate_response_cache(&response_cache_scope(&connection, &name))
+                        .await;
+                }
+
+                if let (Some(policy), Some(cache_key)) = (cache_policy, cache_key) {
+                    let cached = CachedUnifiedResponse {
+                        headers: response.response.headers().clone(),
+                        body: response.response.body().clone(),
+                        hash: response.metadata.hash().map(ToString::to_string),
+                    };
+
+                    if let Err(e) = self.response_cache.insert(&cache_key, &cached, policy.ttl_secs).await {
+                        error!("Failed to store unified response in cache. Key: {cache_key}, Error: {e}");
+                    }
+                }
+
+                Ok(response)
             }
             Action::Passthrough { method, path, .. } => Err(InternalError::invalid_argument(
                 &format!("Passthrough action is not supported for destination {}, in method {method} and path {path}", key.connection_key),
@@ -613,14 +1024,143 @@ impl UnifiedDestination {
             _ => config.clone(),
         };
 
-        self.execute_model_definition(
-            &templated_config,
-            headers,
-            &query_params,
-            &secret.as_value()?,
-            context,
-        )
-        .await
+        let response = self
+            .execute_connection_model_definition(
+                &connection,
+                &templated_config,
+                headers.clone(),
+                &query_params,
+                &secret.as_value()?,
+                context.clone(),
+            )
+            .await?;
+
+        // Replay the request once with a fresh token if the platform rejected the current one
+        let response = match self
+            .refresh_after_unauthorized(&connection, &config, response.status())
+            .await
+        {
+            Some((connection, secret)) => {
+                self.execute_connection_model_definition(
+                    &connection,
+                    &templated_config,
+                    headers,
+                    &query_params,
+                    &secret.as_value()?,
+                    context,
+                )
+                .await?
+            }
+            None => response,
+        };
+
+        // A passthrough write can change any model of the connection
+        if !config.action.is_safe() && response.status().is_success() {
+            self.invalidate_response_cache(&connection.key).await;
+        }
+
+        Ok(response)
+    }
+
+    /// Compares a platform response with the schema of its model in the background, recording
+    /// the drift if they differ. Only the first item of a list is sampled.
+    async fn sample_schema_drift(
+        &self,
+        config: &ConnectionModelDefinition,
+        cms: &ConnectionModelSchema,
+        body: &Value,
+    ) {
+        let sample = match body {
+            Value::Array(items) => items.first(),
+            body => Some(body),
+        };
+        // Only the schema inferred from the sample is kept, never the sample itself
+        let Some(actual) = sample
+            .filter(|sample| sample.is_object())
+            .map(|sample| generate_schema(sample, "$"))
+        else {
+            return;
+        };
+
+        if let Ok(Some(())) = self.schema_drift_samples.get(&cms.id).await {
+            return;
+        }
+
+        if let Err(e) = self.schema_drift_samples.insert(&cms.id, &()).await {
+            error!(
+                "Failed to mark connection model schema as sampled. ID: {}, Error: {e}",
+                cms.id
+            );
+            return;
+        }
+
+        let store = self.schema_drifts_store.clone();
+        let cms = cms.clone();
+        let connection_model_definition_id = config.id;
+
+        tokio::spawn(async move {
+            let actual = match JsonSchema::from_value(actual) {
+                Ok(actual) => actual,
+                Err(e) => {
+                    error!(
+                        "Failed to infer schema of platform response. ID: {}, Error: {e}",
+                        cms.id
+                    );
+                    return;
+                }
+            };
+
+            let changes = cms.schema.diff(&actual);
+            if changes.is_empty() {
+                return;
+            }
+
+            tracing::warn!(
+                "Schema drift detected for connection model schema. ID: {}, Changes: {}",
+                cms.id,
+                changes.len()
+            );
+
+            if let Err(e) =
+                upsert_schema_drift(&store, &cms, connection_model_definition_id, changes).await
+            {
+                error!("Failed to save schema drift. ID: {}, Error: {e}", cms.id);
+            }
+        });
+    }
+
+    /// Common model of the schema, expanded so that nested models are validated as well
+    async fn get_common_model(
+        &self,
+        cms: &ConnectionModelSchema,
+    ) -> Result<Option<CommonModel>, PicaError> {
+        let Some(mapping) = cms.mapping.as_ref() else {
+            return Ok(None);
+        };
+        let id = mapping.common_model_id;
+
+        self.common_models_cache
+            .get_or_insert_with_fn(&id, || async {
+                let model = self
+                    .common_models_store
+                    .get_one_by_id(&id.to_string())
+                    .await?
+                    .ok_or_else(|| {
+                        ApplicationError::not_found(
+                            &format!("Common model with id {id} not found"),
+                            None,
+                        )
+                    })?;
+
+                model
+                    .expand_all(
+                        self.common_models_store.clone(),
+                        self.common_enums_store.clone(),
+                    )
+                    .await
+            })
+            .await
+            .map(Some)
     }
 
     async fn get_dependencies(
@@ -647,23 +1187,7 @@ impl UnifiedDestination {
                 }
             });
 
-        let secret_fut = self
-            .secrets_cache
-            .get_or_insert_with_fn(connection, || async {
-                match self
-                    .secrets_client
-                    .get(&connection.secrets_service_id, &connection.ownership.id)
-                    .map(|v| Some(v).transpose())
-                    .await
-                {
-                    Ok(Some(c)) => Ok(c),
-                    Ok(None) => Err(InternalError::key_not_found("secret", None)),
-                    Err(e) => Err(InternalError::connection_error(
-                        format!("Failed to get secret: {}", e.message().as_ref()).as_str(),
-                        None,
-                    )),
-                }
-            });
+        let secret_fut = self.get_secret(connection);
 
         let schema_key: (Arc<str>, Arc<str>) = (connection.platform.clone(), name.into());
 
@@ -699,6 +1223,71 @@ impl UnifiedDestination {
     }
 }
 
+fn response_cache_scope(connection: &Connection, name: &str) -> String {
+    format!("{}::{name}", connection.key)
+}
+
+/// Whether the platform responded with content that is not JSON, e.g. a file or plain text. Only
+/// `application/json`, `+json` suffixed types and responses without a content type are parsed.
+pub fn is_binary_response(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            let mime = value
+                .split(';')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_lowercase();
+
+            !mime.is_empty() && mime != "application/json" && !mime.ends_with("+json")
+        })
+        .unwrap_or(false)
+}
+
+fn build_binary_response(
+    binary: reqwest::Response,
+    metadata: &mut UnifiedMetadataBuilder,
+) -> Result<UnifiedResponse, PicaError> {
+    let status = binary.status();
+    let metadata = metadata.build()?;
+
+    let mut response = Response::new(json!({ META_KEY: metadata.as_value() }));
+    *response.headers_mut() = binary.headers().clone();
+    response.headers_mut().insert(
+        HeaderName::from_static(STATUS_HEADER_KEY),
+        status.as_u16().into(),
+    );
+
+    Ok(UnifiedResponse {
+        response,
+        metadata,
+        binary: Some(binary),
+    })
+}
+
+fn build_cached_response(
+    mut body: Value,
+    headers: HeaderMap,
+    metadata: &mut UnifiedMetadataBuilder,
+) -> Result<UnifiedResponse, PicaError> {
+    let metadata = metadata.build()?;
+
+    if let Value::Object(ref mut resp) = body {
+        resp.insert(META_KEY.to_string(), metadata.as_value());
+    }
+
+    let mut response = Response::new(body);
+    *response.headers_mut() = headers;
+
+    Ok(UnifiedResponse {
+        response,
+        metadata,
+        binary: None,
+    })
+}
+
 fn build_unified_response(
     config: ConnectionModelDefinition,
     metadata: &mut UnifiedMetadataBuilder,
@@ -805,6 +1394,7 @@ fn build_unified_response(
         Ok(UnifiedResponse {
             response: res,
             metadata: metadata.build()?,
+            binary: None,
         })
     }
 }
@@ -872,7 +1462,7 @@ fn transform_response_with_path(
     }
 }
 
-fn extract_pagination(
+pub fn extract_pagination(
     config: &ConnectionModelDefinition,
     body: &Option<Value>,
 ) -> Result<Option<Value>, PicaError> {
@@ -947,10 +1537,43 @@ fn generate_script_namespace(max_capacity: u64, key: &str) -> String {
     if max_capacity == 0 {
         "$".to_string() + &uuid::Uuid::new_v4().simple().to_string()
     } else {
-        key.to_string().replace([':', '-'], "_")
+        JSRuntimeImpl::namespace(key)
     }
 }
 
+/// Records the drift once per schema and set of changes, counting the responses showing it
+async fn upsert_schema_drift(
+    store: &MongoStore<SchemaDrift>,
+    cms: &ConnectionModelSchema,
+    connection_model_definition_id: Id,
+    changes: Vec<SchemaChange>,
+) -> Result<(), PicaError> {
+    let drift = SchemaDrift::new(cms, connection_model_definition_id, changes)?;
+
+    let mut insert = bson::to_document(&drift)
+        .map_err(|e| InternalError::serialize_error(&e.to_string(), None))?;
+    insert.remove("occurrences");
+    insert.remove("updatedAt");
+
+    store
+        .collection
+        .update_one(
+            doc! {
+                "connectionModelSchemaId": cms.id.to_string(),
+                "changesHash": &drift.changes_hash,
+            },
+            doc! {
+                "$setOnInsert": insert,
+                "$inc": { "occurrences": 1 },
+                "$set": { "updatedAt": Utc::now().timestamp_millis() },
+            },
+        )
+        .upsert(true)
+        .await?;
+
+    Ok(())
+}
+
 fn insert_action_id(secret: Value, id: Option<&Arc<str>>) -> Value {
     if let Value::Object(mut sec) = secret {
         if let Some(id) = id {
@@ -1022,3 +1645,98 @@ fn prepare_crud_mapping(
         .extend_header(custom_headers)
         .add_path_param(ID_KEY.to_string(), id.as_ref().map(|id| id.to_string())))
 }
+
+/// Maps the request with the `from_common_model` of the crud mapping of the definition, the
+/// request is returned as is if the definition has no mapping
+pub async fn map_crud_request(
+    config: &ConnectionModelDefinition,
+    params: RequestCrud,
+    id: Option<&Arc<str>>,
+    body: Option<Value>,
+    namespace: &str,
+) -> Result<RequestCrud, PicaError> {
+    let Some(mapping) = config.mapping.as_ref() else {
+        return Ok(params);
+    };
+
+    let params = match (
+        &mapping.from_common_model_schema,
+        mapping.from_common_model_script(),
+    ) {
+        (Some(definition), _) => {
+            let payload = prepare_crud_mapping(params, config, id)?;
+
+            tracing::debug!("Request crud prepared for declarative mapping in unified destination. RequestCrud: {:?}", payload);
+
+            map_by_schema::<_, RequestCrud>(&payload, definition)?.extend_body(body)
+        }
+        (None, Some(code)) => {
+            let namespace = namespace.to_string() + "_mapFromCrudRequest";
+            let jsruntime = JSRuntimeImpl.create("mapCrudRequest", &namespace, code)?;
+
+            tracing::debug!("Code for mapping crud request ready for unified destination. Code: {code}, Namespace: {namespace}");
+
+            let payload = prepare_crud_mapping(params, config, id)?;
+
+            tracing::debug!(
+                "Request crud prepared for unified destination. RequestCrud: {:?}",
+                payload
+            );
+
+            jsruntime
+                .run::<_, RequestCrud>(&payload, &namespace)
+                .await?
+                .extend_body(body)
+        }
+        (None, None) => params,
+    };
+
+    tracing::debug!(
+        "Request crud prepared for unified destination. RequestCrud: {:?}",
+        params
+    );
+
+    Ok(params)
+}
+
+/// Maps the response of the platform with the `to_common_model` of the crud mapping of the
+/// definition, `None` if the definition has no mapping for responses
+pub async fn map_crud_response(
+    config: &ConnectionModelDefinition,
+    headers: &HeaderMap,
+    pagination: Option<Value>,
+    params: &RequestCrud,
+    namespace: &str,
+) -> Result<Option<ResponseCrud>, PicaError> {
+    let Some(mapping) = config.mapping.as_ref() else {
+        return Ok(None);
+    };
+
+    let to_map = || {
+        ResponseCrudToMapBuilder::default()
+            .headers(headers)
+            .pagination(pagination.clone())
+            .request(ResponseCrudToMapRequest::new(params.get_query_params()))
+            .build()
+    };
+
+    match (
+        &mapping.to_common_model_schema,
+        mapping.to_common_model_script(),
+    ) {
+        (Some(definition), _) => map_by_schema(&to_map()?, definition).map(Some),
+        (None, Some(code)) => {
+            tracing::debug!("Code for mapping crud request ready for unified destination. Code: {code}, Namespace: {namespace}");
+
+            let namespace = namespace.to_string() + "_mapToCrudRequest";
+            let jsruntime = JSRuntimeImpl
+                .create("mapCrudRequest", &namespace, code)
+                .inspect_err(|e| {
+                    error!("Failed to create request crud mapping script for connection model. ID: {}, Error: {}", config.id, e);
+                })?;
+
+            jsruntime.run(&to_map()?, &namespace).await.map(Some)
+        }
+        (None, None) => Ok(None),
+    }
+}
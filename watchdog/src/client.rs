@@ -3,14 +3,41 @@ use bson::doc;
 use cache::remote::RedisCache;
 use chrono::Utc;
 use futures::{stream::FuturesUnordered, StreamExt};
+use http::{header::CONTENT_TYPE, HeaderValue, Method};
+use mongodb::{
+    options::{IndexOptions, ReturnDocument},
+    IndexModel,
+};
 use osentities::{
-    cache::CacheConfig, database::DatabaseConfig, task::Task, Id, InternalError, MongoStore,
-    PicaError, Store, Unit,
+    cache::CacheConfig,
+    database::DatabaseConfig,
+    secrets::SecretServiceProvider,
+    task::{response_body, sign_task_request, Task, TaskFailure, TaskState},
+    workflow::Workflow,
+    GoogleKms, IOSKms, Id, InternalError, MongoStore, PicaError, Secret, SecretExt, Store, Unit,
+    TASK_SIGNATURE_HEADER, TASK_TIMESTAMP_HEADER,
 };
+use rand::Rng;
 use redis::{AsyncCommands, RedisResult};
+use serde_json::Value;
 use std::fmt::Display;
+use std::sync::Arc;
 use std::time::Duration;
-use tracing::{error, info};
+use tracing::{error, info, warn};
+
+/// Times the outcome of a stage is recorded again when its workflow was saved in between
+const MAX_WORKFLOW_UPDATE_ATTEMPTS: usize = 5;
+
+const LEASE_EXPIRED_ERROR: &str = "Lease expired while the task was running";
+
+/// Lease held by this replica on the tasks it runs
+#[derive(Debug, Clone, Copy)]
+struct Lease {
+    /// Identifies the replica, `0` is kept for unclaimed tasks
+    worker_id: i64,
+    duration_ms: i64,
+    heartbeat_interval: Duration,
+}
 
 pub struct WatchdogClient {
     watchdog: WatchdogConfig,
@@ -18,6 +45,9 @@ pub struct WatchdogClient {
     database: DatabaseConfig,
     client: reqwest::Client,
     tasks: MongoStore<Task>,
+    workflows: MongoStore<Workflow>,
+    secrets_client: Arc<dyn SecretExt + Sync + Send>,
+    lease: Lease,
 }
 
 impl Display for WatchdogClient {
@@ -44,6 +74,31 @@ impl WatchdogClient {
         let db = client.database(&database.event_db_name);
 
         let tasks: MongoStore<Task> = MongoStore::new(&db, &Store::Tasks).await?;
+        create_series_index(&tasks).await?;
+        let workflows: MongoStore<Workflow> = MongoStore::new(&db, &Store::Workflows).await?;
+        let secrets_store = MongoStore::<Secret>::new(&db, &Store::Secrets).await?;
+
+        let secrets_client: Arc<dyn SecretExt + Sync + Send> =
+            match watchdog.secrets_config.provider {
+                SecretServiceProvider::GoogleKms => {
+                    Arc::new(GoogleKms::new(&watchdog.secrets_config, secrets_store).await?)
+                }
+                SecretServiceProvider::IosKms => {
+                    Arc::new(IOSKms::new(&watchdog.secrets_config, secrets_store).await?)
+                }
+            };
+
+        let lease = Lease {
+            worker_id: rand::thread_rng().gen_range(1..i64::MAX),
+            duration_ms: (watchdog.task_lease_secs as i64).saturating_mul(1_000),
+            heartbeat_interval: Duration::from_secs(watchdog.task_heartbeat_interval_secs.max(1)),
+        };
+
+        if watchdog.task_heartbeat_interval_secs >= watchdog.task_lease_secs {
+            warn!("The task heartbeat interval should be shorter than the task lease");
+        }
+
+        info!("Watchdog worker id is {}", lease.worker_id);
 
         Ok(Self {
             watchdog,
@@ -51,6 +106,9 @@ impl WatchdogClient {
             database,
             client: http_client,
             tasks,
+            workflows,
+            secrets_client,
+            lease,
         })
     }
 
@@ -86,48 +144,39 @@ impl WatchdogClient {
             let _: RedisResult<String> = async { redis_clone.del(key.clone()).await }.await;
             tracing::info!("Rate limiter cleared for {key} at {}", Utc::now());
 
-            let tasks: Vec<Task> = self
-                .tasks
-                .get_many(
-                    Some(doc! {
-                    "active": true,
-                    "workerId": 0,
-                    "startTime": {
-                        "$lte": Utc::now().timestamp_millis(),
-                    }}),
-                    None,
-                    None,
-                    Some(self.watchdog.max_amount_of_tasks_to_process),
-                    None,
-                )
-                .await?;
+            dead_letter_expired(&self.tasks, &self.workflows).await?;
 
-            tracing::info!("Executing {} tasks", tasks.len());
+            let mut tasks = vec![];
+            while (tasks.len() as u64) < self.watchdog.max_amount_of_tasks_to_process {
+                match claim(&self.tasks, self.lease).await? {
+                    Some(task) => tasks.push(task),
+                    None => break,
+                }
+            }
 
-            self.tasks
-                .update_many(
-                    doc! {
-                        "_id": {
-                            "$in": tasks.iter().map(|t| t.id.to_string()).collect::<Vec<_>>()
-                        }
-                    },
-                    doc! {
-                        "$set": {
-                            "workerId": 1,
-                            "active": false
-                        }
-                    },
-                )
-                .await?;
+            tracing::info!("Executing {} tasks", tasks.len());
 
             let client = self.client.clone();
             let tasks_store = self.tasks.clone();
+            let workflows_store = self.workflows.clone();
+            let secrets_client = self.secrets_client.clone();
             let timeout = self.watchdog.http_client_timeout_secs;
+            let lease = self.lease;
 
             tokio::spawn(async move {
                 let mut tasks = tasks
                     .into_iter()
-                    .map(|task| execute(task, client.clone(), tasks_store.clone(), timeout))
+                    .map(|task| {
+                        execute(
+                            task,
+                            client.clone(),
+                            tasks_store.clone(),
+                            workflows_store.clone(),
+                            secrets_client.clone(),
+                            timeout,
+                            lease,
+                        )
+                    })
                     .collect::<FuturesUnordered<_>>();
 
                 while let Some(result) = tasks.next().await {
@@ -148,11 +197,133 @@ impl WatchdogClient {
     }
 }
 
+/// Atomically claims a due task for this replica. Tasks whose lease expired, because the
+/// replica running them stopped, are claimed again if they have attempts left.
+async fn claim(tasks_store: &MongoStore<Task>, lease: Lease) -> Result<Option<Task>, PicaError> {
+    let now = Utc::now().timestamp_millis();
+
+    let task = tasks_store
+        .collection
+        .find_one_and_update(
+            doc! {
+                "schedule.paused": { "$ne": true },
+                "$or": [
+                    {
+                        "active": true,
+                        "workerId": 0,
+                        "startTime": { "$lte": now },
+                        "nextRunAt": { "$not": { "$gt": now } },
+                    },
+                    {
+                        "state": state_to_bson(TaskState::Running),
+                        "leaseExpiresAt": { "$lte": now },
+                        "$expr": { "$lt": ["$attempt", "$maxAttempts"] },
+                    },
+                ],
+            },
+            doc! {
+                "$set": {
+                    "workerId": lease.worker_id,
+                    "active": false,
+                    "state": state_to_bson(TaskState::Running),
+                    "leaseExpiresAt": now.saturating_add(lease.duration_ms),
+                },
+                "$inc": { "attempt": 1 }
+            },
+        )
+        .sort(doc! { "startTime": 1 })
+        .return_document(ReturnDocument::After)
+        .await?;
+
+    Ok(task)
+}
+
+/// Dead-letters the tasks whose lease expired on their last attempt, as if that attempt had
+/// failed, since claiming them again would exceed their attempts
+async fn dead_letter_expired(
+    tasks_store: &MongoStore<Task>,
+    workflows_store: &MongoStore<Workflow>,
+) -> Result<Unit, PicaError> {
+    loop {
+        let now = Utc::now().timestamp_millis();
+
+        let Some(task) = tasks_store
+            .collection
+            .find_one_and_update(
+                doc! {
+                    "state": state_to_bson(TaskState::Running),
+                    "leaseExpiresAt": { "$lte": now },
+                    "$expr": { "$gte": ["$attempt", "$maxAttempts"] },
+                },
+                doc! {
+                    "$set": {
+                        "state": state_to_bson(TaskState::DeadLettered),
+                        "endTime": now,
+                        "lastError": LEASE_EXPIRED_ERROR,
+                    },
+                    "$unset": { "leaseExpiresAt": "" },
+                },
+            )
+            .return_document(ReturnDocument::After)
+            .await?
+        else {
+            return Ok(());
+        };
+
+        error!(
+            "Task {} failed on attempt {}/{}, dead-lettering: {LEASE_EXPIRED_ERROR}",
+            task.id, task.attempt, task.max_attempts
+        );
+
+        if let Err(e) = complete(
+            &task,
+            Err(LEASE_EXPIRED_ERROR.to_string()),
+            now,
+            tasks_store,
+            workflows_store,
+        )
+        .await
+        {
+            error!("Could not complete dead-lettered task {}: {e}", task.id);
+        }
+    }
+}
+
+/// Extends the lease of a task for as long as it runs, so that it is not claimed by another
+/// replica
+async fn heartbeat(id: Id, lease: Lease, tasks_store: MongoStore<Task>) {
+    loop {
+        tokio::time::sleep(lease.heartbeat_interval).await;
+
+        let lease_expires_at = Utc::now()
+            .timestamp_millis()
+            .saturating_add(lease.duration_ms);
+
+        if let Err(e) = tasks_store
+            .collection
+            .update_one(
+                doc! { "_id": id.to_string(), "workerId": lease.worker_id },
+                doc! { "$set": { "leaseExpiresAt": lease_expires_at } },
+            )
+            .await
+        {
+            error!("Could not extend the lease of task {id}: {e}");
+        }
+    }
+}
+
+/// Runs a single attempt of the task. Retryable failures are rescheduled according to the
+/// backoff of the task until it runs out of attempts, other failures are dead-lettered. Once a
+/// task completes either way, the stages of its workflow depending on it are released, and
+/// the next occurrence of a recurring task is created.
 async fn execute(
     task: Task,
     http_client: reqwest::Client,
     tasks_store: MongoStore<Task>,
+    workflows_store: MongoStore<Workflow>,
+    secrets_client: Arc<dyn SecretExt + Sync + Send>,
     timeout: u64,
+    lease: Lease,
 ) -> Result<Id, PicaError> {
     let timeout = if task.r#await {
         Duration::from_secs(300)
@@ -160,47 +331,559 @@ async fn execute(
         Duration::from_secs(timeout)
     };
 
-    let response = http_client
-        .post(task.endpoint)
-        .timeout(timeout)
-        .json(&task.payload)
-        .send()
-        .await?;
+    let heartbeat = tokio::spawn(heartbeat(task.id, lease, tasks_store.clone()));
 
-    let status = response.status();
-    let mut stream = response.bytes_stream();
-    let mut log_trail = vec![];
+    let response = match request(&task, &http_client, secrets_client.as_ref()).await {
+        Ok(request) => Ok(request.timeout(timeout).send().await),
+        Err(e) => {
+            error!("Could not build the request of task {}: {e}", task.id);
+            Err(TaskFailure::Other(e.to_string()))
+        }
+    };
 
-    while let Some(item) = stream.next().await {
-        tracing::debug!("Response from API {:?}", item);
-        log_trail.push(item);
-    }
+    let (status, log_trail, failure) = match response {
+        Ok(Ok(response)) => {
+            let status = response.status();
+            let mut stream = response.bytes_stream();
+            let mut log_trail = vec![];
+
+            while let Some(item) = stream.next().await {
+                tracing::debug!("Response from API {:?}", item);
+                log_trail.push(item);
+            }
+
+            let log_trail = log_trail
+                .into_iter()
+                .filter_map(|x| x.ok())
+                .collect::<Vec<_>>();
 
-    let log_trail = log_trail
-        .into_iter()
-        .filter_map(|x| x.ok())
-        .collect::<Vec<_>>();
+            let failure = (!status.is_success()).then_some(TaskFailure::Status(status.as_u16()));
+
+            (Some(status.to_string()), log_trail, failure)
+        }
+        Ok(Err(e)) => {
+            let failure = if e.is_timeout() {
+                TaskFailure::Timeout
+            } else if e.is_connect() {
+                TaskFailure::Connect
+            } else {
+                TaskFailure::Other(e.to_string())
+            };
+
+            (None, vec![], Some(failure))
+        }
+        Err(failure) => (None, vec![], Some(failure)),
+    };
+
+    heartbeat.abort();
 
     let bson_log_trail = bson::to_bson(&log_trail).map_err(|e| {
         error!("Could not convert log trail to BSON: {e}");
         InternalError::io_err(e.to_string().as_str(), None)
     })?;
 
-    tasks_store
+    let now = Utc::now().timestamp_millis();
+    let mut update = doc! {
+        "status": status,
+        "logTrail": bson_log_trail,
+    };
+
+    // Only set once the task won't run again, with its response or error
+    let outcome = match failure {
+        None => {
+            update.insert("state", state_to_bson(TaskState::Succeeded));
+            update.insert("endTime", now);
+            Some(Ok(response_body(&log_trail)))
+        }
+        Some(failure) => {
+            update.insert("lastError", failure.to_string());
+
+            match task.next_retry_at(&failure, now) {
+                Some(next_run_at) => {
+                    info!(
+                        "Task {} failed on attempt {}/{}, retrying: {failure}",
+                        task.id, task.attempt, task.max_attempts
+                    );
+                    update.insert("state", state_to_bson(TaskState::Retrying));
+                    update.insert("nextRunAt", next_run_at);
+                    update.insert("workerId", 0);
+                    update.insert("active", true);
+                    None
+                }
+                None => {
+                    error!(
+                        "Task {} failed on attempt {}/{}, dead-lettering: {failure}",
+                        task.id, task.attempt, task.max_attempts
+                    );
+                    update.insert("state", state_to_bson(TaskState::DeadLettered));
+                    update.insert("endTime", now);
+                    Some(Err(failure.to_string()))
+                }
+            }
+        }
+    };
+
+    let current = tasks_store
         .collection
         .find_one_and_update(
             doc! {
-                "_id": task.id.to_string() // Filter by task ID
+                "_id": task.id.to_string(), // Filter by task ID
+                "workerId": lease.worker_id,
             },
+            doc! { "$set": update, "$unset": { "leaseExpiresAt": "" } },
+        )
+        .await?;
+
+    let Some(current) = current else {
+        warn!(
+            "Lease on task {} expired while it was running, leaving it to the replica that reclaimed it",
+            task.id
+        );
+        return Ok(task.id);
+    };
+
+    if let Some(outcome) = outcome {
+        complete(&current, outcome, now, &tasks_store, &workflows_store).await?;
+    }
+
+    Ok(task.id)
+}
+
+/// Releases the stages of the workflow depending on a task that won't run again, and creates
+/// the next occurrence of a recurring task
+async fn complete(
+    task: &Task,
+    outcome: Result<Value, String>,
+    now: i64,
+    tasks_store: &MongoStore<Task>,
+    workflows_store: &MongoStore<Workflow>,
+) -> Result<Unit, PicaError> {
+    if let (Some(workflow_id), Some(stage_id)) = (task.workflow_id, task.stage_id) {
+        advance_workflow(workflow_id, stage_id, outcome, workflows_store, tasks_store).await?;
+    }
+
+    // The stored task is used since the schedule may have been paused while it was running
+    if let Some(next) = task.next_occurrence(now)? {
+        schedule_occurrence(&next, tasks_store).await?;
+    }
+
+    Ok(())
+}
+
+/// Creates an occurrence of a recurring task unless its series already has one for the same run.
+/// A replayed occurrence completes a second time, it must not fork the series.
+async fn schedule_occurrence(
+    next: &Task,
+    tasks_store: &MongoStore<Task>,
+) -> Result<Unit, PicaError> {
+    let (Some(series_id), Some(schedule)) = (next.series_id, &next.schedule) else {
+        return Ok(());
+    };
+
+    let mut occurrence = bson::to_document(next)
+        .map_err(|e| InternalError::serialize_error(&e.to_string(), None))?;
+    // Set from the filter on insert
+    occurrence.remove("seriesId");
+
+    let result = tasks_store
+        .collection
+        .update_one(
             doc! {
-                "$set": {
-                    "status": status.to_string(),
-                    "endTime": Utc::now().timestamp_millis(),
-                    "logTrail": bson_log_trail,
-                }
+                "seriesId": series_id.to_string(),
+                "schedule.run": schedule.run,
             },
+            doc! { "$setOnInsert": occurrence },
         )
+        .upsert(true)
         .await?;
 
-    Ok(task.id)
+    if result.upserted_id.is_some() {
+        info!(
+            "Scheduled the next occurrence {} of series {series_id} at {}",
+            next.id, next.start_time
+        );
+    } else {
+        info!(
+            "Occurrence {} of series {series_id} is already scheduled",
+            schedule.run
+        );
+    }
+
+    Ok(())
+}
+
+/// Keeps a single occurrence per run of a series, even when replicas schedule it concurrently
+async fn create_series_index(tasks_store: &MongoStore<Task>) -> Result<Unit, PicaError> {
+    tasks_store
+        .collection
+        .create_index(
+            IndexModel::builder()
+                .keys(doc! { "seriesId": 1, "schedule.run": 1 })
+                .options(
+                    IndexOptions::builder()
+                        .unique(true)
+                        .partial_filter_expression(doc! { "seriesId": { "$exists": true } })
+                        .build(),
+                )
+                .build(),
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Builds the request of a task. When the task has a signing secret, the request carries the
+/// time it was sent and a signature of that time and its body.
+async fn request(
+    task: &Task,
+    http_client: &reqwest::Client,
+    secrets_client: &(dyn SecretExt + Sync + Send),
+) -> Result<reqwest::RequestBuilder, PicaError> {
+    let body = if matches!(task.method, Method::GET | Method::HEAD) {
+        vec![]
+    } else {
+        serde_json::to_vec(&task.payload)
+            .map_err(|e| InternalError::serialize_error(&e.to_string(), None))?
+    };
+
+    let mut headers = task.headers.clone();
+
+    if !body.is_empty() && !headers.contains_key(CONTENT_TYPE) {
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    }
+
+    if let Some(secret_id) = &task.signing_secret_id {
+        // Secrets are scoped to their owner, a task can only use one of its ownership
+        let ownership = task.ownership.as_ref().ok_or_else(|| {
+            InternalError::invalid_argument("A signed task must have an ownership", None)
+        })?;
+
+        let Value::String(key) = secrets_client
+            .get(secret_id, &ownership.id)
+            .await?
+            .as_value()?
+        else {
+            return Err(InternalError::invalid_argument(
+                "The signing secret of a task must be a string",
+                None,
+            ));
+        };
+
+        let timestamp = Utc::now().timestamp_millis();
+        let signature = sign_task_request(&key, timestamp, &body)?;
+
+        headers.insert(TASK_TIMESTAMP_HEADER, HeaderValue::from(timestamp));
+        headers.insert(
+            TASK_SIGNATURE_HEADER,
+            HeaderValue::from_str(&signature)
+                .map_err(|e| InternalError::invalid_argument(&e.to_string(), None))?,
+        );
+    }
+
+    Ok(http_client
+        .request(task.method.clone(), &task.endpoint)
+        .headers(headers)
+        .body(body))
+}
+
+/// Records the outcome of a workflow stage and creates the tasks of the stages it releases.
+/// Stages of a workflow may complete at the same time on different replicas, so the workflow
+/// is only saved if it is still at the revision it was read at, and read again otherwise.
+async fn advance_workflow(
+    workflow_id: Id,
+    stage_id: Id,
+    outcome: Result<Value, String>,
+    workflows_store: &MongoStore<Workflow>,
+    tasks_store: &MongoStore<Task>,
+) -> Result<Unit, PicaError> {
+    for _ in 0..MAX_WORKFLOW_UPDATE_ATTEMPTS {
+        let Some(mut workflow) = workflows_store
+            .get_one_by_id(&workflow_id.to_string())
+            .await?
+        else {
+            warn!("Workflow {workflow_id} of stage {stage_id} not found");
+            return Ok(());
+        };
+
+        if !workflow.complete_stage(stage_id, outcome.clone()) {
+            warn!("Stage {stage_id} of workflow {workflow_id} is not running");
+            return Ok(());
+        }
+
+        let tasks = workflow.release();
+        let revision = workflow.revision;
+        workflow.revision += 1;
+        workflow.metadata.mark_updated("system");
+
+        let result = workflows_store
+            .collection
+            .replace_one(
+                doc! { "_id": workflow_id.to_string(), "revision": revision },
+                &workflow,
+            )
+            .await?;
+
+        if result.matched_count == 0 {
+            continue;
+        }
+
+        info!(
+            "Stage {stage_id} of workflow {workflow_id} completed, releasing {} stages",
+            tasks.len()
+        );
+
+        if !tasks.is_empty() {
+            tasks_store.create_many(&tasks).await?;
+        }
+
+        return Ok(());
+    }
+
+    Err(InternalError::unknown(
+        &format!("Could not record the outcome of stage {stage_id} of workflow {workflow_id}"),
+        None,
+    ))
+}
+
+fn state_to_bson(state: TaskState) -> bson::Bson {
+    bson::to_bson(&state).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use osentities::{prefix::IdPrefix, record_metadata::RecordMetadata};
+    use std::sync::OnceLock;
+    use testcontainers_modules::{
+        mongo::Mongo,
+        testcontainers::{clients::Cli as Docker, Container},
+    };
+
+    static DOCKER: OnceLock<Docker> = OnceLock::new();
+    static MONGO: OnceLock<Container<'static, Mongo>> = OnceLock::new();
+
+    async fn stores() -> (MongoStore<Task>, MongoStore<Workflow>) {
+        let docker = DOCKER.get_or_init(Default::default);
+        let mongo = MONGO.get_or_init(|| docker.run(Mongo));
+        let url = format!(
+            "mongodb://127.0.0.1:{}/?directConnection=true",
+            mongo.get_host_port_ipv4(27017)
+        );
+
+        let db = mongodb::Client::with_uri_str(&url)
+            .await
+            .expect("Could not connect to mongo")
+            .database(&uuid::Uuid::new_v4().to_string());
+
+        let tasks = MongoStore::new(&db, &Store::Tasks).await.unwrap();
+        create_series_index(&tasks).await.unwrap();
+
+        (
+            tasks,
+            MongoStore::new(&db, &Store::Workflows).await.unwrap(),
+        )
+    }
+
+    fn lease(worker_id: i64) -> Lease {
+        Lease {
+            worker_id,
+            duration_ms: 60_000,
+            heartbeat_interval: Duration::from_millis(10),
+        }
+    }
+
+    fn task(start_time: i64) -> Task {
+        Task {
+            id: Id::now(IdPrefix::Task),
+            worker_id: 0,
+            start_time,
+            end_time: None,
+            payload: Value::Null,
+            endpoint: "http://localhost".to_string(),
+            method: Method::POST,
+            headers: Default::default(),
+            signing_secret_id: None,
+            status: None,
+            r#await: false,
+            log_trail: vec![],
+            state: TaskState::Pending,
+            max_attempts: 2,
+            attempt: 0,
+            backoff: Default::default(),
+            next_run_at: None,
+            last_error: None,
+            lease_expires_at: None,
+            schedule: None,
+            series_id: None,
+            workflow_id: None,
+            stage_id: None,
+            ownership: None,
+            environment: None,
+            metadata: RecordMetadata::default(),
+        }
+    }
+
+    /// A task claimed by `worker_id`, on its `attempt`, whose lease expires at `lease_expires_at`
+    fn running(worker_id: i64, attempt: u32, lease_expires_at: i64) -> Task {
+        let mut task = task(0);
+        task.worker_id = worker_id;
+        task.state = TaskState::Running;
+        task.attempt = attempt;
+        task.lease_expires_at = Some(lease_expires_at);
+        task.metadata.active = false;
+        task
+    }
+
+    #[tokio::test]
+    async fn test_claim_due_tasks_once() {
+        let (tasks, _) = stores().await;
+        let now = Utc::now().timestamp_millis();
+
+        let due = task(now - 1_000);
+        let mut paused = task(now - 2_000);
+        paused.schedule = serde_json::from_value(serde_json::json!({
+            "intervalSecs": 60,
+            "paused": true,
+        }))
+        .unwrap();
+        let mut retrying = task(now - 3_000);
+        retrying.next_run_at = Some(now + 60_000);
+
+        tasks
+            .create_many(&[due.clone(), paused, retrying, task(now + 60_000)])
+            .await
+            .unwrap();
+
+        let claimed = claim(&tasks, lease(1))
+            .await
+            .unwrap()
+            .expect("No task claimed");
+        assert_eq!(claimed.id, due.id);
+        assert_eq!(claimed.worker_id, 1);
+        assert_eq!(claimed.state, TaskState::Running);
+        assert_eq!(claimed.attempt, 1);
+        assert!(claimed.lease_expires_at.is_some_and(|at| at > now));
+
+        assert_eq!(claim(&tasks, lease(2)).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_reclaim_expired_lease_until_attempts_run_out() {
+        let (tasks, workflows) = stores().await;
+        let now = Utc::now().timestamp_millis();
+
+        let held = running(1, 1, now + 60_000);
+        let expired = running(1, 1, now - 1_000);
+        tasks
+            .create_many(&[held.clone(), expired.clone()])
+            .await
+            .unwrap();
+
+        let claimed = claim(&tasks, lease(2))
+            .await
+            .unwrap()
+            .expect("No task claimed");
+        assert_eq!(claimed.id, expired.id);
+        assert_eq!(claimed.worker_id, 2);
+        assert_eq!(claimed.attempt, 2);
+        assert_eq!(claim(&tasks, lease(2)).await.unwrap(), None);
+
+        // The second replica stops as well while running the last attempt
+        tasks
+            .update_one(
+                &expired.id.to_string(),
+                doc! { "$set": { "leaseExpiresAt": now - 1_000 } },
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(claim(&tasks, lease(3)).await.unwrap(), None);
+
+        dead_letter_expired(&tasks, &workflows).await.unwrap();
+
+        let stored = tasks
+            .get_one_by_id(&expired.id.to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.state, TaskState::DeadLettered);
+        assert_eq!(stored.attempt, 2);
+        assert_eq!(stored.last_error.as_deref(), Some(LEASE_EXPIRED_ERROR));
+        assert_eq!(stored.lease_expires_at, None);
+
+        let stored = tasks
+            .get_one_by_id(&held.id.to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.state, TaskState::Running);
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_extends_own_lease() {
+        let (tasks, _) = stores().await;
+        let now = Utc::now().timestamp_millis();
+
+        let own = running(1, 1, now);
+        let other = running(2, 1, now);
+        tasks
+            .create_many(&[own.clone(), other.clone()])
+            .await
+            .unwrap();
+
+        let beat = tokio::spawn(heartbeat(own.id, lease(1), tasks.clone()));
+        // Claimed by another replica after its lease expired, it is not extended
+        let stolen = tokio::spawn(heartbeat(other.id, lease(1), tasks.clone()));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        beat.abort();
+        stolen.abort();
+
+        let stored = tasks
+            .get_one_by_id(&own.id.to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(stored.lease_expires_at.is_some_and(|at| at >= now + 60_000));
+
+        let stored = tasks
+            .get_one_by_id(&other.id.to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.lease_expires_at, Some(now));
+    }
+
+    #[tokio::test]
+    async fn test_replayed_occurrence_does_not_fork_series() {
+        let (tasks, workflows) = stores().await;
+        let now = Utc::now().timestamp_millis();
+
+        let mut first = task(now - 1_000);
+        first.schedule = serde_json::from_value(serde_json::json!({ "intervalSecs": 60 })).unwrap();
+        tasks.create_one(&first).await.unwrap();
+
+        // Dead-lettered first, then replayed and completed again
+        for outcome in [
+            Err("Endpoint responded with status 500".to_string()),
+            Ok(Value::Null),
+        ] {
+            complete(&first, outcome, now, &tasks, &workflows)
+                .await
+                .unwrap();
+        }
+
+        let occurrences = tasks
+            .get_many(
+                Some(doc! { "seriesId": first.id.to_string() }),
+                None,
+                None,
+                None,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(occurrences.len(), 1);
+        assert_eq!(occurrences[0].schedule.as_ref().map(|s| s.run), Some(2));
+        assert_eq!(occurrences[0].state, TaskState::Pending);
+    }
 }
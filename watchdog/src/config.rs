@@ -1,5 +1,5 @@
 use envconfig::Envconfig;
-use osentities::{cache::CacheConfig, database::DatabaseConfig};
+use osentities::{cache::CacheConfig, database::DatabaseConfig, secrets::SecretsConfig};
 use std::fmt::{Display, Formatter};
 
 #[derive(Envconfig, Clone)] // Intentionally no Debug so secret is not printed
@@ -10,6 +10,13 @@ pub struct WatchdogConfig {
     pub http_client_timeout_secs: u64,
     #[envconfig(from = "MAX_AMOUNT_OF_TASKS_TO_PROCESS", default = "100")]
     pub max_amount_of_tasks_to_process: u64,
+    /// How long a claimed task is reserved for this replica before others may reclaim it
+    #[envconfig(from = "TASK_LEASE_SECS", default = "60")]
+    pub task_lease_secs: u64,
+    #[envconfig(from = "TASK_HEARTBEAT_INTERVAL_SECS", default = "20")]
+    pub task_heartbeat_interval_secs: u64,
+    #[envconfig(nested = true)]
+    pub secrets_config: SecretsConfig,
     #[envconfig(nested = true)]
     pub redis: CacheConfig,
     #[envconfig(nested = true)]
@@ -28,6 +35,18 @@ impl Display for WatchdogConfig {
             "HTTP_CLIENT_TIMEOUT_SECS: {}",
             self.http_client_timeout_secs
         )?;
+        writeln!(
+            f,
+            "MAX_AMOUNT_OF_TASKS_TO_PROCESS: {}",
+            self.max_amount_of_tasks_to_process
+        )?;
+        writeln!(f, "TASK_LEASE_SECS: {}", self.task_lease_secs)?;
+        writeln!(
+            f,
+            "TASK_HEARTBEAT_INTERVAL_SECS: {}",
+            self.task_heartbeat_interval_secs
+        )?;
+        writeln!(f, "{}", self.secrets_config)?;
         writeln!(f, "{}", self.redis)?;
         writeln!(f, "{}", self.db)
     }